@@ -17,8 +17,36 @@ pub use self::plane::Plane;
 pub mod triangle;
 pub use self::triangle::Triangle;
 
-// pub mod trianglesoup;
-// pub use self::trianglesoup::TriangleSoup;
+pub mod trianglesoup;
+pub use self::trianglesoup::TriangleSoup;
+
+pub mod cuboid;
+pub use self::cuboid::Cuboid;
+
+pub mod cylinder;
+pub use self::cylinder::Cylinder;
+
+pub mod cone;
+pub use self::cone::Cone;
+
+pub mod disk;
+pub use self::disk::Disk;
+
+pub mod torus;
+pub use self::torus::Torus;
+
+pub mod transformed;
+pub use self::transformed::Transformed;
+
+pub mod csg;
+pub use self::csg::{ Csg, CsgOperation };
+
+pub mod sdf;
+pub use self::sdf::{ Sdf, SdfObject };
+
+pub mod heightfield;
+pub use self::heightfield::Heightfield;
+
 
 
 
@@ -31,4 +59,119 @@ use crate::scene::{ Ray, Material };
 pub trait Object {
    fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool;
    fn get_material(&self) -> &Material;
+
+   // Surface (u, v) coordinates in [0, 1]^2 of a point <p> on the surface.
+   // Primitives without a parameterization return (0, 0).
+   fn uv(&self, _p: &Vector3<f64>) -> (f64, f64)
+   {
+      return (0., 0.);
+   }
+
+   // intersect(), also handing back the material at the hit. Only objects
+   // made of parts with different materials (CSG) need to override this.
+   fn intersect_material(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> Option<&Material>
+   {
+      if self.intersect(ray, min_t, t, n) {
+         return Some(self.get_material());
+      }
+      return None;
+   }
+
+   // Push every interval of the (whole, infinite) line through <ray> that is
+   // inside this object onto <spans>, sorted by t and not overlapping. This
+   // is what constructive solid geometry is built on. Surfaces that don't
+   // enclose a volume (triangles, disks) have no inside and push nothing.
+   fn intervals<'a>(&'a self, _ray: &Ray, _spans: &mut Vec<Interval<'a>>)
+   {
+   }
+
+   // Total surface area. Only objects that can be sampled as area lights
+   // (see sample_surface) report one, the rest return zero.
+   fn area(&self) -> f64
+   {
+      return 0.;
+   }
+
+   // A point on the surface, picked with the uniform random numbers <u>,
+   // and the unit normal there. Only called on objects with a nonzero area().
+   fn sample_surface(&self, _u: [f64; 2]) -> (Vector3<f64>, Vector3<f64>)
+   {
+      panic!("Error: this kind of object can't be sampled as a light");
+   }
+
+   // Density per unit area of sample_surface() picking the point <p>, with
+   // the normal <n>. Points are spread uniformly unless a transform
+   // stretches the surface unevenly (see Transformed).
+   fn surface_pdf(&self, _p: &Vector3<f64>, _n: &Vector3<f64>) -> f64
+   {
+      return 1. / self.area();
+   }
+
+   // A sphere (center, radius) the whole object fits in, which photons are
+   // aimed at. None for unbounded objects and those that don't know theirs.
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      return None;
+   }
+}
+
+
+// A piece of a line inside a solid: it enters at <t_in> through a surface
+// with outward normal <n_in>, and leaves at <t_out> through <n_out>. The ends
+// may be infinite (e.g. for half-spaces), in which case their normals are zero.
+#[derive(Clone, Copy)]
+pub struct Interval<'a> {
+   pub t_in: f64,
+   pub n_in: Vector3<f64>,
+   pub mat_in: &'a Material,
+   pub t_out: f64,
+   pub n_out: Vector3<f64>,
+   pub mat_out: &'a Material
+}
+
+
+impl<'a> Interval<'a> {
+   pub fn new(t_in: f64, n_in: Vector3<f64>, t_out: f64, n_out: Vector3<f64>, mat: &'a Material) -> Interval<'a>
+   {
+      return Interval { t_in, n_in, mat_in: mat, t_out, n_out, mat_out: mat };
+   }
+
+   // The whole line
+   pub fn everything(mat: &'a Material) -> Interval<'a>
+   {
+      let zero = Vector3::new(0., 0., 0.);
+      return Interval::new(f64::NEG_INFINITY, zero, f64::INFINITY, zero, mat);
+   }
+
+   // The part of the line inside both intervals, if there is any
+   pub fn overlap(&self, other: &Interval<'a>) -> Option<Interval<'a>>
+   {
+      let mut result = *self;
+      if other.t_in > result.t_in {
+         result.t_in = other.t_in;
+         result.n_in = other.n_in;
+         result.mat_in = other.mat_in;
+      }
+      if other.t_out < result.t_out {
+         result.t_out = other.t_out;
+         result.n_out = other.n_out;
+         result.mat_out = other.mat_out;
+      }
+      if result.t_in > result.t_out {
+         return None;
+      }
+      return Some(result);
+   }
+}
+
+
+// Build two unit vectors <u> and <v> such that (u, v, w) is a right-handed
+// orthonormal frame. <w> must be unit length.
+pub fn orthonormal_basis(w: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>)
+{
+   // Cross with whichever coordinate axis is least parallel to w
+   let a = if w[0].abs() > 0.9 { Vector3::new(0., 1., 0.) } else { Vector3::new(1., 0., 0.) };
+   let v = w.cross(&a).normalize();
+   let u = v.cross(w);
+   return (u, v);
 }
@@ -0,0 +1,265 @@
+// for the capital A, B, C, in intersect
+#![allow(non_snake_case)]
+
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::{ Object, Interval, orthonormal_basis };
+use crate::numeric::solve_quadratic;
+
+
+// A (possibly truncated) cone along the segment from <base> to
+// <base + height * axis>. The radius goes linearly from <radius> at the base
+// to <top_radius> at the top; a top radius of 0 gives a pointed cone.
+// Both ends can optionally be closed by disks.
+pub struct Cone {
+   pub base: Vector3<f64>,
+   // Unit axis, and two unit vectors perpendicular to it
+   pub axis: Vector3<f64>,
+   pub u: Vector3<f64>,
+   pub v: Vector3<f64>,
+   pub radius: f64,
+   pub top_radius: f64,
+   pub height: f64,
+   pub capped: bool,
+   pub material: Material
+}
+
+
+impl Cone {
+   pub fn new(base: &Vector3<f64>, axis: &Vector3<f64>, radius: f64, top_radius: f64, height: f64, capped: bool, material: Material) -> Cone
+   {
+      let axis = axis.normalize();
+      let (u, v) = orthonormal_basis(&axis);
+      return Cone { base: *base, axis, u, v, radius, top_radius, height, capped, material };
+   }
+
+   // Express a world space vector in the local (u, v, axis) frame
+   fn to_local(&self, x: &Vector3<f64>) -> Vector3<f64>
+   {
+      return Vector3::new(x.dot(&self.u), x.dot(&self.v), x.dot(&self.axis));
+   }
+
+   // Bring a normal from the local frame back to world space
+   fn to_world(&self, n: &Vector3<f64>) -> Vector3<f64>
+   {
+      return n[0] * self.u + n[1] * self.v + n[2] * self.axis;
+   }
+
+   // World space normal of the side at the local point <p>: the gradient of
+   // the implicit function x^2 + y^2 - (r0 + k z)^2
+   fn side_normal(&self, p: &Vector3<f64>, k: f64) -> Vector3<f64>
+   {
+      return self.to_world(&Vector3::new(p[0], p[1], -(self.radius + k * p[2]) * k).normalize());
+   }
+}
+
+
+impl Object for Cone {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      // Work in the local frame where the cone is x^2 + y^2 = (r0 + k z)^2,
+      // 0 <= z <= h, with k = (r1 - r0) / h the change in radius per unit height.
+      let e = self.to_local(&(ray.origin - self.base));
+      let d = self.to_local(&ray.direction);
+      let r0 = self.radius;
+      let k = (self.top_radius - self.radius) / self.height;
+
+      let mut hit = false;
+      let mut t_best = f64::INFINITY;
+
+      // Side of the cone:
+      let r_e = r0 + k * e[2];
+      let A = d[0] * d[0] + d[1] * d[1] - k * k * d[2] * d[2];
+      let B = 2. * (e[0] * d[0] + e[1] * d[1] - r_e * k * d[2]);
+      let C = e[0] * e[0] + e[1] * e[1] - r_e * r_e;
+      // (A ray parallel to the slope of the cone has A = 0 and only one root)
+      let mut roots = [0.; 2];
+      let n_roots = solve_quadratic(A, B, C, &mut roots);
+      for &root in roots[..n_roots].iter() {
+         let p = e + root * d;
+         if root >= min_t && root < t_best && p[2] >= 0. && p[2] <= self.height {
+            t_best = root;
+            *n = self.side_normal(&p, k);
+            hit = true;
+         }
+      }
+
+      // End caps (disks at z = 0 and z = h):
+      if self.capped && d[2] != 0. {
+         for &(z_cap, r_cap, n_z) in [(0., self.radius, -1.), (self.height, self.top_radius, 1.)].iter() {
+            let root = (z_cap - e[2]) / d[2];
+            let p = e + root * d;
+            if root >= min_t && root < t_best && p[0] * p[0] + p[1] * p[1] <= r_cap * r_cap {
+               t_best = root;
+               *n = n_z * self.axis;
+               hit = true;
+            }
+         }
+      }
+
+      if hit {
+         *t = t_best;
+      }
+      return hit;
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return &self.material;
+   }
+
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      let center = self.base + self.height / 2. * self.axis;
+      let radius = self.radius.max(self.top_radius);
+      return Some((center, (radius * radius + self.height * self.height / 4.).sqrt()));
+   }
+
+   // For CSG the cone is always a closed solid, caps or not
+   fn intervals<'a>(&'a self, ray: &Ray, spans: &mut Vec<Interval<'a>>)
+   {
+      let e = self.to_local(&(ray.origin - self.base));
+      let d = self.to_local(&ray.direction);
+      let k = (self.top_radius - self.radius) / self.height;
+      let mat = &self.material;
+      let zero = Vector3::new(0., 0., 0.);
+
+      // Between the planes of the two caps...
+      let slab = if d[2] == 0. {
+         if e[2] < 0. || e[2] > self.height {
+            return;
+         }
+         Interval::everything(mat)
+      } else {
+         let t_base = -e[2] / d[2];
+         let t_top = (self.height - e[2]) / d[2];
+         if t_base < t_top {
+            Interval::new(t_base, -self.axis, t_top, self.axis, mat)
+         } else {
+            Interval::new(t_top, self.axis, t_base, -self.axis, mat)
+         }
+      };
+
+      // ...and inside the (double) infinite cone, where A t^2 + B t + C <= 0.
+      // Between the caps only the nappe we want is there.
+      let r_e = self.radius + k * e[2];
+      let A = d[0] * d[0] + d[1] * d[1] - k * k * d[2] * d[2];
+      let B = 2. * (e[0] * d[0] + e[1] * d[1] - r_e * k * d[2]);
+      let C = e[0] * e[0] + e[1] * e[1] - r_e * r_e;
+      let mut sides = Vec::with_capacity(2);
+      if A.abs() <= 1.0e-12 {
+         // Parallel to the slope: a half line (or all/nothing)
+         if B == 0. {
+            if C <= 0. {
+               sides.push(Interval::everything(mat));
+            }
+         } else {
+            let root = -C / B;
+            let n_root = self.side_normal(&(e + root * d), k);
+            if B > 0. {
+               sides.push(Interval::new(f64::NEG_INFINITY, zero, root, n_root, mat));
+            } else {
+               sides.push(Interval::new(root, n_root, f64::INFINITY, zero, mat));
+            }
+         }
+      } else {
+         let mut roots = [0.; 2];
+         if solve_quadratic(A, B, C, &mut roots) < 2 {
+            // Never crosses the surface: always inside if the parabola opens down
+            if A < 0. {
+               sides.push(Interval::everything(mat));
+            }
+         } else {
+            let (t0, t1) = (roots[0], roots[1]);
+            let n0 = self.side_normal(&(e + t0 * d), k);
+            let n1 = self.side_normal(&(e + t1 * d), k);
+            if A > 0. {
+               sides.push(Interval::new(t0, n0, t1, n1, mat));
+            } else {
+               sides.push(Interval::new(f64::NEG_INFINITY, zero, t0, n0, mat));
+               sides.push(Interval::new(t1, n1, f64::INFINITY, zero, mat));
+            }
+         }
+      }
+
+      for side in sides.iter() {
+         if let Some(span) = side.overlap(&slab) {
+            spans.push(span);
+         }
+      }
+   }
+
+   // The side wraps u around the axis and runs v from base to top.
+   // The caps are mapped straight down the axis onto the unit square.
+   fn uv(&self, p: &Vector3<f64>) -> (f64, f64)
+   {
+      let q = self.to_local(&(p - self.base));
+      let on_base = q[2].abs() < 1.0e-6 * self.height;
+      let on_top = (q[2] - self.height).abs() < 1.0e-6 * self.height;
+      if self.capped && (on_base || (on_top && self.top_radius > 0.)) {
+         let r_cap = if on_base { self.radius } else { self.top_radius };
+         return (0.5 * (q[0] / r_cap + 1.), 0.5 * (q[1] / r_cap + 1.));
+      }
+      let phi = q[1].atan2(q[0]);
+      return ((phi + PI) / (2. * PI), q[2] / self.height);
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Radius 1 at z = 0 narrowing to a point at z = 1, around the z axis
+   fn cone(capped: bool) -> Cone
+   {
+      return Cone::new(&Vector3::zeros(), &Vector3::new(0., 0., 1.), 1., 0., 1., capped, Material::gray());
+   }
+
+   fn hit(cone: &Cone, origin: Vector3<f64>, direction: Vector3<f64>) -> Option<(f64, Vector3<f64>)>
+   {
+      let (mut t, mut n) = (0., Vector3::zeros());
+      if !cone.intersect(&Ray { origin, direction }, 1.0e-6, &mut t, &mut n) {
+         return None;
+      }
+      return Some((t, n));
+   }
+
+   #[test]
+   fn hits_the_slope()
+   {
+      // At half height the radius is 0.5, and the 45 degree slope tilts the
+      // normal up as much as out
+      let (t, n) = hit(&cone(false), Vector3::new(5., 0., 0.5), Vector3::new(-1., 0., 0.)).unwrap();
+      assert!((t - 4.5).abs() < 1.0e-12);
+      assert!((n - Vector3::new(1., 0., 1.).normalize()).norm() < 1.0e-12);
+      // Above the tip, and the other nappe of the double cone doesn't count
+      assert!(hit(&cone(false), Vector3::new(5., 0., 1.5), Vector3::new(-1., 0., 0.)).is_none());
+   }
+
+   #[test]
+   fn ray_along_the_slope_has_one_root()
+   {
+      // Parallel to the far side's slope, it only crosses the near side
+      let direction = Vector3::new(-1., 0., 1.);
+      let (t, _) = hit(&cone(false), Vector3::new(0.5, 0., -1.), direction).unwrap();
+      let p = Vector3::new(0.5, 0., -1.) + t * direction;
+      assert!(p.z >= 0. && p.z <= 1.);
+      assert!(((p.x * p.x + p.y * p.y).sqrt() - (1. - p.z)).abs() < 1.0e-9);
+   }
+
+   #[test]
+   fn base_is_capped()
+   {
+      // Open, the ray goes in through the base and meets the slope inside
+      let (t, _) = hit(&cone(false), Vector3::new(0.2, 0., -1.), Vector3::new(0., 0., 1.)).unwrap();
+      assert!((t - 1.8).abs() < 1.0e-12);
+      let (t, n) = hit(&cone(true), Vector3::new(0.2, 0., -1.), Vector3::new(0., 0., 1.)).unwrap();
+      assert!((t - 1.).abs() < 1.0e-12);
+      assert_eq!(n, Vector3::new(0., 0., -1.));
+   }
+}
@@ -0,0 +1,242 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::{ Object, Interval };
+
+
+pub enum CsgOperation {
+   // Inside either child
+   Union,
+   // Inside both children
+   Intersection,
+   // Inside the left child but not the right one
+   Difference
+}
+
+
+impl CsgOperation {
+   fn inside(&self, in_left: bool, in_right: bool) -> bool
+   {
+      return match self {
+         CsgOperation::Union => in_left || in_right,
+         CsgOperation::Intersection => in_left && in_right,
+         CsgOperation::Difference => in_left && !in_right
+      };
+   }
+}
+
+
+// Constructive solid geometry: a solid made by combining two other solids.
+// Each child keeps its own material, so e.g. the inside of a hole drilled by
+// a difference shows the material of the drill (the right child).
+pub struct Csg {
+   pub operation: CsgOperation,
+   pub left: Box<dyn Object + Sync>,
+   pub right: Box<dyn Object + Sync>
+}
+
+
+impl Csg {
+   pub fn new(operation: CsgOperation, left: Box<dyn Object + Sync>, right: Box<dyn Object + Sync>) -> Csg
+   {
+      return Csg { operation, left, right };
+   }
+}
+
+
+impl Object for Csg {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      return self.intersect_material(ray, min_t, t, n).is_some();
+   }
+
+   // There isn't one material for the whole solid, see intersect_material
+   fn get_material(&self) -> &Material
+   {
+      return self.left.get_material();
+   }
+
+   // Whatever the operation, the result is inside the union of the two
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      let (c1, r1) = self.left.bounding_sphere()?;
+      let (c2, r2) = self.right.bounding_sphere()?;
+      let d = (c2 - c1).norm();
+      if d + r2 <= r1 {
+         return Some((c1, r1));
+      }
+      if d + r1 <= r2 {
+         return Some((c2, r2));
+      }
+      let radius = (d + r1 + r2) / 2.;
+      return Some((c1 + (radius - r1) / d * (c2 - c1), radius));
+   }
+
+   // The first boundary of the result past min_t, and the material of the
+   // child whose surface it is
+   fn intersect_material(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> Option<&Material>
+   {
+      let mut spans = Vec::new();
+      self.intervals(ray, &mut spans);
+
+      // The spans are sorted, and their ends may be infinite
+      for span in spans.iter() {
+         if span.t_in.is_finite() && span.t_in >= min_t {
+            *t = span.t_in;
+            *n = span.n_in;
+            return Some(span.mat_in);
+         }
+         if span.t_out.is_finite() && span.t_out >= min_t {
+            *t = span.t_out;
+            *n = span.n_out;
+            return Some(span.mat_out);
+         }
+      }
+      return None;
+   }
+
+   // Sweep along the line over the boundaries of both children, keeping track
+   // of whether we're inside each of them. Every time the operation flips from
+   // outside to inside (or back) we've found a boundary of the result.
+   fn intervals<'a>(&'a self, ray: &Ray, spans: &mut Vec<Interval<'a>>)
+   {
+      let mut left = Vec::new();
+      let mut right = Vec::new();
+      self.left.intervals(ray, &mut left);
+      self.right.intervals(ray, &mut right);
+
+      // Events are (t, is the left child, entering, normal, material).
+      // Whatever is inside the result is outside the right child for a
+      // difference, so the right child's normals flip there.
+      let flip = match self.operation {
+         CsgOperation::Difference => -1.,
+         _ => 1.
+      };
+      let mut events = Vec::with_capacity(2 * (left.len() + right.len()));
+      for span in left.iter() {
+         events.push((span.t_in, true, true, span.n_in, span.mat_in));
+         events.push((span.t_out, true, false, span.n_out, span.mat_out));
+      }
+      for span in right.iter() {
+         events.push((span.t_in, false, true, flip * span.n_in, span.mat_in));
+         events.push((span.t_out, false, false, flip * span.n_out, span.mat_out));
+      }
+      events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+      let mut in_left = false;
+      let mut in_right = false;
+      let mut inside = false;
+      let mut start: Option<(f64, Vector3<f64>, &'a Material)> = None;
+      for &(t, is_left, entering, n, mat) in events.iter() {
+         if is_left {
+            in_left = entering;
+         } else {
+            in_right = entering;
+         }
+
+         let now_inside = self.operation.inside(in_left, in_right);
+         if now_inside && !inside {
+            start = Some((t, n, mat));
+         } else if !now_inside && inside {
+            let (t_in, n_in, mat_in) = start.unwrap();
+            spans.push(Interval { t_in, n_in, mat_in, t_out: t, n_out: n, mat_out: mat });
+         }
+         inside = now_inside;
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::geometry::{ Sphere, Transformed };
+   use na::Matrix4;
+
+   // The operands are told apart by their material's id
+   fn sphere(center: Vector3<f64>, radius: f64, id: usize) -> Box<dyn Object + Sync>
+   {
+      let mut material = Material::gray();
+      material.id = id;
+      return Box::new(Sphere::new(&center, radius, material));
+   }
+
+   // Straight down the z axis, starting at z = 5
+   fn down_z() -> Ray
+   {
+      return Ray { origin: Vector3::new(0., 0., 5.), direction: Vector3::new(0., 0., -1.) };
+   }
+
+   #[test]
+   fn difference_hollows_out_a_sphere()
+   {
+      let origin = Vector3::zeros();
+      let shell = Csg::new(CsgOperation::Difference, sphere(origin, 1., 1), sphere(origin, 0.5, 2));
+      let (mut t, mut n) = (0., Vector3::zeros());
+
+      let mat = shell.intersect_material(&down_z(), 0., &mut t, &mut n).unwrap();
+      assert!((t - 4.).abs() < 1e-12);
+      assert!((n - Vector3::new(0., 0., 1.)).norm() < 1e-12);
+      assert_eq!(mat.id, 1);
+
+      // The wall of the cavity belongs to the right child, facing into the cavity
+      let mat = shell.intersect_material(&down_z(), 4.1, &mut t, &mut n).unwrap();
+      assert!((t - 4.5).abs() < 1e-12);
+      assert!((n - Vector3::new(0., 0., -1.)).norm() < 1e-12);
+      assert_eq!(mat.id, 2);
+
+      let mut spans = Vec::new();
+      shell.intervals(&down_z(), &mut spans);
+      assert_eq!(spans.len(), 2);
+      assert!((spans[0].t_in - 4.).abs() < 1e-12 && (spans[0].t_out - 4.5).abs() < 1e-12);
+      assert!((spans[1].t_in - 5.5).abs() < 1e-12 && (spans[1].t_out - 6.).abs() < 1e-12);
+   }
+
+   #[test]
+   fn union_has_no_inner_boundary()
+   {
+      let union = Csg::new(CsgOperation::Union, sphere(Vector3::new(0., 0., 0.5), 1., 1), sphere(Vector3::new(0., 0., -0.5), 1., 2));
+      let (mut t, mut n) = (0., Vector3::zeros());
+      let mat = union.intersect_material(&down_z(), 0., &mut t, &mut n).unwrap();
+      assert!((t - 3.5).abs() < 1e-12);
+      assert_eq!(mat.id, 1);
+
+      // The next boundary is where the ray leaves the second sphere
+      let mat = union.intersect_material(&down_z(), 3.6, &mut t, &mut n).unwrap();
+      assert!((t - 6.5).abs() < 1e-12);
+      assert!((n - Vector3::new(0., 0., -1.)).norm() < 1e-12);
+      assert_eq!(mat.id, 2);
+   }
+
+   #[test]
+   fn intersection_is_the_lens()
+   {
+      let lens = Csg::new(CsgOperation::Intersection, sphere(Vector3::new(0., 0., 0.5), 1., 1), sphere(Vector3::new(0., 0., -0.5), 1., 2));
+      let (mut t, mut n) = (0., Vector3::zeros());
+      let mat = lens.intersect_material(&down_z(), 0., &mut t, &mut n).unwrap();
+      // Enters through the second sphere at z = 0.5
+      assert!((t - 4.5).abs() < 1e-12);
+      assert_eq!(mat.id, 2);
+      assert!(lens.intersect(&down_z(), 4.6, &mut t, &mut n));
+      assert!((t - 5.5).abs() < 1e-12);
+
+      // Outside the lens, but inside the first sphere
+      let ray = Ray { origin: Vector3::new(0.9, 0., 5.), direction: Vector3::new(0., 0., -1.) };
+      assert!(!lens.intersect(&ray, 0., &mut t, &mut n));
+   }
+
+   #[test]
+   fn transformed_operands()
+   {
+      // A unit sphere moved up by 2, minus nothing it overlaps
+      let moved: Box<dyn Object + Sync> = Box::new(Transformed::new(sphere(Vector3::zeros(), 1., 1),
+                                                                    Matrix4::new_translation(&Vector3::new(0., 0., 2.))));
+      let csg = Csg::new(CsgOperation::Difference, moved, sphere(Vector3::new(10., 0., 0.), 1., 2));
+      let (mut t, mut n) = (0., Vector3::zeros());
+      let mat = csg.intersect_material(&down_z(), 0., &mut t, &mut n).unwrap();
+      assert!((t - 2.).abs() < 1e-12);
+      assert!((n - Vector3::new(0., 0., 1.)).norm() < 1e-12);
+      assert_eq!(mat.id, 1);
+   }
+}
@@ -0,0 +1,184 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::{ Object, Interval };
+
+
+// An axis-aligned box. (Named Cuboid so it doesn't clash with std's Box.)
+// Oriented boxes are made by wrapping a Cuboid in a Transformed object.
+pub struct Cuboid {
+   // Corner with the smallest x, y, z
+   pub min: Vector3<f64>,
+   // Corner with the largest x, y, z
+   pub max: Vector3<f64>,
+   pub material: Material
+}
+
+
+impl Cuboid {
+   pub fn new(min: &Vector3<f64>, max: &Vector3<f64>, material: Material) -> Cuboid
+   {
+      // Don't trust the caller to have sorted the corners
+      return Cuboid { min: min.inf(max), max: min.sup(max), material };
+   }
+
+   // Slab method: intersect the line through the ray with the three pairs of
+   // parallel planes bounding the box. The line is inside the box between the
+   // largest entering <t> and the smallest exiting <t>.
+   // Returns (t_near, near_axis, t_far, far_axis), or None if we miss.
+   fn slabs(&self, ray: &Ray) -> Option<(f64, usize, f64, usize)>
+   {
+      let e = ray.origin;
+      let d = ray.direction;
+
+      let mut t_near = f64::NEG_INFINITY;
+      let mut t_far = f64::INFINITY;
+      let mut near_axis = 0usize;
+      let mut far_axis = 0usize;
+
+      for axis in 0..3 {
+         if d[axis] == 0. {
+            // Parallel to this slab, so we miss unless we're between the planes
+            if e[axis] < self.min[axis] || e[axis] > self.max[axis] {
+               return None;
+            }
+            continue;
+         }
+
+         let mut t0 = (self.min[axis] - e[axis]) / d[axis];
+         let mut t1 = (self.max[axis] - e[axis]) / d[axis];
+         if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+         }
+
+         if t0 > t_near {
+            t_near = t0;
+            near_axis = axis;
+         }
+         if t1 < t_far {
+            t_far = t1;
+            far_axis = axis;
+         }
+         if t_near > t_far {
+            return None;
+         }
+      }
+
+      return Some((t_near, near_axis, t_far, far_axis));
+   }
+}
+
+
+impl Object for Cuboid {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      let d = ray.direction;
+      let (t_near, near_axis, t_far, far_axis) = match self.slabs(ray) {
+         Some(slabs) => slabs,
+         None => return false
+      };
+
+      // Take the entering hit if we can, otherwise the exiting one (the ray
+      // started inside the box). Normals always point out of the box.
+      let mut normal = Vector3::new(0., 0., 0.);
+      if t_near >= min_t {
+         *t = t_near;
+         normal[near_axis] = -d[near_axis].signum();
+      } else if t_far >= min_t {
+         *t = t_far;
+         normal[far_axis] = d[far_axis].signum();
+      } else {
+         return false;
+      }
+
+      *n = normal;
+      return true;
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return &self.material;
+   }
+
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      return Some(((self.min + self.max) / 2., (self.max - self.min).norm() / 2.));
+   }
+
+   fn intervals<'a>(&'a self, ray: &Ray, spans: &mut Vec<Interval<'a>>)
+   {
+      let d = ray.direction;
+      if let Some((t_near, near_axis, t_far, far_axis)) = self.slabs(ray) {
+         let mut n_near = Vector3::new(0., 0., 0.);
+         let mut n_far = Vector3::new(0., 0., 0.);
+         n_near[near_axis] = -d[near_axis].signum();
+         n_far[far_axis] = d[far_axis].signum();
+         spans.push(Interval::new(t_near, n_near, t_far, n_far, &self.material));
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // From (-1, -2, -3) to (1, 2, 3), given with the corners mixed up
+   fn cuboid() -> Cuboid
+   {
+      return Cuboid::new(&Vector3::new(1., -2., 3.), &Vector3::new(-1., 2., -3.), Material::gray());
+   }
+
+   fn hit(origin: Vector3<f64>, direction: Vector3<f64>) -> Option<(f64, Vector3<f64>)>
+   {
+      let (mut t, mut n) = (0., Vector3::zeros());
+      if !cuboid().intersect(&Ray { origin, direction }, 1.0e-6, &mut t, &mut n) {
+         return None;
+      }
+      return Some((t, n));
+   }
+
+   #[test]
+   fn hits_the_face_in_front()
+   {
+      let (t, n) = hit(Vector3::new(0., 0., 10.), Vector3::new(0., 0., -2.)).unwrap();
+      assert!((t - 3.5).abs() < 1.0e-12);
+      assert_eq!(n, Vector3::new(0., 0., 1.));
+      let (t, n) = hit(Vector3::new(-5., 1., 0.), Vector3::new(1., 0., 0.)).unwrap();
+      assert!((t - 4.).abs() < 1.0e-12);
+      assert_eq!(n, Vector3::new(-1., 0., 0.));
+   }
+
+   #[test]
+   fn from_inside_hits_the_way_out()
+   {
+      let (t, n) = hit(Vector3::zeros(), Vector3::new(0., -1., 0.)).unwrap();
+      assert!((t - 2.).abs() < 1.0e-12);
+      assert_eq!(n, Vector3::new(0., -1., 0.));
+   }
+
+   #[test]
+   fn misses_beside_and_behind()
+   {
+      // Parallel to the x slabs, outside them
+      assert!(hit(Vector3::new(1.5, 0., 10.), Vector3::new(0., 0., -1.)).is_none());
+      // Passing a corner diagonally
+      assert!(hit(Vector3::new(0., 3., 10.), Vector3::new(0., 1., -1.)).is_none());
+      // Pointing away
+      assert!(hit(Vector3::new(0., 0., 10.), Vector3::new(0., 0., 1.)).is_none());
+   }
+
+   #[test]
+   fn interval_spans_the_inside()
+   {
+      let ray = Ray { origin: Vector3::new(0., 0., 10.), direction: Vector3::new(0., 0., -1.) };
+      let cuboid = cuboid();
+      let mut spans = Vec::new();
+      cuboid.intervals(&ray, &mut spans);
+      assert_eq!(spans.len(), 1);
+      assert!((spans[0].t_in - 7.).abs() < 1.0e-12 && (spans[0].t_out - 13.).abs() < 1.0e-12);
+      assert_eq!(spans[0].n_in, Vector3::new(0., 0., 1.));
+      assert_eq!(spans[0].n_out, Vector3::new(0., 0., -1.));
+   }
+}
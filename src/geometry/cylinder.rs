@@ -0,0 +1,246 @@
+// for the capital A, B, C, in intersect
+#![allow(non_snake_case)]
+
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::{ Object, Interval, orthonormal_basis };
+use crate::numeric::solve_quadratic;
+
+
+// A finite cylinder of radius <radius> around the segment from <base> to
+// <base + height * axis>, optionally closed by disks at both ends.
+pub struct Cylinder {
+   pub base: Vector3<f64>,
+   // Unit axis, and two unit vectors perpendicular to it
+   pub axis: Vector3<f64>,
+   pub u: Vector3<f64>,
+   pub v: Vector3<f64>,
+   pub radius: f64,
+   pub height: f64,
+   pub capped: bool,
+   pub material: Material
+}
+
+
+impl Cylinder {
+   pub fn new(base: &Vector3<f64>, axis: &Vector3<f64>, radius: f64, height: f64, capped: bool, material: Material) -> Cylinder
+   {
+      let axis = axis.normalize();
+      let (u, v) = orthonormal_basis(&axis);
+      return Cylinder { base: *base, axis, u, v, radius, height, capped, material };
+   }
+
+   // Express a world space vector in the local (u, v, axis) frame
+   fn to_local(&self, x: &Vector3<f64>) -> Vector3<f64>
+   {
+      return Vector3::new(x.dot(&self.u), x.dot(&self.v), x.dot(&self.axis));
+   }
+
+   // Bring a normal from the local frame back to world space
+   fn to_world(&self, n: &Vector3<f64>) -> Vector3<f64>
+   {
+      return n[0] * self.u + n[1] * self.v + n[2] * self.axis;
+   }
+}
+
+
+impl Object for Cylinder {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      // Work in the local frame where the cylinder is x^2 + y^2 = r^2, 0 <= z <= h.
+      // The frame is orthonormal, so <t> is unchanged.
+      let e = self.to_local(&(ray.origin - self.base));
+      let d = self.to_local(&ray.direction);
+
+      let mut hit = false;
+      let mut t_best = f64::INFINITY;
+      let mut n_local = Vector3::new(0., 0., 0.);
+
+      // Side of the cylinder:
+      let A = d[0] * d[0] + d[1] * d[1];
+      let B = 2. * (e[0] * d[0] + e[1] * d[1]);
+      let C = e[0] * e[0] + e[1] * e[1] - self.radius * self.radius;
+      let mut roots = [0.; 2];
+      if A != 0. {
+         let n_roots = solve_quadratic(A, B, C, &mut roots);
+         for &root in roots[..n_roots].iter() {
+            let z = e[2] + root * d[2];
+            if root >= min_t && root < t_best && z >= 0. && z <= self.height {
+               let p = e + root * d;
+               t_best = root;
+               n_local = Vector3::new(p[0], p[1], 0.) / self.radius;
+               hit = true;
+            }
+         }
+      }
+
+      // End caps (disks at z = 0 and z = h):
+      if self.capped && d[2] != 0. {
+         for &(z_cap, n_z) in [(0., -1.), (self.height, 1.)].iter() {
+            let root = (z_cap - e[2]) / d[2];
+            let p = e + root * d;
+            if root >= min_t && root < t_best && p[0] * p[0] + p[1] * p[1] <= self.radius * self.radius {
+               t_best = root;
+               n_local = Vector3::new(0., 0., n_z);
+               hit = true;
+            }
+         }
+      }
+
+      if hit {
+         *t = t_best;
+         *n = self.to_world(&n_local);
+      }
+      return hit;
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return &self.material;
+   }
+
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      let center = self.base + self.height / 2. * self.axis;
+      return Some((center, (self.radius * self.radius + self.height * self.height / 4.).sqrt()));
+   }
+
+   // For CSG the cylinder is always a closed solid, caps or not
+   fn intervals<'a>(&'a self, ray: &Ray, spans: &mut Vec<Interval<'a>>)
+   {
+      let e = self.to_local(&(ray.origin - self.base));
+      let d = self.to_local(&ray.direction);
+      let mat = &self.material;
+
+      // Inside the infinite cylinder...
+      let A = d[0] * d[0] + d[1] * d[1];
+      let B = 2. * (e[0] * d[0] + e[1] * d[1]);
+      let C = e[0] * e[0] + e[1] * e[1] - self.radius * self.radius;
+      let side = if A == 0. {
+         if C > 0. {
+            return;
+         }
+         Interval::everything(mat)
+      } else {
+         let mut roots = [0.; 2];
+         if solve_quadratic(A, B, C, &mut roots) < 2 {
+            return;
+         }
+         let (t0, t1) = (roots[0], roots[1]);
+         let p0 = e + t0 * d;
+         let p1 = e + t1 * d;
+         let n0 = self.to_world(&(Vector3::new(p0[0], p0[1], 0.) / self.radius));
+         let n1 = self.to_world(&(Vector3::new(p1[0], p1[1], 0.) / self.radius));
+         Interval::new(t0, n0, t1, n1, mat)
+      };
+
+      // ...and between the planes of the two caps
+      let slab = if d[2] == 0. {
+         if e[2] < 0. || e[2] > self.height {
+            return;
+         }
+         Interval::everything(mat)
+      } else {
+         let t_base = -e[2] / d[2];
+         let t_top = (self.height - e[2]) / d[2];
+         if t_base < t_top {
+            Interval::new(t_base, -self.axis, t_top, self.axis, mat)
+         } else {
+            Interval::new(t_top, self.axis, t_base, -self.axis, mat)
+         }
+      };
+
+      if let Some(span) = side.overlap(&slab) {
+         spans.push(span);
+      }
+   }
+
+   // The side wraps u around the axis and runs v from base to top.
+   // The caps are mapped straight down the axis onto the unit square.
+   fn uv(&self, p: &Vector3<f64>) -> (f64, f64)
+   {
+      let q = self.to_local(&(p - self.base));
+      let on_side = (q[0] * q[0] + q[1] * q[1]).sqrt() > self.radius * (1. - 1.0e-6);
+      if !self.capped || on_side {
+         let phi = q[1].atan2(q[0]);
+         return ((phi + PI) / (2. * PI), q[2] / self.height);
+      }
+      return (0.5 * (q[0] / self.radius + 1.), 0.5 * (q[1] / self.radius + 1.));
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Radius 1 around the z axis, from z = 0 to z = 2
+   fn cylinder(capped: bool) -> Cylinder
+   {
+      return Cylinder::new(&Vector3::zeros(), &Vector3::new(0., 0., 3.), 1., 2., capped, Material::gray());
+   }
+
+   fn hit(cylinder: &Cylinder, origin: Vector3<f64>, direction: Vector3<f64>) -> Option<(f64, Vector3<f64>)>
+   {
+      let (mut t, mut n) = (0., Vector3::zeros());
+      if !cylinder.intersect(&Ray { origin, direction }, 1.0e-6, &mut t, &mut n) {
+         return None;
+      }
+      return Some((t, n));
+   }
+
+   #[test]
+   fn hits_the_side()
+   {
+      let (t, n) = hit(&cylinder(false), Vector3::new(5., 0., 1.), Vector3::new(-1., 0., 0.)).unwrap();
+      assert!((t - 4.).abs() < 1.0e-12);
+      assert!((n - Vector3::new(1., 0., 0.)).norm() < 1.0e-12);
+      // Above the top
+      assert!(hit(&cylinder(false), Vector3::new(5., 0., 2.5), Vector3::new(-1., 0., 0.)).is_none());
+   }
+
+   #[test]
+   fn open_ends_show_the_inside()
+   {
+      // Looking down the axis, the far wall is seen from inside
+      let origin = Vector3::new(0., 0., 5.);
+      let direction = Vector3::new(0.3, 0., -1.);
+      let (t, n) = hit(&cylinder(false), origin, direction).unwrap();
+      let p = origin + t * direction;
+      assert!((p.x - 1.).abs() < 1.0e-12 && p.z > 0. && p.z < 2.);
+      assert!((n - Vector3::new(1., 0., 0.)).norm() < 1.0e-12);
+      // Straight down the axis goes right through
+      assert!(hit(&cylinder(false), origin, Vector3::new(0., 0., -1.)).is_none());
+   }
+
+   #[test]
+   fn caps_close_it()
+   {
+      let (t, n) = hit(&cylinder(true), Vector3::new(0.5, 0., 5.), Vector3::new(0., 0., -1.)).unwrap();
+      assert!((t - 3.).abs() < 1.0e-12);
+      assert_eq!(n, Vector3::new(0., 0., 1.));
+      let (t, n) = hit(&cylinder(true), Vector3::new(0.5, 0., -1.), Vector3::new(0., 0., 1.)).unwrap();
+      assert!((t - 1.).abs() < 1.0e-12);
+      assert_eq!(n, Vector3::new(0., 0., -1.));
+   }
+
+   #[test]
+   fn interval_is_closed_either_way()
+   {
+      // Diagonally in through the top and out the side
+      let ray = Ray { origin: Vector3::new(-0.5, 0., 3.), direction: Vector3::new(1., 0., -1.) };
+      for capped in [false, true] {
+         let cylinder = cylinder(capped);
+         let mut spans = Vec::new();
+         cylinder.intervals(&ray, &mut spans);
+         assert_eq!(spans.len(), 1);
+         assert!((spans[0].t_in - 1.).abs() < 1.0e-12 && (spans[0].t_out - 1.5).abs() < 1.0e-12);
+         assert!((spans[0].n_in - Vector3::new(0., 0., 1.)).norm() < 1.0e-12);
+         assert!((spans[0].n_out - Vector3::new(1., 0., 0.)).norm() < 1.0e-12);
+      }
+   }
+}
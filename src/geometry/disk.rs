@@ -0,0 +1,101 @@
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::{ Object, orthonormal_basis };
+
+
+// A flat disk: the part of a plane within <radius> of <center>
+pub struct Disk {
+   pub center: Vector3<f64>,
+   // Unit normal, and two unit vectors in the plane of the disk
+   pub normal: Vector3<f64>,
+   pub u: Vector3<f64>,
+   pub v: Vector3<f64>,
+   pub radius: f64,
+   pub material: Material
+}
+
+
+impl Disk {
+   pub fn new(center: &Vector3<f64>, normal: &Vector3<f64>, radius: f64, material: Material) -> Disk
+   {
+      let normal = normal.normalize();
+      let (u, v) = orthonormal_basis(&normal);
+      return Disk { center: *center, normal, u, v, radius, material };
+   }
+}
+
+
+impl Object for Disk {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      let e = ray.origin;
+      let d = ray.direction;
+
+      // Same as Plane::intersect...
+      if d.dot(&self.normal) == 0. {
+         return false;
+      }
+      let t_plane = self.normal.dot(&(self.center - e)) / self.normal.dot(&d);
+      if t_plane < min_t {
+         return false;
+      }
+
+      // ...but the hit also has to be inside the circle
+      if (e + t_plane * d - self.center).norm_squared() > self.radius * self.radius {
+         return false;
+      }
+
+      *t = t_plane;
+      *n = self.normal;
+      return true;
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return &self.material;
+   }
+
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      return Some((self.center, self.radius));
+   }
+
+   // Polar coordinates: u goes around the center, v goes out to the rim
+   fn uv(&self, p: &Vector3<f64>) -> (f64, f64)
+   {
+      let q = p - self.center;
+      let phi = q.dot(&self.v).atan2(q.dot(&self.u));
+      return ((phi + PI) / (2. * PI), q.norm() / self.radius);
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn disk() -> Disk
+   {
+      return Disk::new(&Vector3::new(0., 1., 0.), &Vector3::new(0., 2., 0.), 1., Material::gray());
+   }
+
+   #[test]
+   fn hits_inside_the_circle_only()
+   {
+      let (mut t, mut n) = (0., Vector3::zeros());
+      let down = Vector3::new(0., -1., 0.);
+      let ray = Ray { origin: Vector3::new(0.6, 3., 0.6), direction: down };
+      assert!(disk().intersect(&ray, 1.0e-6, &mut t, &mut n));
+      assert!((t - 2.).abs() < 1.0e-12);
+      assert_eq!(n, Vector3::new(0., 1., 0.));
+      let ray = Ray { origin: Vector3::new(0.8, 3., 0.8), direction: down };
+      assert!(!disk().intersect(&ray, 1.0e-6, &mut t, &mut n));
+      // Edge on
+      let ray = Ray { origin: Vector3::new(-5., 1., 0.), direction: Vector3::new(1., 0., 0.) };
+      assert!(!disk().intersect(&ray, 1.0e-6, &mut t, &mut n));
+   }
+}
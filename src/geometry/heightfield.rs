@@ -0,0 +1,315 @@
+// for the capital A, B, C, in intersect_cell
+#![allow(non_snake_case)]
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::Object;
+use crate::numeric::solve_quadratic;
+
+
+// A terrain: heights sampled on a regular n_x by n_z grid over the rectangle
+// from <corner> to <corner + (size_x, 0, size_z)>, bilinearly interpolated
+// in between. It is only a surface, not a closed solid, so it can't be used
+// in CSG.
+pub struct Heightfield {
+   // Sample (i, k) is the world space height at x_i, z_k, stored row by row
+   // (one row per z). Image rows become rows along z.
+   pub heights: Vec<f64>,
+   pub n_x: usize,
+   pub n_z: usize,
+   pub corner: Vector3<f64>,
+   pub size_x: f64,
+   pub size_z: f64,
+   // Smooth normals at the samples, interpolated across the cells
+   pub normals: Vec<Vector3<f64>>,
+   pub min_height: f64,
+   pub max_height: f64,
+   pub material: Material
+}
+
+
+// Tolerance for round-off when deciding whether a hit is in a cell
+const HEIGHTFIELD_EPSILON: f64 = 1.0e-9;
+
+
+impl Heightfield {
+   // <samples> are in [0, 1] (e.g. gray levels) and get scaled by
+   // <height_scale> above corner[1]
+   #[allow(clippy::too_many_arguments)]
+   pub fn new(samples: &[f64], n_x: usize, n_z: usize, corner: &Vector3<f64>, size_x: f64, size_z: f64,
+              height_scale: f64, material: Material) -> Heightfield
+   {
+      assert!(n_x >= 2 && n_z >= 2, "Error: a heightfield needs at least 2x2 samples");
+      assert!(samples.len() == n_x * n_z, "Error: wrong number of heightfield samples");
+
+      let heights: Vec<f64> = samples.iter().map(|s| corner[1] + height_scale * s).collect();
+      let min_height = heights.iter().cloned().fold(f64::INFINITY, f64::min);
+      let max_height = heights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+      // Normals from central differences (one-sided at the edges)
+      let dx = size_x / (n_x - 1) as f64;
+      let dz = size_z / (n_z - 1) as f64;
+      let mut normals = Vec::with_capacity(n_x * n_z);
+      for k in 0..n_z {
+         for i in 0..n_x {
+            let (i0, i1) = (i.saturating_sub(1), usize::min(i + 1, n_x - 1));
+            let (k0, k1) = (k.saturating_sub(1), usize::min(k + 1, n_z - 1));
+            let dh_dx = (heights[k * n_x + i1] - heights[k * n_x + i0]) / ((i1 - i0) as f64 * dx);
+            let dh_dz = (heights[k1 * n_x + i] - heights[k0 * n_x + i]) / ((k1 - k0) as f64 * dz);
+            normals.push(Vector3::new(-dh_dx, 1., -dh_dz).normalize());
+         }
+      }
+
+      return Heightfield { heights, n_x, n_z, corner: *corner, size_x, size_z, normals, min_height, max_height, material };
+   }
+
+   fn height(&self, i: usize, k: usize) -> f64
+   {
+      return self.heights[k * self.n_x + i];
+   }
+
+   // Bounding box slabs, like Cuboid::slabs. Returns the (t_near, t_far)
+   // where the line is inside the box, or None if it misses.
+   fn bounds(&self, ray: &Ray) -> Option<(f64, f64)>
+   {
+      // Padded a little in y so flat terrain isn't right on the bottom face
+      let min = Vector3::new(self.corner[0], self.min_height - HEIGHTFIELD_EPSILON, self.corner[2]);
+      let max = Vector3::new(self.corner[0] + self.size_x, self.max_height + HEIGHTFIELD_EPSILON, self.corner[2] + self.size_z);
+      let e = ray.origin;
+      let d = ray.direction;
+
+      let mut t_near = f64::NEG_INFINITY;
+      let mut t_far = f64::INFINITY;
+      for axis in 0..3 {
+         if d[axis] == 0. {
+            if e[axis] < min[axis] || e[axis] > max[axis] {
+               return None;
+            }
+            continue;
+         }
+         let t0 = (min[axis] - e[axis]) / d[axis];
+         let t1 = (max[axis] - e[axis]) / d[axis];
+         t_near = t_near.max(t0.min(t1));
+         t_far = t_far.min(t0.max(t1));
+      }
+      if t_near > t_far {
+         return None;
+      }
+      return Some((t_near, t_far));
+   }
+
+   // Intersect the line through the ray with the bilinear patch over cell
+   // (i, k), looking only at t_from <= t <= t_to
+   #[allow(clippy::too_many_arguments)]
+   fn intersect_cell(&self, ray: &Ray, i: usize, k: usize, t_from: f64, t_to: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      let e = ray.origin;
+      let d = ray.direction;
+      let (h00, h10) = (self.height(i, k), self.height(i + 1, k));
+      let (h01, h11) = (self.height(i, k + 1), self.height(i + 1, k + 1));
+
+      // The ray can't hit the cell if it stays above or below all four corners
+      // (give or take round-off, flat cells are common)
+      let (y_from, y_to) = (e[1] + t_from * d[1], e[1] + t_to * d[1]);
+      let (cell_min, cell_max) = (h00.min(h10).min(h01.min(h11)), h00.max(h10).max(h01.max(h11)));
+      if y_from.min(y_to) > cell_max + HEIGHTFIELD_EPSILON || y_from.max(y_to) < cell_min - HEIGHTFIELD_EPSILON {
+         return false;
+      }
+
+      // Local cell coordinates along the ray: u = u0 + t du, v = v0 + t dv
+      let dx = self.size_x / (self.n_x - 1) as f64;
+      let dz = self.size_z / (self.n_z - 1) as f64;
+      let u0 = (e[0] - self.corner[0]) / dx - i as f64;
+      let v0 = (e[2] - self.corner[2]) / dz - k as f64;
+      let du = d[0] / dx;
+      let dv = d[2] / dz;
+
+      // h(u, v) = h00 + a u + b v + c u v, and we want y(t) - h(u(t), v(t)) = 0
+      let a = h10 - h00;
+      let b = h01 - h00;
+      let c = h00 - h10 - h01 + h11;
+      let A = -c * du * dv;
+      let B = d[1] - (a * du + b * dv + c * (u0 * dv + v0 * du));
+      let C = e[1] - (h00 + a * u0 + b * v0 + c * u0 * v0);
+
+      let mut roots = [0.; 2];
+      let n_roots = if A == 0. {
+         if B == 0. {
+            return false;
+         }
+         roots[0] = -C / B;
+         1
+      } else {
+         solve_quadratic(A, B, C, &mut roots)
+      };
+
+      for &root in roots[..n_roots].iter() {
+         // Hits right on the edge of the cell can land a hair outside it
+         let slack = HEIGHTFIELD_EPSILON * f64::max(1., root.abs());
+         if root >= t_from - slack && root <= t_to + slack {
+            let u = (u0 + root * du).clamp(0., 1.);
+            let v = (v0 + root * dv).clamp(0., 1.);
+            let n00 = self.normals[k * self.n_x + i];
+            let n10 = self.normals[k * self.n_x + i + 1];
+            let n01 = self.normals[(k + 1) * self.n_x + i];
+            let n11 = self.normals[(k + 1) * self.n_x + i + 1];
+            *t = root;
+            *n = ((1. - u) * (1. - v) * n00 + u * (1. - v) * n10 + (1. - u) * v * n01 + u * v * n11).normalize();
+            return true;
+         }
+      }
+      return false;
+   }
+}
+
+
+impl Object for Heightfield {
+   // Walk the grid cells under the ray in order (2D DDA over x and z) and
+   // intersect each cell's patch until one is hit
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      let (t_near, t_far) = match self.bounds(ray) {
+         Some(bounds) => bounds,
+         None => return false
+      };
+      let t_start = t_near.max(min_t);
+      if t_start > t_far {
+         return false;
+      }
+
+      let e = ray.origin;
+      let d = ray.direction;
+      let cell_size = [self.size_x / (self.n_x - 1) as f64, self.size_z / (self.n_z - 1) as f64];
+      let n_cells = [self.n_x - 1, self.n_z - 1];
+      let axes = [0, 2];
+
+      // Cell containing the start point, and for each of x and z: which way
+      // we step, the t of the next cell boundary, and the t between boundaries
+      let mut cell = [0usize; 2];
+      let mut step = [0isize; 2];
+      let mut t_next = [f64::INFINITY; 2];
+      let mut t_delta = [f64::INFINITY; 2];
+      for j in 0..2 {
+         let axis = axes[j];
+         let g = (e[axis] + t_start * d[axis] - self.corner[axis]) / cell_size[j];
+         cell[j] = (g.floor().max(0.) as usize).min(n_cells[j] - 1);
+         if d[axis] > 0. {
+            step[j] = 1;
+            let boundary = self.corner[axis] + (cell[j] + 1) as f64 * cell_size[j];
+            t_next[j] = (boundary - e[axis]) / d[axis];
+            t_delta[j] = cell_size[j] / d[axis];
+         } else if d[axis] < 0. {
+            step[j] = -1;
+            let boundary = self.corner[axis] + cell[j] as f64 * cell_size[j];
+            t_next[j] = (boundary - e[axis]) / d[axis];
+            t_delta[j] = -cell_size[j] / d[axis];
+         }
+      }
+
+      let mut t_cell = t_start;
+      loop {
+         let t_exit = t_next[0].min(t_next[1]).min(t_far);
+         if self.intersect_cell(ray, cell[0], cell[1], t_cell, t_exit, t, n) {
+            return true;
+         }
+         if t_exit >= t_far {
+            return false;
+         }
+
+         // Step into the neighbouring cell across the nearer boundary
+         let j = if t_next[0] < t_next[1] { 0 } else { 1 };
+         let next = cell[j] as isize + step[j];
+         if next < 0 || next >= n_cells[j] as isize {
+            return false;
+         }
+         cell[j] = next as usize;
+         t_cell = t_exit;
+         t_next[j] += t_delta[j];
+      }
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return &self.material;
+   }
+
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      let min = Vector3::new(self.corner[0], self.min_height, self.corner[2]);
+      let max = Vector3::new(self.corner[0] + self.size_x, self.max_height, self.corner[2] + self.size_z);
+      return Some(((min + max) / 2., (max - min).norm() / 2.));
+   }
+
+   // The grid's footprint mapped onto the unit square
+   fn uv(&self, p: &Vector3<f64>) -> (f64, f64)
+   {
+      return ((p[0] - self.corner[0]) / self.size_x, (p[2] - self.corner[2]) / self.size_z);
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // A unit square from the origin, with <samples> on a 2x2 grid scaled by 2
+   fn heightfield(samples: &[f64]) -> Heightfield
+   {
+      return Heightfield::new(samples, 2, 2, &Vector3::zeros(), 1., 1., 2., Material::gray());
+   }
+
+   fn straight_down(x: f64, z: f64) -> Ray
+   {
+      return Ray { origin: Vector3::new(x, 10., z), direction: Vector3::new(0., -1., 0.) };
+   }
+
+   #[test]
+   fn flat_terrain_is_hit_at_its_height_and_only_over_its_rectangle()
+   {
+      let flat = heightfield(&[0.5, 0.5, 0.5, 0.5]);
+      let (mut t, mut n) = (0., Vector3::zeros());
+      assert!(flat.intersect(&straight_down(0.3, 0.7), 0., &mut t, &mut n));
+      assert!((t - 9.).abs() < 1e-12);
+      assert!((n - Vector3::new(0., 1., 0.)).norm() < 1e-12);
+
+      // Outside the rectangle
+      assert!(!flat.intersect(&straight_down(1.5, 0.5), 0., &mut t, &mut n));
+
+      // Along the surface, just above and just below it
+      let above = Ray { origin: Vector3::new(-1., 1. + 1e-6, 0.5), direction: Vector3::new(1., 0., 0.) };
+      assert!(!flat.intersect(&above, 0., &mut t, &mut n));
+      let below = Ray { origin: Vector3::new(0.5, 0.5, 0.5), direction: Vector3::new(0., 1., 0.) };
+      assert!(flat.intersect(&below, 0., &mut t, &mut n));
+      assert!((t - 0.5).abs() < 1e-12);
+   }
+
+   #[test]
+   fn sloped_terrain_tilts_its_normal_and_can_be_hit_from_the_side()
+   {
+      // Rising from 0 to 2 along x
+      let slope = heightfield(&[0., 1., 0., 1.]);
+      let (mut t, mut n) = (0., Vector3::zeros());
+      assert!(slope.intersect(&straight_down(0.25, 0.5), 0., &mut t, &mut n));
+      assert!((t - 9.5).abs() < 1e-12);
+      assert!((n - Vector3::new(-2., 1., 0.).normalize()).norm() < 1e-12);
+
+      // Horizontally into the slope at height 1
+      let ray = Ray { origin: Vector3::new(-1., 1., 0.5), direction: Vector3::new(1., 0., 0.) };
+      assert!(slope.intersect(&ray, 0., &mut t, &mut n));
+      assert!((t - 1.5).abs() < 1e-12);
+   }
+
+   #[test]
+   fn curved_cells_are_hit_at_the_root_of_the_bilinear_patch()
+   {
+      // h = 2 u v: the bilinear patch is curved, so this takes the quadratic
+      let saddle = heightfield(&[0., 0., 0., 1.]);
+      let (mut t, mut n) = (0., Vector3::zeros());
+      let ray = Ray { origin: Vector3::new(0., 3., 0.), direction: Vector3::new(1., -1., 1.) };
+      assert!(saddle.intersect(&ray, 0., &mut t, &mut n));
+      // 3 - t = 2 t^2
+      assert!((t - 1.).abs() < 1e-12);
+   }
+}
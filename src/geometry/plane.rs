@@ -2,7 +2,7 @@ use nalgebra as na;
 use na::{ Vector3 };
 
 use crate::scene::{ Ray, Material };
-use crate::geometry::Object;
+use crate::geometry::{ Object, Interval };
 
 
 pub struct Plane {
@@ -55,4 +55,28 @@ impl Object for Plane {
    {
       return &self.material;
    }
+
+   // A plane bounds the half-space behind it (opposite its normal)
+   fn intervals<'a>(&'a self, ray: &Ray, spans: &mut Vec<Interval<'a>>)
+   {
+      let e = ray.origin;
+      let d = ray.direction;
+      let zero = Vector3::new(0., 0., 0.);
+
+      let d_dot_n = d.dot(&self.normal);
+      if d_dot_n == 0. {
+         // Parallel, so the line is either all inside or all outside
+         if self.normal.dot(&(e - self.point)) <= 0. {
+            spans.push(Interval::everything(&self.material));
+         }
+         return;
+      }
+
+      let t = self.normal.dot(&(self.point - e)) / d_dot_n;
+      if d_dot_n < 0. {
+         spans.push(Interval::new(t, self.normal, f64::INFINITY, zero, &self.material));
+      } else {
+         spans.push(Interval::new(f64::NEG_INFINITY, zero, t, self.normal, &self.material));
+      }
+   }
 }
@@ -0,0 +1,308 @@
+use nalgebra as na;
+use na::{ Vector2, Vector3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::Object;
+
+
+/**
+ * A signed distance function as an expression tree: primitives at the
+ * leaves, operators that combine or warp them above. distance() is negative
+ * inside the shape, positive outside, and (roughly) never more than the
+ * distance to the closest point on the surface.
+ * The formulas are the usual ones from https://iquilezles.org/articles/distfunctions/
+ */
+pub enum Sdf {
+   // Primitives
+   Sphere { center: Vector3<f64>, radius: f64 },
+   // A box with its edges rounded off by <radius>
+   RoundBox { center: Vector3<f64>, half_extents: Vector3<f64>, radius: f64 },
+   // All points within <radius> of the segment from a to b
+   Capsule { a: Vector3<f64>, b: Vector3<f64>, radius: f64 },
+   // A torus around the y axis through <center>
+   Torus { center: Vector3<f64>, major_radius: f64, minor_radius: f64 },
+   // A capped cylinder around the y axis through <center>
+   Cylinder { center: Vector3<f64>, radius: f64, half_height: f64 },
+   // The half-space below the plane p . normal = offset
+   Plane { normal: Vector3<f64>, offset: f64 },
+
+   // Operators
+   Union(Box<Sdf>, Box<Sdf>),
+   Intersection(Box<Sdf>, Box<Sdf>),
+   // The left shape with the right one cut out of it
+   Subtraction(Box<Sdf>, Box<Sdf>),
+   // Blended versions of the above, <k> is the size of the blend
+   SmoothUnion(Box<Sdf>, Box<Sdf>, f64),
+   SmoothIntersection(Box<Sdf>, Box<Sdf>, f64),
+   SmoothSubtraction(Box<Sdf>, Box<Sdf>, f64),
+   // Move a shape by <offset>
+   Translate(Box<Sdf>, Vector3<f64>),
+   // Twist a shape around the y axis by <rate> radians per unit height.
+   // This bends the distances, so the object needs a step scale below 1.
+   Twist(Box<Sdf>, f64),
+   // Repeat a shape every <period> along each axis (0 means don't repeat
+   // along that axis), at most <limit> copies away from the original if given
+   Repeat(Box<Sdf>, Vector3<f64>, Option<Vector3<f64>>)
+}
+
+
+// Blend factor for the smooth operators
+fn smooth_mix(a: f64, b: f64, h: f64) -> f64
+{
+   return b + (a - b) * h;
+}
+
+
+impl Sdf {
+   pub fn distance(&self, p: &Vector3<f64>) -> f64
+   {
+      match self {
+         Sdf::Sphere { center, radius } => {
+            return (p - center).norm() - radius;
+         }
+         Sdf::RoundBox { center, half_extents, radius } => {
+            let q = (p - center).abs() - half_extents;
+            let outside = q.map(|x| x.max(0.)).norm();
+            let inside = q.max().min(0.);
+            return outside + inside - radius;
+         }
+         Sdf::Capsule { a, b, radius } => {
+            let pa = p - a;
+            let ba = b - a;
+            let h = (pa.dot(&ba) / ba.dot(&ba)).clamp(0., 1.);
+            return (pa - h * ba).norm() - radius;
+         }
+         Sdf::Torus { center, major_radius, minor_radius } => {
+            let q = p - center;
+            let ring = Vector2::new(Vector2::new(q[0], q[2]).norm() - major_radius, q[1]);
+            return ring.norm() - minor_radius;
+         }
+         Sdf::Cylinder { center, radius, half_height } => {
+            let q = p - center;
+            let d = Vector2::new(Vector2::new(q[0], q[2]).norm() - radius, q[1].abs() - half_height);
+            return d.max().min(0.) + d.map(|x| x.max(0.)).norm();
+         }
+         Sdf::Plane { normal, offset } => {
+            return p.dot(normal) - offset;
+         }
+
+         Sdf::Union(a, b) => {
+            return a.distance(p).min(b.distance(p));
+         }
+         Sdf::Intersection(a, b) => {
+            return a.distance(p).max(b.distance(p));
+         }
+         Sdf::Subtraction(a, b) => {
+            return a.distance(p).max(-b.distance(p));
+         }
+         Sdf::SmoothUnion(a, b, k) => {
+            let (d1, d2) = (a.distance(p), b.distance(p));
+            let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0., 1.);
+            return smooth_mix(d1, d2, h) - k * h * (1. - h);
+         }
+         Sdf::SmoothIntersection(a, b, k) => {
+            let (d1, d2) = (a.distance(p), b.distance(p));
+            let h = (0.5 - 0.5 * (d2 - d1) / k).clamp(0., 1.);
+            return smooth_mix(d1, d2, h) + k * h * (1. - h);
+         }
+         Sdf::SmoothSubtraction(a, b, k) => {
+            let (d1, d2) = (a.distance(p), -b.distance(p));
+            let h = (0.5 - 0.5 * (d2 - d1) / k).clamp(0., 1.);
+            return smooth_mix(d1, d2, h) + k * h * (1. - h);
+         }
+         Sdf::Translate(a, offset) => {
+            return a.distance(&(p - offset));
+         }
+         Sdf::Twist(a, rate) => {
+            let (s, c) = (rate * p[1]).sin_cos();
+            let q = Vector3::new(c * p[0] - s * p[2], p[1], s * p[0] + c * p[2]);
+            return a.distance(&q);
+         }
+         Sdf::Repeat(a, period, limit) => {
+            let mut q = *p;
+            for axis in 0..3 {
+               if period[axis] > 0. {
+                  let mut cell = (p[axis] / period[axis]).round();
+                  if let Some(limit) = limit {
+                     cell = cell.clamp(-limit[axis], limit[axis]);
+                  }
+                  q[axis] = p[axis] - period[axis] * cell;
+               }
+            }
+            return a.distance(&q);
+         }
+      }
+   }
+}
+
+
+// An object whose surface is the zero set of a signed distance function,
+// rendered by sphere tracing: march along the ray, each time stepping by the
+// distance to the closest surface (which can't overshoot it).
+pub struct SdfObject {
+   pub sdf: Sdf,
+   pub material: Material,
+   // Fraction of the distance to step each time. Below 1 for warped distance
+   // fields (e.g. twists) that overestimate the distance.
+   pub step_scale: f64,
+   // Give up after this many steps or this far along the ray
+   pub max_steps: usize,
+   pub max_distance: f64
+}
+
+
+// How close to the surface counts as a hit
+const SDF_EPSILON: f64 = 1.0e-5;
+
+
+impl SdfObject {
+   pub fn new(sdf: Sdf, material: Material, step_scale: f64, max_steps: usize, max_distance: f64) -> SdfObject
+   {
+      return SdfObject { sdf, material, step_scale, max_steps, max_distance };
+   }
+
+   // The normal is the gradient of the distance function, estimated by
+   // central differences
+   fn normal(&self, p: &Vector3<f64>) -> Vector3<f64>
+   {
+      let h = 1.0e-5;
+      let mut grad = Vector3::new(0., 0., 0.);
+      for axis in 0..3 {
+         let mut offset = Vector3::new(0., 0., 0.);
+         offset[axis] = h;
+         grad[axis] = self.sdf.distance(&(p + offset)) - self.sdf.distance(&(p - offset));
+      }
+      return grad.normalize();
+   }
+}
+
+
+impl Object for SdfObject {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      let e = ray.origin;
+      let d = ray.direction;
+      // Distances are in world units, <t> is in units of |d|
+      let d_norm = d.norm();
+
+      // Rays leaving the surface (shadow and mirror rays) start within
+      // epsilon of it. Those don't count as hits until we have moved away.
+      let mut t_march = min_t;
+      let mut escaping = self.sdf.distance(&(e + t_march * d)).abs() < SDF_EPSILON;
+      let mut prev_sign = 0.;
+      for _ in 0..self.max_steps {
+         let p = e + t_march * d;
+         let distance = self.sdf.distance(&p);
+         if distance.abs() < SDF_EPSILON {
+            if !escaping {
+               *t = t_march;
+               *n = self.normal(&p);
+               return true;
+            }
+            t_march += 2. * SDF_EPSILON / d_norm;
+            continue;
+         }
+         escaping = false;
+
+         // Stepped through the surface (the distance field overestimated),
+         // close enough for a hit
+         if prev_sign != 0. && distance.signum() != prev_sign {
+            *t = t_march;
+            *n = self.normal(&p);
+            return true;
+         }
+         prev_sign = distance.signum();
+
+         // Inside the shape the distance is negative, but stepping by its
+         // size still can't take us past the surface
+         t_march += self.step_scale * distance.abs() / d_norm;
+         if t_march * d_norm > self.max_distance {
+            break;
+         }
+      }
+      return false;
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return &self.material;
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn sphere(center: Vector3<f64>, radius: f64) -> Sdf
+   {
+      return Sdf::Sphere { center, radius };
+   }
+
+   fn object(sdf: Sdf) -> SdfObject
+   {
+      return SdfObject::new(sdf, Material::gray(), 1., 256, 100.);
+   }
+
+   #[test]
+   fn primitives_measure_true_distances()
+   {
+      let p = Vector3::new(3., 4., 0.);
+      assert!((sphere(Vector3::zeros(), 1.).distance(&p) - 4.).abs() < 1.0e-12);
+      let cube = Sdf::RoundBox { center: Vector3::zeros(), half_extents: Vector3::new(1., 1., 1.), radius: 0. };
+      assert!((cube.distance(&Vector3::new(3., 0., 0.)) - 2.).abs() < 1.0e-12);
+      assert!((cube.distance(&Vector3::new(2., 2., 1.)) - 2f64.sqrt()).abs() < 1.0e-12);
+      assert!((cube.distance(&Vector3::new(0.5, 0., 0.)) + 0.5).abs() < 1.0e-12);
+      let torus = Sdf::Torus { center: Vector3::zeros(), major_radius: 2., minor_radius: 0.5 };
+      assert!((torus.distance(&Vector3::new(2., 0., 0.)) + 0.5).abs() < 1.0e-12);
+      assert!((torus.distance(&Vector3::zeros()) - 1.5).abs() < 1.0e-12);
+      let capsule = Sdf::Capsule { a: Vector3::zeros(), b: Vector3::new(0., 2., 0.), radius: 0.5 };
+      assert!((capsule.distance(&Vector3::new(1., 1., 0.)) - 0.5).abs() < 1.0e-12);
+      assert!((capsule.distance(&Vector3::new(0., 4., 0.)) - 1.5).abs() < 1.0e-12);
+   }
+
+   #[test]
+   fn operators_combine_distances()
+   {
+      let a = || Box::new(sphere(Vector3::new(-1., 0., 0.), 1.5));
+      let b = || Box::new(sphere(Vector3::new(1., 0., 0.), 1.5));
+      let p = Vector3::new(0., 0., 0.);
+      assert!((Sdf::Union(a(), b()).distance(&p) + 0.5).abs() < 1.0e-12);
+      assert!((Sdf::Intersection(a(), b()).distance(&p) + 0.5).abs() < 1.0e-12);
+      assert!((Sdf::Subtraction(a(), b()).distance(&p) - 0.5).abs() < 1.0e-12);
+      // Blending pulls the union in where the two are close, and changes
+      // nothing far from where they meet
+      let k = 0.5;
+      assert!(Sdf::SmoothUnion(a(), b(), k).distance(&p) < Sdf::Union(a(), b()).distance(&p));
+      let far = Vector3::new(-4., 0., 0.);
+      assert!((Sdf::SmoothUnion(a(), b(), k).distance(&far) - Sdf::Union(a(), b()).distance(&far)).abs() < 1.0e-12);
+      assert!(Sdf::SmoothIntersection(a(), b(), k).distance(&p) > Sdf::Intersection(a(), b()).distance(&p));
+   }
+
+   #[test]
+   fn repeat_copies_within_the_limit()
+   {
+      let repeated = Sdf::Repeat(Box::new(sphere(Vector3::zeros(), 0.5)), Vector3::new(2., 0., 0.),
+                                 Some(Vector3::new(1., 0., 0.)));
+      assert!((repeated.distance(&Vector3::new(2., 0., 0.)) + 0.5).abs() < 1.0e-12);
+      assert!((repeated.distance(&Vector3::new(-2., 1., 0.)) - 0.5).abs() < 1.0e-12);
+      // Only cells -1, 0 and 1 have a copy
+      assert!((repeated.distance(&Vector3::new(4., 0., 0.)) - 1.5).abs() < 1.0e-12);
+   }
+
+   #[test]
+   fn sphere_tracing_finds_the_surface()
+   {
+      let object = object(sphere(Vector3::new(0., 0., -5.), 1.));
+      let (mut t, mut n) = (0., Vector3::zeros());
+      let ray = Ray { origin: Vector3::zeros(), direction: Vector3::new(0., 0., -2.) };
+      assert!(object.intersect(&ray, 1.0e-6, &mut t, &mut n));
+      assert!((t - 2.).abs() < 1.0e-4);
+      assert!((n - Vector3::new(0., 0., 1.)).norm() < 1.0e-4);
+      // Leaving the surface outwards doesn't hit it again
+      let ray = Ray { origin: Vector3::new(0., 0., -4.), direction: Vector3::new(0., 0., 1.) };
+      assert!(!object.intersect(&ray, 1.0e-6, &mut t, &mut n));
+      let ray = Ray { origin: Vector3::zeros(), direction: Vector3::new(0., 1., 0.) };
+      assert!(!object.intersect(&ray, 1.0e-6, &mut t, &mut n));
+   }
+}
@@ -1,11 +1,13 @@
 // for the capital A, B, C, in intersect
 #![allow(non_snake_case)]
 
+use std::f64::consts::PI;
+
 use nalgebra as na;
 use na::{ Vector3 };
 
 use crate::scene::{ Ray, Material };
-use crate::geometry::Object;
+use crate::geometry::{ Object, Interval };
 
 
 pub struct Sphere {
@@ -49,6 +51,11 @@ impl Object for Sphere {
       // *t = f64::max(f64::min(t_plus, t_minus), 0.0);
 
       *t = (-B - discriminant.sqrt()) / (2. * A);
+      if *t < min_t {
+         // The near side is behind the start of the ray (e.g. a ray inside
+         // the sphere), so the ray can only hit the far side
+         *t = (-B + discriminant.sqrt()) / (2. * A);
+      }
       if *t < min_t {
          // don't consider this hit
          return false;
@@ -63,6 +70,49 @@ impl Object for Sphere {
    {
       return &self.material;
    }
+
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      return Some((self.center, self.radius));
+   }
+
+   fn intervals<'a>(&'a self, ray: &Ray, spans: &mut Vec<Interval<'a>>)
+   {
+      let c = self.center;
+      let d = ray.direction;
+      let e = ray.origin;
+
+      let A: f64 = d.dot(&d);
+      let B = 2. * d.dot(&(e - c));
+      let C = (e - c).dot(&(e - c)) - f64::powf(self.radius, 2.0);
+
+      let discriminant = B.powf(2.0) - (4. * A * C);
+      if discriminant < 0. {
+         return;
+      }
+
+      // Inside the sphere between the two solutions
+      let t_minus = (-B - discriminant.sqrt()) / (2. * A);
+      let t_plus = (-B + discriminant.sqrt()) / (2. * A);
+      let n_minus = ((e + t_minus * d) - c) / self.radius;
+      let n_plus = ((e + t_plus * d) - c) / self.radius;
+      spans.push(Interval::new(t_minus, n_minus, t_plus, n_plus, &self.material));
+   }
+
+   fn area(&self) -> f64
+   {
+      return 4. * PI * self.radius * self.radius;
+   }
+
+   // Uniform in z and in the angle around z (Archimedes' hat-box theorem)
+   fn sample_surface(&self, u: [f64; 2]) -> (Vector3<f64>, Vector3<f64>)
+   {
+      let z = 1. - 2. * u[0];
+      let r = (1. - z * z).max(0.).sqrt();
+      let phi = 2. * PI * u[1];
+      let n = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+      return (self.center + self.radius * n, n);
+   }
 }
 
 
@@ -123,3 +173,95 @@ impl Object for Sphere {
 
 //    return hit;
 // }
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::scene::Camera;
+
+   // The unit sphere at the origin
+   fn sphere() -> Sphere
+   {
+      return Sphere::new(&Vector3::zeros(), 1., Material::gray());
+   }
+
+   fn hit(origin: Vector3<f64>, direction: Vector3<f64>, min_t: f64) -> Option<(f64, Vector3<f64>)>
+   {
+      let (mut t, mut n) = (0., Vector3::zeros());
+      if !sphere().intersect(&Ray { origin, direction }, min_t, &mut t, &mut n) {
+         return None;
+      }
+      return Some((t, n));
+   }
+
+   #[test]
+   fn hits_the_near_side_from_outside()
+   {
+      let (t, n) = hit(Vector3::new(0., 0., 5.), Vector3::new(0., 0., -1.), 1.0e-6).unwrap();
+      assert!((t - 4.).abs() < 1.0e-12);
+      assert!((n - Vector3::new(0., 0., 1.)).norm() < 1.0e-12);
+   }
+
+   #[test]
+   fn ray_leaving_the_surface_inwards_finds_the_far_side()
+   {
+      // As refracted into glass, or sent inwards by a glowing sphere
+      let (t, n) = hit(Vector3::new(0., 0., 1.), Vector3::new(0., 0., -1.), 1.0e-6).unwrap();
+      assert!((t - 2.).abs() < 1.0e-9);
+      assert!((n - Vector3::new(0., 0., -1.)).norm() < 1.0e-9);
+
+      // Along a chord, starting a rounding error off the surface
+      let p = Vector3::new(0.6, 0.8, 0.) * (1. + 1.0e-12);
+      let (t, _) = hit(p, Vector3::new(-1., 0., 0.), 1.0e-6).unwrap();
+      assert!((t - 1.2).abs() < 1.0e-9);
+   }
+
+   #[test]
+   fn ray_leaving_the_surface_outwards_misses()
+   {
+      assert!(hit(Vector3::new(0., 0., 1.), Vector3::new(0., 0., 1.), 1.0e-6).is_none());
+      assert!(hit(Vector3::new(0.6, 0.8, 0.), Vector3::new(0.6, 0.8, 0.), 1.0e-6).is_none());
+   }
+
+   #[test]
+   fn ray_starting_inside_finds_the_way_out()
+   {
+      let (t, n) = hit(Vector3::new(0., 0., 0.5), Vector3::new(0., 0., -2.), 1.0e-6).unwrap();
+      assert!((t - 0.75).abs() < 1.0e-12);
+      assert!((n - Vector3::new(0., 0., -1.)).norm() < 1.0e-12);
+   }
+
+   #[test]
+   fn camera_rays_see_into_spheres_cut_by_the_near_plane()
+   {
+      // The eye outside, with the near side closer than the image plane: the
+      // ray starts past it and sees the inside of the far side
+      let (t, n) = hit(Vector3::new(0., 0., 1.5), Vector3::new(0., 0., -1.), Camera::NEAR_PLANE).unwrap();
+      assert!((t - 2.5).abs() < 1.0e-12);
+      assert!((n - Vector3::new(0., 0., -1.)).norm() < 1.0e-12);
+   }
+
+   #[test]
+   fn samples_spread_evenly_over_the_surface()
+   {
+      let sphere = Sphere::new(&Vector3::new(1., 2., 3.), 2., sphere().material);
+      assert!((sphere.area() - 16. * PI).abs() < 1.0e-12);
+      // As many points above any height as below it, by Archimedes
+      let steps = 100;
+      let mut above = 0;
+      for i in 0..steps {
+         for j in 0..steps {
+            let u = [(i as f64 + 0.5) / steps as f64, (j as f64 + 0.5) / steps as f64];
+            let (p, n) = sphere.sample_surface(u);
+            assert!(((p - sphere.center).norm() - 2.).abs() < 1.0e-12);
+            assert!((n - (p - sphere.center) / 2.).norm() < 1.0e-12);
+            if p.z > 3. + 2. * 0.3 {
+               above += 1;
+            }
+         }
+      }
+      // The cap above 0.3 r has (1 - 0.3) / 2 of the area
+      assert_eq!(above, 35 * steps);
+   }
+}
@@ -0,0 +1,253 @@
+// for the capital R
+#![allow(non_snake_case)]
+
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::{ Object, Interval, orthonormal_basis };
+use crate::numeric::solve_quartic;
+
+
+// A torus around <center>: a circle of radius <minor_radius> swept around
+// <axis> at a distance <major_radius> from the center.
+pub struct Torus {
+   pub center: Vector3<f64>,
+   // Unit axis, and two unit vectors perpendicular to it
+   pub axis: Vector3<f64>,
+   pub u: Vector3<f64>,
+   pub v: Vector3<f64>,
+   pub major_radius: f64,
+   pub minor_radius: f64,
+   pub material: Material
+}
+
+
+impl Torus {
+   pub fn new(center: &Vector3<f64>, axis: &Vector3<f64>, major_radius: f64, minor_radius: f64, material: Material) -> Torus
+   {
+      let axis = axis.normalize();
+      let (u, v) = orthonormal_basis(&axis);
+      return Torus { center: *center, axis, u, v, major_radius, minor_radius, material };
+   }
+
+   // Express a world space vector in the local (u, v, axis) frame
+   fn to_local(&self, x: &Vector3<f64>) -> Vector3<f64>
+   {
+      return Vector3::new(x.dot(&self.u), x.dot(&self.v), x.dot(&self.axis));
+   }
+
+   // Find every <t> (sorted) where the line through the ray crosses the torus,
+   // looking no further back than <t_from>. Returns how many there are.
+   fn crossings(&self, ray: &Ray, t_from: f64, roots: &mut [f64; 4]) -> usize
+   {
+      // In the local frame the torus is the zero set of
+      //    f(p) = (|p|^2 + R^2 - r^2)^2 - 4 R^2 (x^2 + y^2)
+      let mut e = self.to_local(&(ray.origin - self.center));
+      let d = self.to_local(&ray.direction);
+      let R = self.major_radius;
+      let r = self.minor_radius;
+
+      // Cull against the bounding sphere first. This is also where we start
+      // the ray from: the quartic is badly conditioned when the origin is far
+      // away compared to the size of the torus.
+      let dd = d.dot(&d);
+      let bound = R + r;
+      let b = e.dot(&d);
+      let discriminant = b * b - dd * (e.dot(&e) - bound * bound);
+      if discriminant < 0. {
+         return 0;
+      }
+      let t_far = (-b + discriminant.sqrt()) / dd;
+      if t_far < t_from {
+         return 0;
+      }
+      let t_start = f64::max((-b - discriminant.sqrt()) / dd, t_from);
+      e += t_start * d;
+
+      // Substitute p = e + s d into f and divide by the leading |d|^4
+      let ed = e.dot(&d);
+      let K = e.dot(&e) + R * R - r * r;
+      let dxy = d[0] * d[0] + d[1] * d[1];
+      let exy = e[0] * d[0] + e[1] * d[1];
+      let a3 = 4. * dd * ed;
+      let a2 = 2. * dd * K + 4. * ed * ed - 4. * R * R * dxy;
+      let a1 = 4. * ed * K - 8. * R * R * exy;
+      let a0 = K * K - 4. * R * R * (e[0] * e[0] + e[1] * e[1]);
+      let dd2 = dd * dd;
+
+      let n_roots = solve_quartic(a3 / dd2, a2 / dd2, a1 / dd2, a0 / dd2, roots);
+      for root in roots[..n_roots].iter_mut() {
+         *root += t_start;
+      }
+      return n_roots;
+   }
+
+   // World space normal at the point <p> on the torus: the gradient of f
+   fn normal(&self, p: &Vector3<f64>) -> Vector3<f64>
+   {
+      let q = self.to_local(&(p - self.center));
+      let R = self.major_radius;
+      let r = self.minor_radius;
+      let grad = 4. * (q.dot(&q) + R * R - r * r) * q - 8. * R * R * Vector3::new(q[0], q[1], 0.);
+      let n_local = grad.normalize();
+      return n_local[0] * self.u + n_local[1] * self.v + n_local[2] * self.axis;
+   }
+}
+
+
+impl Object for Torus {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      let mut roots = [0.; 4];
+      let n_roots = self.crossings(ray, 0., &mut roots);
+
+      // Roots are sorted, so the first one past min_t is the closest hit
+      for &root in roots[..n_roots].iter() {
+         if root >= min_t {
+            *t = root;
+            *n = self.normal(&(ray.origin + root * ray.direction));
+            return true;
+         }
+      }
+      return false;
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return &self.material;
+   }
+
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      return Some((self.center, self.major_radius + self.minor_radius));
+   }
+
+   fn intervals<'a>(&'a self, ray: &Ray, spans: &mut Vec<Interval<'a>>)
+   {
+      // Classify the pieces of the line between consecutive crossings by
+      // checking the sign of f halfway along. Just pairing up the crossings
+      // goes wrong when the line grazes the surface (a double root).
+      let mut roots = [0.; 4];
+      let n_roots = self.crossings(ray, f64::NEG_INFINITY, &mut roots);
+      let R = self.major_radius;
+      let r = self.minor_radius;
+
+      let mut start: Option<usize> = None;
+      for i in 0..n_roots.saturating_sub(1) {
+         let q = self.to_local(&(ray.origin + 0.5 * (roots[i] + roots[i + 1]) * ray.direction - self.center));
+         let f = (q.dot(&q) + R * R - r * r).powi(2) - 4. * R * R * (q[0] * q[0] + q[1] * q[1]);
+         let inside = f < 0.;
+         if inside && start.is_none() {
+            start = Some(i);
+         } else if !inside && start.is_some() {
+            let j = start.unwrap();
+            let n_in = self.normal(&(ray.origin + roots[j] * ray.direction));
+            let n_out = self.normal(&(ray.origin + roots[i] * ray.direction));
+            spans.push(Interval::new(roots[j], n_in, roots[i], n_out, &self.material));
+            start = None;
+         }
+      }
+      if let Some(j) = start {
+         let i = n_roots - 1;
+         let n_in = self.normal(&(ray.origin + roots[j] * ray.direction));
+         let n_out = self.normal(&(ray.origin + roots[i] * ray.direction));
+         spans.push(Interval::new(roots[j], n_in, roots[i], n_out, &self.material));
+      }
+   }
+
+   // u goes around the axis, v goes around the tube
+   fn uv(&self, p: &Vector3<f64>) -> (f64, f64)
+   {
+      let q = self.to_local(&(p - self.center));
+      let phi = q[1].atan2(q[0]);
+      let theta = q[2].atan2((q[0] * q[0] + q[1] * q[1]).sqrt() - self.major_radius);
+      return ((phi + PI) / (2. * PI), (theta + PI) / (2. * PI));
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Major radius 1 and minor radius 0.25 around the y axis
+   fn torus() -> Torus
+   {
+      return Torus::new(&Vector3::new(0., 0., 0.), &Vector3::new(0., 1., 0.), 1., 0.25, Material::gray());
+   }
+
+   // A ray along x at height <y>, starting at x = -2
+   fn ray_at(y: f64) -> Ray
+   {
+      return Ray { origin: Vector3::new(-2., y, 0.), direction: Vector3::new(1., 0., 0.) };
+   }
+
+   #[test]
+   fn hits_the_near_side_of_the_tube()
+   {
+      let (mut t, mut n) = (0., Vector3::zeros());
+      assert!(torus().intersect(&ray_at(0.), 0., &mut t, &mut n));
+      assert!((t - 0.75).abs() < 1e-12);
+      assert!((n - Vector3::new(-1., 0., 0.)).norm() < 1e-9);
+
+      // Past the first crossing, the next one is the inner wall
+      assert!(torus().intersect(&ray_at(0.), 1., &mut t, &mut n));
+      assert!((t - 1.25).abs() < 1e-12);
+      assert!((n - Vector3::new(1., 0., 0.)).norm() < 1e-9);
+   }
+
+   #[test]
+   fn grazing_rays()
+   {
+      let (mut t, mut n) = (0., Vector3::zeros());
+      let y = 0.25 - 1e-7;
+      let half_span = (0.25_f64 * 0.25 - y * y).sqrt();
+      assert!(torus().intersect(&ray_at(y), 0., &mut t, &mut n));
+      assert!((t - (1. - half_span)).abs() < 1e-9);
+      // Nearly straight up at the top of the tube
+      assert!(n[1] > 0.99);
+
+      assert!(!torus().intersect(&ray_at(0.25 + 1e-7), 0., &mut t, &mut n));
+   }
+
+   #[test]
+   fn ray_from_inside_the_tube_leaves_it()
+   {
+      let ray = Ray { origin: Vector3::new(1., 0., 0.), direction: Vector3::new(0., 1., 0.) };
+      let (mut t, mut n) = (0., Vector3::zeros());
+      assert!(torus().intersect(&ray, 1e-6, &mut t, &mut n));
+      assert!((t - 0.25).abs() < 1e-12);
+      assert!((n - Vector3::new(0., 1., 0.)).norm() < 1e-9);
+   }
+
+   #[test]
+   fn misses_through_the_hole()
+   {
+      let ray = Ray { origin: Vector3::new(0., -2., 0.), direction: Vector3::new(0., 1., 0.) };
+      let (mut t, mut n) = (0., Vector3::zeros());
+      assert!(!torus().intersect(&ray, 0., &mut t, &mut n));
+   }
+
+   #[test]
+   fn intervals_through_both_sides_of_the_tube()
+   {
+      let torus = torus();
+      let mut spans = Vec::new();
+      torus.intervals(&ray_at(0.), &mut spans);
+      assert_eq!(spans.len(), 2);
+      assert!((spans[0].t_in - 0.75).abs() < 1e-12 && (spans[0].t_out - 1.25).abs() < 1e-12);
+      assert!((spans[1].t_in - 2.75).abs() < 1e-12 && (spans[1].t_out - 3.25).abs() < 1e-12);
+
+      // A grazing line is inside for two short spans
+      let y = 0.25 - 1e-7;
+      let half_span = (0.25_f64 * 0.25 - y * y).sqrt();
+      spans.clear();
+      torus.intervals(&ray_at(y), &mut spans);
+      assert_eq!(spans.len(), 2);
+      assert!((spans[0].t_out - spans[0].t_in - 2. * half_span).abs() < 1e-9);
+      assert!((spans[1].t_out - spans[1].t_in - 2. * half_span).abs() < 1e-9);
+   }
+}
@@ -0,0 +1,240 @@
+use nalgebra as na;
+use na::{ Vector3, Matrix3, Matrix4, Point3 };
+
+use crate::scene::{ Ray, Material };
+use crate::geometry::{ Object, Interval };
+
+
+// An object placed in the world by a 4x4 affine transform. This is how groups
+// from the scene graph are realized: the world transform of every leaf is the
+// product of the transforms of all the groups above it (see helper::setup_objects).
+pub struct Transformed {
+   pub object: Box<dyn Object + Sync>,
+   // Object space -> world space, and back
+   pub to_world: Matrix4<f64>,
+   pub to_object: Matrix4<f64>,
+   // Inverse transpose of the linear part of <to_world> (for normals)
+   pub normal_matrix: Matrix3<f64>
+}
+
+
+impl Transformed {
+   pub fn new(object: Box<dyn Object + Sync>, to_world: Matrix4<f64>) -> Transformed
+   {
+      let to_object = to_world.try_inverse().expect("Error: object transform is not invertible.");
+      let normal_matrix = to_object.fixed_slice::<3, 3>(0, 0).transpose();
+      return Transformed { object, to_world, to_object, normal_matrix };
+   }
+}
+
+
+impl Transformed {
+   // Bring a world space ray into object space. The direction is NOT
+   // renormalized, so the parametric distance <t> is the same in both spaces.
+   fn local_ray(&self, ray: &Ray) -> Ray
+   {
+      return Ray {
+         origin: self.to_object.transform_point(&Point3::from(ray.origin)).coords,
+         direction: self.to_object.transform_vector(&ray.direction)
+      };
+   }
+
+   // Normals transform by the inverse transpose
+   fn world_normal(&self, n: &Vector3<f64>) -> Vector3<f64>
+   {
+      if *n == Vector3::zeros() {
+         return *n;
+      }
+      return (self.normal_matrix * n).normalize();
+   }
+
+   // How much the transform stretches areas on a surface whose normal is
+   // <n> in object space (the Jacobian determinant of the map restricted
+   // to the surface)
+   fn area_scale(&self, n: &Vector3<f64>) -> f64
+   {
+      return self.to_world.fixed_slice::<3, 3>(0, 0).determinant().abs() * (self.normal_matrix * n).norm();
+   }
+}
+
+
+impl Object for Transformed {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      if !self.object.intersect(&self.local_ray(ray), min_t, t, n) {
+         return false;
+      }
+      *n = self.world_normal(n);
+      return true;
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return self.object.get_material();
+   }
+
+   // The object's sphere taken to world space, grown by the most the
+   // transform can stretch anything (bounded by the norm of its linear part)
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      let (center, radius) = self.object.bounding_sphere()?;
+      let to_world = self.to_object.try_inverse()?;
+      let center = to_world.transform_point(&Point3::from(center)).coords;
+      return Some((center, radius * to_world.fixed_slice::<3, 3>(0, 0).norm()));
+   }
+
+   fn intersect_material(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> Option<&Material>
+   {
+      let material = self.object.intersect_material(&self.local_ray(ray), min_t, t, n)?;
+      *n = self.world_normal(n);
+      return Some(material);
+   }
+
+   fn intervals<'a>(&'a self, ray: &Ray, spans: &mut Vec<Interval<'a>>)
+   {
+      let first = spans.len();
+      self.object.intervals(&self.local_ray(ray), spans);
+      for span in spans[first..].iter_mut() {
+         span.n_in = self.world_normal(&span.n_in);
+         span.n_out = self.world_normal(&span.n_out);
+      }
+   }
+
+   fn uv(&self, p: &Vector3<f64>) -> (f64, f64)
+   {
+      return self.object.uv(&self.to_object.transform_point(&Point3::from(*p)).coords);
+   }
+
+   // The area in object space stretched by the transform. That is exact
+   // when the stretch is the same everywhere (rigid motions, uniform
+   // scaling, flat faces); otherwise it's averaged over a grid of surface
+   // samples, which is plenty for weighting lights against each other.
+   fn area(&self) -> f64
+   {
+      if self.object.area() <= 0. {
+         return 0.;
+      }
+      let steps = 16;
+      let mut area = 0.;
+      for i in 0..steps {
+         for j in 0..steps {
+            let u = [(i as f64 + 0.5) / steps as f64, (j as f64 + 0.5) / steps as f64];
+            let (p, n) = self.object.sample_surface(u);
+            area += self.area_scale(&n) / self.object.surface_pdf(&p, &n);
+         }
+      }
+      return area / (steps * steps) as f64;
+   }
+
+   // Sampled in object space and brought over to the world
+   fn sample_surface(&self, u: [f64; 2]) -> (Vector3<f64>, Vector3<f64>)
+   {
+      let (p, n) = self.object.sample_surface(u);
+      return (self.to_world.transform_point(&Point3::from(p)).coords, self.world_normal(&n));
+   }
+
+   // The object's density, spread over the stretched area
+   fn surface_pdf(&self, p: &Vector3<f64>, n: &Vector3<f64>) -> f64
+   {
+      let local_p = self.to_object.transform_point(&Point3::from(*p)).coords;
+      let local_n = (self.to_world.fixed_slice::<3, 3>(0, 0).transpose() * n).normalize();
+      return self.object.surface_pdf(&local_p, &local_n) / self.area_scale(&local_n);
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use std::f64::consts::PI;
+   use crate::geometry::{ Sphere, Triangle };
+
+   // The unit sphere at the origin stretched by <s>, then moved to <c>
+   fn ellipsoid(s: Vector3<f64>, c: Vector3<f64>) -> Transformed
+   {
+      let sphere = Box::new(Sphere::new(&Vector3::zeros(), 1., Material::gray()));
+      return Transformed::new(sphere, Matrix4::new_translation(&c) * Matrix4::new_nonuniform_scaling(&s));
+   }
+
+   // u on a grid of <steps> x <steps> cells
+   fn grid(steps: usize) -> impl Iterator<Item = [f64; 2]>
+   {
+      return (0..steps * steps).map(move |k| {
+         [((k / steps) as f64 + 0.5) / steps as f64, ((k % steps) as f64 + 0.5) / steps as f64]
+      });
+   }
+
+   #[test]
+   fn uniform_scaling_scales_the_area_exactly()
+   {
+      let object = ellipsoid(Vector3::new(2., 2., 2.), Vector3::new(1., -3., 5.));
+      assert!((object.area() - 16. * PI).abs() < 1.0e-9);
+      for u in grid(8) {
+         let (p, n) = object.sample_surface(u);
+         assert!(((p - Vector3::new(1., -3., 5.)).norm() - 2.).abs() < 1.0e-9);
+         assert!((object.surface_pdf(&p, &n) - 1. / (16. * PI)).abs() < 1.0e-12);
+      }
+   }
+
+   #[test]
+   fn flat_faces_keep_an_even_spread()
+   {
+      let corners = (Vector3::new(0., 0., 0.), Vector3::new(1., 0., 0.), Vector3::new(0., 1., 0.));
+      let triangle = Box::new(Triangle::new(corners, Material::gray()));
+      let object = Transformed::new(triangle, Matrix4::new_nonuniform_scaling(&Vector3::new(3., 2., 7.)));
+      assert!((object.area() - 3.).abs() < 1.0e-9);
+      let (p, n) = object.sample_surface([0.3, 0.6]);
+      assert!((n - Vector3::new(0., 0., 1.)).norm() < 1.0e-12);
+      assert!((object.surface_pdf(&p, &n) - 1. / 3.).abs() < 1.0e-12);
+   }
+
+   #[test]
+   fn samples_land_on_a_stretched_sphere()
+   {
+      let object = ellipsoid(Vector3::new(1., 2., 3.), Vector3::zeros());
+      for u in grid(8) {
+         let (p, n) = object.sample_surface(u);
+         let on_surface = p.x * p.x + p.y * p.y / 4. + p.z * p.z / 9.;
+         assert!((on_surface - 1.).abs() < 1.0e-9);
+         // The gradient of the implicit function
+         let gradient = Vector3::new(p.x, p.y / 4., p.z / 9.).normalize();
+         assert!((n - gradient).norm() < 1.0e-9);
+      }
+   }
+
+   #[test]
+   fn uneven_stretch_is_weighted_by_the_density()
+   {
+      // A prolate spheroid, whose area has a closed form
+      let (a, c): (f64, f64) = (1., 3.);
+      let e = (1. - a * a / (c * c)).sqrt();
+      let exact = 2. * PI * a * a * (1. + c / (a * e) * e.asin());
+
+      let object = ellipsoid(Vector3::new(a, a, c), Vector3::zeros());
+      assert!((object.area() - exact).abs() < 0.01 * exact);
+
+      // Points bunch up where the sphere was stretched the least, so the
+      // density is higher at the poles than around the waist...
+      let pole = object.surface_pdf(&Vector3::new(0., 0., c), &Vector3::new(0., 0., 1.));
+      let waist = object.surface_pdf(&Vector3::new(a, 0., 0.), &Vector3::new(1., 0., 0.));
+      assert!(pole > waist);
+      // ...and still adds up to one over the surface
+      let steps = 200;
+      let total: f64 = grid(steps).map(|u| {
+         let (p, n) = object.sample_surface(u);
+         return 1. / object.surface_pdf(&p, &n);
+      }).sum();
+      assert!((total / (steps * steps) as f64 - exact).abs() < 1.0e-3 * exact);
+   }
+
+   #[test]
+   fn nested_transforms_compose()
+   {
+      let inner = Box::new(ellipsoid(Vector3::new(1., 1., 3.), Vector3::zeros()));
+      let outer = Transformed::new(inner, Matrix4::new_scaling(2.));
+      let single = ellipsoid(Vector3::new(2., 2., 6.), Vector3::zeros());
+      assert!((outer.area() - single.area()).abs() < 1.0e-9);
+      let (p, n) = outer.sample_surface([0.2, 0.7]);
+      assert!((outer.surface_pdf(&p, &n) - single.surface_pdf(&p, &n)).abs() < 1.0e-12);
+   }
+}
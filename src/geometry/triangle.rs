@@ -11,13 +11,22 @@ use crate::geometry::Object;
 
 pub struct Triangle {
    pub corners: (Vector3<f64>, Vector3<f64>, Vector3<f64>),
+   // Optional unit normals at the corners for smooth shading. Without them
+   // the triangle is flat shaded with its face normal.
+   pub normals: Option<(Vector3<f64>, Vector3<f64>, Vector3<f64>)>,
    pub material: Material
 }
 
 
 impl Triangle {
    pub fn new(corners: (Vector3<f64>, Vector3<f64>, Vector3<f64>), material: Material) -> Triangle {
-      return Triangle{ corners, material };
+      return Triangle{ corners, normals: None, material };
+   }
+
+   pub fn with_normals(corners: (Vector3<f64>, Vector3<f64>, Vector3<f64>),
+                       normals: (Vector3<f64>, Vector3<f64>, Vector3<f64>), material: Material) -> Triangle {
+      let normals = (normals.0.normalize(), normals.1.normalize(), normals.2.normalize());
+      return Triangle{ corners, normals: Some(normals), material };
    }
 }
 
@@ -86,7 +95,12 @@ impl Object for Triangle {
       let gamma = (i*ak_minus_jb + h*jc_minus_al + g*bl_minus_kc) / M;
 
       if beta >= 0. && gamma >= 0. && beta + gamma <= 1. {
-         *n = normal.normalize();
+         // The hit is at P + beta PQ + gamma PR, so the same weights blend
+         // the corner normals
+         *n = match self.normals {
+            Some((nP, nQ, nR)) => ((1. - beta - gamma) * nP + beta * nQ + gamma * nR).normalize(),
+            None => normal.normalize()
+         };
          hit = true;
       }
 
@@ -98,4 +112,94 @@ impl Object for Triangle {
    {
       return &self.material;
    }
+
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      let (P, Q, R) = self.corners;
+      let center = (P + Q + R) / 3.;
+      let radius = (P - center).norm().max((Q - center).norm()).max((R - center).norm());
+      return Some((center, radius));
+   }
+
+   fn area(&self) -> f64
+   {
+      let (P, Q, R) = self.corners;
+      return 0.5 * (Q - P).cross(&(R - P)).norm();
+   }
+
+   // Taking the square root of one of the numbers keeps the points from
+   // bunching up at the corner P. The normal is always the face normal.
+   fn sample_surface(&self, u: [f64; 2]) -> (Vector3<f64>, Vector3<f64>)
+   {
+      let (P, Q, R) = self.corners;
+      let s = u[0].sqrt();
+      let beta = s * (1. - u[1]);
+      let gamma = s * u[1];
+      let p = P + beta * (Q - P) + gamma * (R - P);
+      return (p, (Q - P).cross(&(R - P)).normalize());
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // In the z = 0 plane, facing +z
+   fn corners() -> (Vector3<f64>, Vector3<f64>, Vector3<f64>)
+   {
+      return (Vector3::new(0., 0., 0.), Vector3::new(1., 0., 0.), Vector3::new(0., 1., 0.));
+   }
+
+   fn normal_at(triangle: &Triangle, x: f64, y: f64) -> Option<Vector3<f64>>
+   {
+      let ray = Ray { origin: Vector3::new(x, y, 1.), direction: Vector3::new(0., 0., -1.) };
+      let (mut t, mut n) = (0., Vector3::zeros());
+      if !triangle.intersect(&ray, 1.0e-6, &mut t, &mut n) {
+         return None;
+      }
+      assert!((t - 1.).abs() < 1.0e-12);
+      return Some(n);
+   }
+
+   #[test]
+   fn flat_triangles_use_the_face_normal()
+   {
+      let triangle = Triangle::new(corners(), Material::gray());
+      assert_eq!(normal_at(&triangle, 0.2, 0.3), Some(Vector3::new(0., 0., 1.)));
+      assert_eq!(normal_at(&triangle, 0.6, 0.6), None);
+   }
+
+   #[test]
+   fn corner_normals_are_blended_across()
+   {
+      let normals = (Vector3::new(0., 0., 1.), Vector3::new(1., 0., 1.), Vector3::new(0., 1., 1.));
+      let triangle = Triangle::with_normals(corners(), normals, Material::gray());
+      // At the corners themselves (given unnormalized, used normalized)
+      assert!((normal_at(&triangle, 0., 0.).unwrap() - Vector3::new(0., 0., 1.)).norm() < 1.0e-12);
+      assert!((normal_at(&triangle, 1., 0.).unwrap() - Vector3::new(1., 0., 1.).normalize()).norm() < 1.0e-12);
+      // Halfway along the edge from P to Q, halfway between their normals
+      let halfway = (Vector3::new(0., 0., 1.) + Vector3::new(1., 0., 1.).normalize()).normalize();
+      assert!((normal_at(&triangle, 0.5, 0.).unwrap() - halfway).norm() < 1.0e-12);
+   }
+
+   #[test]
+   fn samples_spread_evenly()
+   {
+      let triangle = Triangle::new(corners(), Material::gray());
+      assert!((triangle.area() - 0.5).abs() < 1.0e-12);
+      // The centroid of samples on a grid is the triangle's
+      let steps = 100;
+      let mut sum = Vector3::zeros();
+      for i in 0..steps {
+         for j in 0..steps {
+            let u = [(i as f64 + 0.5) / steps as f64, (j as f64 + 0.5) / steps as f64];
+            let (p, n) = triangle.sample_surface(u);
+            assert!(p.x >= 0. && p.y >= 0. && p.x + p.y <= 1. + 1.0e-12 && p.z == 0.);
+            assert_eq!(n, Vector3::new(0., 0., 1.));
+            sum += p;
+         }
+      }
+      assert!((sum / (steps * steps) as f64 - Vector3::new(1. / 3., 1. / 3., 0.)).norm() < 1.0e-3);
+   }
 }
@@ -1,25 +1,220 @@
-// TODO: Not implemented. To let the compiler see this file, uncomment the lines in <geometry.rs>
+// for the capital letters
+#![allow(non_snake_case)]
 
 use nalgebra as na;
 use na::{ Vector3 };
 
 use crate::scene::{ Ray, Material };
-use crate::geometry::Object;
+use crate::geometry::{ Object, Triangle };
 
 
+// A mesh: a bunch of triangles sharing one material. The rays are tested
+// against every triangle, after a cheap check against the bounding box.
 pub struct TriangleSoup {
-   triangles: Vec<dyn Object + Sync>,
-   material: Material
+   pub triangles: Vec<Triangle>,
+   // Corners of the bounding box
+   pub min: Vector3<f64>,
+   pub max: Vector3<f64>,
+   // Running total of the triangle areas, to pick triangles by area when
+   // the mesh is sampled as a light
+   pub areas: Vec<f64>,
+   pub material: Material
 }
 
 
 impl TriangleSoup {
-   pub fn new(triangles: Vec<dyn Object + Sync>, material: Material) -> TriangleSoup {
-      return TriangleSoup{triangles, material};
+   pub fn new(triangles: Vec<Triangle>, material: Material) -> TriangleSoup {
+      let mut min = Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+      let mut max = Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+      let mut areas = Vec::with_capacity(triangles.len());
+      let mut total = 0.;
+      for triangle in triangles.iter() {
+         let (P, Q, R) = triangle.corners;
+         min = min.inf(&P).inf(&Q).inf(&R);
+         max = max.sup(&P).sup(&Q).sup(&R);
+         total += triangle.area();
+         areas.push(total);
+      }
+      return TriangleSoup{ triangles, min, max, areas, material };
    }
+
+   /**
+    * Build a mesh from indexed vertices. Each face is three indices into
+    * <positions>, and optionally three into <normals>. Faces without normals
+    * get smooth normals computed from their neighbours if <smooth> is set,
+    * and are flat shaded otherwise.
+    */
+   pub fn from_indexed(positions: &Vec<Vector3<f64>>, normals: &[Vector3<f64>], faces: &[[usize; 3]],
+                       face_normals: &[Option<[usize; 3]>], smooth: bool, material: Material) -> TriangleSoup {
+      let auto_normals = if smooth { angle_weighted_normals(positions, faces) } else { Vec::new() };
+
+      let mut triangles = Vec::with_capacity(faces.len());
+      for (f, face) in faces.iter().enumerate() {
+         let corners = (positions[face[0]], positions[face[1]], positions[face[2]]);
+         let triangle = match face_normals[f] {
+            Some(nf) => Triangle::with_normals(corners, (normals[nf[0]], normals[nf[1]], normals[nf[2]]), material.clone()),
+            None if smooth => {
+               let n = (auto_normals[face[0]], auto_normals[face[1]], auto_normals[face[2]]);
+               // Vertices only touched by degenerate faces have no normal
+               if n.0.norm() > 0. && n.1.norm() > 0. && n.2.norm() > 0. {
+                  Triangle::with_normals(corners, n, material.clone())
+               } else {
+                  Triangle::new(corners, material.clone())
+               }
+            }
+            None => Triangle::new(corners, material.clone())
+         };
+         triangles.push(triangle);
+      }
+      return TriangleSoup::new(triangles, material);
+   }
+
+   // Slab test against the bounding box (see Cuboid::slabs), true if the
+   // ray could hit something at or after min_t
+   fn hits_bounds(&self, ray: &Ray, min_t: f64) -> bool
+   {
+      let mut t_near = f64::NEG_INFINITY;
+      let mut t_far = f64::INFINITY;
+      for axis in 0..3 {
+         let e = ray.origin[axis];
+         let d = ray.direction[axis];
+         if d == 0. {
+            if e < self.min[axis] || e > self.max[axis] {
+               return false;
+            }
+            continue;
+         }
+         let t0 = (self.min[axis] - e) / d;
+         let t1 = (self.max[axis] - e) / d;
+         t_near = t_near.max(t0.min(t1));
+         t_far = t_far.min(t0.max(t1));
+      }
+      return t_near <= t_far && t_far >= min_t;
+   }
+}
+
+
+/**
+ * Smooth vertex normals for an indexed mesh: each vertex gets the sum of the
+ * normals of the faces around it, weighted by the angle each face makes at
+ * that vertex. Weighting by angle (rather than plain averaging) keeps the
+ * result from depending on how the surface happens to be triangulated.
+ * Vertices that only belong to degenerate faces get a zero vector.
+ */
+pub fn angle_weighted_normals(positions: &[Vector3<f64>], faces: &[[usize; 3]]) -> Vec<Vector3<f64>>
+{
+   let mut normals = vec![Vector3::new(0., 0., 0.); positions.len()];
+   for face in faces.iter() {
+      let face_normal = (positions[face[1]] - positions[face[0]]).cross(&(positions[face[2]] - positions[face[0]]));
+      if face_normal.norm() == 0. {
+         continue;
+      }
+      let face_normal = face_normal.normalize();
+      for corner in 0..3 {
+         let p = positions[face[corner]];
+         let a = positions[face[(corner + 1) % 3]] - p;
+         let b = positions[face[(corner + 2) % 3]] - p;
+         normals[face[corner]] += a.angle(&b) * face_normal;
+      }
+   }
+   for normal in normals.iter_mut() {
+      if normal.norm() > 0. {
+         *normal = normal.normalize();
+      }
+   }
+   return normals;
 }
 
 
 impl Object for TriangleSoup {
+   fn intersect(&self, ray: &Ray, min_t: f64, t: &mut f64, n: &mut Vector3<f64>) -> bool
+   {
+      if !self.hits_bounds(ray, min_t) {
+         return false;
+      }
+
+      // Closest hit over all the triangles, like first_hit does for objects
+      let mut hit = false;
+      *t = f64::INFINITY;
+      let mut t_triangle = 0.;
+      let mut n_triangle = Vector3::new(0., 0., 0.);
+      for triangle in self.triangles.iter() {
+         if triangle.intersect(ray, min_t, &mut t_triangle, &mut n_triangle) && t_triangle < *t {
+            *t = t_triangle;
+            *n = n_triangle;
+            hit = true;
+         }
+      }
+      return hit;
+   }
+
+   fn get_material(&self) -> &Material
+   {
+      return &self.material;
+   }
 
+   fn bounding_sphere(&self) -> Option<(Vector3<f64>, f64)>
+   {
+      if self.triangles.is_empty() {
+         return None;
+      }
+      return Some(((self.min + self.max) / 2., (self.max - self.min).norm() / 2.));
+   }
+
+   fn area(&self) -> f64
+   {
+      return *self.areas.last().unwrap_or(&0.);
+   }
+
+   // Pick a triangle in proportion to its area, then reuse the part of u[0]
+   // that falls inside it as a fresh random number for the point
+   fn sample_surface(&self, u: [f64; 2]) -> (Vector3<f64>, Vector3<f64>)
+   {
+      let target = u[0] * self.area();
+      let k = self.areas.partition_point(|&a| a <= target).min(self.triangles.len() - 1);
+      let before = if k > 0 { self.areas[k - 1] } else { 0. };
+      let u0 = ((target - before) / (self.areas[k] - before)).clamp(0., 1.);
+      return self.triangles[k].sample_surface([u0, u[1]]);
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // The cube from -1 to 1, corner <i> at (-1 or 1 by bit 0, bit 1, bit 2),
+   // each face split into two triangles facing out
+   fn cube() -> (Vec<Vector3<f64>>, Vec<[usize; 3]>)
+   {
+      let positions = (0..8).map(|i| Vector3::new(
+         if i & 1 == 0 { -1. } else { 1. },
+         if i & 2 == 0 { -1. } else { 1. },
+         if i & 4 == 0 { -1. } else { 1. })).collect();
+      let quads = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+      let faces = quads.iter().flat_map(|q| vec![[q[0], q[1], q[2]], [q[0], q[2], q[3]]]).collect();
+      return (positions, faces);
+   }
+
+   #[test]
+   fn corners_of_a_cube_point_along_the_diagonal()
+   {
+      // Every corner is where three faces meet at right angles, however
+      // each face was split, so its normal points straight out of the cube
+      let (positions, faces) = cube();
+      let normals = angle_weighted_normals(&positions, &faces);
+      for (p, n) in positions.iter().zip(normals.iter()) {
+         assert!((n - p.normalize()).norm() < 1.0e-12);
+      }
+   }
+
+   #[test]
+   fn degenerate_faces_are_left_out()
+   {
+      let positions = vec![Vector3::new(0., 0., 0.), Vector3::new(1., 0., 0.), Vector3::new(0., 1., 0.),
+                           Vector3::new(2., 0., 0.)];
+      let normals = angle_weighted_normals(&positions, &[[0, 1, 2], [0, 1, 3]]);
+      assert_eq!(normals[0], Vector3::new(0., 0., 1.));
+      assert_eq!(normals[3], Vector3::new(0., 0., 0.));
+   }
 }
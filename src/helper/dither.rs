@@ -0,0 +1,219 @@
+/**
+ * Threshold maps for dithering. Quantizing a value v to the levels 0..max as
+ * floor(v max + t), with a threshold t in [0, 1) that changes from pixel to
+ * pixel, rounds up just often enough that an area averages out to v, so
+ * smooth gradients don't break up into bands. The map is tiled over the image.
+ */
+use rand::{ Rng, SeedableRng, rngs::SmallRng };
+
+use crate::render::Dither;
+
+
+// Side of the blue noise tile
+const BLUE_NOISE_SIZE: usize = 64;
+
+
+pub struct ThresholdMap {
+   size: usize,
+   thresholds: Vec<f64>
+}
+
+
+impl ThresholdMap {
+   pub fn new(dither: Dither) -> ThresholdMap
+   {
+      return match dither {
+         // Every threshold zero, which truncates
+         Dither::None => ThresholdMap { size: 1, thresholds: vec![0.] },
+         Dither::Bayer => ThresholdMap::from_ranks(8, &bayer_ranks(8)),
+         Dither::BlueNoise => ThresholdMap::from_ranks(BLUE_NOISE_SIZE, &blue_noise_ranks(BLUE_NOISE_SIZE))
+      };
+   }
+
+   // Thresholds spread evenly over [0, 1) in the order of <ranks>
+   fn from_ranks(size: usize, ranks: &[usize]) -> ThresholdMap
+   {
+      let n = (size * size) as f64;
+      return ThresholdMap { size, thresholds: ranks.iter().map(|&rank| (rank as f64 + 0.5) / n).collect() };
+   }
+
+   // The threshold for pixel (<x>, <y>)
+   pub fn at(&self, x: usize, y: usize) -> f64
+   {
+      return self.thresholds[(y % self.size) * self.size + x % self.size];
+   }
+}
+
+
+// The order of the cells of a <size> x <size> Bayer matrix (<size> a power of
+// two), built up from the 2 x 2 one
+fn bayer_ranks(size: usize) -> Vec<usize>
+{
+   let mut ranks = vec![0];
+   let mut n = 1;
+   while n < size {
+      let mut bigger = vec![0; 4 * n * n];
+      for y in 0..n {
+         for x in 0..n {
+            let r = 4 * ranks[y * n + x];
+            bigger[y * 2 * n + x] = r;
+            bigger[y * 2 * n + x + n] = r + 2;
+            bigger[(y + n) * 2 * n + x] = r + 3;
+            bigger[(y + n) * 2 * n + x + n] = r + 1;
+         }
+      }
+      ranks = bigger;
+      n *= 2;
+   }
+   return ranks;
+}
+
+
+/**
+ * The order of the cells of a <size> x <size> blue noise tile, made with
+ * Ulichney's void and cluster method: pixels are switched on one at a time,
+ * always in the biggest hole left between the ones already on (and switched
+ * off from the tightest clump, for the first ones). How crowded a spot is
+ * comes from a Gaussian around every pixel that is on, wrapped around the
+ * edges so the tile repeats seamlessly.
+ */
+fn blue_noise_ranks(size: usize) -> Vec<usize>
+{
+   let n = size * size;
+   let sigma: f64 = 1.5;
+
+   // The Gaussian for every offset, wrapped around
+   let mut kernel = vec![0.; n];
+   for dy in 0..size {
+      for dx in 0..size {
+         let wx = dx.min(size - dx) as f64;
+         let wy = dy.min(size - dy) as f64;
+         kernel[dy * size + dx] = (-(wx * wx + wy * wy) / (2. * sigma * sigma)).exp();
+      }
+   }
+
+   let mut on = vec![false; n];
+   let mut energy = vec![0.; n];
+   let toggle = |on: &mut Vec<bool>, energy: &mut Vec<f64>, p: usize| {
+      on[p] = !on[p];
+      let sign = if on[p] { 1. } else { -1. };
+      let (px, py) = (p % size, p / size);
+      for y in 0..size {
+         for x in 0..size {
+            let k = ((y + size - py) % size) * size + (x + size - px) % size;
+            energy[y * size + x] += sign * kernel[k];
+         }
+      }
+   };
+   // The most crowded pixel that is on, and the emptiest one that is off
+   let tightest_cluster = |on: &Vec<bool>, energy: &Vec<f64>| -> usize {
+      return (0..n).filter(|&p| on[p]).max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap()).unwrap();
+   };
+   let largest_void = |on: &Vec<bool>, energy: &Vec<f64>| -> usize {
+      return (0..n).filter(|&p| !on[p]).min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap()).unwrap();
+   };
+
+   // A random tenth of the pixels, then evened out by moving the most
+   // crowded one into the biggest hole until that is where it already was
+   let mut rng = SmallRng::seed_from_u64(0);
+   let initial = n / 10;
+   let mut count = 0;
+   while count < initial {
+      let p = rng.gen_range(0..n);
+      if !on[p] {
+         toggle(&mut on, &mut energy, p);
+         count += 1;
+      }
+   }
+   loop {
+      let cluster = tightest_cluster(&on, &energy);
+      toggle(&mut on, &mut energy, cluster);
+      let void = largest_void(&on, &energy);
+      toggle(&mut on, &mut energy, void);
+      if void == cluster {
+         break;
+      }
+   }
+
+   // Rank the initial pixels by taking them away again, most crowded first
+   let mut ranks = vec![0; n];
+   let mut pattern = on.clone();
+   let mut pattern_energy = energy.clone();
+   for rank in (0..initial).rev() {
+      let cluster = tightest_cluster(&pattern, &pattern_energy);
+      toggle(&mut pattern, &mut pattern_energy, cluster);
+      ranks[cluster] = rank;
+   }
+
+   // And all the others by filling the biggest hole, over and over
+   for rank in initial..n {
+      let void = largest_void(&on, &energy);
+      toggle(&mut on, &mut energy, void);
+      ranks[void] = rank;
+   }
+   return ranks;
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn is_permutation(ranks: &[usize]) -> bool
+   {
+      let mut sorted = ranks.to_vec();
+      sorted.sort();
+      return sorted.iter().enumerate().all(|(k, &rank)| rank == k);
+   }
+
+   #[test]
+   fn bayer_matrices_grow_from_the_2_by_2_one()
+   {
+      assert_eq!(bayer_ranks(2), vec![0, 2, 3, 1]);
+      let ranks = bayer_ranks(8);
+      assert!(is_permutation(&ranks));
+      // Each quarter of the matrix is the 4 x 4 one, interleaved with the others
+      let quarter = bayer_ranks(4);
+      for y in 0..4 {
+         for x in 0..4 {
+            assert_eq!(ranks[y * 8 + x], 4 * quarter[y * 4 + x]);
+         }
+      }
+   }
+
+   #[test]
+   fn blue_noise_spreads_out_its_first_pixels()
+   {
+      let size = 32;
+      let ranks = blue_noise_ranks(size);
+      assert!(is_permutation(&ranks));
+      // The first sixteenth of the pixels are about four apart: none of them
+      // touch, even across the edges of the tile
+      let first: Vec<usize> = (0..size * size).filter(|&p| ranks[p] < size * size / 16).collect();
+      for &p in first.iter() {
+         for &q in first.iter() {
+            let dx = (p % size).abs_diff(q % size);
+            let dy = (p / size).abs_diff(q / size);
+            let (dx, dy) = (dx.min(size - dx), dy.min(size - dy));
+            assert!(p == q || dx > 1 || dy > 1);
+         }
+      }
+   }
+
+   #[test]
+   fn thresholds_fill_the_unit_interval_evenly()
+   {
+      for dither in [Dither::Bayer, Dither::BlueNoise] {
+         let map = ThresholdMap::new(dither);
+         let mut thresholds = map.thresholds.clone();
+         thresholds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+         let n = thresholds.len() as f64;
+         for (k, t) in thresholds.iter().enumerate() {
+            assert!((t - (k as f64 + 0.5) / n).abs() < 1.0e-12);
+         }
+         // Tiled over the image
+         assert_eq!(map.at(1, 2), map.at(1 + map.size, 2 + 3 * map.size));
+      }
+      assert_eq!(ThresholdMap::new(Dither::None).at(5, 7), 0.);
+   }
+}
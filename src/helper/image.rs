@@ -0,0 +1,148 @@
+/**
+ * Reading images from disk, e.g. heightmaps for heightfields.
+ *
+ * Netpbm files (PGM/PPM, both the ASCII and the binary flavours) are simple
+ * enough to parse by hand; PNG goes through the png crate. Colour images are
+ * turned into luminance, and every format's values are normalized to [0, 1].
+ */
+use std::{
+   fs::File,
+   io::Read
+};
+
+
+// Rec. 709 luminance weights
+fn luminance(r: f64, g: f64, b: f64) -> f64
+{
+   return 0.2126 * r + 0.7152 * g + 0.0722 * b;
+}
+
+
+/**
+ * Read the image at <path> as grayscale values in [0, 1], row by row from
+ * the top. Returns (values, width, height). The format is picked from the
+ * file extension: .pgm, .ppm or .png.
+ */
+pub fn read_grayscale(path: &str) -> (Vec<f64>, usize, usize)
+{
+   let lower = path.to_lowercase();
+   if lower.ends_with(".png") {
+      return read_png(path);
+   } else if lower.ends_with(".pgm") || lower.ends_with(".ppm") || lower.ends_with(".pnm") {
+      return read_netpbm(path);
+   }
+   panic!("Error: can't read image {} (only .pgm, .ppm and .png are supported)", path);
+}
+
+
+// P2/P5 (gray) and P3/P6 (rgb) netpbm files, with 8 or 16 bit samples
+fn read_netpbm(path: &str) -> (Vec<f64>, usize, usize)
+{
+   let mut bytes = Vec::new();
+   File::open(path).unwrap_or_else(|_| panic!("Error opening image {}", path))
+      .read_to_end(&mut bytes).unwrap_or_else(|_| panic!("Error reading image {}", path));
+
+   // The header is four whitespace separated tokens (magic, width, height,
+   // maxval), with '#' comments allowed in between
+   let mut pos = 0;
+   let mut next_token = || -> String {
+      loop {
+         while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+         }
+         if pos < bytes.len() && bytes[pos] == b'#' {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+               pos += 1;
+            }
+            continue;
+         }
+         break;
+      }
+      let start = pos;
+      while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+         pos += 1;
+      }
+      return String::from_utf8_lossy(&bytes[start..pos]).into_owned();
+   };
+   let bad_header = format!("Error: bad netpbm header in {}", path);
+   let magic = next_token();
+   let width: usize = next_token().parse().expect(bad_header.as_str());
+   let height: usize = next_token().parse().expect(bad_header.as_str());
+   let maxval: f64 = next_token().parse().expect(bad_header.as_str());
+
+   let nchannels = match magic.as_str() {
+      "P2" | "P5" => 1,
+      "P3" | "P6" => 3,
+      _ => panic!("Error: {} is not a PGM or PPM file", path)
+   };
+   let count = nchannels * width * height;
+
+   let samples: Vec<f64> = if magic == "P2" || magic == "P3" {
+      // ASCII: the rest of the file is more whitespace separated numbers
+      (0..count).map(|_| next_token().parse::<f64>().unwrap_or_else(|_| panic!("Error: bad pixel data in {}", path))).collect()
+   } else {
+      // Binary: exactly one whitespace character after maxval, then the
+      // samples, big endian if they need two bytes
+      let start = pos + 1;
+      let wide = maxval > 255.;
+      let sample_size = if wide { 2 } else { 1 };
+      assert!(bytes.len() >= start + sample_size * count, "Error: {} is truncated", path);
+      (0..count).map(|i| {
+         if wide {
+            return u16::from_be_bytes([bytes[start + 2 * i], bytes[start + 2 * i + 1]]) as f64;
+         }
+         return bytes[start + i] as f64;
+      }).collect()
+   };
+
+   let values = if nchannels == 1 {
+      samples.iter().map(|s| s / maxval).collect()
+   } else {
+      samples.chunks(3).map(|rgb| luminance(rgb[0], rgb[1], rgb[2]) / maxval).collect()
+   };
+   return (values, width, height);
+}
+
+
+fn read_png(path: &str) -> (Vec<f64>, usize, usize)
+{
+   let file = File::open(path).unwrap_or_else(|_| panic!("Error opening image {}", path));
+   let mut decoder = png::Decoder::new(file);
+   // Palettes become rgb and 1, 2, 4 bit grays become 8 bit
+   decoder.set_transformations(png::Transformations::EXPAND);
+   let mut reader = decoder.read_info().unwrap_or_else(|_| panic!("Error reading PNG header of {}", path));
+   let mut buffer = vec![0u8; reader.output_buffer_size()];
+   let info = reader.next_frame(&mut buffer).unwrap_or_else(|_| panic!("Error decoding {}", path));
+   let (width, height) = (info.width as usize, info.height as usize);
+
+   let nchannels = match info.color_type {
+      png::ColorType::Grayscale => 1,
+      png::ColorType::GrayscaleAlpha => 2,
+      png::ColorType::Rgb => 3,
+      png::ColorType::Rgba => 4,
+      png::ColorType::Indexed => panic!("Error: {} still has a palette after expanding", path)
+   };
+   let wide = info.bit_depth == png::BitDepth::Sixteen;
+   let maxval = if wide { 65535. } else { 255. };
+   let sample = |row: usize, i: usize| -> f64 {
+      let offset = row * info.line_size;
+      if wide {
+         return u16::from_be_bytes([buffer[offset + 2 * i], buffer[offset + 2 * i + 1]]) as f64 / maxval;
+      }
+      return buffer[offset + i] as f64 / maxval;
+   };
+
+   // Alpha is ignored
+   let mut values = Vec::with_capacity(width * height);
+   for row in 0..height {
+      for x in 0..width {
+         let i = nchannels * x;
+         if nchannels < 3 {
+            values.push(sample(row, i));
+         } else {
+            values.push(luminance(sample(row, i), sample(row, i + 1), sample(row, i + 2)));
+         }
+      }
+   }
+   return (values, width, height);
+}
@@ -10,7 +10,7 @@ use std::{
    io::{ Write, BufWriter }
 };
 use nalgebra as na;
-use na::{ Vector3 };
+use na::{ Vector3, Matrix4, Rotation3 };
 use serde_json::Value;
 
 use crate::scene::{ 
@@ -18,16 +18,60 @@ use crate::scene::{
    Light,
    PointLight,
    DirectionalLight,
-   Material
+   AmbientLight,
+   Material,
+   Scattering,
+   BlinnPhong,
+   Lambertian,
+   Mirror,
+   Dielectric,
+   Microfacet,
+   Interface,
+   Medium,
+   Dispersion
 };
+use crate::scene::dispersion::REFERENCE_WAVELENGTH;
+
+use crate::render::{ RenderMode, RenderSettings, ImageFormat, Aov, ToneMapper, Encoding, Dither };
 
 use crate::geometry::{
    Object,
    Sphere,
    Plane,
-   Triangle
+   Triangle,
+   TriangleSoup,
+   Cuboid,
+   Cylinder,
+   Cone,
+   Disk,
+   Torus,
+   Transformed,
+   Csg,
+   CsgOperation,
+   Sdf,
+   SdfObject,
+   Heightfield
 };
 
+use crate::noise::fractal_noise;
+
+mod image;
+use self::image::read_grayscale;
+
+mod obj;
+use self::obj::{ ObjMesh, read_obj };
+
+mod options;
+pub use self::options::Options;
+
+mod output;
+pub use self::output::write_images;
+
+mod dither;
+
+mod voxels;
+use self::voxels::read_voxels;
+
 
 
 /**
@@ -48,18 +92,25 @@ use crate::geometry::{
  * if you were simply accessing the 1D array and looking at the color of that
  * pixel. However, the loop order does matter when you're also writing back
  * to a file.
+ *
+ * The samples go from 0 to <maxval>: 255 for 8-bit images, 65535 for 16-bit
+ * ones, which take two bytes per sample (most significant first).
  */
-pub fn write_ppm(path: &str, rgb_image: &Vec<u8>, n_x: usize, n_y: usize, nchannels: usize)
+pub fn write_ppm(path: &str, rgb_image: &[u16], n_x: usize, n_y: usize, nchannels: usize, maxval: u16)
 {
-   let f = File::create(path).expect(format!("Error creating file {}", path).as_str());
+   let f = File::create(path).unwrap_or_else(|_| panic!("Error creating file {}", path));
    let mut stream = BufWriter::new(&f);
-   let header = format!("P6\n{} {}\n255\n", n_x, n_y);
-   stream.write(header.as_bytes()).expect("Problem writing header.");
+   let header = format!("P6\n{} {}\n{}\n", n_x, n_y, maxval);
+   stream.write_all(header.as_bytes()).expect("Problem writing header.");
 
 
    // Not making any assumptions on how the image data is stored
-   for subpxl in 0..nchannels * n_x * n_y {
-      stream.write(&[rgb_image[subpxl] as u8]).expect("Error writing pixels.");
+   for subpxl in rgb_image[..nchannels * n_x * n_y].iter() {
+      if maxval < 256 {
+         stream.write_all(&[*subpxl as u8]).expect("Error writing pixels.");
+      } else {
+         stream.write_all(&subpxl.to_be_bytes()).expect("Error writing pixels.");
+      }
    }
 
    // 1D indices are 0, 1, 2, 3, 4, ..., n_x * n_y
@@ -104,86 +155,834 @@ pub fn setup_camera(n_x: usize, n_y: usize, json: &Value, cam: &mut Camera)
 }
 
 
-// TODO: Could definitely add a function that returns a material to eliminate the duplicate code for each geometric primative
-pub fn setup_objects(json: &Value, objects: &mut Vec<Box<dyn Object + Sync>>)
+// Read a JSON array of three numbers, e.g. [0, 1, 0], into a vector
+pub fn json_to_vec3(json: &Value) -> Vector3<f64>
 {
-   let objects_json = json.get("objects").unwrap().as_array().unwrap();
-   let mats_json = json.get("materials").unwrap().as_array().unwrap().to_vec();
+   return Vector3::from_vec(json.as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
+}
+
+
+// Look up the material called <mat_name> in the "materials" array
+pub fn setup_material(mat_name: &str, mats_json: &[Value]) -> Material
+{
+   let mat_idx = mats_json.iter().position(|j| j["name"].as_str().unwrap() == mat_name)
+      .unwrap_or_else(|| panic!("Error: no material named \"{}\"", mat_name));
+
+   let mat_json = &mats_json[mat_idx];
+   let scattering = match mat_json["type"].as_str().unwrap_or("blinn_phong") {
+      "blinn_phong" => {
+         let ka = json_to_vec3(&mat_json["ka"]);
+         let kd = json_to_vec3(&mat_json["kd"]);
+         let ks = json_to_vec3(&mat_json["ks"]);
+         let km = json_to_vec3(&mat_json["km"]);
+         let phong_exp = mat_json["phong_exponent"].as_f64().unwrap();
+         Scattering::BlinnPhong(BlinnPhong::new(ka, kd, ks, km, phong_exp))
+      }
+      "lambertian" => {
+         Scattering::Lambertian(Lambertian::new(json_to_vec3(&mat_json["albedo"])))
+      }
+      "mirror" => {
+         let color = if mat_json.get("color").is_some() { json_to_vec3(&mat_json["color"]) } else { Vector3::new(1., 1., 1.) };
+         Scattering::Mirror(Mirror::new(color))
+      }
+      "dielectric" => {
+         let ior = mat_json["ior"].as_f64().unwrap_or(1.5);
+         let tint = if mat_json.get("tint").is_some() { json_to_vec3(&mat_json["tint"]) } else { Vector3::new(1., 1., 1.) };
+         let mut dielectric = Dielectric::new(ior, tint);
+         if let Some(dispersion_json) = mat_json.get("dispersion") {
+            let dispersion = setup_dispersion(dispersion_json);
+            dielectric.ior = dispersion.ior(REFERENCE_WAVELENGTH);
+            dielectric.dispersion = Some(dispersion);
+         }
+         Scattering::Dielectric(dielectric)
+      }
+      // Physically based metal/roughness material
+      "pbr" => {
+         let base_color = json_to_vec3(&mat_json["base_color"]);
+         let metallic = mat_json["metallic"].as_f64().unwrap_or(0.);
+         let roughness = mat_json["roughness"].as_f64().unwrap_or(0.5);
+         let specular = mat_json["specular"].as_f64().unwrap_or(0.5);
+         Scattering::Microfacet(Microfacet::new(base_color, metallic, roughness, specular))
+      }
+      // Just the boundary of the medium inside
+      "interface" => Scattering::Interface(Interface),
+      other => panic!("Error: material \"{}\" has unknown type \"{}\"", mat_name, other)
+   };
+
+   // Any kind of material can glow: "emission" is a color, scaled by
+   // "emission_strength"
+   let mut material = Material::from_scattering(scattering);
+   material.id = mat_idx;
+   if mat_json.get("emission").is_some() {
+      let strength = mat_json["emission_strength"].as_f64().unwrap_or(1.);
+      material.emission = strength * json_to_vec3(&mat_json["emission"]);
+   }
+   if let Some(medium) = mat_json.get("medium") {
+      material.medium = Some(setup_medium(medium));
+   }
+
+   return material;
+}
+
+
+/**
+ * How a dielectric's index of refraction depends on the wavelength: one of
+ * the named glasses ("bk7", "sf11", "fused_silica", "diamond"), or
+ *    { "cauchy": [a, b] }
+ *    { "sellmeier": { "b": [b1, b2, b3], "c": [c1, c2, c3] } }
+ * with wavelengths in micrometers.
+ */
+pub fn setup_dispersion(json: &Value) -> Dispersion
+{
+   if let Some(name) = json.as_str() {
+      return Dispersion::named(name).unwrap_or_else(|| panic!("Error: unknown dispersion \"{}\" (expected \"bk7\", \"sf11\", \"fused_silica\" or \"diamond\")", name));
+   }
+   let coefficients = |json: &Value, n: usize| -> Vec<f64> {
+      let values: Vec<f64> = json.as_array().map(|a| a.iter().filter_map(|v| v.as_f64()).collect()).unwrap_or_default();
+      if values.len() != n {
+         panic!("Error: expected {} numbers for the dispersion, got {}", n, json);
+      }
+      return values;
+   };
+   if let Some(cauchy) = json.get("cauchy") {
+      let ab = coefficients(cauchy, 2);
+      return Dispersion::Cauchy { a: ab[0], b: ab[1] };
+   }
+   if let Some(sellmeier) = json.get("sellmeier") {
+      let b = coefficients(&sellmeier["b"], 3);
+      let c = coefficients(&sellmeier["c"], 3);
+      return Dispersion::Sellmeier { b: [b[0], b[1], b[2]], c: [c[0], c[1], c[2]] };
+   }
+   panic!("Error: the dispersion must be a glass name, \"cauchy\" or \"sellmeier\"");
+}
+
+
+/**
+ * A medium, for the scene's "fog" or a material's "medium":
+ *    { "absorption": [r, g, b], "scattering": [r, g, b], "g": 0.3, "density": 1 }
+ * The coefficients are per unit of distance, and each can also be a single
+ * number for gray. "density" scales both. "g" is the Henyey-Greenstein
+ * asymmetry: positive scatters forward, negative backward, 0 (the default)
+ * evenly.
+ *
+ * The medium is the same everywhere unless a voxel "grid" file (see
+ * voxels.rs) gives its density, which then multiplies the coefficients:
+ *    { ..., "grid": "smoke.vol", "bounds": [[-1, 0, -1], [1, 2, 1]],
+ *      "emission_strength": 1, "temperature_scale": 1 }
+ * "bounds" places the grid, instead of the file's own. A temperature channel
+ * (in kelvin, after multiplying by "temperature_scale") makes the medium
+ * glow, as brightly as "emission_strength" at 1500 K.
+ */
+pub fn setup_medium(json: &Value) -> Medium
+{
+   let coefficient = |key: &str| -> Vector3<f64> {
+      return match json.get(key) {
+         None => Vector3::new(0., 0., 0.),
+         Some(value) => match value.as_f64() {
+            Some(s) => Vector3::new(s, s, s),
+            None => json_to_vec3(value)
+         }
+      };
+   };
+   let density = json["density"].as_f64().unwrap_or(1.);
+   let g = json["g"].as_f64().unwrap_or(0.);
+   if g <= -1. || g >= 1. {
+      panic!("Error: the medium's \"g\" must be between -1 and 1, not {}", g);
+   }
+   let mut medium = Medium::new(density * coefficient("absorption"), density * coefficient("scattering"), g);
+
+   if let Some(grid_path) = json.get("grid") {
+      let grid_path = grid_path.as_str().expect("Error: the medium's \"grid\" must be a file name");
+      let mut grid = read_voxels(grid_path);
+      if let Some(bounds) = json.get("bounds") {
+         let corners = bounds.as_array().expect("Error: \"bounds\" must be two corners");
+         if corners.len() != 2 {
+            panic!("Error: \"bounds\" must be two corners");
+         }
+         let (a, b) = (json_to_vec3(&corners[0]), json_to_vec3(&corners[1]));
+         grid.min = a.inf(&b);
+         grid.max = a.sup(&b);
+      }
+      let emission_strength = json["emission_strength"].as_f64().unwrap_or(1.);
+      let temperature_scale = json["temperature_scale"].as_f64().unwrap_or(1.);
+      medium.set_grid(grid, emission_strength, temperature_scale);
+   }
+   return medium;
+}
 
-   for i in 0..objects_json.len() {
-      if objects_json[i]["type"].as_str().unwrap() == "sphere" {
-         let mat_name = objects_json[i]["material"].as_str().unwrap();
-         let mat_idx = mats_json.iter().position(|j| j["name"].as_str().unwrap() == mat_name).unwrap();
-         let ka: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["ka"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let kd: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["kd"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let ks: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["ks"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let km: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["km"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let phong_exp = mats_json[mat_idx]["phong_exponent"].as_f64().unwrap();
-         
-         let mat = Material::new(ka, kd, ks, km, phong_exp);
-
-         let center: Vector3<f64> = Vector3::from_vec(objects_json[i]["center"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let radius: f64 = objects_json[i]["radius"].as_f64().unwrap();
-         objects.push(Box::new(Sphere::new(&center, radius, mat)));
-      
-      } else if objects_json[i]["type"].as_str().unwrap() == "plane" {
-         let mat_name = objects_json[i]["material"].as_str().unwrap();
-         let mat_idx = mats_json.iter().position(|j| j["name"].as_str().unwrap() == mat_name).unwrap();
-         let ka: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["ka"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let kd: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["kd"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let ks: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["ks"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let km: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["km"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let phong_exp = mats_json[mat_idx]["phong_exponent"].as_f64().unwrap();
-         
-         let mat = Material::new(ka, kd, ks, km, phong_exp);
-
-         let point: Vector3<f64> = Vector3::from_vec(objects_json[i]["point"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let normal: Vector3<f64> = Vector3::from_vec(objects_json[i]["normal"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect()).normalize();
-         objects.push(Box::new(Plane::new(&point, &normal, mat)));
-      
-      } else if objects_json[i]["type"].as_str().unwrap() == "triangle" {
-         let mat_name = objects_json[i]["material"].as_str().unwrap();
-         let mat_idx = mats_json.iter().position(|j| j["name"].as_str().unwrap() == mat_name).unwrap();
-         let ka: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["ka"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let kd: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["kd"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let ks: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["ks"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let km: Vector3<f64> = Vector3::from_vec(mats_json[mat_idx]["km"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let phong_exp = mats_json[mat_idx]["phong_exponent"].as_f64().unwrap();
 
-         let mat = Material::new(ka, kd, ks, km, phong_exp);
+/**
+ * Build the local transform of a scene graph node from its (optional)
+ * "transform" entry:
+ *    "transform": { "translate": [x, y, z], "rotate": [rx, ry, rz], "scale": s or [sx, sy, sz] }
+ * Every key is optional. Rotations are in degrees about the x, y then z axis.
+ * The node is scaled first, then rotated, then translated (M = T * R * S).
+ */
+pub fn setup_transform(json: &Value) -> Matrix4<f64>
+{
+   let mut M = Matrix4::identity();
+   if json.is_null() {
+      return M;
+   }
+
+   if let Some(scale) = json.get("scale") {
+      let s = match scale.as_f64() {
+         Some(s) => Vector3::new(s, s, s),
+         None => json_to_vec3(scale)
+      };
+      M = Matrix4::new_nonuniform_scaling(&s) * M;
+   }
+   if let Some(rotate) = json.get("rotate") {
+      let r = json_to_vec3(rotate).map(|x| x.to_radians());
+      M = Rotation3::from_euler_angles(r[0], r[1], r[2]).to_homogeneous() * M;
+   }
+   if let Some(translate) = json.get("translate") {
+      M = Matrix4::new_translation(&json_to_vec3(translate)) * M;
+   }
+
+   return M;
+}
+
+
+// Build a single geometric primitive (in its own local space) from JSON.
+// Returns None for types we don't know about.
+pub fn setup_object(object_json: &Value, mats_json: &[Value]) -> Option<Box<dyn Object + Sync>>
+{
+   let object_type = object_json["type"].as_str().unwrap();
+
+   if object_type == "sphere" {
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let center = json_to_vec3(&object_json["center"]);
+      let radius: f64 = object_json["radius"].as_f64().unwrap();
+      return Some(Box::new(Sphere::new(&center, radius, mat)));
+
+   } else if object_type == "plane" {
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let point = json_to_vec3(&object_json["point"]);
+      let normal = json_to_vec3(&object_json["normal"]).normalize();
+      return Some(Box::new(Plane::new(&point, &normal, mat)));
+
+   } else if object_type == "triangle" {
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let P = json_to_vec3(&object_json["corners"][0]);
+      let Q = json_to_vec3(&object_json["corners"][1]);
+      let R = json_to_vec3(&object_json["corners"][2]);
+      let corners = (P, Q, R);
+      // Optional per-corner "normals" for smooth shading
+      if let Some(normals_json) = object_json.get("normals") {
+         let normals = (json_to_vec3(&normals_json[0]), json_to_vec3(&normals_json[1]), json_to_vec3(&normals_json[2]));
+         return Some(Box::new(Triangle::with_normals(corners, normals, mat)));
+      }
+      return Some(Box::new(Triangle::new(corners, mat)));
+
+   } else if object_type == "mesh" {
+      // Either an "obj" file, or inline "vertices" and "faces" (triples of
+      // vertex indices) with optional per-vertex "normals". Meshes without
+      // normals are smoothed automatically unless "smooth" is false.
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let smooth = object_json["smooth"].as_bool().unwrap_or(true);
+      let mesh = if let Some(obj_path) = object_json["obj"].as_str() {
+         read_obj(obj_path)
+      } else {
+         let positions: Vec<Vector3<f64>> = object_json["vertices"].as_array().unwrap().iter().map(json_to_vec3).collect();
+         let faces: Vec<[usize; 3]> = object_json["faces"].as_array().unwrap().iter().map(|face| {
+            let index = |i: usize| face[i].as_u64().unwrap() as usize;
+            return [index(0), index(1), index(2)];
+         }).collect();
+         let normals: Vec<Vector3<f64>> = match object_json["normals"].as_array() {
+            Some(normals_json) => normals_json.iter().map(json_to_vec3).collect(),
+            None => Vec::new()
+         };
+         // Normals are per vertex, so they share the vertex indices
+         let face_normals = faces.iter().map(|&face| if normals.is_empty() { None } else { Some(face) }).collect();
+         ObjMesh { positions, normals, faces, face_normals }
+      };
+      return Some(Box::new(TriangleSoup::from_indexed(&mesh.positions, &mesh.normals, &mesh.faces, &mesh.face_normals, smooth, mat)));
+
+   } else if object_type == "box" {
+      // Either "min"/"max" corners, or a "center" with "half_extents" and
+      // an optional "rotate" (degrees about x, y then z) about the center.
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      if object_json.get("center").is_none() {
+         let min = json_to_vec3(&object_json["min"]);
+         let max = json_to_vec3(&object_json["max"]);
+         return Some(Box::new(Cuboid::new(&min, &max, mat)));
+      }
+
+      let center = json_to_vec3(&object_json["center"]);
+      let half = json_to_vec3(&object_json["half_extents"]);
+      match object_json.get("rotate") {
+         None => {
+            return Some(Box::new(Cuboid::new(&(center - half), &(center + half), mat)));
+         }
+         Some(rotate) => {
+            let r = json_to_vec3(rotate).map(|x| x.to_radians());
+            let M = Matrix4::new_translation(&center) * Rotation3::from_euler_angles(r[0], r[1], r[2]).to_homogeneous();
+            return Some(Box::new(Transformed::new(Box::new(Cuboid::new(&-half, &half, mat)), M)));
+         }
+      }
+
+   } else if object_type == "cylinder" {
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let base = json_to_vec3(&object_json["base"]);
+      let axis = json_to_vec3(&object_json["axis"]);
+      let radius = object_json["radius"].as_f64().unwrap();
+      let height = object_json["height"].as_f64().unwrap();
+      let capped = object_json["capped"].as_bool().unwrap_or(false);
+      if height <= 0. {
+         panic!("Error: cylinder height must be positive, got {}", height);
+      }
+      if radius < 0. {
+         panic!("Error: cylinder radius can't be negative, got {}", radius);
+      }
+      return Some(Box::new(Cylinder::new(&base, &axis, radius, height, capped, mat)));
 
-         let P = Vector3::from_vec(objects_json[i]["corners"][0].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let Q = Vector3::from_vec(objects_json[i]["corners"][1].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let R = Vector3::from_vec(objects_json[i]["corners"][2].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let corners = (P, Q, R);
-         objects.push(Box::new(Triangle::new(corners, mat)));
+   } else if object_type == "cone" {
+      // The base radius is either given, or follows from the half angle
+      // (in degrees) at the apex
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let base = json_to_vec3(&object_json["base"]);
+      let axis = json_to_vec3(&object_json["axis"]);
+      let height = object_json["height"].as_f64().unwrap();
+      if height <= 0. {
+         panic!("Error: cone height must be positive, got {}", height);
       }
+      let radius = match object_json["radius"].as_f64() {
+         Some(r) => r,
+         None => {
+            let angle = object_json["angle"].as_f64().unwrap();
+            if angle <= 0. || angle >= 90. {
+               panic!("Error: cone angle must be between 0 and 90 degrees, got {}", angle);
+            }
+            height * angle.to_radians().tan()
+         }
+      };
+      let top_radius = object_json["top_radius"].as_f64().unwrap_or(0.);
+      let capped = object_json["capped"].as_bool().unwrap_or(false);
+      if radius < 0. || top_radius < 0. {
+         panic!("Error: cone radii can't be negative, got {} and {}", radius, top_radius);
+      }
+      return Some(Box::new(Cone::new(&base, &axis, radius, top_radius, height, capped, mat)));
+
+   } else if object_type == "disk" {
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let center = json_to_vec3(&object_json["center"]);
+      let normal = json_to_vec3(&object_json["normal"]);
+      let radius = object_json["radius"].as_f64().unwrap();
+      return Some(Box::new(Disk::new(&center, &normal, radius, mat)));
+
+   } else if object_type == "torus" {
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let center = json_to_vec3(&object_json["center"]);
+      let axis = json_to_vec3(&object_json["axis"]);
+      let major_radius = object_json["major_radius"].as_f64().unwrap();
+      let minor_radius = object_json["minor_radius"].as_f64().unwrap();
+      return Some(Box::new(Torus::new(&center, &axis, major_radius, minor_radius, mat)));
+
+   } else if object_type == "csg" {
+      // "left" and "right" are objects of their own (with their own materials
+      // and optional transforms), and can be CSG nodes too
+      let operation = match object_json["operation"].as_str().unwrap() {
+         "union" => CsgOperation::Union,
+         "intersection" => CsgOperation::Intersection,
+         "difference" => CsgOperation::Difference,
+         op => panic!("Error: unknown CSG operation \"{}\"", op)
+      };
+      let left = setup_csg_child(&object_json["left"], mats_json);
+      let right = setup_csg_child(&object_json["right"], mats_json);
+      return Some(Box::new(Csg::new(operation, left, right)));
+
+   } else if object_type == "sdf" {
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let sdf = setup_sdf(&object_json["shape"]);
+      let step_scale = object_json["step_scale"].as_f64().unwrap_or(1.);
+      let max_steps = object_json["max_steps"].as_u64().unwrap_or(256) as usize;
+      let max_distance = object_json["max_distance"].as_f64().unwrap_or(100.);
+      return Some(Box::new(SdfObject::new(sdf, mat, step_scale, max_steps, max_distance)));
+
+   } else if object_type == "heightfield" {
+      // Heights come from a grayscale "image" or are generated from "noise",
+      // and are spread over "extent" ([size_x, size_z]) from "corner"
+      let mat = setup_material(object_json["material"].as_str().unwrap(), mats_json);
+      let (samples, n_x, n_z) = if let Some(image_path) = object_json["image"].as_str() {
+         read_grayscale(image_path)
+      } else {
+         setup_noise(&object_json["noise"])
+      };
+      let corner = json_to_vec3(&object_json["corner"]);
+      let extent = &object_json["extent"];
+      let size_x = extent[0].as_f64().unwrap();
+      let size_z = extent[1].as_f64().unwrap();
+      let height_scale = object_json["height_scale"].as_f64().unwrap_or(1.);
+      return Some(Box::new(Heightfield::new(&samples, n_x, n_z, &corner, size_x, size_z, height_scale, mat)));
    }
+
+   return None;
+}
+
+
+/**
+ * Build a signed distance function from its expression tree in JSON. Each
+ * node has a "type": a primitive
+ *    "sphere"     "center", "radius"
+ *    "round_box"  "center", "half_extents", "radius" (0 for sharp edges)
+ *    "capsule"    "a", "b", "radius"
+ *    "torus"      "center", "major_radius", "minor_radius" (around the y axis)
+ *    "cylinder"   "center", "radius", "height" (along the y axis)
+ *    "plane"      "normal", "offset"
+ * or an operator
+ *    "union", "intersection", "subtraction"                   "left", "right"
+ *    "smooth_union", "smooth_intersection", "smooth_subtraction"  "left", "right", "k"
+ *    "translate"  "child", "offset"
+ *    "twist"      "child", "rate" (radians per unit along y)
+ *    "repeat"     "child", "period" (0 to not repeat along an axis), optional "limit"
+ */
+pub fn setup_sdf(json: &Value) -> Sdf
+{
+   let child = |key: &str| Box::new(setup_sdf(&json[key]));
+   let number = |key: &str| json[key].as_f64().unwrap_or_else(|| panic!("Error: SDF node is missing \"{}\"", key));
+
+   return match json["type"].as_str().unwrap() {
+      "sphere" => Sdf::Sphere { center: json_to_vec3(&json["center"]), radius: number("radius") },
+      "round_box" => Sdf::RoundBox {
+         center: json_to_vec3(&json["center"]),
+         half_extents: json_to_vec3(&json["half_extents"]),
+         radius: json["radius"].as_f64().unwrap_or(0.)
+      },
+      "capsule" => Sdf::Capsule { a: json_to_vec3(&json["a"]), b: json_to_vec3(&json["b"]), radius: number("radius") },
+      "torus" => Sdf::Torus {
+         center: json_to_vec3(&json["center"]),
+         major_radius: number("major_radius"),
+         minor_radius: number("minor_radius")
+      },
+      "cylinder" => Sdf::Cylinder {
+         center: json_to_vec3(&json["center"]),
+         radius: number("radius"),
+         half_height: 0.5 * number("height")
+      },
+      "plane" => Sdf::Plane { normal: json_to_vec3(&json["normal"]).normalize(), offset: number("offset") },
+
+      "union" => Sdf::Union(child("left"), child("right")),
+      "intersection" => Sdf::Intersection(child("left"), child("right")),
+      "subtraction" => Sdf::Subtraction(child("left"), child("right")),
+      "smooth_union" => Sdf::SmoothUnion(child("left"), child("right"), number("k")),
+      "smooth_intersection" => Sdf::SmoothIntersection(child("left"), child("right"), number("k")),
+      "smooth_subtraction" => Sdf::SmoothSubtraction(child("left"), child("right"), number("k")),
+      "translate" => Sdf::Translate(child("child"), json_to_vec3(&json["offset"])),
+      "twist" => Sdf::Twist(child("child"), number("rate")),
+      "repeat" => {
+         let limit = json.get("limit").map(json_to_vec3);
+         Sdf::Repeat(child("child"), json_to_vec3(&json["period"]), limit)
+      }
+      other => panic!("Error: unknown SDF node \"{}\"", other)
+   };
 }
 
 
-pub fn setup_lights(json: &Value, lights: &mut Vec<Box<dyn Light + Sync>>)
+/**
+ * Sample fractal noise on a grid for a heightfield. All keys are optional:
+ *    "seed"         (0)
+ *    "resolution"   samples along each side, at least 2 (129)
+ *    "frequency"    features across the whole grid (4)
+ *    "octaves"      at least 1 (6)
+ *    "lacunarity"   frequency multiplier between octaves (2)
+ *    "gain"         amplitude multiplier between octaves (0.5)
+ * Returns (samples in [0, 1], n_x, n_z) like read_grayscale.
+ */
+fn setup_noise(json: &Value) -> (Vec<f64>, usize, usize)
+{
+   let seed = json["seed"].as_u64().unwrap_or(0);
+   let resolution = json["resolution"].as_u64().unwrap_or(129) as usize;
+   let frequency = json["frequency"].as_f64().unwrap_or(4.);
+   let octaves = json["octaves"].as_u64().unwrap_or(6) as usize;
+   let lacunarity = json["lacunarity"].as_f64().unwrap_or(2.);
+   let gain = json["gain"].as_f64().unwrap_or(0.5);
+   if octaves < 1 {
+      panic!("Error: heightfield noise needs at least 1 octave");
+   }
+   if resolution < 2 {
+      panic!("Error: heightfield noise needs a resolution of at least 2");
+   }
+
+   let mut samples = Vec::with_capacity(resolution * resolution);
+   for k in 0..resolution {
+      for i in 0..resolution {
+         let x = frequency * i as f64 / (resolution - 1) as f64;
+         let z = frequency * k as f64 / (resolution - 1) as f64;
+         samples.push(fractal_noise(x, z, seed, octaves, lacunarity, gain));
+      }
+   }
+   return (samples, resolution, resolution);
+}
+
+
+// Objects that enclose a volume, the only ones that can be CSG operands
+const CSG_SOLIDS: [&str; 7] = ["sphere", "plane", "box", "cylinder", "cone", "torus", "csg"];
+
+
+// An operand of a CSG node: a primitive with an optional "transform"
+fn setup_csg_child(child_json: &Value, mats_json: &[Value]) -> Box<dyn Object + Sync>
+{
+   // Anything else has no inside and would silently drop out of the result
+   if !CSG_SOLIDS.contains(&child_json["type"].as_str().unwrap_or("")) {
+      panic!("Error: can't use {} in CSG, operands must be one of {}", child_json["type"], CSG_SOLIDS.join(", "));
+   }
+   let object = setup_object(child_json, mats_json)
+      .unwrap_or_else(|| panic!("Error: can't use {} in CSG", child_json["type"]));
+   let M = setup_transform(&child_json["transform"]);
+   if M == Matrix4::identity() {
+      return object;
+   }
+   return Box::new(Transformed::new(object, M));
+}
+
+
+/**
+ * The "objects" array is a scene graph. Besides primitives it may contain
+ * named groups:
+ *    { "type": "group", "name": "table", "transform": {...}, "children": [...] }
+ * Children can be primitives or other groups. Any node may have a "transform"
+ * (see setup_transform), and the world transform of a primitive is the product
+ * of all the transforms from the root down to it. This way a whole assembly
+ * can be moved by editing the transform of its group.
+ */
+fn setup_object_list(objects_json: &[Value], mats_json: &[Value], parent: &Matrix4<f64>, objects: &mut Vec<Box<dyn Object + Sync>>)
+{
+   for object_json in objects_json.iter() {
+      let world = parent * setup_transform(&object_json["transform"]);
+
+      if object_json["type"].as_str().unwrap() == "group" {
+         let children = object_json["children"].as_array()
+            .unwrap_or_else(|| panic!("Error: group \"{}\" has no children array", object_json["name"]));
+         setup_object_list(children, mats_json, &world, objects);
+
+      } else if let Some(object) = setup_object(object_json, mats_json) {
+         // Only pay for the transform when there is one
+         if world == Matrix4::identity() {
+            objects.push(object);
+         } else {
+            objects.push(Box::new(Transformed::new(object, world)));
+         }
+      }
+   }
+}
+
+
+pub fn setup_objects(json: &Value, objects: &mut Vec<Box<dyn Object + Sync>>)
+{
+   let objects_json = json.get("objects").unwrap().as_array().unwrap();
+   let mats_json = json.get("materials").unwrap().as_array().unwrap().to_vec();
+
+   setup_object_list(objects_json, &mats_json, &Matrix4::identity(), objects);
+}
+
+
+/**
+ * Read the "lights" array. Besides "point" and "directional" lights it may
+ * hold an "ambient" light:
+ *    { "type": "ambient", "color": [r, g, b], "occlusion": { "samples": 16, "distance": 2 } }
+ * The "occlusion" block is optional and turns on ambient occlusion ("distance"
+ * defaults to infinity). Without an ambient light the scene gets a dim white one.
+ */
+pub fn setup_lights(json: &Value, lights: &mut Vec<Box<dyn Light + Sync>>, ambient: &mut AmbientLight)
 {
    let lights_json = json.get("lights").unwrap().as_array().unwrap();
 
-   for i in 0..lights_json.len() {
-      if lights_json[i]["type"].as_str().unwrap() == "directional" {
-         let dir: Vector3<f64> = Vector3::from_vec(lights_json[i]["direction"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect()).normalize();
-         let color: Vector3<f64> = Vector3::from_vec(lights_json[i]["color"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
+   for light_json in lights_json.iter() {
+      if light_json["type"].as_str().unwrap() == "directional" {
+         let dir: Vector3<f64> = Vector3::from_vec(light_json["direction"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect()).normalize();
+         let color: Vector3<f64> = Vector3::from_vec(light_json["color"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
          lights.push(Box::new(DirectionalLight::new(color, dir)));
       
-      } else if lights_json[i]["type"].as_str().unwrap() == "point" {
-         let pos: Vector3<f64> = Vector3::from_vec(lights_json[i]["position"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
-         let color: Vector3<f64> = Vector3::from_vec(lights_json[i]["color"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
+      } else if light_json["type"].as_str().unwrap() == "point" {
+         let pos: Vector3<f64> = Vector3::from_vec(light_json["position"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
+         let color: Vector3<f64> = Vector3::from_vec(light_json["color"].as_array().unwrap().to_vec().iter().map(|x| x.as_f64().unwrap()).collect());
          lights.push(Box::new(PointLight::new(color, pos)));
+
+      } else if light_json["type"].as_str().unwrap() == "ambient" {
+         *ambient = AmbientLight::new(json_to_vec3(&light_json["color"]));
+         if let Some(occlusion) = light_json.get("occlusion") {
+            ambient.occlusion_samples = occlusion["samples"].as_u64().unwrap_or(16) as usize;
+            ambient.occlusion_distance = occlusion["distance"].as_f64().unwrap_or(f64::INFINITY);
+         }
       }
    }
 }
 
 
-pub fn setup_scene(n_x: usize, n_y: usize, json: &Value, cam: &mut Camera, lights: &mut Vec<Box<dyn Light + Sync>>, objects: &mut Vec<Box<dyn Object + Sync>>)
+/**
+ * Read the optional "render" block:
+ *    "mode"         "whitted" (default), "path", "bdpt" or "ao"
+ *    "samples"      paths per pixel when path tracing (16)
+ *    "max_depth"    bounces per path (8)
+ *    "spectral"     trace each path at a single wavelength, for dispersion (false)
+ *    "photons"      photons shot for the caustics (0, for none)
+ *    "photon_neighbors"  nearest photons the light at a point is found from (50)
+ *    "photon_radius"     farthest they can be (0, for a distance to suit them)
+ *    "ao_samples"   hemisphere rays per pixel for ambient occlusion (16)
+ *    "ao_distance"  hits further away don't occlude (infinity)
+ *    "ao_pass"      also write an ambient occlusion image (false)
+ *    "aovs"         names of extra passes to write, e.g. ["depth", "normal"] (none)
+ *    "formats"      file formats to save, any of "ppm", "png", "pfm", "hdr", "exr" (["ppm"])
+ *    "exposure"     in stops, applied before tone mapping (0)
+ *    "tone_map"     "clamp" (default), "reinhard", "extended_reinhard", "aces" or "hable"
+ *    "white"        radiance that becomes white with extended_reinhard (4) and hable (11.2)
+ *    "encoding"     "linear" (default), "srgb" or a gamma, e.g. 2.2
+ *    "bit_depth"    8 (default) or 16 bits per channel
+ *    "dither"       "none" (default), "bayer" or "blue_noise"
+ * The last six only change the PPM and PNG images.
+ *    "progressive"       render in passes of one sample per pixel (false)
+ *    "time_limit"        seconds after which a progressive render stops (infinity)
+ *    "snapshot_passes"   save the images every this many passes (never)
+ *    "snapshot_seconds"  and at least this often (never)
+ *    "adaptive"          sample noisy pixels more, up to "samples" (false)
+ *    "min_samples"       samples every pixel gets when adaptive (8)
+ *    "error_threshold"   relative standard error a pixel is sampled down to (0.02)
+ *    "sample_map"        also write an image of the samples per pixel (false)
+ *    "denoise"           filter the noise out of the render before saving (false)
+ *    "denoise_radius"    pixels around each one that it's averaged with (5)
+ * Any of these can be overridden from the command line (see Options).
+ */
+pub fn setup_render(json: &Value) -> RenderSettings
+{
+   let mut settings = RenderSettings::default();
+   let render_json = &json["render"];
+   if let Some(mode) = render_json["mode"].as_str() {
+      settings.mode = RenderMode::from_name(mode);
+   }
+   if let Some(samples) = render_json["samples"].as_u64() {
+      settings.samples = samples as usize;
+   }
+   if let Some(max_depth) = render_json["max_depth"].as_u64() {
+      settings.max_depth = max_depth as usize;
+   }
+   if let Some(spectral) = render_json["spectral"].as_bool() {
+      settings.spectral = spectral;
+   }
+   if let Some(photons) = render_json["photons"].as_u64() {
+      settings.photons = photons as usize;
+   }
+   if let Some(photon_neighbors) = render_json["photon_neighbors"].as_u64() {
+      settings.photon_neighbors = photon_neighbors as usize;
+   }
+   if let Some(photon_radius) = render_json["photon_radius"].as_f64() {
+      settings.photon_radius = photon_radius;
+   }
+   if let Some(ao_samples) = render_json["ao_samples"].as_u64() {
+      if ao_samples == 0 {
+         panic!("Error: \"ao_samples\" must be at least 1");
+      }
+      settings.ao_samples = ao_samples as usize;
+   }
+   if let Some(ao_distance) = render_json["ao_distance"].as_f64() {
+      settings.ao_distance = ao_distance;
+   }
+   if let Some(ao_pass) = render_json["ao_pass"].as_bool() {
+      settings.ao_pass = ao_pass;
+   }
+   if let Some(aovs) = render_json["aovs"].as_array() {
+      settings.aovs = aovs.iter().map(|name| Aov::from_name(name.as_str().unwrap())).collect();
+   }
+   if let Some(formats) = render_json["formats"].as_array() {
+      settings.formats = formats.iter().map(|name| ImageFormat::from_name(name.as_str().unwrap())).collect();
+   }
+   if let Some(exposure) = render_json["exposure"].as_f64() {
+      settings.display.exposure = exposure;
+   }
+   if let Some(tone_map) = render_json["tone_map"].as_str() {
+      settings.display.tone_mapper = ToneMapper::from_name(tone_map);
+   }
+   if let Some(white) = render_json["white"].as_f64() {
+      settings.display.white = Some(white);
+   }
+   if let Some(gamma) = render_json["encoding"].as_f64() {
+      if gamma <= 0. {
+         panic!("Error: the gamma in \"encoding\" must be positive");
+      }
+      settings.display.encoding = Encoding::Gamma(gamma);
+   } else if let Some(encoding) = render_json["encoding"].as_str() {
+      settings.display.encoding = Encoding::from_name(encoding);
+   }
+   if let Some(bit_depth) = render_json["bit_depth"].as_u64() {
+      if bit_depth != 8 && bit_depth != 16 {
+         panic!("Error: \"bit_depth\" must be 8 or 16, not {}", bit_depth);
+      }
+      settings.bit_depth = bit_depth as usize;
+   }
+   if let Some(dither) = render_json["dither"].as_str() {
+      settings.dither = Dither::from_name(dither);
+   }
+   if let Some(progressive) = render_json["progressive"].as_bool() {
+      settings.progressive = progressive;
+   }
+   if let Some(time_limit) = render_json["time_limit"].as_f64() {
+      settings.time_limit = time_limit;
+   }
+   if let Some(snapshot_passes) = render_json["snapshot_passes"].as_u64() {
+      settings.snapshot_passes = snapshot_passes as usize;
+   }
+   if let Some(snapshot_seconds) = render_json["snapshot_seconds"].as_f64() {
+      settings.snapshot_seconds = snapshot_seconds;
+   }
+   if let Some(adaptive) = render_json["adaptive"].as_bool() {
+      settings.adaptive = adaptive;
+   }
+   if let Some(min_samples) = render_json["min_samples"].as_u64() {
+      settings.min_samples = min_samples as usize;
+   }
+   if let Some(error_threshold) = render_json["error_threshold"].as_f64() {
+      settings.error_threshold = error_threshold;
+   }
+   if let Some(sample_map) = render_json["sample_map"].as_bool() {
+      settings.sample_map = sample_map;
+   }
+   if let Some(denoise) = render_json["denoise"].as_bool() {
+      settings.denoise = denoise;
+   }
+   if let Some(denoise_radius) = render_json["denoise_radius"].as_u64() {
+      settings.denoise_radius = denoise_radius as usize;
+   }
+   return settings;
+}
+
+
+#[allow(clippy::too_many_arguments)]
+pub fn setup_scene(n_x: usize, n_y: usize, json: &Value, cam: &mut Camera, lights: &mut Vec<Box<dyn Light + Sync>>,
+                   ambient: &mut AmbientLight, fog: &mut Option<Medium>, objects: &mut Vec<Box<dyn Object + Sync>>)
 {
    setup_camera(n_x, n_y, json, cam);
-   setup_lights(json, lights);
+   setup_lights(json, lights, ambient);
+   // The medium everything sits in, if it isn't vacuum
+   if let Some(fog_json) = json.get("fog") {
+      *fog = Some(setup_medium(fog_json));
+   }
    setup_objects(json, objects);
 }
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use serde_json::json;
+   use crate::scene::Ray;
+
+   fn close(a: &Vector3<f64>, b: &Vector3<f64>) -> bool
+   {
+      return (a - b).norm() < 1.0e-9;
+   }
+
+   #[test]
+   fn transforms_scale_then_rotate_then_translate()
+   {
+      let M = setup_transform(&json!({ "translate": [1, 2, 3], "rotate": [0, 0, 90], "scale": 2 }));
+      let p = M.transform_point(&na::Point3::new(1., 0., 0.)).coords;
+      // (1, 0, 0) -> (2, 0, 0) -> (0, 2, 0) -> (1, 4, 3)
+      assert!(close(&p, &Vector3::new(1., 4., 3.)));
+      assert_eq!(setup_transform(&Value::Null), Matrix4::identity());
+   }
+
+   #[test]
+   fn groups_compose_their_transforms()
+   {
+      let scene = json!({
+         "materials": [{ "name": "gray", "ka": [0.5, 0.5, 0.5], "kd": [0.5, 0.5, 0.5], "ks": [0, 0, 0],
+                         "km": [0, 0, 0], "phong_exponent": 1 }],
+         "objects": [{
+            "type": "group", "name": "outer", "transform": { "translate": [0, 0, -10] },
+            "children": [{
+               "type": "group", "name": "inner", "transform": { "scale": 2 },
+               "children": [{ "type": "sphere", "material": "gray", "center": [0, 0, 0], "radius": 1 }]
+            }]
+         }]
+      });
+      let mut objects: Vec<Box<dyn Object + Sync>> = Vec::new();
+      setup_objects(&scene, &mut objects);
+      assert_eq!(objects.len(), 1);
+
+      // A sphere of radius 2 around (0, 0, -10)
+      let ray = Ray { origin: Vector3::zeros(), direction: Vector3::new(0., 0., -1.) };
+      let (mut t, mut n) = (0., Vector3::zeros());
+      assert!(objects[0].intersect(&ray, 1.0e-6, &mut t, &mut n));
+      assert!((t - 8.).abs() < 1.0e-9);
+      assert!(close(&n, &Vector3::new(0., 0., 1.)));
+      let ray = Ray { origin: Vector3::new(2.1, 0., 0.), direction: Vector3::new(0., 0., -1.) };
+      assert!(!objects[0].intersect(&ray, 1.0e-6, &mut t, &mut n));
+   }
+
+   // setup_object() for a single object using the one material "gray"
+   fn object_from(object_json: Value) -> Option<Box<dyn Object + Sync>>
+   {
+      let gray = json!({ "name": "gray", "ka": [0.5, 0.5, 0.5], "kd": [0.5, 0.5, 0.5], "ks": [0, 0, 0],
+                         "km": [0, 0, 0], "phong_exponent": 1 });
+      return setup_object(&object_json, &[gray]);
+   }
+
+   #[test]
+   fn cones_can_be_given_by_their_angle()
+   {
+      let cone = object_from(json!({ "type": "cone", "material": "gray", "base": [0, 0, 0], "axis": [0, 1, 0],
+                                     "height": 1, "angle": 45 })).unwrap();
+      // The slope meets y = 0.5 at a radius of 0.5
+      let ray = Ray { origin: Vector3::new(2., 0.5, 0.), direction: Vector3::new(-1., 0., 0.) };
+      let (mut t, mut n) = (0., Vector3::zeros());
+      assert!(cone.intersect(&ray, 1.0e-6, &mut t, &mut n));
+      assert!((t - 1.5).abs() < 1.0e-9);
+   }
+
+   #[test]
+   #[should_panic(expected = "cylinder height must be positive")]
+   fn flat_cylinders_are_rejected()
+   {
+      object_from(json!({ "type": "cylinder", "material": "gray", "base": [0, 0, 0], "axis": [0, 1, 0],
+                          "radius": 1, "height": 0 }));
+   }
+
+   #[test]
+   #[should_panic(expected = "cone radii can't be negative")]
+   fn negative_cone_radii_are_rejected()
+   {
+      object_from(json!({ "type": "cone", "material": "gray", "base": [0, 0, 0], "axis": [0, 1, 0],
+                          "radius": 1, "top_radius": -0.5, "height": 1 }));
+   }
+
+   #[test]
+   #[should_panic(expected = "cone angle must be between 0 and 90 degrees")]
+   fn right_angled_cones_are_rejected()
+   {
+      object_from(json!({ "type": "cone", "material": "gray", "base": [0, 0, 0], "axis": [0, 1, 0],
+                          "height": 1, "angle": 90 }));
+   }
+
+   #[test]
+   #[should_panic(expected = "can't use \"disk\" in CSG")]
+   fn csg_operands_must_be_solid()
+   {
+      object_from(json!({ "type": "csg", "operation": "union",
+                          "left": { "type": "sphere", "material": "gray", "center": [0, 0, 0], "radius": 1 },
+                          "right": { "type": "disk", "material": "gray", "center": [0, 0, 0], "normal": [0, 1, 0],
+                                     "radius": 1 } }));
+   }
+
+   #[test]
+   #[should_panic(expected = "needs at least 1 octave")]
+   fn noise_without_octaves_is_rejected()
+   {
+      setup_noise(&json!({ "octaves": 0 }));
+   }
+
+   #[test]
+   fn ambient_lights_read_their_occlusion()
+   {
+      let mut lights: Vec<Box<dyn Light + Sync>> = Vec::new();
+      let mut ambient = AmbientLight::default();
+      setup_lights(&json!({ "lights": [] }), &mut lights, &mut ambient);
+      assert_eq!(ambient.occlusion_samples, 0);
+
+      let scene = json!({ "lights": [{ "type": "ambient", "color": [0.2, 0.3, 0.4], "occlusion": { "samples": 8 } }] });
+      setup_lights(&scene, &mut lights, &mut ambient);
+      assert!(lights.is_empty());
+      assert_eq!(ambient.I, Vector3::new(0.2, 0.3, 0.4));
+      assert_eq!(ambient.occlusion_samples, 8);
+      assert_eq!(ambient.occlusion_distance, f64::INFINITY);
+   }
+}
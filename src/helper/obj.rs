@@ -0,0 +1,99 @@
+/**
+ * A minimal Wavefront OBJ reader for triangle meshes.
+ *
+ * Only the geometry is read: vertex positions ("v"), vertex normals ("vn")
+ * and faces ("f"). Faces may use any of the v, v/vt, v//vn and v/vt/vn forms
+ * and negative (relative) indices. Polygons with more than three corners are
+ * split into a fan of triangles. Everything else (texture coordinates,
+ * groups, materials, ...) is skipped.
+ */
+use std::{
+   fs::File,
+   io::{ BufRead, BufReader }
+};
+use nalgebra as na;
+use na::{ Vector3 };
+
+
+pub struct ObjMesh {
+   pub positions: Vec<Vector3<f64>>,
+   pub normals: Vec<Vector3<f64>>,
+   // Indices into positions, and into normals if the face had them
+   pub faces: Vec<[usize; 3]>,
+   pub face_normals: Vec<Option<[usize; 3]>>
+}
+
+
+// OBJ indices start at 1, and negative ones count back from the last element
+// read so far
+fn resolve_index(token: &str, count: usize, path: &str, line: usize) -> usize
+{
+   let index: i64 = token.parse().unwrap_or_else(|_| panic!("Error: bad index \"{}\" in {} line {}", token, path, line));
+   let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+   if resolved < 0 || resolved >= count as i64 {
+      panic!("Error: index {} out of range in {} line {}", index, path, line);
+   }
+   return resolved as usize;
+}
+
+
+pub fn read_obj(path: &str) -> ObjMesh
+{
+   let file = File::open(path).unwrap_or_else(|_| panic!("Error opening OBJ file {}", path));
+   let mut mesh = ObjMesh { positions: Vec::new(), normals: Vec::new(), faces: Vec::new(), face_normals: Vec::new() };
+
+   for (line_number, line) in BufReader::new(file).lines().enumerate() {
+      let line = line.unwrap_or_else(|_| panic!("Error reading {}", path));
+      let line_number = line_number + 1;
+      let mut tokens = line.split_whitespace();
+      let keyword = match tokens.next() {
+         Some(keyword) => keyword,
+         None => continue
+      };
+
+      match keyword {
+         "v" | "vn" => {
+            let xyz: Vec<f64> = tokens.take(3)
+               .map(|x| x.parse().unwrap_or_else(|_| panic!("Error: bad number \"{}\" in {} line {}", x, path, line_number)))
+               .collect();
+            if xyz.len() < 3 {
+               panic!("Error: expected 3 coordinates in {} line {}", path, line_number);
+            }
+            let v = Vector3::new(xyz[0], xyz[1], xyz[2]);
+            if keyword == "v" {
+               mesh.positions.push(v);
+            } else {
+               mesh.normals.push(v);
+            }
+         }
+         "f" => {
+            // Each corner is v, v/vt, v//vn or v/vt/vn
+            let mut corners = Vec::new();
+            for corner in tokens {
+               let mut parts = corner.split('/');
+               let v = resolve_index(parts.next().unwrap(), mesh.positions.len(), path, line_number);
+               let vn = match parts.nth(1) {
+                  Some(vn) if !vn.is_empty() => Some(resolve_index(vn, mesh.normals.len(), path, line_number)),
+                  _ => None
+               };
+               corners.push((v, vn));
+            }
+            if corners.len() < 3 {
+               panic!("Error: face with fewer than 3 corners in {} line {}", path, line_number);
+            }
+
+            for i in 1..corners.len() - 1 {
+               let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+               mesh.faces.push([a.0, b.0, c.0]);
+               // Only use normals if every corner has one
+               mesh.face_normals.push(match (a.1, b.1, c.1) {
+                  (Some(n_a), Some(n_b), Some(n_c)) => Some([n_a, n_b, n_c]),
+                  _ => None
+               });
+            }
+         }
+         _ => {}
+      }
+   }
+   return mesh;
+}
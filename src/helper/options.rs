@@ -0,0 +1,296 @@
+use crate::render::{ RenderMode, RenderSettings, ImageFormat, Aov, ToneMapper, Encoding, Dither };
+
+
+const USAGE: &str = "=======> To run raytracer, type: <=======\n\
+>>> cargo run --release ./data/<json-file-name>.json [options]\n\n\
+Options (these override the scene's \"render\" block):\n\
+   --mode <name>              rendering algorithm: whitted, path, bdpt or ao\n\
+   --samples <n>              paths per pixel when path tracing\n\
+   --max-depth <n>            bounces per path\n\
+   --spectral                 trace each path at a single wavelength, for dispersion\n\
+   --photons <n>              photons shot for the caustics\n\
+   --photon-neighbors <n>     nearest photons the light at a point is found from\n\
+   --photon-radius <r>        farthest those photons can be\n\
+   --ao-samples <n>           hemisphere rays per pixel for ambient occlusion\n\
+   --ao-distance <d>          hits further away don't occlude\n\
+   --ao-pass                  also write images/<name>-ao.ppm\n\
+   --aov <pass,pass,...>      also write images/<name>-<pass>.ppm for each of depth, normal,\n\
+                              position, albedo, uv, object_id, material_id and shadow\n\
+   --format <fmt,fmt,...>     save as any of ppm, png, pfm, hdr and exr (default ppm)\n\
+   --exposure <stops>         brighten (or darken, if negative) the 8-bit images\n\
+   --tone-map <name>          clamp, reinhard, extended_reinhard, aces or hable\n\
+   --white <radiance>         value that becomes white with extended_reinhard and hable\n\
+   --encoding <name>          linear, srgb or a gamma like 2.2\n\
+   --bit-depth <8|16>         bits per channel of the PPM and PNG images\n\
+   --dither <name>            none, bayer or blue_noise\n\
+   --progressive              render in passes of one sample per pixel, Ctrl-C stops\n\
+   --time-limit <seconds>     stop a progressive render after this long\n\
+   --snapshot-passes <n>      save the images every n passes\n\
+   --snapshot-seconds <s>     save the images at least every s seconds\n\
+   --adaptive                 sample noisy pixels more, up to --samples\n\
+   --min-samples <n>          samples every pixel gets when adaptive\n\
+   --error-threshold <e>      relative error a pixel is sampled down to when adaptive\n\
+   --sample-map               also write images/<name>-samples.ppm\n\
+   --denoise                  filter the noise out of the render before saving\n\
+   --denoise-radius <n>       pixels around each one that it's averaged with";
+
+
+/**
+ * The command line: a scene file and optional settings. Anything given here
+ * wins over the scene's "render" block, so the same scene can be rendered
+ * different ways without editing it.
+ */
+pub struct Options {
+   pub path: String,
+   pub mode: Option<RenderMode>,
+   pub samples: Option<usize>,
+   pub max_depth: Option<usize>,
+   pub spectral: bool,
+   pub photons: Option<usize>,
+   pub photon_neighbors: Option<usize>,
+   pub photon_radius: Option<f64>,
+   pub ao_samples: Option<usize>,
+   pub ao_distance: Option<f64>,
+   pub ao_pass: bool,
+   pub aovs: Vec<Aov>,
+   pub formats: Vec<ImageFormat>,
+   pub exposure: Option<f64>,
+   pub tone_mapper: Option<ToneMapper>,
+   pub white: Option<f64>,
+   pub encoding: Option<Encoding>,
+   pub bit_depth: Option<usize>,
+   pub dither: Option<Dither>,
+   pub progressive: bool,
+   pub time_limit: Option<f64>,
+   pub snapshot_passes: Option<usize>,
+   pub snapshot_seconds: Option<f64>,
+   pub adaptive: bool,
+   pub min_samples: Option<usize>,
+   pub error_threshold: Option<f64>,
+   pub sample_map: bool,
+   pub denoise: bool,
+   pub denoise_radius: Option<usize>
+}
+
+
+// The value following the flag <args[i]>, parsed
+fn flag_value<T: std::str::FromStr>(args: &[String], i: usize) -> T
+{
+   let value = args.get(i + 1).unwrap_or_else(|| panic!("Error: {} needs a value\n\n{}", args[i], USAGE));
+   return value.parse().ok().unwrap_or_else(|| panic!("Error: bad value \"{}\" for {}\n\n{}", value, args[i], USAGE));
+}
+
+
+impl Options {
+   // <args> as from std::env::args(), program name first
+   pub fn parse(args: &[String]) -> Options
+   {
+      let mut options = Options { path: String::new(), mode: None, samples: None, max_depth: None, spectral: false,
+                                  photons: None, photon_neighbors: None, photon_radius: None,
+                                  ao_samples: None, ao_distance: None, ao_pass: false, aovs: Vec::new(),
+                                  formats: Vec::new(), exposure: None, tone_mapper: None, white: None, encoding: None,
+                                  bit_depth: None, dither: None, progressive: false, time_limit: None,
+                                  snapshot_passes: None, snapshot_seconds: None, adaptive: false,
+                                  min_samples: None, error_threshold: None, sample_map: false,
+                                  denoise: false, denoise_radius: None };
+      let mut i = 1;
+      while i < args.len() {
+         match args[i].as_str() {
+            "--mode" => { options.mode = Some(RenderMode::from_name(&flag_value::<String>(args, i))); i += 1; }
+            "--samples" => { options.samples = Some(flag_value(args, i)); i += 1; }
+            "--max-depth" => { options.max_depth = Some(flag_value(args, i)); i += 1; }
+            "--spectral" => { options.spectral = true; }
+            "--photons" => { options.photons = Some(flag_value(args, i)); i += 1; }
+            "--photon-neighbors" => { options.photon_neighbors = Some(flag_value(args, i)); i += 1; }
+            "--photon-radius" => { options.photon_radius = Some(flag_value(args, i)); i += 1; }
+            "--ao-samples" => {
+               let ao_samples: usize = flag_value(args, i);
+               if ao_samples == 0 {
+                  panic!("Error: --ao-samples must be at least 1\n\n{}", USAGE);
+               }
+               options.ao_samples = Some(ao_samples);
+               i += 1;
+            }
+            "--ao-distance" => { options.ao_distance = Some(flag_value(args, i)); i += 1; }
+            "--ao-pass" => { options.ao_pass = true; }
+            "--aov" => {
+               let names: String = flag_value(args, i);
+               options.aovs.extend(names.split(',').map(Aov::from_name));
+               i += 1;
+            }
+            "--format" => {
+               let names: String = flag_value(args, i);
+               options.formats = names.split(',').map(ImageFormat::from_name).collect();
+               i += 1;
+            }
+            "--exposure" => { options.exposure = Some(flag_value(args, i)); i += 1; }
+            "--tone-map" => { options.tone_mapper = Some(ToneMapper::from_name(&flag_value::<String>(args, i))); i += 1; }
+            "--white" => { options.white = Some(flag_value(args, i)); i += 1; }
+            "--encoding" => { options.encoding = Some(Encoding::from_name(&flag_value::<String>(args, i))); i += 1; }
+            "--bit-depth" => {
+               let bit_depth: usize = flag_value(args, i);
+               if bit_depth != 8 && bit_depth != 16 {
+                  panic!("Error: --bit-depth must be 8 or 16\n\n{}", USAGE);
+               }
+               options.bit_depth = Some(bit_depth);
+               i += 1;
+            }
+            "--dither" => { options.dither = Some(Dither::from_name(&flag_value::<String>(args, i))); i += 1; }
+            "--progressive" => { options.progressive = true; }
+            "--time-limit" => { options.time_limit = Some(flag_value(args, i)); i += 1; }
+            "--snapshot-passes" => { options.snapshot_passes = Some(flag_value(args, i)); i += 1; }
+            "--snapshot-seconds" => { options.snapshot_seconds = Some(flag_value(args, i)); i += 1; }
+            "--adaptive" => { options.adaptive = true; }
+            "--min-samples" => { options.min_samples = Some(flag_value(args, i)); i += 1; }
+            "--error-threshold" => { options.error_threshold = Some(flag_value(args, i)); i += 1; }
+            "--sample-map" => { options.sample_map = true; }
+            "--denoise" => { options.denoise = true; }
+            "--denoise-radius" => { options.denoise_radius = Some(flag_value(args, i)); i += 1; }
+            flag if flag.starts_with("--") => panic!("Error: unknown option {}\n\n{}", flag, USAGE),
+            path => {
+               if !options.path.is_empty() {
+                  panic!("Error: more than one scene given\n\n{}", USAGE);
+               }
+               options.path = path.to_string();
+            }
+         }
+         i += 1;
+      }
+
+      if options.path.is_empty() {
+         options.path = "./data/sphere-and-plane.json".to_string();      // default path
+         println!("No path given. Running on default path \"/data/sphere-and-plane.json\"\n");
+         println!("{}", USAGE);
+      }
+      return options;
+   }
+
+   // Override <settings> with whatever was given on the command line
+   pub fn apply(&self, settings: &mut RenderSettings)
+   {
+      if let Some(mode) = self.mode {
+         settings.mode = mode;
+      }
+      if let Some(samples) = self.samples {
+         settings.samples = samples;
+      }
+      if let Some(max_depth) = self.max_depth {
+         settings.max_depth = max_depth;
+      }
+      if self.spectral {
+         settings.spectral = true;
+      }
+      if let Some(photons) = self.photons {
+         settings.photons = photons;
+      }
+      if let Some(photon_neighbors) = self.photon_neighbors {
+         settings.photon_neighbors = photon_neighbors;
+      }
+      if let Some(photon_radius) = self.photon_radius {
+         settings.photon_radius = photon_radius;
+      }
+      if let Some(ao_samples) = self.ao_samples {
+         settings.ao_samples = ao_samples;
+      }
+      if let Some(ao_distance) = self.ao_distance {
+         settings.ao_distance = ao_distance;
+      }
+      if self.ao_pass {
+         settings.ao_pass = true;
+      }
+      if !self.formats.is_empty() {
+         settings.formats = self.formats.clone();
+      }
+      if let Some(exposure) = self.exposure {
+         settings.display.exposure = exposure;
+      }
+      if let Some(tone_mapper) = self.tone_mapper {
+         settings.display.tone_mapper = tone_mapper;
+      }
+      if let Some(white) = self.white {
+         settings.display.white = Some(white);
+      }
+      if let Some(encoding) = self.encoding {
+         settings.display.encoding = encoding;
+      }
+      if let Some(bit_depth) = self.bit_depth {
+         settings.bit_depth = bit_depth;
+      }
+      if let Some(dither) = self.dither {
+         settings.dither = dither;
+      }
+      if self.progressive {
+         settings.progressive = true;
+      }
+      if let Some(time_limit) = self.time_limit {
+         settings.time_limit = time_limit;
+      }
+      if let Some(snapshot_passes) = self.snapshot_passes {
+         settings.snapshot_passes = snapshot_passes;
+      }
+      if let Some(snapshot_seconds) = self.snapshot_seconds {
+         settings.snapshot_seconds = snapshot_seconds;
+      }
+      if self.adaptive {
+         settings.adaptive = true;
+      }
+      if let Some(min_samples) = self.min_samples {
+         settings.min_samples = min_samples;
+      }
+      if let Some(error_threshold) = self.error_threshold {
+         settings.error_threshold = error_threshold;
+      }
+      if self.sample_map {
+         settings.sample_map = true;
+      }
+      if self.denoise {
+         settings.denoise = true;
+      }
+      if let Some(denoise_radius) = self.denoise_radius {
+         settings.denoise_radius = denoise_radius;
+      }
+      for aov in self.aovs.iter() {
+         if !settings.aovs.contains(aov) {
+            settings.aovs.push(*aov);
+         }
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn parse(args: &[&str]) -> Options
+   {
+      let args: Vec<String> = std::iter::once("raytracer").chain(args.iter().copied()).map(String::from).collect();
+      return Options::parse(&args);
+   }
+
+   #[test]
+   fn reads_the_ao_samples()
+   {
+      assert_eq!(parse(&["./data/triangle.json", "--ao-samples", "4"]).ao_samples, Some(4));
+   }
+
+   #[test]
+   #[should_panic(expected = "--ao-samples must be at least 1")]
+   fn rejects_zero_ao_samples()
+   {
+      parse(&["./data/triangle.json", "--ao-samples", "0"]);
+   }
+
+   #[test]
+   fn progressive_flags_override_the_scene()
+   {
+      let scene = serde_json::json!({ "render": { "progressive": true, "time_limit": 60, "snapshot_passes": 8 } });
+      let mut settings = crate::helper::setup_render(&scene);
+      assert!(settings.progressive);
+      assert_eq!((settings.time_limit, settings.snapshot_passes, settings.snapshot_seconds), (60., 8, f64::INFINITY));
+
+      parse(&["./data/triangle.json", "--time-limit", "2.5", "--snapshot-seconds", "10"]).apply(&mut settings);
+      assert!(settings.progressive);
+      assert_eq!((settings.time_limit, settings.snapshot_passes, settings.snapshot_seconds), (2.5, 8, 10.));
+   }
+}
@@ -0,0 +1,292 @@
+/**
+ * Saving the rendered images. The renderer fills float buffers of linear
+ * radiance, and nothing is clamped or rounded until one of these writers
+ * turns a buffer into a file: quantize() for the 8 and 16-bit PPMs and PNGs,
+ * or one of the high dynamic range formats (PFM, Radiance .hdr, OpenEXR)
+ * that keep every value as it was rendered.
+ */
+use std::{
+   fs::File,
+   io::{ Write, BufWriter }
+};
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::render::{ RenderSettings, ImageFormat, Dither, aov_to_display };
+use crate::helper::write_ppm;
+use crate::helper::dither::ThresholdMap;
+
+
+// The explicit rounding step: scale display values in [0, 1] (an image <n_x>
+// pixels wide) to the levels 0..<maxval>, dithered by <thresholds>
+pub fn quantize(pixels: &[Vector3<f64>], thresholds: &ThresholdMap, maxval: u16, n_x: usize) -> Vec<u16>
+{
+   let levels = maxval as f64;
+   let mut rgb_image = Vec::with_capacity(3 * pixels.len());
+   for (i, rgb) in pixels.iter().enumerate() {
+      let t = thresholds.at(i % n_x, i / n_x);
+      for c in 0..3 {
+         rgb_image.push((rgb[c].clamp(0., 1.) * levels + t).floor().min(levels) as u16);
+      }
+   }
+   return rgb_image;
+}
+
+
+// PNG, RGB with 8 bits per channel if <maxval> is 255, 16 otherwise
+pub fn write_png(path: &str, rgb_image: &[u16], n_x: usize, n_y: usize, maxval: u16)
+{
+   let f = File::create(path).unwrap_or_else(|_| panic!("Error creating file {}", path));
+   let mut encoder = png::Encoder::new(BufWriter::new(f), n_x as u32, n_y as u32);
+   encoder.set_color(png::ColorType::Rgb);
+   let bytes: Vec<u8> = if maxval < 256 {
+      encoder.set_depth(png::BitDepth::Eight);
+      rgb_image.iter().map(|&s| s as u8).collect()
+   } else {
+      encoder.set_depth(png::BitDepth::Sixteen);
+      rgb_image.iter().flat_map(|s| s.to_be_bytes()).collect()
+   };
+   let mut writer = encoder.write_header().unwrap_or_else(|_| panic!("Error writing PNG header of {}", path));
+   writer.write_image_data(&bytes).unwrap_or_else(|_| panic!("Error writing {}", path));
+}
+
+
+// Portable float map: a tiny header, then little-endian 32-bit floats with
+// the rows from the bottom up
+pub fn write_pfm(path: &str, pixels: &[Vector3<f64>], n_x: usize, n_y: usize)
+{
+   let f = File::create(path).unwrap_or_else(|_| panic!("Error creating file {}", path));
+   let mut stream = BufWriter::new(&f);
+   let header = format!("PF\n{} {}\n-1.0\n", n_x, n_y);
+   stream.write_all(header.as_bytes()).expect("Problem writing header.");
+   for y in (0..n_y).rev() {
+      for x in 0..n_x {
+         for value in pixels[y * n_x + x].iter() {
+            stream.write_all(&(*value as f32).to_le_bytes()).expect("Error writing pixels.");
+         }
+      }
+   }
+   stream.flush().expect("Error flushing buffer stream");
+}
+
+
+// A color as Radiance's shared exponent RGBE: three 8-bit mantissas and one
+// exponent for the brightest channel
+fn rgbe(rgb: &Vector3<f64>) -> [u8; 4]
+{
+   let v = rgb.max();
+   if v.is_nan() || v < 1.0e-32 {
+      return [0, 0, 0, 0];
+   }
+   // v = m 2^e with m in [0.5, 1)
+   let e = v.log2().floor() as i32 + 1;
+   let scale = 256. / 2f64.powi(e);
+   let mantissa = |s: f64| (s.max(0.) * scale).min(255.) as u8;
+   return [mantissa(rgb[0]), mantissa(rgb[1]), mantissa(rgb[2]), (e + 128).clamp(0, 255) as u8];
+}
+
+
+// Radiance .hdr, written as flat (not run-length encoded) RGBE scanlines
+pub fn write_rgbe(path: &str, pixels: &[Vector3<f64>], n_x: usize, n_y: usize)
+{
+   let f = File::create(path).unwrap_or_else(|_| panic!("Error creating file {}", path));
+   let mut stream = BufWriter::new(&f);
+   let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", n_y, n_x);
+   stream.write_all(header.as_bytes()).expect("Problem writing header.");
+   for rgb in pixels.iter() {
+      stream.write_all(&rgbe(rgb)).expect("Error writing pixels.");
+   }
+   stream.flush().expect("Error flushing buffer stream");
+}
+
+
+// OpenEXR with one named RGB layer (32-bit float) per buffer in <layers>
+pub fn write_exr(path: &str, layers: &Vec<(&str, &Vec<Vector3<f64>>)>, n_x: usize, n_y: usize)
+{
+   use exr::prelude::*;
+
+   let channel = |name: &str, pixels: &Vec<Vector3<f64>>, c: usize| -> AnyChannel<FlatSamples> {
+      return AnyChannel::new(name, FlatSamples::F32(pixels.iter().map(|rgb| rgb[c] as f32).collect()));
+   };
+   let mut exr_layers: Vec<Layer<AnyChannels<FlatSamples>>> = Vec::new();
+   for (name, pixels) in layers.iter() {
+      let channels = AnyChannels::sort(SmallVec::from_vec(vec![channel("R", pixels, 0), channel("G", pixels, 1), channel("B", pixels, 2)]));
+      exr_layers.push(Layer::new((n_x, n_y), LayerAttributes::named(*name), Encoding::SMALL_LOSSLESS, channels));
+   }
+
+   let image = Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions((n_x, n_y))), SmallVec::from_vec(exr_layers));
+   image.write().to_file(path).unwrap_or_else(|_| panic!("Error writing {}", path));
+}
+
+
+// A color for <t> in [0, 1] on a heat map going from black through blue,
+// red and yellow to white
+fn heat(t: f64) -> Vector3<f64>
+{
+   let stops = [Vector3::new(0., 0., 0.), Vector3::new(0.1, 0.1, 0.6), Vector3::new(0.9, 0.1, 0.1),
+                Vector3::new(1., 0.9, 0.), Vector3::new(1., 1., 1.)];
+   let x = t.clamp(0., 1.) * (stops.len() - 1) as f64;
+   let k = (x.floor() as usize).min(stops.len() - 2);
+   return stops[k].lerp(&stops[k + 1], x - k as f64);
+}
+
+
+/**
+ * Save the render <radiance> and whichever of the ambient occlusion pass
+ * <ao>, the AOVs <aovs> and the samples taken per pixel <sample_counts> were
+ * asked for, as images/<name>.<format> (and images/<name>-<pass>.<format> for
+ * the passes) in every format in <settings>. EXR puts all of them in one file
+ * instead, as layers.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn write_images(name: &str, settings: &RenderSettings, radiance: &Vec<Vector3<f64>>, ao: &Vec<Vector3<f64>>,
+                    aovs: &Vec<Vec<Vector3<f64>>>, sample_counts: &[usize], n_x: usize, n_y: usize)
+{
+   let counts: Vec<Vector3<f64>> = sample_counts.iter().map(|&n| Vector3::new(n as f64, n as f64, n as f64)).collect();
+
+   // Passes with no value (NaN, e.g. normals where nothing was hit) are
+   // stored as zero in the float formats
+   let raw_aovs: Vec<Vec<Vector3<f64>>> = aovs.iter()
+      .map(|pass| pass.iter().map(|v| if v[0].is_nan() { Vector3::new(0., 0., 0.) } else { *v }).collect())
+      .collect();
+
+   let mut passes: Vec<(&str, &Vec<Vector3<f64>>)> = Vec::new();
+   if settings.ao_pass {
+      passes.push(("ao", ao));
+   }
+   for (k, aov) in settings.aovs.iter().enumerate() {
+      passes.push((aov.name(), &raw_aovs[k]));
+   }
+   if settings.sample_map {
+      passes.push(("samples", &counts));
+   }
+
+   // The 8 and 16-bit images: the render through the display transform, the
+   // ambient occlusion (a fraction, not radiance, so not tone mapped) and the
+   // AOVs in viewable colors, and the sample counts as a heat map scaled to
+   // the most any pixel took. Only the first two are dithered, as the others
+   // are flat colors and data.
+   let mut quantized: Vec<(&str, Vec<u16>)> = Vec::new();
+   let maxval: u16 = if settings.bit_depth == 16 { 65535 } else { 255 };
+   if settings.formats.iter().any(|format| *format == ImageFormat::Ppm || *format == ImageFormat::Png) {
+      let thresholds = ThresholdMap::new(settings.dither);
+      let display: Vec<Vector3<f64>> = radiance.iter().map(|rgb| settings.display.apply(rgb)).collect();
+      quantized.push(("", quantize(&display, &thresholds, maxval, n_x)));
+      if settings.ao_pass {
+         quantized.push(("ao", quantize(ao, &thresholds, maxval, n_x)));
+      }
+      let flat = ThresholdMap::new(Dither::None);
+      for (k, aov) in settings.aovs.iter().enumerate() {
+         quantized.push((aov.name(), quantize(&aov_to_display(*aov, &aovs[k]), &flat, maxval, n_x)));
+      }
+      if settings.sample_map {
+         let most = sample_counts.iter().max().cloned().unwrap_or(0).max(1) as f64;
+         let heat_map: Vec<Vector3<f64>> = sample_counts.iter().map(|&n| heat(n as f64 / most)).collect();
+         quantized.push(("samples", quantize(&heat_map, &flat, maxval, n_x)));
+      }
+   }
+
+   for format in settings.formats.iter() {
+      let file = |pass: &str| -> String {
+         if pass.is_empty() {
+            return format!("./images/{}.{}", name, format.extension());
+         }
+         return format!("./images/{}-{}.{}", name, pass, format.extension());
+      };
+      match format {
+         ImageFormat::Ppm => {
+            for (pass, rgb_image) in quantized.iter() {
+               write_ppm(file(pass).as_str(), rgb_image, n_x, n_y, 3, maxval);
+            }
+         }
+         ImageFormat::Png => {
+            for (pass, rgb_image) in quantized.iter() {
+               write_png(file(pass).as_str(), rgb_image, n_x, n_y, maxval);
+            }
+         }
+         ImageFormat::Pfm | ImageFormat::Hdr => {
+            let write = if *format == ImageFormat::Pfm { write_pfm } else { write_rgbe };
+            write(file("").as_str(), radiance, n_x, n_y);
+            for (pass, pixels) in passes.iter() {
+               write(file(pass).as_str(), pixels, n_x, n_y);
+            }
+         }
+         ImageFormat::Exr => {
+            let mut layers = vec![("beauty", radiance)];
+            layers.extend(passes.iter().cloned());
+            write_exr(file("").as_str(), &layers, n_x, n_y);
+         }
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // What a reader makes of an RGBE pixel
+   fn from_rgbe(rgbe: [u8; 4]) -> Vector3<f64>
+   {
+      if rgbe[3] == 0 {
+         return Vector3::new(0., 0., 0.);
+      }
+      let scale = 2f64.powi(rgbe[3] as i32 - 128) / 256.;
+      return Vector3::new(rgbe[0] as f64 + 0.5, rgbe[1] as f64 + 0.5, rgbe[2] as f64 + 0.5) * scale;
+   }
+
+   #[test]
+   fn rgbe_keeps_the_brightest_channel_to_within_a_level()
+   {
+      for rgb in [Vector3::new(1., 0.5, 0.25), Vector3::new(1000., 3., 0.), Vector3::new(0.001, 0.002, 0.003),
+                  Vector3::new(0.999, 0.999, 0.999)] {
+         let encoded = rgbe(&rgb);
+         // The largest mantissa uses the upper half of the range
+         assert!(encoded[0..3].iter().max().cloned().unwrap() >= 128);
+         let error = (from_rgbe(encoded) - rgb).abs().max();
+         assert!(error <= rgb.max() / 256., "{:?} came back as {:?}", rgb, from_rgbe(encoded));
+      }
+      assert_eq!(rgbe(&Vector3::new(0., 0., 0.)), [0, 0, 0, 0]);
+      assert_eq!(rgbe(&Vector3::new(f64::NAN, 0., 0.)), [0, 0, 0, 0]);
+      // Negative channels are clamped, not wrapped
+      assert_eq!(rgbe(&Vector3::new(1., -1., 0.))[1], 0);
+   }
+
+   #[test]
+   fn pfm_rows_go_from_the_bottom_up()
+   {
+      let path = std::env::temp_dir().join(format!("raytracer-test-{}.pfm", std::process::id()));
+      let path = path.to_str().unwrap();
+      let pixels = vec![Vector3::new(1., 2., 3.), Vector3::new(4., 5., 6.),
+                        Vector3::new(-1., 0.5, 1.0e6), Vector3::new(0., 0., 0.)];
+      write_pfm(path, &pixels, 2, 2);
+      let bytes = std::fs::read(path).unwrap();
+      std::fs::remove_file(path).unwrap();
+
+      let header = b"PF\n2 2\n-1.0\n";
+      assert_eq!(&bytes[..header.len()], header);
+      let floats: Vec<f32> = bytes[header.len()..].chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+      assert_eq!(floats, vec![-1., 0.5, 1.0e6, 0., 0., 0., 1., 2., 3., 4., 5., 6.]);
+   }
+
+   #[test]
+   fn dithering_keeps_the_average_level()
+   {
+      let (n_x, n_y) = (64, 64);
+      let pixels = vec![Vector3::new(0.3, 0.5, 0.999); n_x * n_y];
+      // Truncated, everything lands on the level below
+      let flat = quantize(&pixels, &ThresholdMap::new(Dither::None), 255, n_x);
+      assert_eq!(&flat[0..3], &[76, 127, 254]);
+      assert_eq!(quantize(&[Vector3::new(-1., 2., 1.)], &ThresholdMap::new(Dither::None), 255, 1), vec![0, 255, 255]);
+
+      // Up to the half a step between the thresholds of the tile (the 8 x 8
+      // Bayer matrix has 64)
+      for dither in [Dither::Bayer, Dither::BlueNoise] {
+         let levels = quantize(&pixels, &ThresholdMap::new(dither), 255, n_x);
+         for (c, value) in pixels[0].iter().enumerate() {
+            let mean = levels.iter().skip(c).step_by(3).map(|&s| s as f64).sum::<f64>() / (n_x * n_y) as f64;
+            assert!((mean - 255. * value).abs() <= 0.5 / 64., "channel {} averages {}", c, mean);
+         }
+      }
+   }
+}
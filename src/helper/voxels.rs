@@ -0,0 +1,125 @@
+/**
+ * A reader for density grids, in a small format of our own: a text header,
+ * one "key values" line each, ended by a line "data", then the voxels.
+ *
+ *    # smoke from the simulation, frame 40
+ *    dims 64 96 64
+ *    bounds -1 0 -1 1 3 1
+ *    channels density temperature
+ *    encoding binary
+ *    data
+ *
+ * "dims" (the number of voxels along x, y and z) is required. "bounds" are
+ * the two opposite corners of the box the grid fills, by default the unit
+ * cube from the origin. "channels" is "density" (the default) or "density
+ * temperature". With "encoding ascii" (the default) the voxels follow as
+ * whitespace separated numbers, with "encoding binary" as little-endian 32
+ * bit floats. Either way they go x first, then y, then z, with the channels
+ * of each voxel together. '#' starts a comment in the header.
+ */
+use std::{
+   fs::File,
+   io::Read
+};
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::VoxelGrid;
+
+
+pub fn read_voxels(path: &str) -> VoxelGrid
+{
+   let mut bytes = Vec::new();
+   File::open(path).unwrap_or_else(|_| panic!("Error opening voxel grid {}", path))
+      .read_to_end(&mut bytes).unwrap_or_else(|_| panic!("Error reading voxel grid {}", path));
+
+   let mut dims: Option<[usize; 3]> = None;
+   let mut min = Vector3::new(0., 0., 0.);
+   let mut max = Vector3::new(1., 1., 1.);
+   let mut n_channels = 1;
+   let mut binary = false;
+
+   // The header, line by line, up to "data"
+   let mut pos = 0;
+   let mut line_number = 0;
+   loop {
+      if pos >= bytes.len() {
+         panic!("Error: voxel grid {} has no \"data\" line", path);
+      }
+      let end = bytes[pos..].iter().position(|&b| b == b'\n').map(|k| pos + k).unwrap_or(bytes.len());
+      let line = String::from_utf8_lossy(&bytes[pos..end]).to_string();
+      pos = end + 1;
+      line_number += 1;
+
+      let line = line.split('#').next().unwrap().trim();
+      let tokens: Vec<&str> = line.split_whitespace().collect();
+      if tokens.is_empty() {
+         continue;
+      }
+      let numbers = || -> Vec<f64> {
+         return tokens[1..].iter().map(|token| token.parse().unwrap_or_else(|_| panic!("Error: bad number \"{}\" in {} line {}", token, path, line_number))).collect();
+      };
+      match tokens[0] {
+         "data" => break,
+         "dims" => {
+            let n = numbers();
+            if n.len() != 3 || n.iter().any(|&k| k < 1. || k.fract() != 0.) {
+               panic!("Error: \"dims\" in {} needs three whole numbers of voxels", path);
+            }
+            dims = Some([n[0] as usize, n[1] as usize, n[2] as usize]);
+         }
+         "bounds" => {
+            let b = numbers();
+            if b.len() != 6 {
+               panic!("Error: \"bounds\" in {} needs six numbers (two corners)", path);
+            }
+            min = Vector3::new(b[0], b[1], b[2]);
+            max = Vector3::new(b[3], b[4], b[5]);
+         }
+         "channels" => {
+            n_channels = match &tokens[1..] {
+               ["density"] => 1,
+               ["density", "temperature"] => 2,
+               _ => panic!("Error: \"channels\" in {} must be \"density\" or \"density temperature\"", path)
+            };
+         }
+         "encoding" => {
+            binary = match tokens.get(1) {
+               Some(&"ascii") => false,
+               Some(&"binary") => true,
+               _ => panic!("Error: \"encoding\" in {} must be \"ascii\" or \"binary\"", path)
+            };
+         }
+         other => panic!("Error: unknown key \"{}\" in {} line {}", other, path, line_number)
+      }
+   }
+   let dims = dims.unwrap_or_else(|| panic!("Error: voxel grid {} has no \"dims\"", path));
+
+   let count = dims[0] * dims[1] * dims[2] * n_channels;
+   let values: Vec<f64> = if binary {
+      let data = &bytes[pos.min(bytes.len())..];
+      if data.len() < 4 * count {
+         panic!("Error: voxel grid {} has {} bytes of data, not {}", path, data.len(), 4 * count);
+      }
+      data.chunks_exact(4).take(count)
+         .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+         .collect()
+   } else {
+      let text = String::from_utf8_lossy(&bytes[pos.min(bytes.len())..]).to_string();
+      let values: Vec<f64> = text.split_whitespace()
+         .map(|token| token.parse().unwrap_or_else(|_| panic!("Error: bad voxel value \"{}\" in {}", token, path)))
+         .collect();
+      if values.len() != count {
+         panic!("Error: voxel grid {} has {} values, not {}", path, values.len(), count);
+      }
+      values
+   };
+
+   let density: Vec<f64> = values.iter().step_by(n_channels).map(|&v| v.max(0.)).collect();
+   let temperature = if n_channels == 2 {
+      Some(values.iter().skip(1).step_by(2).map(|&v| v.max(0.)).collect())
+   } else {
+      None
+   };
+   return VoxelGrid::new(dims, &min, &max, density, temperature);
+}
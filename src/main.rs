@@ -1,19 +1,34 @@
 use nalgebra as na;
 use na::{ Vector3 };
 use rayon::prelude::*;
+use rand::{ Rng, SeedableRng };
+use rand::rngs::SmallRng;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::time::Instant;
 
 
 mod scene;
-use scene::{ Camera, Ray, Light };
+use scene::{ Camera, Ray, Light, AmbientLight, Medium };
 
 mod helper;
-use helper::{ write_ppm, setup_scene };
+use helper::{ write_images, setup_scene, setup_render, Options };
 
 mod geometry;
 use geometry::Object;
 
 mod render;
-use render::raycolor;
+use render::{ raycolor, path_trace, bidirectional, check_no_media, ambient_occlusion, aov_values, denoise, AreaLights, PhotonMap, PixelStats, RenderMode, Aov };
+
+mod numeric;
+
+mod noise;
+
+mod spectrum;
+use spectrum::{ sample_wavelength, wavelength_to_rgb };
+
+
+// Set by Ctrl-C during a progressive render: finish the pass and save
+static STOP: AtomicBool = AtomicBool::new(false);
 
 
 
@@ -22,19 +37,8 @@ fn main() {
 
    // Parse command line arguments:
    let args: Vec<String> = std::env::args().collect();
-   
-   let mut path = "./data/sphere-and-plane.json";      // default path
-   match args.len() {
-      2 => {
-         path = &args[1];
-      }
-      _ => {
-         println!("No path given, or too many args. Running on default path \
-         \"/data/sphere-and-plane.json\"\n");
-         println!("=======> To run raytracer, type: <=======\n\
-         >>> cargo run --release ./data/<json-file-name>.json");
-      }
-   }
+   let options = Options::parse(&args);
+   let path = options.path.as_str();
    let file = std::fs::File::open(path).expect("Invalid path given. Try\n\
    >>> cargo run --release ./data/<json-file-name>.json\n\n");
    let json: serde_json::Value = serde_json::from_reader(file).expect("Error: Check JSON format.");
@@ -47,45 +51,255 @@ fn main() {
    // Set up the scene:
    let mut cam = Camera::default();
    let mut lights: Vec<Box<dyn Light + Sync>> = Vec::default();
+   let mut ambient = AmbientLight::default();
    let mut objects: Vec<Box<dyn Object + Sync>> = Vec::default();
-   setup_scene(N_X, N_Y, &json, &mut cam, &mut lights, &mut objects);
-
-   // Initialize the image and add a lock so we can iterate in parallel:
-   let rgb_image = std::sync::Mutex::new(vec![0u8; 3 * N_X * N_Y]);
-
-   
-   // Iterate over each pixel (i, j) = (x, y) = (col, row):
-   // Outter for loop is parallelized
-   (0..N_Y).into_par_iter().for_each(|j|
-   // for j in 0..N_Y 
-   {
-      for i in 0..N_X 
+   let mut fog: Option<Medium> = None;
+   setup_scene(N_X, N_Y, &json, &mut cam, &mut lights, &mut ambient, &mut fog, &mut objects);
+   let mut settings = setup_render(&json);
+   options.apply(&mut settings);
+   let area_lights = AreaLights::new(&objects);
+   if settings.mode == RenderMode::Bidirectional {
+      check_no_media(fog.as_ref(), &objects);
+   }
+
+   // Initialize the samples of every pixel (from which the float, linear
+   // radiance, image is the mean) and the other images, and add a lock so we
+   // can iterate in parallel:
+   let stats = std::sync::Mutex::new(vec![PixelStats::default(); N_X * N_Y]);
+   let ao_image = std::sync::Mutex::new(vec![Vector3::new(0., 0., 0.); N_X * N_Y]);
+   let aov_buffers = std::sync::Mutex::new(vec![vec![Vector3::new(0., 0., 0.); N_X * N_Y]; settings.aovs.len()]);
+   // What the denoiser goes by
+   let guides = vec![Aov::Albedo, Aov::Normal, Aov::Depth];
+   let guide_buffers = std::sync::Mutex::new(vec![vec![Vector3::new(0., 0., 0.); N_X * N_Y];
+                                                  if settings.denoise { guides.len() } else { 0 }]);
+   // Light the bidirectional path tracer's light subpaths took straight to
+   // the camera, landing anywhere in the image, and how many were traced
+   let splats = std::sync::Mutex::new(vec![Vector3::new(0., 0., 0.); N_X * N_Y]);
+   let light_paths = AtomicUsize::new(0);
+
+
+   // Samples per pixel in each pass, and how many passes: all of them in one
+   // go, or one per pass when rendering progressively. Adaptive sampling may
+   // stop short of these. Progressive Whitted renders only have more than one
+   // pass to give the photons.
+   let photon_mapping = settings.photons > 0 && matches!(settings.mode, RenderMode::Whitted | RenderMode::PathTrace);
+   let total_samples = match settings.mode {
+      RenderMode::Whitted => if settings.adaptive || (settings.progressive && photon_mapping) { settings.samples } else { 1 },
+      RenderMode::PathTrace | RenderMode::Bidirectional => settings.samples,
+      RenderMode::AmbientOcclusion => settings.ao_samples
+   };
+   let (per_pass, n_passes) = if settings.progressive { (1, total_samples) } else { (total_samples, 1) };
+
+   // The first Ctrl-C lets the pass finish and saves, a second one quits
+   if settings.progressive {
+      ctrlc::set_handler(|| {
+         if STOP.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+         }
+         println!("\nStopping after this pass (Ctrl-C again to quit without saving)");
+      }).expect("Error setting the Ctrl-C handler");
+   }
+
+   // ./data/file-name.json ===> file-name
+   let img_path = std::path::Path::new(path).file_stem().and_then(|stem| stem.to_str())
+      .unwrap_or_else(|| panic!("Error: can't name the image after {}", path));
+   let save = || {
+      let stats = stats.lock().unwrap();
+      let mut radiance: Vec<Vector3<f64>> = stats.iter().map(|pixel| pixel.mean()).collect();
+      let light_paths = light_paths.load(Ordering::Relaxed);
+      if light_paths > 0 {
+         let scale = (N_X * N_Y) as f64 / light_paths as f64;
+         for (pixel, splat) in radiance.iter_mut().zip(splats.lock().unwrap().iter()) {
+            *pixel += scale * splat;
+         }
+      }
+      if settings.denoise {
+         let variance = stats.iter().map(|pixel| pixel.variance_of_mean()).collect::<Vec<f64>>();
+         let guide_buffers = guide_buffers.lock().unwrap();
+         radiance = denoise(&radiance, &variance, &guide_buffers[0], &guide_buffers[1], &guide_buffers[2],
+                            N_X, N_Y, settings.denoise_radius);
+      }
+      let sample_counts = stats.iter().map(|pixel| pixel.n).collect::<Vec<usize>>();
+      write_images(img_path, &settings, &radiance, &ao_image.lock().unwrap(), &aov_buffers.lock().unwrap(),
+                   &sample_counts, N_X, N_Y);
+   };
+
+   let start = Instant::now();
+   let mut last_snapshot = 0.;
+   let mut pass = 0;
+   // How much of the nearest photons' disk the next photon map gathers from
+   let mut photon_scale = 1.;
+   while pass < n_passes {
+      // The caustics' photons, shot again every pass (from the area lights
+      // too when path tracing, as only the path tracer sees them)
+      let photon_map = if photon_mapping {
+         let path = settings.mode == RenderMode::PathTrace;
+         let map = PhotonMap::shoot(settings.photons, &objects, &lights, if path { Some(&area_lights) } else { None },
+                                    fog.as_ref(), path && settings.spectral, settings.max_depth,
+                                    settings.photon_neighbors, settings.photon_radius, photon_scale, pass as u64);
+         if pass == 0 && map.is_empty() {
+            println!("No photons were stored: nothing in the scene focuses light from a point or area light");
+         } else if pass == 0 {
+            println!("Stored {} caustic photons, gathered from the {} nearest within {:.4}",
+                     map.len(), map.neighbors, map.max_radius);
+         }
+         photon_scale = map.next_scale(pass);
+         Some(map)
+      } else {
+         None
+      };
+      let photon_map = photon_map.as_ref();
+
+      // Whether any pixel still needed samples
+      let sampled = AtomicBool::new(false);
+
+      // Iterate over each pixel (i, j) = (x, y) = (col, row):
+      // Outter for loop is parallelized
+      (0..N_Y).into_par_iter().for_each(|j|
+      // for j in 0..N_Y 
       {
-         // Compute the viewing ray:
-         let ray = Ray::new(&cam, i, j, N_X, N_Y);
-
-         // Set the background color by default:
-         let mut rgb = Vector3::new(0., 0., 0.);
-
-         // Shoot a ray and collect the color:
-         raycolor(&ray, 1., &objects, &lights, 0, &mut rgb);
-         
-         // Define an anonymous function (lambda function or closure in Rust)
-         // to clamp the rgb values after adding lights from blinn_phong_shading
-         let clamp = |s: f64| -> f64 {
-            // return 0.0f64.max(s.min(1.0));
-            return f64::max(f64::min(s, 1.0), 0.0);
-         };
-
-         // Set the color
-         let mut rgb_image = rgb_image.lock().unwrap();
-         rgb_image[3 * (j * N_X + i) + 0] = (255.0 * clamp(rgb[0])) as u8;
-         rgb_image[3 * (j * N_X + i) + 1] = (255.0 * clamp(rgb[1])) as u8;
-         rgb_image[3 * (j * N_X + i) + 2] = (255.0 * clamp(rgb[2])) as u8;
+         // Seeded by row (and pass) so the image doesn't depend on how rows
+         // are scheduled
+         let mut rng = SmallRng::seed_from_u64((pass * N_Y + j) as u64);
+         // The row's light subpaths' light for other pixels
+         let mut row_splats: Vec<(usize, Vector3<f64>)> = Vec::new();
+         let mut row_light_paths = 0;
+
+         for i in 0..N_X 
+         {
+            // Compute the viewing ray:
+            let ray = Ray::new(&cam, i, j, N_X, N_Y);
+
+            // Take this pass's samples, or fewer once the pixel is good enough
+            let mut pixel = stats.lock().unwrap()[j * N_X + i];
+            let mut taken = 0;
+            while taken < per_pass && !(settings.adaptive && pixel.converged(settings.min_samples, settings.error_threshold)) {
+               // Set the background color by default:
+               let mut rgb = Vector3::new(0., 0., 0.);
+
+               match settings.mode {
+                  RenderMode::Whitted => {
+                     // Shoot a ray and collect the color. With adaptive
+                     // sampling the rays go through random points of the pixel.
+                     let ray = if settings.adaptive {
+                        let x = i as f64 + rng.gen::<f64>();
+                        let y = j as f64 + rng.gen::<f64>();
+                        Ray::through(&cam, x, y, N_X, N_Y)
+                     } else {
+                        Ray::new(&cam, i, j, N_X, N_Y)
+                     };
+                     raycolor(&ray, Camera::NEAR_PLANE, &objects, &lights, &ambient, fog.as_ref(), fog.as_ref(), photon_map, 0, &mut rng, &mut rgb);
+                  }
+                  RenderMode::PathTrace => {
+                     // A path through a random point of the pixel
+                     let x = i as f64 + rng.gen::<f64>();
+                     let y = j as f64 + rng.gen::<f64>();
+                     let ray = Ray::through(&cam, x, y, N_X, N_Y);
+                     if settings.spectral {
+                        // At a random wavelength, with the light found
+                        // turned back into a color
+                        let (lambda, pdf) = sample_wavelength(rng.gen());
+                        let radiance = path_trace(&ray, &objects, &lights, &area_lights, fog.as_ref(), photon_map, Some(lambda),
+                                                  settings.max_depth, &mut rng);
+                        rgb = wavelength_to_rgb(lambda, radiance.mean() / pdf);
+                     } else {
+                        rgb = path_trace(&ray, &objects, &lights, &area_lights, fog.as_ref(), photon_map, None,
+                                         settings.max_depth, &mut rng);
+                     }
+                  }
+                  RenderMode::Bidirectional => {
+                     let x = i as f64 + rng.gen::<f64>();
+                     let y = j as f64 + rng.gen::<f64>();
+                     let ray = Ray::through(&cam, x, y, N_X, N_Y);
+                     if settings.spectral {
+                        let (lambda, pdf) = sample_wavelength(rng.gen());
+                        let radiance = bidirectional(&ray, &objects, &lights, &area_lights, &cam, N_X, N_Y, Some(lambda),
+                                                     settings.max_depth, &mut rng, &mut |pixel, light| {
+                           row_splats.push((pixel, wavelength_to_rgb(lambda, light.mean() / pdf)));
+                        });
+                        rgb = wavelength_to_rgb(lambda, radiance.mean() / pdf);
+                     } else {
+                        rgb = bidirectional(&ray, &objects, &lights, &area_lights, &cam, N_X, N_Y, None,
+                                            settings.max_depth, &mut rng, &mut |pixel, light| row_splats.push((pixel, light)));
+                     }
+                     row_light_paths += 1;
+                  }
+                  RenderMode::AmbientOcclusion => {
+                     let ao = ambient_occlusion(&ray, &objects, 1, settings.ao_distance, &mut rng);
+                     rgb = Vector3::new(ao, ao, ao);
+                  }
+               }
+
+               // Add the color, as is: clamping (if any) happens when the
+               // image is saved
+               pixel.add(&rgb);
+               taken += 1;
+            }
+            stats.lock().unwrap()[j * N_X + i] = pixel;
+            if taken > 0 {
+               sampled.store(true, Ordering::Relaxed);
+            }
+
+            // The other passes are done once, in the first pass
+            if pass > 0 {
+               continue;
+            }
+
+            // The separate occlusion image, as gray
+            if settings.ao_pass {
+               let ao = ambient_occlusion(&ray, &objects, settings.ao_samples, settings.ao_distance, &mut rng);
+               ao_image.lock().unwrap()[j * N_X + i] = Vector3::new(ao, ao, ao);
+            }
+
+            // And the raw values of the other passes
+            if !settings.aovs.is_empty() {
+               let values = aov_values(&ray, &objects, &lights, &settings.aovs);
+               let mut aov_buffers = aov_buffers.lock().unwrap();
+               for (k, value) in values.into_iter().enumerate() {
+                  aov_buffers[k][j * N_X + i] = value;
+               }
+            }
+            if settings.denoise {
+               let values = aov_values(&ray, &objects, &lights, &guides);
+               let mut guide_buffers = guide_buffers.lock().unwrap();
+               for (k, value) in values.into_iter().enumerate() {
+                  guide_buffers[k][j * N_X + i] = value;
+               }
+            }
+         }
+
+         if row_light_paths > 0 {
+            let mut splats = splats.lock().unwrap();
+            for (pixel, light) in row_splats {
+               splats[pixel] += light;
+            }
+            light_paths.fetch_add(row_light_paths, Ordering::Relaxed);
+         }
+      }); 
+      if !sampled.load(Ordering::Relaxed) {
+         // Every pixel is done
+         break;
+      }
+      pass += 1;
+
+      let elapsed = start.elapsed().as_secs_f64();
+      if pass == n_passes || elapsed >= settings.time_limit || STOP.load(Ordering::SeqCst) {
+         break;
       }
-   }); 
+      if (settings.snapshot_passes > 0 && pass % settings.snapshot_passes == 0)
+         || elapsed - last_snapshot >= settings.snapshot_seconds {
+         save();
+         println!("Saved a snapshot after {} of {} passes ({:.1} s)", pass, n_passes, elapsed);
+         last_snapshot = elapsed;
+      }
+   }
 
-   let img_path = &path[7..path.len()-5];       // ./data/file-name.json ===> file-name
-   let rgb_image = rgb_image.lock().unwrap();
-   write_ppm(format!("./images/{}.ppm", img_path).as_str(), &rgb_image, N_X, N_Y, 3);
+   save();
+   if settings.progressive {
+      println!("Rendered {} of {} passes in {:.1} s", pass, n_passes, start.elapsed().as_secs_f64());
+   }
+   if settings.adaptive {
+      let taken: usize = stats.lock().unwrap().iter().map(|pixel| pixel.n).sum();
+      println!("Took {:.1} samples per pixel on average", taken as f64 / (N_X * N_Y) as f64);
+   }
 }
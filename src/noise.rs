@@ -0,0 +1,110 @@
+/*!
+ * Procedural noise for generated content (e.g. heightfield terrain).
+ *
+ * value_noise is lattice value noise: a pseudo-random value at every integer
+ * point, smoothly interpolated in between. fractal_noise sums octaves of it
+ * at increasing frequency and decreasing amplitude (fractional Brownian
+ * motion), which is what gives terrain its rough-at-every-scale look.
+ * Everything is a pure function of the inputs and the seed, so the same
+ * scene always renders the same.
+ */
+
+// Hash a lattice point and a seed to a value in [0, 1)
+fn lattice_value(x: i64, y: i64, seed: u64) -> f64
+{
+   // A few rounds of multiply and xorshift (the splitmix64 finalizer)
+   let mut h = seed
+      .wrapping_add((x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+      .wrapping_add((y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f));
+   h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+   h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+   h ^= h >> 31;
+   return (h >> 11) as f64 / (1u64 << 53) as f64;
+}
+
+
+// 6t^5 - 15t^4 + 10t^3: zero first and second derivatives at 0 and 1, so
+// the noise has no visible creases along the lattice lines
+fn fade(t: f64) -> f64
+{
+   return t * t * t * (t * (t * 6. - 15.) + 10.);
+}
+
+
+// Smooth noise in [0, 1] with features about one unit apart
+pub fn value_noise(x: f64, y: f64, seed: u64) -> f64
+{
+   let (x0, y0) = (x.floor(), y.floor());
+   let (ix, iy) = (x0 as i64, y0 as i64);
+   let (u, v) = (fade(x - x0), fade(y - y0));
+
+   let v00 = lattice_value(ix, iy, seed);
+   let v10 = lattice_value(ix + 1, iy, seed);
+   let v01 = lattice_value(ix, iy + 1, seed);
+   let v11 = lattice_value(ix + 1, iy + 1, seed);
+   let bottom = v00 + u * (v10 - v00);
+   let top = v01 + u * (v11 - v01);
+   return bottom + v * (top - bottom);
+}
+
+
+// <octaves> layers of value noise, each <lacunarity> times the frequency and
+// <gain> times the amplitude of the one before. Normalized back to [0, 1].
+pub fn fractal_noise(x: f64, y: f64, seed: u64, octaves: usize, lacunarity: f64, gain: f64) -> f64
+{
+   let mut sum = 0.;
+   let mut total_amplitude = 0.;
+   let mut amplitude = 1.;
+   let mut frequency = 1.;
+   for octave in 0..octaves {
+      // Give every octave its own lattice so they don't line up at the origin
+      sum += amplitude * value_noise(frequency * x, frequency * y, seed.wrapping_add(octave as u64));
+      total_amplitude += amplitude;
+      amplitude *= gain;
+      frequency *= lacunarity;
+   }
+   return sum / total_amplitude;
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn value_noise_goes_through_the_lattice_values()
+   {
+      for &(x, y) in [(0, 0), (3, -2), (-7, 11)].iter() {
+         let at_point = value_noise(x as f64, y as f64, 5);
+         assert_eq!(at_point, lattice_value(x, y, 5));
+         assert!((0. ..1.).contains(&at_point));
+      }
+   }
+
+   #[test]
+   fn value_noise_is_smooth_and_repeatable()
+   {
+      let mut previous = value_noise(0., 0.3, 1);
+      for i in 1..1000 {
+         let x = i as f64 * 1e-3 * 2.7;
+         let value = value_noise(x, 0.3, 1);
+         // The fade curve's slope is at most 15/8, per unit of lattice
+         // difference (at most 1)
+         assert!((value - previous).abs() <= 2. * 2.7e-3);
+         assert_eq!(value, value_noise(x, 0.3, 1));
+         previous = value;
+      }
+      assert_ne!(value_noise(0.5, 0.5, 1), value_noise(0.5, 0.5, 2));
+   }
+
+   #[test]
+   fn fractal_noise_stays_in_range()
+   {
+      assert_eq!(fractal_noise(1.3, 2.9, 4, 1, 2., 0.5), value_noise(1.3, 2.9, 4));
+      for i in 0..500 {
+         let (x, y) = (i as f64 * 0.137, i as f64 * 0.071);
+         let value = fractal_noise(x, y, 9, 6, 2., 0.5);
+         assert!((0. ..=1.).contains(&value), "noise {} out of range", value);
+      }
+   }
+}
@@ -0,0 +1,435 @@
+/*!
+ * Real roots of low degree polynomials, for surfaces whose ray intersection
+ * reduces to one (e.g. the quartic for a torus).
+ *
+ * Every solver writes the real roots into <roots> in increasing order and
+ * returns how many there are. Repeated roots are only reported once.
+ * The cubic follows the closed-form solution from "Solving Quartics and
+ * Cubics for Graphics" (Graphics Gems I), with each root refined by a few
+ * Newton steps since the closed form loses precision when the coefficients
+ * differ by orders of magnitude. See solve_quartic for the quartic.
+ */
+
+// Anything smaller than this is treated as zero
+const EPSILON: f64 = 1.0e-12;
+
+
+fn is_zero(x: f64) -> bool
+{
+   return x.abs() < EPSILON;
+}
+
+
+// Sort the first <n> roots and drop duplicates, returning the new count
+fn sort_roots(roots: &mut [f64], n: usize) -> usize
+{
+   roots[..n].sort_by(|a, b| a.total_cmp(b));
+   let mut count = 0;
+   for i in 0..n {
+      if count == 0 || roots[i] != roots[count - 1] {
+         roots[count] = roots[i];
+         count += 1;
+      }
+   }
+   return count;
+}
+
+
+// Evaluate the polynomial with <coeffs> (highest degree first) and its
+// derivative at <x>, with Horner's method
+fn evaluate(coeffs: &[f64], x: f64) -> (f64, f64)
+{
+   let mut f = 0.;
+   let mut df = 0.;
+   for &c in coeffs.iter() {
+      df = df * x + f;
+      f = f * x + c;
+   }
+   return (f, df);
+}
+
+
+// Refine <x> as a root of the polynomial with <coeffs> (highest degree first)
+fn polish_root(coeffs: &[f64], x: f64) -> f64
+{
+   let mut x = x;
+   for _ in 0..4 {
+      let (f, df) = evaluate(coeffs, x);
+      if df == 0. {
+         break;
+      }
+      let step = f / df;
+      x -= step;
+      if step.abs() <= EPSILON * x.abs().max(1.) {
+         break;
+      }
+   }
+   return x;
+}
+
+
+// Find the root of the polynomial in [lo, hi], where it changes sign exactly
+// once. Newton steps, falling back to bisection whenever Newton would leave
+// the bracket.
+fn bracketed_root(coeffs: &[f64], lo: f64, hi: f64) -> f64
+{
+   let mut lo = lo;
+   let mut hi = hi;
+   let lo_negative = evaluate(coeffs, lo).0 < 0.;
+   let mut x = 0.5 * (lo + hi);
+   for _ in 0..100 {
+      let (f, df) = evaluate(coeffs, x);
+      if f == 0. {
+         break;
+      }
+      // Shrink the bracket
+      if (f < 0.) == lo_negative {
+         lo = x;
+      } else {
+         hi = x;
+      }
+
+      let mut next = x - f / df;
+      if !(next > lo && next < hi) {
+         next = 0.5 * (lo + hi);
+      }
+      if (next - x).abs() <= EPSILON * x.abs().max(1.) {
+         x = next;
+         break;
+      }
+      x = next;
+   }
+   return x;
+}
+
+
+// a x^2 + b x + c = 0
+pub fn solve_quadratic(a: f64, b: f64, c: f64, roots: &mut [f64; 2]) -> usize
+{
+   if a == 0. {
+      // Degenerates to a line
+      if b == 0. {
+         return 0;
+      }
+      roots[0] = -c / b;
+      return 1;
+   }
+
+   let discriminant = b * b - 4. * a * c;
+   if discriminant < 0. {
+      return 0;
+   }
+   if discriminant == 0. {
+      roots[0] = -0.5 * b / a;
+      return 1;
+   }
+
+   // Avoid the cancellation in -b + sqrt(discriminant) when b is large
+   let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+   if q == 0. {
+      // b == 0 and c == 0
+      roots[0] = 0.;
+      return 1;
+   }
+   roots[0] = q / a;
+   roots[1] = c / q;
+   return sort_roots(roots, 2);
+}
+
+
+// x^3 + a x^2 + b x + c = 0
+pub fn solve_cubic(a: f64, b: f64, c: f64, roots: &mut [f64; 3]) -> usize
+{
+   // Substitute x = y - a/3 to get the depressed cubic y^3 + p y + q = 0
+   let p = b - a * a / 3.;
+   let q = 2. * a * a * a / 27. - a * b / 3. + c;
+
+   let p3 = p / 3.;
+   let q2 = q / 2.;
+   let discriminant = q2 * q2 + p3 * p3 * p3;
+
+   let mut n;
+   if is_zero(discriminant) {
+      if is_zero(q) {
+         // One triple root
+         roots[0] = 0.;
+         n = 1;
+      } else {
+         // One single and one double root
+         let u = (-q2).cbrt();
+         roots[0] = 2. * u;
+         roots[1] = -u;
+         n = 2;
+      }
+   } else if discriminant < 0. {
+      // Three real roots (casus irreducibilis), use the trigonometric form
+      let phi = (-q2 / (-p3 * p3 * p3).sqrt()).clamp(-1., 1.).acos() / 3.;
+      let m = 2. * (-p3).sqrt();
+      roots[0] = m * phi.cos();
+      roots[1] = -m * (phi + std::f64::consts::PI / 3.).cos();
+      roots[2] = -m * (phi - std::f64::consts::PI / 3.).cos();
+      n = 3;
+   } else {
+      // One real root
+      let sqrt_disc = discriminant.sqrt();
+      roots[0] = (sqrt_disc - q2).cbrt() - (sqrt_disc + q2).cbrt();
+      n = 1;
+   }
+
+   // Undo the substitution
+   for root in roots[..n].iter_mut() {
+      *root = polish_root(&[1., a, b, c], *root - a / 3.);
+   }
+   n = sort_roots(roots, n);
+   return n;
+}
+
+
+// x^4 + a x^3 + b x^2 + c x + d = 0
+//
+// The closed form is too fragile near double roots (e.g. rays grazing a
+// torus), so instead the roots are isolated: between two neighbouring
+// critical points (roots of the derivative, a cubic) the quartic is monotonic,
+// so it has a root there exactly when it changes sign, and that root can be
+// found with a safeguarded Newton iteration. Critical points where the
+// quartic is zero (up to round-off) are double roots.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, roots: &mut [f64; 4]) -> usize
+{
+   let coeffs = [1., a, b, c, d];
+
+   // Every root lies within this (Cauchy) bound
+   let bound = 1. + a.abs().max(b.abs()).max(c.abs()).max(d.abs());
+
+   // Critical points, from the derivative 4 x^3 + 3a x^2 + 2b x + c
+   let mut critical = [0.; 3];
+   let n_critical = solve_cubic(0.75 * a, 0.5 * b, 0.25 * c, &mut critical);
+
+   let mut points = [0.; 5];
+   points[0] = -bound;
+   for i in 0..n_critical {
+      points[i + 1] = critical[i].clamp(-bound, bound);
+   }
+   points[n_critical + 1] = bound;
+   let n_points = n_critical + 2;
+
+   let mut n = 0;
+   for i in 0..n_points - 1 {
+      let (lo, hi) = (points[i], points[i + 1]);
+      let f_lo = evaluate(&coeffs, lo).0;
+      let f_hi = evaluate(&coeffs, hi).0;
+
+      // Double root at a critical point: zero compared to the size of the terms
+      if i > 0 {
+         let scale = lo.abs().max(1.).powi(4) * (1. + a.abs() + b.abs() + c.abs() + d.abs());
+         if f_lo.abs() <= 1.0e-14 * scale && n < 4 {
+            roots[n] = lo;
+            n += 1;
+            continue;
+         }
+      }
+
+      if (f_lo < 0.) != (f_hi < 0.) && n < 4 {
+         roots[n] = bracketed_root(&coeffs, lo, hi);
+         n += 1;
+      }
+   }
+
+   n = sort_roots(roots, n);
+   return n;
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Check the first <n> of <roots> against <expected>, in order
+   fn assert_roots(roots: &[f64], n: usize, expected: &[f64], tolerance: f64)
+   {
+      assert_eq!(n, expected.len(), "roots {:?}, expected {:?}", &roots[..n], expected);
+      for (root, want) in roots[..n].iter().zip(expected.iter()) {
+         assert!((root - want).abs() <= tolerance, "root {} != {} (roots {:?})", root, want, &roots[..n]);
+      }
+   }
+
+   // Coefficients (after the leading 1) of the monic polynomial with <roots>
+   fn from_roots(roots: &[f64]) -> Vec<f64>
+   {
+      let mut coeffs = vec![1.];
+      for root in roots.iter() {
+         let mut next = coeffs.clone();
+         next.push(0.);
+         for (i, c) in coeffs.iter().enumerate() {
+            next[i + 1] -= root * c;
+         }
+         coeffs = next;
+      }
+      return coeffs[1..].to_vec();
+   }
+
+   #[test]
+   fn quadratic_two_roots()
+   {
+      let mut roots = [0.; 2];
+      let n = solve_quadratic(2., -2., -12., &mut roots);
+      assert_roots(&roots, n, &[-2., 3.], 1e-12);
+   }
+
+   #[test]
+   fn quadratic_double_root()
+   {
+      let mut roots = [0.; 2];
+      let n = solve_quadratic(1., -4., 4., &mut roots);
+      assert_roots(&roots, n, &[2.], 1e-12);
+   }
+
+   #[test]
+   fn quadratic_no_real_roots()
+   {
+      let mut roots = [0.; 2];
+      assert_eq!(solve_quadratic(1., 0., 1., &mut roots), 0);
+      assert_eq!(solve_quadratic(1., 2., 1.0001, &mut roots), 0);
+   }
+
+   #[test]
+   fn quadratic_root_near_zero()
+   {
+      // (x - 1e-9)(x - 1e3): the naive formula cancels the small root away
+      let mut roots = [0.; 2];
+      let n = solve_quadratic(1., -(1e3 + 1e-9), 1e-6, &mut roots);
+      assert_roots(&roots, n, &[1e-9, 1e3], 1e-12);
+      assert!((roots[0] - 1e-9).abs() <= 1e-12 * 1e-9);
+   }
+
+   #[test]
+   fn quadratic_degenerate_to_line()
+   {
+      let mut roots = [0.; 2];
+      let n = solve_quadratic(0., 2., -1., &mut roots);
+      assert_roots(&roots, n, &[0.5], 1e-15);
+   }
+
+   #[test]
+   fn cubic_three_roots()
+   {
+      let c = from_roots(&[-1., 0.5, 4.]);
+      let mut roots = [0.; 3];
+      let n = solve_cubic(c[0], c[1], c[2], &mut roots);
+      assert_roots(&roots, n, &[-1., 0.5, 4.], 1e-12);
+   }
+
+   #[test]
+   fn cubic_double_root()
+   {
+      let c = from_roots(&[1., 1., -2.]);
+      let mut roots = [0.; 3];
+      let n = solve_cubic(c[0], c[1], c[2], &mut roots);
+      assert_roots(&roots, n, &[-2., 1.], 1e-7);
+   }
+
+   #[test]
+   fn cubic_triple_root()
+   {
+      let c = from_roots(&[3., 3., 3.]);
+      let mut roots = [0.; 3];
+      let n = solve_cubic(c[0], c[1], c[2], &mut roots);
+      assert_roots(&roots, n, &[3.], 1e-5);
+   }
+
+   #[test]
+   fn cubic_one_real_root()
+   {
+      // (x - 2)(x^2 + 1)
+      let mut roots = [0.; 3];
+      let n = solve_cubic(-2., 1., -2., &mut roots);
+      assert_roots(&roots, n, &[2.], 1e-12);
+   }
+
+   #[test]
+   fn cubic_root_near_zero()
+   {
+      let c = from_roots(&[-3., 1e-10, 2.]);
+      let mut roots = [0.; 3];
+      let n = solve_cubic(c[0], c[1], c[2], &mut roots);
+      assert_roots(&roots, n, &[-3., 1e-10, 2.], 1e-12);
+      assert!((roots[1] - 1e-10).abs() <= 1e-6 * 1e-10);
+   }
+
+   #[test]
+   fn quartic_four_roots()
+   {
+      let c = from_roots(&[-2., -0.5, 1., 3.]);
+      let mut roots = [0.; 4];
+      let n = solve_quartic(c[0], c[1], c[2], c[3], &mut roots);
+      assert_roots(&roots, n, &[-2., -0.5, 1., 3.], 1e-12);
+   }
+
+   #[test]
+   fn quartic_double_roots()
+   {
+      let c = from_roots(&[-1., -1., 2., 2.]);
+      let mut roots = [0.; 4];
+      let n = solve_quartic(c[0], c[1], c[2], c[3], &mut roots);
+      assert_roots(&roots, n, &[-1., 2.], 1e-7);
+
+      // A double root next to two simple ones
+      let c = from_roots(&[0.5, 0.5, 1.5, 4.]);
+      let n = solve_quartic(c[0], c[1], c[2], c[3], &mut roots);
+      assert_roots(&roots, n, &[0.5, 1.5, 4.], 1e-7);
+   }
+
+   #[test]
+   fn quartic_no_real_roots()
+   {
+      let mut roots = [0.; 4];
+      // x^4 + 1
+      assert_eq!(solve_quartic(0., 0., 0., 1., &mut roots), 0);
+      // (x^2 + 1)(x^2 - 2x + 2)
+      assert_eq!(solve_quartic(-2., 3., -2., 2., &mut roots), 0);
+   }
+
+   #[test]
+   fn quartic_root_near_zero()
+   {
+      let c = from_roots(&[-4., 1e-9, 1., 2.5]);
+      let mut roots = [0.; 4];
+      let n = solve_quartic(c[0], c[1], c[2], c[3], &mut roots);
+      assert_roots(&roots, n, &[-4., 1e-9, 1., 2.5], 1e-12);
+      assert!((roots[1] - 1e-9).abs() <= 1e-6 * 1e-9);
+   }
+
+   // The quartic for a ray along x at height <y>, starting at x = -2, through
+   // a torus around the y axis (major radius 1, minor radius 0.25)
+   fn torus_ray(y: f64) -> [f64; 4]
+   {
+      let (major, minor) = (1_f64, 0.25_f64);
+      // |p|^2 + R^2 - r^2 = t^2 - 4t + k and x = t - 2
+      let k = 4. + y * y + major * major - minor * minor;
+      // (t^2 - 4t + k)^2 - 4 R^2 (t - 2)^2
+      let r2 = major * major;
+      return [-8., 16. + 2. * k - 4. * r2, -8. * k + 16. * r2, k * k - 16. * r2];
+   }
+
+   #[test]
+   fn quartic_grazing_torus()
+   {
+      // Just under the top of the tube, the ray is inside it for a short span
+      // around x = -1 and x = 1
+      let y = 0.25 - 1e-7;
+      let half_span = (0.25_f64 * 0.25 - y * y).sqrt();
+      let c = torus_ray(y);
+      let mut roots = [0.; 4];
+      let n = solve_quartic(c[0], c[1], c[2], c[3], &mut roots);
+      let expected = [1. - half_span, 1. + half_span, 3. - half_span, 3. + half_span];
+      assert_roots(&roots, n, &expected, 1e-9);
+
+      // Just over the top it misses
+      let c = torus_ray(0.25 + 1e-7);
+      assert_eq!(solve_quartic(c[0], c[1], c[2], c[3], &mut roots), 0);
+
+      // Through the middle of the tube
+      let c = torus_ray(0.);
+      let n = solve_quartic(c[0], c[1], c[2], c[3], &mut roots);
+      assert_roots(&roots, n, &[0.75, 1.25, 2.75, 3.25], 1e-12);
+   }
+}
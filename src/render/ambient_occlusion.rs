@@ -0,0 +1,103 @@
+// for the capital P
+#![allow(non_snake_case)]
+
+use nalgebra as na;
+use na::{ Vector3 };
+use rand::Rng;
+use rand::rngs::SmallRng;
+
+use crate::scene::{ Ray, Camera };
+use crate::scene::bsdf::{ facing, cosine_sample_hemisphere };
+use crate::geometry::Object;
+use crate::render::first_hit;
+
+
+// Fraction of <samples> cosine weighted rays from <P>, around the unit normal
+// <n>, that get further than <max_distance> without hitting anything
+pub fn visibility(P: &Vector3<f64>, n: &Vector3<f64>, samples: usize, max_distance: f64,
+                  objects: &Vec<Box<dyn Object + Sync>>, rng: &mut SmallRng) -> f64
+{
+   let mut open = 0;
+   let mut s_hit_id: usize = 0;
+   let mut s_t: f64 = 0.;
+   let mut s_n = Vector3::new(0.,0.,0.);
+   for _ in 0..samples {
+      let direction = cosine_sample_hemisphere(n, rng.gen(), rng.gen());
+      let aoray = Ray{origin: *P, direction};
+      if !first_hit(&aoray, 1.0e-6, objects, &mut s_hit_id, &mut s_t, &mut s_n) || s_t >= max_distance {
+         open += 1;
+      }
+   }
+   return open as f64 / samples as f64;
+}
+
+
+/**
+ * The ambient occlusion pass: the visibility of the hemisphere on the
+ * viewer's side of the first surface along <ray>, from 0 (fully enclosed)
+ * to 1 (nothing nearby). Rays that hit nothing count as fully open.
+ */
+pub fn ambient_occlusion(ray: &Ray, objects: &Vec<Box<dyn Object + Sync>>, samples: usize, max_distance: f64,
+                         rng: &mut SmallRng) -> f64
+{
+   let mut n = Vector3::new(0.,0.,0.);
+   let mut t = 0.;
+   let mut hit_id = 0usize;
+   if !first_hit(ray, Camera::NEAR_PLANE, objects, &mut hit_id, &mut t, &mut n) {
+      return 1.;
+   }
+   let P = ray.origin + t * ray.direction;
+   let n = facing(&n.normalize(), &-ray.direction.normalize());
+   return visibility(&P, &n, samples, max_distance, objects, rng);
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use rand::SeedableRng;
+   use crate::scene::Material;
+   use crate::geometry::Plane;
+
+   fn plane(p: Vector3<f64>, n: Vector3<f64>) -> Box<dyn Object + Sync>
+   {
+      return Box::new(Plane::new(&p, &n, Material::gray()));
+   }
+
+   #[test]
+   fn a_wall_hides_half_the_sky()
+   {
+      // Standing on the floor, right next to a wall at x = 0.01
+      let objects = vec![plane(Vector3::zeros(), Vector3::new(0., 1., 0.)),
+                         plane(Vector3::new(0.01, 0., 0.), Vector3::new(-1., 0., 0.))];
+      let mut rng = SmallRng::seed_from_u64(1);
+      let up = Vector3::new(0., 1., 0.);
+      let open = visibility(&Vector3::zeros(), &up, 20000, f64::INFINITY, &objects, &mut rng);
+      assert!((open - 0.5).abs() < 0.02);
+      // Within 0.02 only rays with x > 0.5 reach the wall. Cosine weighted
+      // directions are spread evenly over the disk below them, so that's the
+      // part of the unit disk past x = 0.5.
+      let open = visibility(&Vector3::zeros(), &up, 20000, 0.02, &objects, &mut rng);
+      let segment = (f64::acos(0.5) - 0.5 * f64::sqrt(0.75)) / std::f64::consts::PI;
+      assert!((open - (1. - segment)).abs() < 0.02);
+      // Nothing blocks the wall's own side
+      let open = visibility(&Vector3::new(0.01, 0.5, 0.), &Vector3::new(-1., 0., 0.), 1000, 0.4, &objects, &mut rng);
+      assert_eq!(open, 1.);
+   }
+
+   #[test]
+   fn the_pass_looks_from_the_viewers_side()
+   {
+      let objects = vec![plane(Vector3::zeros(), Vector3::new(0., 1., 0.)),
+                         plane(Vector3::new(0., 1., 0.), Vector3::new(0., 1., 0.))];
+      let mut rng = SmallRng::seed_from_u64(1);
+      // Looking up at the ceiling from between the planes (through its back).
+      // Like camera rays, the ray only counts from t = 1.
+      let ray = Ray { origin: Vector3::new(0., 0.5, 0.), direction: Vector3::new(0., 0.25, 0.) };
+      assert_eq!(ambient_occlusion(&ray, &objects, 64, f64::INFINITY, &mut rng), 0.);
+      assert_eq!(ambient_occlusion(&ray, &objects, 64, 0.5, &mut rng), 1.);
+      // Nothing there at all
+      let ray = Ray { origin: Vector3::new(0., 0.5, 0.), direction: Vector3::new(1., 0., 0.) };
+      assert_eq!(ambient_occlusion(&ray, &objects, 64, f64::INFINITY, &mut rng), 1.);
+   }
+}
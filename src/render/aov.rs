@@ -0,0 +1,258 @@
+// for the capital P
+#![allow(non_snake_case)]
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::{ Ray, Camera, Light };
+use crate::geometry::Object;
+use crate::render::{ first_hit, first_hit_material };
+
+
+// Arbitrary output variables: images of what the camera rays hit, rather
+// than its color, written next to the render for compositing and debugging
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+   // Distance t along the camera ray
+   Depth,
+   // World space unit normal
+   Normal,
+   // World space hit point
+   Position,
+   // The material's base color
+   Albedo,
+   // Surface (u, v) coordinates
+   Uv,
+   // Index of the hit object, as first_hit() reports it
+   ObjectId,
+   // Index of the material in the scene's "materials" array
+   MaterialId,
+   // Fraction of the lights that aren't blocked
+   Shadow
+}
+
+
+impl Aov {
+   // The pass called <name> in scene files and on the command line
+   pub fn from_name(name: &str) -> Aov
+   {
+      return match name {
+         "depth" => Aov::Depth,
+         "normal" => Aov::Normal,
+         "position" => Aov::Position,
+         "albedo" => Aov::Albedo,
+         "uv" => Aov::Uv,
+         "object_id" => Aov::ObjectId,
+         "material_id" => Aov::MaterialId,
+         "shadow" => Aov::Shadow,
+         _ => panic!("Error: unknown AOV \"{}\" (expected depth, normal, position, albedo, uv, object_id, material_id or shadow)", name)
+      };
+   }
+
+   pub fn name(&self) -> &str
+   {
+      return match self {
+         Aov::Depth => "depth",
+         Aov::Normal => "normal",
+         Aov::Position => "position",
+         Aov::Albedo => "albedo",
+         Aov::Uv => "uv",
+         Aov::ObjectId => "object_id",
+         Aov::MaterialId => "material_id",
+         Aov::Shadow => "shadow"
+      };
+   }
+
+   // The value for camera rays that hit nothing. NaN marks passes with no
+   // sensible value there.
+   fn background(&self) -> Vector3<f64>
+   {
+      return match self {
+         Aov::Depth => Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+         Aov::Normal | Aov::Position => Vector3::new(f64::NAN, f64::NAN, f64::NAN),
+         Aov::ObjectId | Aov::MaterialId => Vector3::new(-1., -1., -1.),
+         Aov::Shadow => Vector3::new(1., 1., 1.),
+         _ => Vector3::new(0., 0., 0.)
+      };
+   }
+}
+
+
+// Fraction of <lights> that reach <P> unblocked
+fn unshadowed(P: &Vector3<f64>, objects: &Vec<Box<dyn Object + Sync>>, lights: &Vec<Box<dyn Light + Sync>>) -> f64
+{
+   if lights.is_empty() {
+      return 1.;
+   }
+   let mut lit = 0;
+   let mut l = Vector3::new(0.,0.,0.);
+   let mut s_hit_id: usize = 0;
+   let mut s_t: f64 = 0.;
+   let mut s_n = Vector3::new(0.,0.,0.);
+   for light in lights.iter() {
+      let mut max_t = 0.;
+      light.direction(P, &mut l, &mut max_t);
+      let sray = Ray{origin: *P, direction: l};
+      if !first_hit(&sray, 1.0e-6, objects, &mut s_hit_id, &mut s_t, &mut s_n) || s_t >= max_t {
+         lit += 1;
+      }
+   }
+   return lit as f64 / lights.len() as f64;
+}
+
+
+/**
+ * The raw value of every pass in <aovs> for the camera <ray>, in the same
+ * order. Scalar passes (depth, ids, shadow) repeat their value in all three
+ * channels.
+ */
+pub fn aov_values(ray: &Ray, objects: &Vec<Box<dyn Object + Sync>>, lights: &Vec<Box<dyn Light + Sync>>,
+                  aovs: &[Aov]) -> Vec<Vector3<f64>>
+{
+   let mut n = Vector3::new(0.,0.,0.);
+   let mut t = 0.;
+   let mut hit_id = 0usize;
+   let material = match first_hit_material(ray, Camera::NEAR_PLANE, objects, &mut hit_id, &mut t, &mut n) {
+      Some(material) => material,
+      None => return aovs.iter().map(|aov| aov.background()).collect()
+   };
+
+   let P = ray.origin + t * ray.direction;
+   let scalar = |s: f64| Vector3::new(s, s, s);
+   return aovs.iter().map(|aov| match aov {
+      Aov::Depth => scalar(t),
+      Aov::Normal => n.normalize(),
+      Aov::Position => P,
+      Aov::Albedo => material.bsdf().albedo(),
+      Aov::Uv => {
+         let (u, v) = objects[hit_id].uv(&P);
+         Vector3::new(u, v, 0.)
+      }
+      Aov::ObjectId => scalar(hit_id as f64),
+      Aov::MaterialId => scalar(material.id as f64),
+      Aov::Shadow => scalar(unshadowed(&P, objects, lights))
+   }).collect();
+}
+
+
+// A bright color that's easy to tell from its neighbours for index <id>,
+// black for -1 (nothing): hues spaced by the golden ratio
+fn id_color(id: f64) -> Vector3<f64>
+{
+   if id < 0. {
+      return Vector3::new(0., 0., 0.);
+   }
+   let hue = 6. * (id * 0.618033988749895).fract();
+   let ramp = |offset: f64| (((hue + offset) % 6. - 3.).abs() - 1.).clamp(0., 1.);
+   return 0.2 * Vector3::new(1., 1., 1.) + 0.8 * Vector3::new(ramp(0.), ramp(4.), ramp(2.));
+}
+
+
+// The finite values of channel <c> of <values>, sorted
+fn sorted_channel(values: &Vec<Vector3<f64>>, c: usize) -> Vec<f64>
+{
+   let mut finite: Vec<f64> = values.iter().map(|value| value[c]).filter(|x| x.is_finite()).collect();
+   finite.sort_by(|a, b| a.partial_cmp(b).unwrap());
+   return finite;
+}
+
+
+/**
+ * Turn the raw values of one pass into viewable colors in [0, 1]. Depth
+ * goes from black at the camera through gray at the median depth to white
+ * far away (and for misses), so a few distant hits, say on a plane at a
+ * grazing angle, don't squash everything else. Each coordinate of the
+ * positions is scaled to the range of most (98%) of the hits, normals map
+ * [-1, 1] to [0, 1] and ids get a distinct color each. NaNs come out black.
+ */
+pub fn aov_to_display(aov: Aov, values: &Vec<Vector3<f64>>) -> Vec<Vector3<f64>>
+{
+   // Scales, for the passes that need them
+   let mut median_depth = 1.;
+   let mut min = Vector3::new(0., 0., 0.);
+   let mut max = Vector3::new(1., 1., 1.);
+   if aov == Aov::Depth {
+      let depths = sorted_channel(values, 0);
+      if !depths.is_empty() && depths[depths.len() / 2] > 0. {
+         median_depth = depths[depths.len() / 2];
+      }
+   }
+   if aov == Aov::Position {
+      for c in 0..3 {
+         let coordinates = sorted_channel(values, c);
+         if !coordinates.is_empty() {
+            min[c] = coordinates[coordinates.len() / 100];
+            max[c] = coordinates[coordinates.len() - 1 - coordinates.len() / 100].max(min[c] + 1.0e-9);
+         }
+      }
+   }
+
+   return values.iter().map(|value| {
+      let rgb = match aov {
+         _ if value[0].is_nan() => Vector3::new(0., 0., 0.),
+         Aov::Depth if value[0].is_infinite() => Vector3::new(1., 1., 1.),
+         Aov::Depth => Vector3::new(1., 1., 1.) * (value[0] / (value[0] + median_depth)),
+         Aov::Normal => 0.5 * (value + Vector3::new(1., 1., 1.)),
+         Aov::Position => (value - min).component_div(&(max - min)),
+         Aov::ObjectId | Aov::MaterialId => id_color(value[0]),
+         _ => *value
+      };
+      rgb.map(|s| s.clamp(0., 1.))
+   }).collect();
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::scene::{ Material, Scattering, Lambertian, PointLight };
+   use crate::geometry::Sphere;
+
+   #[test]
+   fn passes_describe_the_first_hit()
+   {
+      let mut material = Material::from_scattering(Scattering::Lambertian(Lambertian::new(Vector3::new(0.2, 0.4, 0.6))));
+      material.id = 3;
+      let objects: Vec<Box<dyn Object + Sync>> = vec![Box::new(Sphere::new(&Vector3::new(0., 0., -5.), 1., material))];
+      // One light in front of the sphere, one behind it
+      let lights: Vec<Box<dyn Light + Sync>> = vec![
+         Box::new(PointLight::new(Vector3::new(1., 1., 1.), Vector3::zeros())),
+         Box::new(PointLight::new(Vector3::new(1., 1., 1.), Vector3::new(0., 0., -10.)))
+      ];
+      let aovs = [Aov::Depth, Aov::Normal, Aov::Position, Aov::Albedo, Aov::ObjectId, Aov::MaterialId, Aov::Shadow];
+
+      let ray = Ray { origin: Vector3::zeros(), direction: Vector3::new(0., 0., -2.) };
+      let values = aov_values(&ray, &objects, &lights, &aovs);
+      assert!((values[0] - Vector3::new(2., 2., 2.)).norm() < 1.0e-9);
+      assert!((values[1] - Vector3::new(0., 0., 1.)).norm() < 1.0e-9);
+      assert!((values[2] - Vector3::new(0., 0., -4.)).norm() < 1.0e-9);
+      assert!((values[3] - Vector3::new(0.2, 0.4, 0.6)).norm() < 1.0e-9);
+      assert_eq!(values[4], Vector3::new(0., 0., 0.));
+      assert_eq!(values[5], Vector3::new(3., 3., 3.));
+      assert_eq!(values[6], Vector3::new(0.5, 0.5, 0.5));
+
+      let ray = Ray { origin: Vector3::zeros(), direction: Vector3::new(0., 1., 0.) };
+      let values = aov_values(&ray, &objects, &lights, &aovs);
+      for (aov, value) in aovs.iter().zip(values.iter()) {
+         assert!(value == &aov.background() || (value[0].is_nan() && aov.background()[0].is_nan()));
+      }
+   }
+
+   #[test]
+   fn display_maps_the_median_depth_to_gray()
+   {
+      let scalar = |s: f64| Vector3::new(s, s, s);
+      let depths = vec![scalar(1.), scalar(2.), scalar(2.), scalar(100.), scalar(f64::INFINITY)];
+      let rgb = aov_to_display(Aov::Depth, &depths);
+      assert_eq!(rgb[0], scalar(1. / 3.));
+      assert_eq!(rgb[1], scalar(0.5));
+      assert!(rgb[3].x > 0.95 && rgb[3].x < 1.);
+      assert_eq!(rgb[4], scalar(1.));
+
+      let normals = vec![Vector3::new(0., 0., 1.), Vector3::new(-1., 0., 0.), scalar(f64::NAN)];
+      let rgb = aov_to_display(Aov::Normal, &normals);
+      assert_eq!(rgb[0], Vector3::new(0.5, 0.5, 1.));
+      assert_eq!(rgb[1], Vector3::new(0., 0.5, 0.5));
+      assert_eq!(rgb[2], scalar(0.));
+   }
+}
@@ -0,0 +1,213 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::bsdf::luminance;
+use crate::geometry::Object;
+
+
+/**
+ * The emissive objects of a scene, which the path tracer samples as light
+ * sources. A light is picked with probability proportional to its power
+ * (area times brightness), then a point on it (see Object::sample_surface).
+ */
+pub struct AreaLights {
+   // Indices of the emissive objects
+   ids: Vec<usize>,
+   // Running total of the probabilities of picking each of <ids>
+   cdf: Vec<f64>,
+   // Probability of picking each object, zero for the ones that aren't lights
+   pick: Vec<f64>
+}
+
+
+// A point on a light as seen from a shading point
+pub struct AreaLightSample {
+   // Unit direction towards the point, and its distance
+   pub wi: Vector3<f64>,
+   pub distance: f64,
+   // Radiance the light sends back along -wi
+   pub radiance: Vector3<f64>,
+   // Density per solid angle of having picked <wi>
+   pub pdf: f64
+}
+
+
+impl AreaLights {
+   // Only objects whose whole surface can be sampled count (see
+   // Object::sample_surface); other glowing objects are still seen when
+   // paths happen to hit them.
+   pub fn new(objects: &Vec<Box<dyn Object + Sync>>) -> AreaLights
+   {
+      let mut ids = Vec::new();
+      let mut power = Vec::new();
+      for (id, object) in objects.iter().enumerate() {
+         let material = object.get_material();
+         if material.is_emissive() && object.area() > 0. {
+            ids.push(id);
+            power.push(object.area() * luminance(&material.emission).max(1.0e-12));
+         }
+      }
+
+      let total: f64 = power.iter().sum();
+      let mut cdf = Vec::with_capacity(ids.len());
+      let mut pick = vec![0.; objects.len()];
+      let mut sum = 0.;
+      for (k, &id) in ids.iter().enumerate() {
+         pick[id] = power[k] / total;
+         sum += pick[id];
+         cdf.push(sum);
+      }
+      return AreaLights { ids, cdf, pick };
+   }
+
+   pub fn is_empty(&self) -> bool
+   {
+      return self.ids.is_empty();
+   }
+
+   // Whether the object <id> is one of the lights
+   pub fn contains(&self, id: usize) -> bool
+   {
+      return self.pick[id] > 0.;
+   }
+
+   // Pick a point on one of the lights as seen from <p>, with the uniform
+   // random numbers <u>. None if the point can't light <p>.
+   pub fn sample(&self, objects: &Vec<Box<dyn Object + Sync>>, p: &Vector3<f64>, u: [f64; 3]) -> Option<AreaLightSample>
+   {
+      let (id, q, n, _) = self.sample_point(objects, u)?;
+      let object = &objects[id];
+
+      let to_light = q - p;
+      let distance = to_light.norm();
+      if distance == 0. {
+         return None;
+      }
+      let wi = to_light / distance;
+      let pdf = self.pdf(objects, id, p, &q, &n);
+      if pdf <= 0. {
+         return None;
+      }
+      return Some(AreaLightSample { wi, distance, radiance: object.get_material().emission, pdf });
+   }
+
+   // Pick one of the lights and a point on it with the uniform random
+   // numbers <u>: the object, the point and the unit normal there, and the
+   // density (per unit area) of having picked the point
+   pub fn sample_point(&self, objects: &[Box<dyn Object + Sync>], u: [f64; 3])
+                       -> Option<(usize, Vector3<f64>, Vector3<f64>, f64)>
+   {
+      if self.is_empty() {
+         return None;
+      }
+      let k = self.cdf.partition_point(|&c| c <= u[0]).min(self.ids.len() - 1);
+      let id = self.ids[k];
+      let (q, n) = objects[id].sample_surface([u[1], u[2]]);
+      return Some((id, q, n, self.point_pdf(objects, id, &q, &n)));
+   }
+
+   // Density per unit area of sample_point() picking the point <q>, with
+   // normal <n>, on the object <id>. Zero if <id> isn't a light.
+   pub fn point_pdf(&self, objects: &[Box<dyn Object + Sync>], id: usize, q: &Vector3<f64>, n: &Vector3<f64>) -> f64
+   {
+      if self.pick[id] == 0. {
+         return 0.;
+      }
+      return self.pick[id] * objects[id].surface_pdf(q, n);
+   }
+
+   // Density per solid angle of sample() picking the point <q>, with normal
+   // <n>, on the object <id>, as seen from <p>. Zero if <id> isn't a light.
+   pub fn pdf(&self, objects: &[Box<dyn Object + Sync>], id: usize, p: &Vector3<f64>, q: &Vector3<f64>, n: &Vector3<f64>) -> f64
+   {
+      if self.pick[id] == 0. {
+         return 0.;
+      }
+      let to_light = q - p;
+      let distance2 = to_light.norm_squared();
+      let cos_light = n.dot(&to_light).abs() / distance2.sqrt();
+      if cos_light <= 0. {
+         return 0.;
+      }
+      // Per unit area, turned into a density per solid angle
+      return self.pick[id] * objects[id].surface_pdf(q, n) * distance2 / cos_light;
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use std::f64::consts::PI;
+   use crate::scene::Material;
+   use crate::geometry::{ Sphere, Triangle };
+
+   fn material(emission: f64) -> Material
+   {
+      let mut material = Material::gray();
+      material.emission = Vector3::new(emission, emission, emission);
+      return material;
+   }
+
+   // A glowing unit sphere, a plain one, and a glowing triangle of area 1/2
+   // that is twice as bright
+   fn objects() -> Vec<Box<dyn Object + Sync>>
+   {
+      let corners = (Vector3::new(5., 0., 0.), Vector3::new(6., 0., 0.), Vector3::new(5., 1., 0.));
+      return vec![Box::new(Sphere::new(&Vector3::zeros(), 1., material(1.))),
+                  Box::new(Sphere::new(&Vector3::new(0., 5., 0.), 1., material(0.))),
+                  Box::new(Triangle::new(corners, material(2.)))];
+   }
+
+   #[test]
+   fn lights_are_picked_by_power()
+   {
+      let objects = objects();
+      let lights = AreaLights::new(&objects);
+      let sphere_pick = 4. * PI / (4. * PI + 1.);
+      let p = Vector3::new(2., 2., 3.);
+
+      let steps = 1000;
+      let on_sphere = (0..steps).filter(|&i| {
+         let u = [(i as f64 + 0.5) / steps as f64, 0.5, 0.5];
+         let sample = lights.sample(&objects, &p, u).unwrap();
+         (p + sample.distance * sample.wi).norm() < 1. + 1.0e-9
+      }).count();
+      assert!((on_sphere as f64 / steps as f64 - sphere_pick).abs() < 2. / steps as f64);
+
+      // The rest goes to the triangle, uniformly over its area of 1/2
+      let sample = lights.sample(&objects, &p, [0.99, 0.3, 0.3]).unwrap();
+      assert!((p + sample.distance * sample.wi).z.abs() < 1.0e-12);
+      assert_eq!(sample.radiance, Vector3::new(2., 2., 2.));
+      let pdf = (1. - sphere_pick) / 0.5 * sample.distance.powi(2) / sample.wi.z.abs();
+      assert!((sample.pdf - pdf).abs() < 1.0e-9 * pdf);
+
+      // The plain sphere is never picked
+      assert_eq!(lights.pdf(&objects, 1, &p, &Vector3::new(0., 4., 0.), &Vector3::new(0., -1., 0.)), 0.);
+   }
+
+   #[test]
+   fn solid_angle_pdf_covers_what_the_light_subtends()
+   {
+      // Just the sphere, seen from 3 units away. Every direction towards it
+      // meets its surface twice (the far side counts too), so the average of
+      // 1 / pdf over the samples is twice the solid angle.
+      let objects: Vec<Box<dyn Object + Sync>> = vec![Box::new(Sphere::new(&Vector3::zeros(), 1., material(1.)))];
+      let lights = AreaLights::new(&objects);
+      let p = Vector3::new(0., 0., 3.);
+      let steps = 300;
+      let mut sum = 0.;
+      for i in 0..steps {
+         for j in 0..steps {
+            let u = [0.5, (i as f64 + 0.5) / steps as f64, (j as f64 + 0.5) / steps as f64];
+            let sample = lights.sample(&objects, &p, u).unwrap();
+            let q = p + sample.distance * sample.wi;
+            assert!((sample.pdf - lights.pdf(&objects, 0, &p, &q, &q)).abs() < 1.0e-9 * sample.pdf);
+            assert_eq!(sample.radiance, Vector3::new(1., 1., 1.));
+            sum += 1. / sample.pdf;
+         }
+      }
+      let solid_angle = 2. * PI * (1. - (1. - 1. / 9f64).sqrt());
+      assert!((sum / (steps * steps) as f64 - 2. * solid_angle).abs() < 1.0e-3);
+   }
+}
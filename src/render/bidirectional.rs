@@ -0,0 +1,672 @@
+// for the capital I
+#![allow(non_snake_case)]
+
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+use rand::Rng;
+use rand::rngs::SmallRng;
+
+use crate::scene::{ Camera, Ray, Light, Bsdf, Scattering, Medium };
+use crate::scene::bsdf::cosine_sample_hemisphere;
+use crate::geometry::Object;
+use crate::render::{ first_hit_material, transmittance, AreaLights };
+use crate::spectrum::color_at;
+
+
+// What a vertex of a subpath is
+#[derive(Clone, Copy)]
+enum Kind {
+   Camera,
+   // The start of a light subpath, or a light picked to connect to
+   PointLight,
+   AreaLight(usize),
+   // A hit on the object with this index
+   Surface(usize)
+}
+
+
+// A point of a camera or light subpath
+struct Vertex {
+   kind: Kind,
+   p: Vector3<f64>,
+   // Unit surface normal, zero for the camera and point lights
+   n: Vector3<f64>,
+   // Unit direction back towards the vertex before this one
+   wo: Vector3<f64>,
+   // How surfaces scatter (at the path's wavelength)
+   scattering: Option<Scattering>,
+   // Radiance given off (for lights and glowing surfaces)
+   emission: Vector3<f64>,
+   // Throughput of the subpath up to here, divided by its density
+   beta: Vector3<f64>,
+   // Density per unit area of the vertex being picked coming from the
+   // subpath's start, and coming from the other end (zero after perfectly
+   // specular bounces)
+   pdf_fwd: f64,
+   pdf_rev: f64,
+   // Whether the path left it in a perfectly specular direction
+   delta: bool
+}
+
+
+impl Vertex {
+   fn new(kind: Kind, p: Vector3<f64>, n: Vector3<f64>, emission: Vector3<f64>, beta: Vector3<f64>, pdf_fwd: f64) -> Vertex
+   {
+      let zero = Vector3::new(0., 0., 0.);
+      return Vertex { kind, p, n, wo: zero, scattering: None, emission, beta, pdf_fwd, pdf_rev: 0., delta: false };
+   }
+
+   fn bsdf(&self) -> &dyn Bsdf
+   {
+      return self.scattering.as_ref().unwrap().bsdf();
+   }
+
+   // Whether a connection can be made to it: anything but a surface that
+   // only scatters in perfectly specular directions
+   fn is_connectible(&self) -> bool
+   {
+      return match &self.scattering {
+         Some(scattering) => !scattering.is_perfectly_specular(),
+         None => true
+      };
+   }
+
+   fn is_delta_light(&self) -> bool
+   {
+      return matches!(self.kind, Kind::PointLight);
+   }
+
+   // The light scattered at the vertex from the unit direction <w> back
+   // along the subpath (or the other way around, it's the same): the BSDF
+   // times |n . w|. Point lights and the camera count as white.
+   fn scatter(&self, w: &Vector3<f64>) -> Vector3<f64>
+   {
+      return match self.scattering {
+         Some(_) => self.bsdf().eval(&self.wo, w, &self.n),
+         None => Vector3::new(1., 1., 1.)
+      };
+   }
+}
+
+
+// The scene as the integrator needs it
+struct Scene<'a> {
+   objects: &'a Vec<Box<dyn Object + Sync>>,
+   // The lights that can start a light subpath: the point lights and,
+   // together as one, the area lights
+   point_lights: Vec<&'a (dyn Light + Sync)>,
+   area_lights: Option<&'a AreaLights>,
+   camera: &'a Camera,
+   n_x: usize,
+   n_y: usize,
+   // Area of the image plane moved to distance 1 from the eye
+   film_area: f64,
+   wavelength: Option<f64>
+}
+
+
+impl<'a> Scene<'a> {
+   fn n_emitters(&self) -> usize
+   {
+      return self.point_lights.len() + self.area_lights.is_some() as usize;
+   }
+
+   // Importance the camera gives the unit direction <w> from the eye, and
+   // the density per solid angle of a camera ray going that way. Zero
+   // outside the image.
+   fn camera_importance(&self, w: &Vector3<f64>) -> (f64, f64)
+   {
+      let cos = -w.dot(&self.camera.w);
+      if cos <= 0. || self.camera.raster(&(self.camera.e + w), self.n_x, self.n_y).is_none() {
+         return (0., 0.);
+      }
+      let pdf = 1. / (self.film_area * cos * cos * cos);
+      return (pdf / cos, pdf);
+   }
+
+   // Density per unit area of a light subpath starting at <v>, which is on
+   // a light. Zero for glowing objects that can't be sampled.
+   fn origin_pdf(&self, v: &Vertex) -> f64
+   {
+      let pick = 1. / self.n_emitters().max(1) as f64;
+      return match v.kind {
+         Kind::PointLight => pick,
+         Kind::AreaLight(id) | Kind::Surface(id) => match self.area_lights {
+            Some(area_lights) => pick * area_lights.point_pdf(self.objects, id, &v.p, &v.n),
+            None => 0.
+         },
+         Kind::Camera => 0.
+      };
+   }
+
+   // Density per unit area of the light at <v> sending its light to <next>
+   fn emission_pdf(&self, v: &Vertex, next: &Vertex) -> f64
+   {
+      let w = (next.p - v.p).normalize();
+      let pdf = match v.kind {
+         Kind::PointLight => 1. / (4. * PI),
+         // Cosine weighted, from either side
+         _ => v.n.dot(&w).abs() / (2. * PI)
+      };
+      return to_area(pdf, &v.p, next);
+   }
+
+   // Density per unit area of the subpath going on from <v> to <next>,
+   // having come to it from <prev>
+   fn pdf(&self, v: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> f64
+   {
+      let w = (next.p - v.p).normalize();
+      let pdf = match v.kind {
+         Kind::PointLight | Kind::AreaLight(_) => return self.emission_pdf(v, next),
+         Kind::Camera => self.camera_importance(&w).1,
+         Kind::Surface(_) => {
+            let wo = (prev.unwrap().p - v.p).normalize();
+            v.bsdf().pdf(&wo, &w, &v.n)
+         }
+      };
+      return to_area(pdf, &v.p, next);
+   }
+
+   // Whether nothing but interfaces is between <p> and <q>
+   fn visible(&self, p: &Vector3<f64>, q: &Vector3<f64>, rng: &mut SmallRng) -> bool
+   {
+      let to_q = q - p;
+      let distance = to_q.norm();
+      let ray = Ray { origin: *p, direction: to_q / distance };
+      return transmittance(&ray, distance * (1. - 1.0e-6), self.objects, None, None, None, rng) != Vector3::new(0., 0., 0.);
+   }
+}
+
+
+// A density per solid angle at <p> turned into one per unit area at <next>
+fn to_area(pdf: f64, p: &Vector3<f64>, next: &Vertex) -> f64
+{
+   let to_next = next.p - p;
+   let distance2 = to_next.norm_squared();
+   if distance2 == 0. {
+      return 0.;
+   }
+   let mut pdf = pdf / distance2;
+   // Points (the camera, point lights) have no surface to be seen at an angle
+   if next.n != Vector3::new(0., 0., 0.) {
+      pdf *= next.n.dot(&to_next).abs() / distance2.sqrt();
+   }
+   return pdf;
+}
+
+
+// Extend <path> from its last vertex along the unit direction <d>, picked
+// with density <pdf> per solid angle, sampling the BSDF at each hit, until
+// it has <max_vertices>, the path leaves the scene or Russian roulette ends
+// it. <beta> is the throughput arriving at the first hit. Lights at a point
+// send the same light to every distance (see path_trace), so from those the
+// first hit's throughput is scaled up by its squared distance.
+#[allow(clippy::too_many_arguments)]
+fn random_walk(scene: &Scene, d: Vector3<f64>, mut beta: Vector3<f64>, mut pdf: f64, max_vertices: usize,
+               from_point: bool, rng: &mut SmallRng, path: &mut Vec<Vertex>)
+{
+   let mut ray = Ray { origin: path.last().unwrap().p, direction: d };
+   let first = path.len();
+   while path.len() < max_vertices {
+      let mut n = Vector3::new(0., 0., 0.);
+      let mut t = 0.;
+      let mut hit_id = 0usize;
+      let material = match first_hit_material(&ray, 1.0e-6, scene.objects, &mut hit_id, &mut t, &mut n) {
+         Some(material) => material,
+         None => break
+      };
+      let p = ray.origin + t * ray.direction;
+      let n = n.normalize();
+
+      // Media are left out, so interfaces are just gone through
+      if material.is_interface() {
+         ray = Ray { origin: p, direction: ray.direction };
+         continue;
+      }
+
+      let prev = path.len() - 1;
+      if from_point && prev == 0 {
+         beta *= (p - path[0].p).norm_squared();
+      }
+      let scattering = match scene.wavelength {
+         Some(lambda) => material.scattering.at_wavelength(lambda),
+         None => material.scattering.clone()
+      };
+      let mut vertex = Vertex::new(Kind::Surface(hit_id), p, n, color_at(&material.emission, scene.wavelength), beta, 0.);
+      vertex.pdf_fwd = to_area(pdf, &path[prev].p, &vertex);
+      vertex.wo = -ray.direction;
+      vertex.scattering = Some(scattering);
+      if path.len() + 1 == max_vertices {
+         path.push(vertex);
+         break;
+      }
+
+      let sample = match vertex.bsdf().sample(&vertex.wo, &n, [rng.gen(), rng.gen(), rng.gen()]) {
+         Some(sample) => sample,
+         None => {
+            path.push(vertex);
+            break;
+         }
+      };
+      let pdf_rev;
+      if sample.pdf == 0. {
+         vertex.delta = true;
+         pdf = 0.;
+         pdf_rev = 0.;
+      } else {
+         pdf = sample.pdf;
+         pdf_rev = vertex.bsdf().pdf(&sample.wi, &vertex.wo, &n);
+      }
+      path[prev].pdf_rev = to_area(pdf_rev, &p, &path[prev]);
+      path.push(vertex);
+
+      beta = beta.component_mul(&sample.weight);
+      if beta == Vector3::new(0., 0., 0.) {
+         break;
+      }
+
+      // Russian roulette after a few bounces, as in path_trace, by how much
+      // is left of the throughput at the first hit
+      if path.len() - first > 3 {
+         let survive = (beta.max() / path[first].beta.max()).min(0.95);
+         if rng.gen::<f64>() >= survive {
+            break;
+         }
+         beta /= survive;
+      }
+      ray = Ray { origin: p, direction: sample.wi };
+   }
+}
+
+
+// A light picked at random, uniformly from the point lights and the area
+// lights as a whole (which pick one of theirs and a point on it), as the
+// first vertex of a light subpath
+fn pick_light(scene: &Scene, rng: &mut SmallRng) -> Option<Vertex>
+{
+   let n_emitters = scene.n_emitters();
+   if n_emitters == 0 {
+      return None;
+   }
+   let k = ((rng.gen::<f64>() * n_emitters as f64) as usize).min(n_emitters - 1);
+   let pick = 1. / n_emitters as f64;
+
+   if k < scene.point_lights.len() {
+      let light = scene.point_lights[k];
+      let I = color_at(&light.get_intensity(), scene.wavelength);
+      return Some(Vertex::new(Kind::PointLight, light.position().unwrap(), Vector3::new(0., 0., 0.), PI * I, PI * I / pick, pick));
+   }
+   let (id, q, n, area_pdf) = scene.area_lights.unwrap().sample_point(scene.objects, [rng.gen(), rng.gen(), rng.gen()])?;
+   let emission = color_at(&scene.objects[id].get_material().emission, scene.wavelength);
+   return Some(Vertex::new(Kind::AreaLight(id), q, n, emission, emission / (pick * area_pdf), pick * area_pdf));
+}
+
+
+// A subpath from a light picked at random, of up to <max_vertices>
+fn light_subpath(scene: &Scene, max_vertices: usize, rng: &mut SmallRng) -> Vec<Vertex>
+{
+   let mut path = Vec::with_capacity(max_vertices);
+   let origin = match pick_light(scene, rng) {
+      Some(origin) => origin,
+      None => return path
+   };
+
+   // The direction the light leaves in, its density and the cosine the
+   // light is sent with
+   let from_point = origin.is_delta_light();
+   let (d, pdf, cos) = if from_point {
+      // Uniformly over the sphere
+      let z = 1. - 2. * rng.gen::<f64>();
+      let r = (1. - z * z).max(0.).sqrt();
+      let phi = 2. * PI * rng.gen::<f64>();
+      (Vector3::new(r * phi.cos(), r * phi.sin(), z), 1. / (4. * PI), 1.)
+   } else {
+      // Cosine weighted from a random side
+      let side = if rng.gen::<f64>() < 0.5 { origin.n } else { -origin.n };
+      let d = cosine_sample_hemisphere(&side, rng.gen(), rng.gen());
+      let cos = origin.n.dot(&d).abs();
+      (d, cos / (2. * PI), cos)
+   };
+   if pdf <= 0. {
+      return path;
+   }
+   let beta = origin.beta * cos / pdf;
+   path.push(origin);
+   random_walk(scene, d, beta, pdf, max_vertices, from_point, rng, &mut path);
+   return path;
+}
+
+
+// A light picked as for a light subpath, as a vertex to connect <pt> to,
+// with the light it sends there (over the density of picking it)
+fn sample_light(scene: &Scene, pt: &Vertex, rng: &mut SmallRng) -> Option<(Vertex, Vector3<f64>)>
+{
+   let vertex = pick_light(scene, rng)?;
+   if vertex.is_delta_light() {
+      // No falloff with distance, so nothing else to it
+      let light = vertex.beta;
+      return Some((vertex, light));
+   }
+   let to_light = vertex.p - pt.p;
+   let distance2 = to_light.norm_squared();
+   let cos = vertex.n.dot(&to_light).abs() / distance2.sqrt();
+   let light = vertex.beta * cos / distance2;
+   return Some((vertex, light));
+}
+
+
+// The multiple importance sampling weight (power heuristic) of the path
+// made of the first <t> vertices of <camera> and first <s> of <light>, among
+// every other way of splitting it between a camera and a light subpath.
+// <sampled> stands in for the last light vertex when s = 1, and the camera
+// when t = 1, as those are picked afresh for the connection.
+fn mis_weight(scene: &Scene, camera: &[Vertex], light: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f64
+{
+   if s + t == 2 {
+      return 1.;
+   }
+
+   // The ends of the connection and the vertices before them
+   let pt = if t == 1 { sampled.unwrap() } else { &camera[t - 1] };
+   let qs = match s {
+      0 => None,
+      1 => Some(sampled.unwrap()),
+      _ => Some(&light[s - 1])
+   };
+   let pt_minus = if t >= 2 { Some(&camera[t - 2]) } else { None };
+   let qs_minus = if s >= 2 { Some(&light[s - 2]) } else { None };
+
+   // Their densities coming from the other side, through the connection
+   let pt_rev = match qs {
+      Some(qs) => scene.pdf(qs, qs_minus, pt),
+      None => scene.origin_pdf(pt)
+   };
+   let pt_minus_rev = pt_minus.map_or(0., |pt_minus| match qs {
+      Some(qs) => scene.pdf(pt, Some(qs), pt_minus),
+      None => scene.emission_pdf(pt, pt_minus)
+   });
+   let qs_rev = qs.map_or(0., |qs| scene.pdf(pt, pt_minus, qs));
+   let qs_minus_rev = qs_minus.map_or(0., |qs_minus| scene.pdf(qs.unwrap(), Some(pt), qs_minus));
+
+   // Perfectly specular bounces have no density: they just don't count
+   let squared = |pdf: f64| if pdf == 0. { 1. } else { pdf * pdf };
+
+   // The same path with its camera subpath one, two... vertices shorter
+   let mut sum = 0.;
+   let mut ratio = 1.;
+   for i in (1..t).rev() {
+      let (pdf_rev, delta) = if i == t - 1 {
+         (pt_rev, false)
+      } else if i == t - 2 {
+         (pt_minus_rev, camera[i].delta)
+      } else {
+         (camera[i].pdf_rev, camera[i].delta)
+      };
+      ratio *= squared(pdf_rev) / squared(camera[i].pdf_fwd);
+      if !delta && !camera[i - 1].delta {
+         sum += ratio;
+      }
+   }
+
+   // And with its light subpath shorter
+   ratio = 1.;
+   for i in (0..s).rev() {
+      let v = if i == s - 1 { qs.unwrap() } else { &light[i] };
+      let (pdf_rev, delta) = if i == s - 1 {
+         (qs_rev, false)
+      } else if i == s - 2 {
+         (qs_minus_rev, v.delta)
+      } else {
+         (v.pdf_rev, v.delta)
+      };
+      ratio *= squared(pdf_rev) / squared(v.pdf_fwd);
+      let delta_before = if i > 0 { light[i - 1].delta } else { v.is_delta_light() };
+      if !delta && !delta_before {
+         sum += ratio;
+      }
+   }
+   return 1. / (1. + sum);
+}
+
+
+// The light of the path made of the first <t> vertices of <camera> and the
+// first <s> of <light> (with a light picked afresh when s = 1), weighted
+// for multiple importance sampling. t must be at least 2.
+fn connect(scene: &Scene, camera: &[Vertex], light: &[Vertex], s: usize, t: usize, rng: &mut SmallRng) -> Vector3<f64>
+{
+   let zero = Vector3::new(0., 0., 0.);
+   let pt = &camera[t - 1];
+   let mut sampled = None;
+   let rgb;
+   if s == 0 {
+      // The camera subpath found a light by itself
+      if !matches!(pt.kind, Kind::Surface(_)) || pt.emission == zero {
+         return zero;
+      }
+      rgb = pt.beta.component_mul(&pt.emission);
+   } else if s == 1 {
+      if !pt.is_connectible() {
+         return zero;
+      }
+      let (vertex, light) = match sample_light(scene, pt, rng) {
+         Some(sample) => sample,
+         None => return zero
+      };
+      let w = (vertex.p - pt.p).normalize();
+      rgb = pt.beta.component_mul(&pt.scatter(&w)).component_mul(&light);
+      if rgb == zero || !scene.visible(&pt.p, &vertex.p, rng) {
+         return zero;
+      }
+      sampled = Some(vertex);
+   } else {
+      let qs = &light[s - 1];
+      if !pt.is_connectible() || !qs.is_connectible() {
+         return zero;
+      }
+      let to_qs = qs.p - pt.p;
+      let distance2 = to_qs.norm_squared();
+      let w = to_qs / distance2.sqrt();
+      rgb = pt.beta.component_mul(&pt.scatter(&w)).component_mul(&qs.scatter(&-w)).component_mul(&qs.beta) / distance2;
+      if rgb == zero || !scene.visible(&pt.p, &qs.p, rng) {
+         return zero;
+      }
+   }
+   return mis_weight(scene, camera, light, sampled.as_ref(), s, t) * rgb;
+}
+
+
+// The light subpath's vertex <s> - 1 connected straight to the camera: the
+// pixel it shows up in and the light it adds there (over all the light
+// subpaths traced for the image, as the film's importance is spread over
+// all of it)
+fn connect_to_camera(scene: &Scene, light: &[Vertex], s: usize, rng: &mut SmallRng) -> Option<(usize, Vector3<f64>)>
+{
+   let qs = &light[s - 1];
+   if !qs.is_connectible() {
+      return None;
+   }
+   let (x, y) = scene.camera.raster(&qs.p, scene.n_x, scene.n_y)?;
+   let to_qs = qs.p - scene.camera.e;
+   let distance2 = to_qs.norm_squared();
+   let w = to_qs / distance2.sqrt();
+   let (importance, _) = scene.camera_importance(&w);
+   let cos = -w.dot(&scene.camera.w);
+   let rgb = qs.beta.component_mul(&qs.scatter(&-w)) * importance * cos / distance2;
+   if rgb == Vector3::new(0., 0., 0.) || !scene.visible(&qs.p, &scene.camera.e, rng) {
+      return None;
+   }
+
+   let eye = Vertex::new(Kind::Camera, scene.camera.e, Vector3::new(0., 0., 0.), Vector3::new(0., 0., 0.),
+                         Vector3::new(1., 1., 1.), 1.);
+   let weight = mis_weight(scene, &[], light, Some(&eye), s, 1);
+   let pixel = (y as usize).min(scene.n_y - 1) * scene.n_x + (x as usize).min(scene.n_x - 1);
+   return Some((pixel, weight * rgb));
+}
+
+
+/**
+ * Estimate the light coming back along the camera <ray> by bidirectional
+ * path tracing (Veach's thesis, chapter 10). A subpath is traced from the
+ * camera and another from a light, each sampling the BSDF at every bounce,
+ * and every vertex of one is connected to every vertex of the other with a
+ * shadow ray. Each of those connections builds the whole path in its own
+ * way, and multiple importance sampling (the power heuristic, over the
+ * densities of the subpaths reaching each vertex from either end) weighs
+ * each one by how well it would have sampled the path, so paths the camera
+ * hardly finds (light through a small opening, or off a light's close
+ * surroundings) come from the light's side.
+ *
+ * The camera subpath alone gives the lights it hits, and with a light
+ * picked for it the light sampling of path_trace. The light subpath joined
+ * straight to the camera lands anywhere in the image, so that light is
+ * handed to <splat> with its pixel instead, to be added up separately and
+ * divided by the number of light subpaths traced for the image.
+ *
+ * Light subpaths start from the point lights and from the area lights,
+ * which are picked as one; directional lights are only connected to, as the
+ * path tracer does. Participating media aren't supported (see
+ * check_no_media), and interfaces are gone through. Paths have up to <max_depth> bounces, without Russian roulette.
+ * With a <wavelength> everything is taken at that wavelength as in
+ * path_trace.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn bidirectional(ray: &Ray,
+                     objects: &Vec<Box<dyn Object + Sync>>,
+                     lights: &Vec<Box<dyn Light + Sync>>,
+                     area_lights: &AreaLights,
+                     camera: &Camera,
+                     n_x: usize,
+                     n_y: usize,
+                     wavelength: Option<f64>,
+                     max_depth: usize,
+                     rng: &mut SmallRng,
+                     splat: &mut dyn FnMut(usize, Vector3<f64>)) -> Vector3<f64>
+{
+   let zero = Vector3::new(0., 0., 0.);
+   let scene = Scene {
+      objects,
+      point_lights: lights.iter().filter(|light| light.position().is_some()).map(|light| light.as_ref()).collect(),
+      area_lights: if area_lights.is_empty() { None } else { Some(area_lights) },
+      camera,
+      n_x,
+      n_y,
+      film_area: camera.width * camera.height / (camera.d * camera.d),
+      wavelength
+   };
+
+   // The two subpaths: up to <max_depth> bounces between the camera and a
+   // light
+   let d = ray.direction.normalize();
+   let mut camera_path = vec![Vertex::new(Kind::Camera, camera.e, zero, zero, Vector3::new(1., 1., 1.), 1.)];
+   let pdf = scene.camera_importance(&d).1;
+   random_walk(&scene, d, Vector3::new(1., 1., 1.), pdf, max_depth + 2, false, rng, &mut camera_path);
+   let light_path = light_subpath(&scene, max_depth + 1, rng);
+
+   let mut rgb = zero;
+   let mut l = zero;
+   for t in 1..=camera_path.len() {
+      for s in 0..=light_path.len() {
+         if (s == 1 && t == 1) || s + t < 2 || s + t - 2 > max_depth {
+            continue;
+         }
+         if t == 1 {
+            if let Some((pixel, light)) = connect_to_camera(&scene, &light_path, s, rng) {
+               splat(pixel, light);
+            }
+         } else {
+            rgb += connect(&scene, &camera_path, &light_path, s, t, rng);
+         }
+      }
+
+      // Directional lights, which no other way of building the path can find
+      let pt = &camera_path[t - 1];
+      if t < 2 || t - 1 > max_depth || !pt.is_connectible() {
+         continue;
+      }
+      for light in lights.iter().filter(|light| light.position().is_none()) {
+         let mut max_t = 0.;
+         light.direction(&pt.p, &mut l, &mut max_t);
+         let f = pt.scatter(&l);
+         if f == zero {
+            continue;
+         }
+         let sray = Ray { origin: pt.p, direction: l };
+         if transmittance(&sray, max_t, objects, None, None, None, rng) != zero {
+            let I = color_at(&light.get_intensity(), wavelength);
+            rgb += PI * pt.beta.component_mul(&f).component_mul(&I);
+         }
+      }
+   }
+   return rgb;
+}
+
+
+// Refuse scenes with a <fog>, or objects filled with a medium: bidirectional()
+// would go straight through them and give a wrong image.
+pub fn check_no_media(fog: Option<&Medium>, objects: &[Box<dyn Object + Sync>])
+{
+   if fog.is_some() || objects.iter().any(|object| object.get_material().medium.is_some()) {
+      panic!("Error: the bidirectional path tracer doesn't support fog or object media (render with --mode path instead)");
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use rand::SeedableRng;
+   use crate::scene::{ Material, PointLight };
+   use crate::geometry::{ Plane, Sphere };
+   use crate::render::path_trace;
+
+   #[test]
+   fn agrees_with_the_path_tracer()
+   {
+      // A gray floor under a point light, next to a glowing gray ball
+      let gray = Material::gray();
+      let mut glowing = gray.clone();
+      glowing.emission = Vector3::new(2., 1., 0.5);
+      let objects: Vec<Box<dyn Object + Sync>> = vec![
+         Box::new(Plane::new(&Vector3::zeros(), &Vector3::new(0., 1., 0.), gray)),
+         Box::new(Sphere::new(&Vector3::new(0.8, 0.5, 0.), 0.5, glowing))
+      ];
+      let lights: Vec<Box<dyn Light + Sync>> = vec![Box::new(PointLight::new(Vector3::new(0.5, 0.5, 0.5), Vector3::new(-1., 2., 0.)))];
+      let area_lights = AreaLights::new(&objects);
+
+      // Looking straight down at the floor beside the ball
+      let camera = Camera::new(Vector3::new(0., 1., 0.), Vector3::new(1., 0., 0.), Vector3::new(0., 0., -1.),
+                               Vector3::new(0., 1., 0.), 1., 0.5, 0.5);
+      let (n_x, n_y, samples) = (4, 4, 400);
+      let mut rng = SmallRng::seed_from_u64(1);
+      let (mut path, mut bdpt, mut splats) = (Vector3::zeros(), Vector3::zeros(), Vector3::zeros());
+      for j in 0..n_y {
+         for i in 0..n_x {
+            for _ in 0..samples {
+               let ray = Ray::through(&camera, i as f64 + rng.gen::<f64>(), j as f64 + rng.gen::<f64>(), n_x, n_y);
+               path += path_trace(&ray, &objects, &lights, &area_lights, None, None, None, 4, &mut rng);
+               bdpt += bidirectional(&ray, &objects, &lights, &area_lights, &camera, n_x, n_y, None, 4, &mut rng,
+                                     &mut |pixel, light| {
+                  assert!(pixel < n_x * n_y);
+                  splats += light;
+               });
+            }
+         }
+      }
+      // Light paths that reach the camera are spread over the whole image
+      let n = (n_x * n_y * samples) as f64;
+      let (path, bdpt) = (path / n, (bdpt + (n_x * n_y) as f64 * splats / n) / n);
+      assert!((bdpt - path).abs().max() < 0.03 * path.max(), "{:?} against {:?}", bdpt, path);
+   }
+
+   #[test]
+   #[should_panic(expected = "doesn't support fog or object media")]
+   fn media_are_refused()
+   {
+      let mut water = Material::gray();
+      water.medium = Some(Medium::new(Vector3::new(0.5, 0.2, 0.1), Vector3::zeros(), 0.));
+      let objects: Vec<Box<dyn Object + Sync>> = vec![Box::new(Sphere::new(&Vector3::zeros(), 1., water))];
+      check_no_media(None, &objects);
+   }
+}
@@ -4,24 +4,28 @@
 use nalgebra as na;
 use na::{ Vector3 };
 
-use crate::scene::{ Ray, Light };
+use rand::rngs::SmallRng;
+
+use std::f64::consts::PI;
+
+use crate::scene::{ Ray, Light, Material, Scattering, BlinnPhong, AmbientLight, Medium };
+use crate::scene::bsdf::facing;
 use crate::geometry::Object;
-use crate::render::first_hit;
+use crate::render::{ visibility, medium_after, transmittance };
 
 
-pub fn blinn_phong_shading(ray: &Ray, hit_id: usize, t: &f64, n: &Vector3<f64>,
+#[allow(clippy::too_many_arguments)]
+pub fn blinn_phong_shading(ray: &Ray, mat: &Material, t: &f64, n: &Vector3<f64>,
                            objects: &Vec<Box<dyn Object + Sync>>,
-                           lights: &Vec<Box<dyn Light + Sync>>) -> Vector3<f64>
+                           lights: &Vec<Box<dyn Light + Sync>>,
+                           ambient: &AmbientLight,
+                           fog: Option<&Medium>,
+                           medium: Option<&Medium>,
+                           rng: &mut SmallRng) -> Vector3<f64>
 {
-   // Pull all the variables from this object's material:
-   let mat = objects[hit_id].get_material();
-   let kd = mat.kd;
-   let ks = mat.ks;
-   let ka = mat.ka;
-   let phong_exp = mat.phong_exp;
-
-   // Initialize pixel color with intensity of ambient light 
-   let mut rgb = Vector3::new(ka[0] * 0.1, ka[1] * 0.1, ka[2] * 0.1);
+   // Any material works, through its BSDF. Blinn-Phong keeps the original
+   // shading terms below, so its images stay exactly as they were.
+   let bsdf = mat.bsdf();
 
    // Set up vectors for readability and notation:
    let e = ray.origin;
@@ -29,12 +33,21 @@ pub fn blinn_phong_shading(ray: &Ray, hit_id: usize, t: &f64, n: &Vector3<f64>,
 
    // Find the point <P> of intersection between the viewing ray and the surface:
    let P = e + *t * d;
+
+   // Initialize pixel color with intensity of ambient light, dimmed by how
+   // much of the hemisphere on our side of the surface is blocked
+   let mut rgb = bsdf.ambient().component_mul(&ambient.I);
+   if ambient.occlusion_samples > 0 && rgb != Vector3::new(0.,0.,0.) {
+      let n_viewer = facing(&n.normalize(), &-d);
+      rgb *= visibility(&P, &n_viewer, ambient.occlusion_samples, ambient.occlusion_distance, objects, rng);
+   }
    let mut sray: Ray = Ray{origin: P, direction: Vector3::new(0.,0.,0.)};       // shadow ray
 
    // Iterate through every light and calculate all the vectors needed
    // to set the pixel color. Since light obeys the superposition principle,
    // we sum all the light, then clip if required:
    let v = (P + -d).normalize();     // v only depends on the viewing ray (not the light source)
+   let wo = (-d).normalize();        // the other BSDFs take the direction back to the viewer
    let mut l = Vector3::new(0.,0.,0.);
    for light in lights.iter() {
       // For each light, find the vectors l, and h, and set the shadow ray direction:
@@ -44,23 +57,71 @@ pub fn blinn_phong_shading(ray: &Ray, hit_id: usize, t: &f64, n: &Vector3<f64>,
 
       // Now we need to check if we're in a shadow.
       // (i.e. can we see the light source from <P>?)
-      let mut s_hit_id: usize = 0;
-      let mut s_t: f64 = 0.;
-      let mut s_n = Vector3::new(0.,0.,0.);
-      if !first_hit(&sray, 1.0e-6, objects, &mut s_hit_id, &mut s_t, &mut s_n) || s_t >= max_t {
+      // NOTE: we only look as far as max_t because we might hit an object,
+      // but that object might be above the light source, so we still want
+      // that light. Without it all our images are dimmer!
+      // Media on the way (starting with the one on the light's side of this
+      // surface) only dim the light.
+      let smedium = medium_after(&l, n, mat, medium, fog);
+      let through = transmittance(&sray, max_t, objects, fog, smedium, None, rng);
+      if through != Vector3::new(0.,0.,0.) {
          // Then we're not in the shadow, so we can add the diffuse and specular components
-         // NOTE: don't forget the s_t >= max_t because we might hit an object,
-         // but that object might be above the light source, so we still want
-         // that light. Without the <or> all our images are dimmer!
-         let h = (v + l).normalize();      // only need to compute h if we're not in a shadow
-         
-         // Add the diffuse component (Lambertian shading) to the pixel color
-         let I = light.get_intensity();
-         rgb += Vector3::new(kd[0] * I[0], kd[1] * I[1], kd[2] * I[2]) * f64::max(0.0, n.dot(&l));
-         // Add the specular component (Blinn-Phong shading) to the pixel color
-         rgb += Vector3::new(ks[0] * I[0], ks[1] * I[1], ks[2] * I[2]) * f64::powf(f64::max(0.0, n.dot(&h)), phong_exp);
+         let I = light.get_intensity().component_mul(&through);
+         if let Scattering::BlinnPhong(BlinnPhong { kd, ks, phong_exp, .. }) = &mat.scattering {
+            let h = (v + l).normalize();      // only need to compute h if we're not in a shadow
+
+            // Add the diffuse component (Lambertian shading) to the pixel color
+            rgb += Vector3::new(kd[0] * I[0], kd[1] * I[1], kd[2] * I[2]) * f64::max(0.0, n.dot(&l));
+            // Add the specular component (Blinn-Phong shading) to the pixel color
+            rgb += Vector3::new(ks[0] * I[0], ks[1] * I[1], ks[2] * I[2]) * f64::powf(f64::max(0.0, n.dot(&h)), *phong_exp);
+         } else {
+            // Light intensities are scaled so a white Lambertian surface
+            // facing the light reflects exactly I, hence the PI
+            rgb += PI * bsdf.eval(&wo, &l, n).component_mul(&I);
+         }
       }
    }
 
    return rgb;
 }
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use rand::SeedableRng;
+   use crate::geometry::Plane;
+
+   // The ambient light a floor at y = 0 reflects straight up, with a ceiling
+   // at y = 1 if <covered>
+   fn ambient_on_the_floor(ambient: &AmbientLight, covered: bool) -> Vector3<f64>
+   {
+      let mut objects: Vec<Box<dyn Object + Sync>> = vec![
+         Box::new(Plane::new(&Vector3::zeros(), &Vector3::new(0., 1., 0.), Material::gray()))
+      ];
+      if covered {
+         objects.push(Box::new(Plane::new(&Vector3::new(0., 1., 0.), &Vector3::new(0., -1., 0.), Material::gray())));
+      }
+      let ray = Ray { origin: Vector3::new(0., 0.5, 0.), direction: Vector3::new(0., -1., 0.) };
+      let mut rng = SmallRng::seed_from_u64(1);
+      return blinn_phong_shading(&ray, &Material::gray(), &0.5, &Vector3::new(0., 1., 0.), &objects, &Vec::new(),
+                                 ambient, None, None, &mut rng);
+   }
+
+   #[test]
+   fn ambient_light_is_dimmed_by_occluders()
+   {
+      let mut ambient = AmbientLight::new(Vector3::new(1., 0.5, 0.25));
+      let full = Material::gray().bsdf().ambient().component_mul(&ambient.I);
+      assert!(full != Vector3::zeros());
+      // Without occlusion the ceiling makes no difference
+      assert_eq!(ambient_on_the_floor(&ambient, true), full);
+
+      ambient.occlusion_samples = 64;
+      assert_eq!(ambient_on_the_floor(&ambient, false), full);
+      assert_eq!(ambient_on_the_floor(&ambient, true), Vector3::zeros());
+      // Nor does it when it's further away than occluders count
+      ambient.occlusion_distance = 0.5;
+      assert_eq!(ambient_on_the_floor(&ambient, true), full);
+   }
+}
@@ -0,0 +1,189 @@
+use nalgebra as na;
+use na::{ Vector3 };
+use rayon::prelude::*;
+
+use crate::scene::bsdf::luminance;
+
+
+// How closely neighbours have to match to be averaged in. Normals are
+// compared by the cosine between them raised to this power,
+const NORMAL_POWER: i32 = 32;
+// depths by their difference relative to the depth, per pixel apart,
+const DEPTH_SIGMA: f64 = 0.02;
+// albedos by their (absolute) difference,
+const ALBEDO_SIGMA: f64 = 0.1;
+// and colors by their difference in standard errors of the two means
+const COLOR_SIGMA: f64 = 2.;
+
+// The noise level of a pixel is averaged over this many pixels around it
+const VARIANCE_RADIUS: i64 = 2;
+
+// Albedo below this isn't divided out, to keep black surfaces from blowing up
+const MIN_ALBEDO: f64 = 0.01;
+
+
+/**
+ * A joint bilateral filter for noisy renders. Every pixel becomes a weighted
+ * average of the pixels up to <radius> away, where the weights fall off with
+ * distance and with how different the neighbour is in <albedo>, <normal> and
+ * <depth> (the AOVs of the first hits, as aov_values() gives them) and in
+ * color. Noise doesn't change those guides, so they keep edges and surfaces
+ * apart, while the color is compared against the noise level of the two
+ * pixels, <variance> (the variance of each pixel's mean luminance; infinite
+ * where it isn't known), so that shadow edges stay sharp but noise doesn't.
+ *
+ * The filtering is done on the light arriving at the surfaces: the radiance
+ * divided by the albedo, which is multiplied back in afterwards, so textures
+ * stay as sharp as they were.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn denoise(radiance: &[Vector3<f64>], variance: &[f64], albedo: &[Vector3<f64>],
+               normal: &[Vector3<f64>], depth: &[Vector3<f64>], n_x: usize, n_y: usize,
+               radius: usize) -> Vec<Vector3<f64>>
+{
+   let demodulate = |c: &Vector3<f64>| c.map(|s| s.max(MIN_ALBEDO));
+   let irradiance: Vec<Vector3<f64>> = (0..n_x * n_y)
+      .map(|p| radiance[p].component_div(&demodulate(&albedo[p])))
+      .collect();
+   // The variance of each pixel's irradiance, roughly, from that of its
+   // radiance. With few samples a pixel's own estimate is poor (four samples
+   // that happen to agree say it has no noise at all), so it's raised to the
+   // average of its neighbours' where that is higher.
+   let irradiance_variance: Vec<f64> = (0..n_x * n_y)
+      .map(|p| variance[p] / luminance(&demodulate(&albedo[p])).powi(2))
+      .collect();
+   let irradiance_variance: Vec<f64> = (0..n_x * n_y).into_par_iter().map(|p| {
+      let (x, y) = ((p % n_x) as i64, (p / n_x) as i64);
+      let mut sum = 0.;
+      let mut count = 0;
+      for qy in (y - VARIANCE_RADIUS).max(0)..=(y + VARIANCE_RADIUS).min(n_y as i64 - 1) {
+         for qx in (x - VARIANCE_RADIUS).max(0)..=(x + VARIANCE_RADIUS).min(n_x as i64 - 1) {
+            sum += irradiance_variance[qy as usize * n_x + qx as usize];
+            count += 1;
+         }
+      }
+      return f64::max(irradiance_variance[p], sum / count as f64);
+   }).collect();
+
+   let spatial_sigma = f64::max(radius as f64 / 2., 0.5);
+   let r = radius as i64;
+
+   let rows: Vec<Vec<Vector3<f64>>> = (0..n_y).into_par_iter().map(|y| {
+      let mut row = Vec::with_capacity(n_x);
+      for x in 0..n_x {
+         let p = y * n_x + x;
+         let depth_p = depth[p][0];
+         let luminance_p = luminance(&irradiance[p]);
+
+         let mut sum = Vector3::new(0., 0., 0.);
+         let mut total_weight = 0.;
+         for dy in -r..=r {
+            for dx in -r..=r {
+               let (qx, qy) = (x as i64 + dx, y as i64 + dy);
+               if qx < 0 || qy < 0 || qx >= n_x as i64 || qy >= n_y as i64 {
+                  continue;
+               }
+               let q = qy as usize * n_x + qx as usize;
+               let distance2 = (dx * dx + dy * dy) as f64;
+               let mut weight = (-distance2 / (2. * spatial_sigma * spatial_sigma)).exp();
+
+               // Pixels that see the background only mix with each other
+               let depth_q = depth[q][0];
+               if depth_p.is_infinite() != depth_q.is_infinite() {
+                  continue;
+               }
+               if depth_p.is_finite() {
+                  weight *= f64::max(normal[p].dot(&normal[q]), 0.).powi(NORMAL_POWER);
+                  let relative = (depth_p - depth_q).abs() / (DEPTH_SIGMA * depth_p * distance2.sqrt().max(1.));
+                  weight *= (-relative).exp();
+                  weight *= (-(albedo[p] - albedo[q]).norm_squared() / (2. * ALBEDO_SIGMA * ALBEDO_SIGMA)).exp();
+               }
+
+               let noise = irradiance_variance[p] + irradiance_variance[q];
+               if noise.is_finite() {
+                  let difference = luminance_p - luminance(&irradiance[q]);
+                  weight *= (-difference * difference / (COLOR_SIGMA * COLOR_SIGMA * noise + 1.0e-10)).exp();
+               }
+
+               sum += weight * irradiance[q];
+               total_weight += weight;
+            }
+         }
+         // The pixel itself always has some weight, unless it is NaN
+         let filtered = if total_weight > 0. { sum / total_weight } else { irradiance[p] };
+         row.push(filtered.component_mul(&demodulate(&albedo[p])));
+      }
+      return row;
+   }).collect();
+
+   return rows.concat();
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use rand::{ Rng, SeedableRng, rngs::SmallRng };
+
+   const N: usize = 16;
+
+   fn flat(value: Vector3<f64>) -> Vec<Vector3<f64>>
+   {
+      return vec![value; N * N];
+   }
+
+   fn close(a: &[Vector3<f64>], b: &[Vector3<f64>]) -> bool
+   {
+      return a.iter().zip(b.iter()).all(|(x, y)| (x - y).norm() < 1.0e-9);
+   }
+
+   #[test]
+   fn flat_images_stay_flat()
+   {
+      let radiance = flat(Vector3::new(0.2, 0.4, 0.6));
+      let out = denoise(&radiance, &vec![0.01; N * N], &flat(Vector3::new(0.5, 0.5, 0.5)),
+                        &flat(Vector3::new(0., 0., 1.)), &flat(Vector3::new(3., 3., 3.)), N, N, 3);
+      assert!(close(&out, &radiance));
+   }
+
+   #[test]
+   fn noise_is_smoothed_but_keeps_its_mean()
+   {
+      let mut rng = SmallRng::seed_from_u64(1);
+      let radiance: Vec<Vector3<f64>> = (0..N * N).map(|_| Vector3::new(1., 1., 1.) * rng.gen_range(0.5..1.5)).collect();
+      // The variance of a uniform sample in [0.5, 1.5)
+      let variance = vec![1. / 12.; N * N];
+      let out = denoise(&radiance, &variance, &flat(Vector3::new(1., 1., 1.)),
+                        &flat(Vector3::new(0., 0., 1.)), &flat(Vector3::new(3., 3., 3.)), N, N, 3);
+
+      let spread = |image: &[Vector3<f64>]| -> (f64, f64) {
+         let mean = image.iter().map(|rgb| rgb.x).sum::<f64>() / image.len() as f64;
+         let variance = image.iter().map(|rgb| (rgb.x - mean).powi(2)).sum::<f64>() / image.len() as f64;
+         return (mean, variance);
+      };
+      let (mean_in, variance_in) = spread(&radiance);
+      let (mean_out, variance_out) = spread(&out);
+      assert!((mean_out - mean_in).abs() < 0.02);
+      assert!(variance_out < variance_in / 5.);
+   }
+
+   #[test]
+   fn edges_in_the_guides_stay_sharp()
+   {
+      // Two walls meeting down the middle, one lit and one dark, and a
+      // checkered texture on a third image
+      let left = |p: usize| p % N < N / 2;
+      let radiance: Vec<Vector3<f64>> = (0..N * N).map(|p| if left(p) { Vector3::new(1., 1., 1.) } else { Vector3::new(0.1, 0.1, 0.1) }).collect();
+      let normal: Vec<Vector3<f64>> = (0..N * N).map(|p| if left(p) { Vector3::new(0., 0., 1.) } else { Vector3::new(1., 0., 0.) }).collect();
+      let gray = flat(Vector3::new(1., 1., 1.));
+      let depth = flat(Vector3::new(3., 3., 3.));
+      // Noisy enough that the colors alone wouldn't keep the walls apart
+      let out = denoise(&radiance, &vec![1.; N * N], &gray, &normal, &depth, N, N, 3);
+      assert!(close(&out, &radiance));
+
+      let albedo: Vec<Vector3<f64>> = (0..N * N).map(|p| if (p % N + p / N).is_multiple_of(2) { Vector3::new(0.9, 0.9, 0.9) } else { Vector3::new(0.1, 0.1, 0.1) }).collect();
+      let textured: Vec<Vector3<f64>> = albedo.iter().map(|a| 0.5 * a).collect();
+      let out = denoise(&textured, &vec![1.; N * N], &albedo, &flat(Vector3::new(0., 0., 1.)), &depth, N, N, 3);
+      assert!(close(&out, &textured));
+   }
+}
@@ -0,0 +1,206 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+
+// Curves that squeeze radiance in [0, infinity) into the [0, 1] a display
+// can show. All of them work on each channel separately.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ToneMapper {
+   // No curve: anything above 1 is cut off
+   Clamp,
+   // x / (1 + x), which never quite reaches white
+   Reinhard,
+   // Reinhard stretched so that the white point maps to exactly 1
+   ExtendedReinhard,
+   // Krzysztof Narkowicz's fit of the ACES filmic curve
+   Aces,
+   // John Hable's filmic curve from Uncharted 2
+   Hable
+}
+
+
+impl ToneMapper {
+   // The tone mapper called <name> in scene files and on the command line
+   pub fn from_name(name: &str) -> ToneMapper
+   {
+      return match name {
+         "clamp" => ToneMapper::Clamp,
+         "reinhard" => ToneMapper::Reinhard,
+         "extended_reinhard" => ToneMapper::ExtendedReinhard,
+         "aces" => ToneMapper::Aces,
+         "hable" => ToneMapper::Hable,
+         _ => panic!("Error: unknown tone mapper \"{}\" (expected clamp, reinhard, extended_reinhard, aces or hable)", name)
+      };
+   }
+
+   // The radiance that becomes white, for the curves that take one
+   fn default_white(&self) -> f64
+   {
+      return match self {
+         ToneMapper::Hable => 11.2,
+         _ => 4.
+      };
+   }
+}
+
+
+// How the tone mapped values in [0, 1] are turned into pixel values
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+   // Written as they are
+   Linear,
+   // The sRGB transfer function, what monitors expect
+   Srgb,
+   // A plain power curve x^(1 / gamma)
+   Gamma(f64)
+}
+
+
+impl Encoding {
+   // "linear", "srgb" or a number, which is a gamma
+   pub fn from_name(name: &str) -> Encoding
+   {
+      return match name {
+         "linear" => Encoding::Linear,
+         "srgb" => Encoding::Srgb,
+         _ => match name.parse::<f64>() {
+            Ok(gamma) if gamma > 0. => Encoding::Gamma(gamma),
+            _ => panic!("Error: unknown encoding \"{}\" (expected \"linear\", \"srgb\" or a gamma like 2.2)", name)
+         }
+      };
+   }
+}
+
+
+/**
+ * The display transform: what happens to the rendered radiance on its way
+ * into an 8-bit image. It's scaled by the exposure, tone mapped into [0, 1]
+ * and then encoded. The default (no exposure, clamp, linear) is a plain clamp,
+ * which is how the images always came out.
+ */
+#[derive(Clone, Copy)]
+pub struct DisplayTransform {
+   // In stops: the radiance is multiplied by 2^exposure
+   pub exposure: f64,
+   pub tone_mapper: ToneMapper,
+   // Radiance that maps to white with extended Reinhard and Hable, None for
+   // each curve's usual value
+   pub white: Option<f64>,
+   pub encoding: Encoding
+}
+
+
+impl Default for DisplayTransform {
+   fn default() -> DisplayTransform
+   {
+      return DisplayTransform { exposure: 0., tone_mapper: ToneMapper::Clamp, white: None, encoding: Encoding::Linear };
+   }
+}
+
+
+// Hable's curve before it is scaled to put the white point at 1
+fn hable_partial(x: f64) -> f64
+{
+   let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+   return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+
+impl DisplayTransform {
+   // One channel <x> of linear radiance as a display value in [0, 1]
+   fn map(&self, x: f64) -> f64
+   {
+      let x = f64::max(x * 2f64.powf(self.exposure), 0.);
+      let white = self.white.unwrap_or(self.tone_mapper.default_white());
+      let mapped = match self.tone_mapper {
+         ToneMapper::Clamp => x,
+         ToneMapper::Reinhard => x / (1. + x),
+         ToneMapper::ExtendedReinhard => x * (1. + x / (white * white)) / (1. + x),
+         ToneMapper::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+         ToneMapper::Hable => hable_partial(2. * x) / hable_partial(2. * white)
+      };
+      let mapped = mapped.clamp(0., 1.);
+
+      return match self.encoding {
+         Encoding::Linear => mapped,
+         Encoding::Srgb => {
+            if mapped <= 0.0031308 {
+               12.92 * mapped
+            } else {
+               1.055 * mapped.powf(1. / 2.4) - 0.055
+            }
+         }
+         Encoding::Gamma(gamma) => mapped.powf(1. / gamma)
+      };
+   }
+
+   // The pixel <rgb> of linear radiance as display values in [0, 1]
+   pub fn apply(&self, rgb: &Vector3<f64>) -> Vector3<f64>
+   {
+      return Vector3::new(self.map(rgb[0]), self.map(rgb[1]), self.map(rgb[2]));
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn transform(tone_mapper: ToneMapper, encoding: Encoding) -> DisplayTransform
+   {
+      return DisplayTransform { tone_mapper, encoding, ..DisplayTransform::default() };
+   }
+
+   #[test]
+   fn tone_mappers_rise_from_black_to_white()
+   {
+      for tone_mapper in [ToneMapper::Clamp, ToneMapper::Reinhard, ToneMapper::ExtendedReinhard,
+                          ToneMapper::Aces, ToneMapper::Hable] {
+         let display = transform(tone_mapper, Encoding::Linear);
+         assert!(display.map(0.).abs() < 1.0e-9);
+         assert_eq!(display.map(-1.), display.map(0.));
+         let mut previous = display.map(0.);
+         for k in 1..=200 {
+            let mapped = display.map(0.1 * k as f64);
+            assert!(mapped >= previous && mapped <= 1.);
+            previous = mapped;
+         }
+      }
+      assert_eq!(transform(ToneMapper::Clamp, Encoding::Linear).map(0.25), 0.25);
+      assert_eq!(transform(ToneMapper::Reinhard, Encoding::Linear).map(1.), 0.5);
+   }
+
+   #[test]
+   fn the_white_point_comes_out_white()
+   {
+      for tone_mapper in [ToneMapper::ExtendedReinhard, ToneMapper::Hable] {
+         let mut display = transform(tone_mapper, Encoding::Linear);
+         assert!((display.map(tone_mapper.default_white()) - 1.).abs() < 1.0e-12);
+         assert!(display.map(0.9 * tone_mapper.default_white()) < 1.);
+         display.white = Some(2.);
+         assert!((display.map(2.) - 1.).abs() < 1.0e-12);
+      }
+   }
+
+   #[test]
+   fn exposure_is_in_stops()
+   {
+      let display = DisplayTransform { exposure: 2., ..DisplayTransform::default() };
+      assert_eq!(display.apply(&Vector3::new(0.125, 0.25, 1.)), Vector3::new(0.5, 1., 1.));
+   }
+
+   #[test]
+   fn srgb_follows_its_transfer_function()
+   {
+      let srgb = transform(ToneMapper::Clamp, Encoding::Srgb);
+      assert_eq!(srgb.map(0.), 0.);
+      assert!((srgb.map(1.) - 1.).abs() < 1.0e-12);
+      // Linear near black, and the two pieces meet
+      assert!((srgb.map(0.001) - 0.01292).abs() < 1.0e-12);
+      assert!((srgb.map(0.0031308) - srgb.map(0.0031309)).abs() < 1.0e-5);
+      // Mid gray (18%) lands close to the middle
+      assert!((srgb.map(0.18) - 0.4614).abs() < 1.0e-3);
+      let gamma = transform(ToneMapper::Clamp, Encoding::from_name("2"));
+      assert_eq!(gamma.map(0.25), 0.5);
+   }
+}
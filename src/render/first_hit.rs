@@ -1,7 +1,7 @@
 use nalgebra as na;
 use na::{ Vector3 };
 
-use crate::scene::Ray;
+use crate::scene::{ Ray, Material };
 use crate::geometry::Object;
 
 
@@ -24,7 +24,7 @@ pub fn first_hit(ray: &Ray, min_t: f64, objects: &Vec<Box<dyn Object + Sync>>, h
             min_distance = _t;
             *t = _t;
             *n = _n;
-            *hit_id = i as usize;
+            *hit_id = i;
          }
       }
    }
@@ -34,6 +34,33 @@ pub fn first_hit(ray: &Ray, min_t: f64, objects: &Vec<Box<dyn Object + Sync>>, h
 }
 
 
+// first_hit() for a hit that gets shaded: also hands back the material there
+// (see Object::intersect_material)
+pub fn first_hit_material<'a>(ray: &Ray, min_t: f64, objects: &'a [Box<dyn Object + Sync>], hit_id: &mut usize, t: &mut f64, n: &mut Vector3<f64>) -> Option<&'a Material>
+{
+   let mut material = None;
+
+   let mut _t = 0.0;
+   let mut _n = Vector3::new(0.,0.,0.);
+   let mut min_distance = f64::INFINITY;
+
+   for (i, object) in objects.iter().enumerate() {
+      if let Some(mat) = object.intersect_material(ray, min_t, &mut _t, &mut _n) {
+         // Keep the closest hit
+         if _t < min_distance {
+            min_distance = _t;
+            *t = _t;
+            *n = _n;
+            *hit_id = i;
+            material = Some(mat);
+         }
+      }
+   }
+
+   return material;
+}
+
+
 
 
 // Equivalent to above, maybe more readable?
@@ -0,0 +1,366 @@
+/**
+ * Light going through participating media: which medium a ray is in, how
+ * much of the light makes it along a ray, and (for the Whitted renderer)
+ * the light scattered towards the viewer along the way. Media are either
+ * the scene's fog, which fills everything, or the inside of closed objects
+ * whose material has a medium. Objects with media don't nest: leaving one
+ * always means going back into the fog.
+ *
+ * Media shaped by a voxel grid are handled by tracking: points are picked
+ * along rays as if the medium were everywhere as dense as its densest voxel,
+ * and the actual density at each says what happens there.
+ */
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+use rand::Rng;
+use rand::rngs::SmallRng;
+
+use crate::scene::{ Ray, Light, AmbientLight, Material, Medium };
+use crate::geometry::Object;
+use crate::render::first_hit_material;
+
+
+// Points along each ray where the Whitted renderer gathers scattered light
+const SCATTER_SAMPLES: usize = 8;
+
+
+// The medium a ray leaving a surface (with outward normal <n>, made of
+// <material>) in <direction> is in, if it was in <current> before. Only
+// surfaces around a medium change it.
+pub fn medium_after<'a>(direction: &Vector3<f64>, n: &Vector3<f64>, material: &'a Material,
+                        current: Option<&'a Medium>, fog: Option<&'a Medium>) -> Option<&'a Medium>
+{
+   if material.medium.is_none() {
+      return current;
+   }
+   if direction.dot(n) < 0. {
+      return material.medium.as_ref();
+   }
+   return fog;
+}
+
+
+/**
+ * Fraction of light that makes it along <ray> from its origin, which is in
+ * <medium>, to <max_t> (in units of the ray's direction, as for first_hit).
+ * Zero if anything solid is in the way. Interfaces (surfaces light goes
+ * straight through) don't block it, but the media they bound dim it. (For
+ * media with a grid this is a random estimate.) With a <wavelength>, the
+ * media are seen at it (see Medium::at_wavelength).
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn transmittance(ray: &Ray, max_t: f64, objects: &Vec<Box<dyn Object + Sync>>, fog: Option<&Medium>,
+                     medium: Option<&Medium>, wavelength: Option<f64>, rng: &mut SmallRng) -> Vector3<f64>
+{
+   let mut through = Vector3::new(1., 1., 1.);
+   let mut sray = Ray { origin: ray.origin, direction: ray.direction };
+   let mut remaining = max_t;
+   let mut medium = medium;
+   let length = ray.direction.norm();
+   let mut s_hit_id = 0usize;
+   let mut s_t = 0.;
+   let mut s_n = Vector3::new(0., 0., 0.);
+   loop {
+      let material = first_hit_material(&sray, 1.0e-6, objects, &mut s_hit_id, &mut s_t, &mut s_n);
+      let hit = material.is_some();
+      let end = if hit && s_t < remaining { s_t } else { remaining };
+      if let Some(medium) = medium {
+         let spectral = wavelength.map(|lambda| medium.at_wavelength(lambda));
+         let medium = spectral.as_ref().unwrap_or(medium);
+         let d = sray.direction / length;
+         through = through.component_mul(&medium.transmittance_along(&sray.origin, &d, end * length, rng));
+      }
+      if !hit || s_t >= remaining {
+         return through;
+      }
+
+      let material = material.unwrap();
+      if !material.is_interface() {
+         return Vector3::new(0., 0., 0.);
+      }
+      medium = medium_after(&sray.direction, &s_n, material, medium, fog);
+      sray.origin += s_t * sray.direction;
+      remaining -= s_t;
+   }
+}
+
+
+/**
+ * Light scattered towards the viewer by <medium> along <ray> up to <max_t>
+ * (possibly infinite), from the lights (through whatever is in the way) and
+ * the ambient light. Estimated at a few points picked at random, more of them
+ * where less of the medium is in front.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn in_scattering(ray: &Ray, max_t: f64, medium: &Medium, fog: Option<&Medium>,
+                     objects: &Vec<Box<dyn Object + Sync>>, lights: &Vec<Box<dyn Light + Sync>>,
+                     ambient: &AmbientLight, rng: &mut SmallRng) -> Vector3<f64>
+{
+   if medium.grid.is_some() {
+      return grid_in_scattering(ray, max_t, medium, fog, objects, lights, ambient, rng);
+   }
+   let mut rgb = Vector3::new(0., 0., 0.);
+   // Distances are sampled by how dense the medium is on average
+   let sigma = medium.sigma_t().mean();
+   if sigma == 0. || medium.sigma_s == Vector3::new(0., 0., 0.) {
+      return rgb;
+   }
+   let length = ray.direction.norm();
+   let d = ray.direction / length;
+   let wo = -d;
+   // Chance of a distance being picked before the end
+   let reach = 1. - (-sigma * max_t * length).exp();
+
+   for k in 0..SCATTER_SAMPLES {
+      let u = (k as f64 + rng.gen::<f64>()) / SCATTER_SAMPLES as f64;
+      let s = -(1. - u * reach).ln() / sigma;
+      let pdf = sigma * (-sigma * s).exp() / reach;
+      let x = ray.origin + s * d;
+
+      let incoming = incoming_light(&x, &wo, medium, fog, objects, lights, ambient, rng);
+      rgb += medium.transmittance(s).component_mul(&medium.sigma_s).component_mul(&incoming) / pdf;
+   }
+   return rgb / SCATTER_SAMPLES as f64;
+}
+
+
+// Light arriving at the point <x> in <medium>, weighted by the phase function
+// for scattering it towards <wo>
+#[allow(clippy::too_many_arguments)]
+fn incoming_light(x: &Vector3<f64>, wo: &Vector3<f64>, medium: &Medium, fog: Option<&Medium>,
+                  objects: &Vec<Box<dyn Object + Sync>>, lights: &Vec<Box<dyn Light + Sync>>,
+                  ambient: &AmbientLight, rng: &mut SmallRng) -> Vector3<f64>
+{
+   // The ambient light comes from everywhere, and the phase function adds up
+   // to one over all directions
+   let mut incoming = ambient.I;
+   let mut l = Vector3::new(0., 0., 0.);
+   for light in lights.iter() {
+      let mut max_t = 0.;
+      light.direction(x, &mut l, &mut max_t);
+      let sray = Ray { origin: *x, direction: l };
+      let through = transmittance(&sray, max_t, objects, fog, Some(medium), None, rng);
+      // The same scale as for surfaces: PI I is what a white Lambertian
+      // surface would need to reflect I
+      incoming += PI * medium.phase.phase(wo, &l) * light.get_intensity().component_mul(&through);
+   }
+   return incoming;
+}
+
+
+// in_scattering() for a medium shaped by a grid, which also glows: the part
+// of the ray inside the grid is split into equal steps, with a random point
+// in each, and how much light gets from one point to the next is tracked
+#[allow(clippy::too_many_arguments)]
+fn grid_in_scattering(ray: &Ray, max_t: f64, medium: &Medium, fog: Option<&Medium>,
+                      objects: &Vec<Box<dyn Object + Sync>>, lights: &Vec<Box<dyn Light + Sync>>,
+                      ambient: &AmbientLight, rng: &mut SmallRng) -> Vector3<f64>
+{
+   let mut rgb = Vector3::new(0., 0., 0.);
+   let grid = medium.grid.as_ref().unwrap();
+   let length = ray.direction.norm();
+   let d = ray.direction / length;
+   let (t_near, t_far) = match grid.clip(&ray.origin, &d, max_t * length) {
+      Some(span) => span,
+      None => return rgb
+   };
+
+   let step = (t_far - t_near) / SCATTER_SAMPLES as f64;
+   let mut through = Vector3::new(1., 1., 1.);
+   let mut previous = t_near;
+   for k in 0..SCATTER_SAMPLES {
+      let s = t_near + (k as f64 + rng.gen::<f64>()) * step;
+      through = through.component_mul(&medium.transmittance_along(&(ray.origin + previous * d), &d, s - previous, rng));
+      previous = s;
+      if through == Vector3::new(0., 0., 0.) {
+         break;
+      }
+
+      let x = ray.origin + s * d;
+      let density = grid.density(&x);
+      let mut light = medium.emission(&x);
+      if density > 0. {
+         let incoming = incoming_light(&x, &-d, medium, fog, objects, lights, ambient, rng);
+         light += density * medium.sigma_s.component_mul(&incoming);
+      }
+      rgb += step * through.component_mul(&light);
+   }
+   return rgb;
+}
+
+
+/**
+ * Pick how far a path along the unit direction <d> from <origin> in
+ * <medium> gets before it scatters, if it does before <distance>. The
+ * <throughput> of the path is weighted for what happened on the way (and
+ * zero if it was absorbed), and light the medium gives off on the way is
+ * added to <rgb>.
+ *
+ * Without a grid, the distance is sampled from the density of one of the
+ * channels, picked at random. With one, by delta tracking: points are picked
+ * as if the medium were everywhere as dense as it gets, and the path
+ * scatters at each with the chance that the medium there actually does.
+ *
+ * With a <wavelength>, the medium is seen at it (see Medium::at_wavelength).
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn free_flight(origin: &Vector3<f64>, d: &Vector3<f64>, distance: f64, medium: &Medium, wavelength: Option<f64>,
+                   rng: &mut SmallRng, throughput: &mut Vector3<f64>, rgb: &mut Vector3<f64>) -> Option<f64>
+{
+   let spectral = wavelength.map(|lambda| medium.at_wavelength(lambda));
+   let medium = spectral.as_ref().unwrap_or(medium);
+   let sigma_t = medium.sigma_t();
+   let grid = match &medium.grid {
+      Some(grid) => grid,
+      None => {
+         let c = ((rng.gen::<f64>() * 3.) as usize).min(2);
+         let s = if sigma_t[c] > 0. { -(1. - rng.gen::<f64>()).ln() / sigma_t[c] } else { f64::INFINITY };
+         if s < distance {
+            // Scattered. Weighted by the medium over the density of picking
+            // <s>, averaged over the channels that could have.
+            let through = medium.transmittance(s);
+            let pdf = sigma_t.component_mul(&through).mean();
+            *throughput = throughput.component_mul(&medium.sigma_s).component_mul(&through) / pdf;
+            return Some(s);
+         }
+         // Made it through, which was as likely as the transmittance is on
+         // average
+         let through = medium.transmittance(distance);
+         let pdf = through.mean();
+         *throughput = if pdf == 0. { Vector3::new(0., 0., 0.) } else { throughput.component_mul(&through) / pdf };
+         return None;
+      }
+   };
+
+   let (t_near, t_far) = grid.clip(origin, d, distance)?;
+   let majorant = medium.majorant();
+   if majorant == 0. {
+      return None;
+   }
+   let mut s = t_near;
+   loop {
+      s -= (1. - rng.gen::<f64>()).ln() / majorant;
+      if s >= t_far {
+         return None;
+      }
+      let x = origin + s * d;
+      let density = grid.density(&x);
+      *rgb += throughput.component_mul(&medium.emission(&x)) / majorant;
+
+      // A real collision with the chance of the medium being there, on
+      // average over the channels, otherwise a null one that lets the path
+      // go on. Either way weighted so that every channel comes out right.
+      let sigma_t = density * medium.sigma_t();
+      let real = sigma_t.mean() / majorant;
+      if rng.gen::<f64>() < real {
+         *throughput = throughput.component_mul(&(density * medium.sigma_s)) / sigma_t.mean();
+         return Some(s);
+      }
+      let null = sigma_t.map(|sigma| majorant - sigma);
+      *throughput = throughput.component_mul(&null) / (majorant - sigma_t.mean());
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use rand::SeedableRng;
+   use crate::scene::{ Scattering, Lambertian, Interface, VoxelGrid };
+   use crate::geometry::{ Sphere, Plane };
+
+   // The average throughput of free flights of <distance> through <medium>,
+   // split by whether they scattered
+   fn average_flight(medium: &Medium, distance: f64) -> (Vector3<f64>, Vector3<f64>)
+   {
+      let mut rng = SmallRng::seed_from_u64(1);
+      let (mut scattered, mut through) = (Vector3::zeros(), Vector3::zeros());
+      let n = 200000;
+      for _ in 0..n {
+         let mut throughput = Vector3::new(1., 1., 1.);
+         let mut rgb = Vector3::zeros();
+         let d = Vector3::new(0., 0., 1.);
+         match free_flight(&Vector3::zeros(), &d, distance, medium, None, &mut rng, &mut throughput, &mut rgb) {
+            Some(s) => {
+               assert!(s >= 0. && s < distance);
+               scattered += throughput;
+            }
+            None => through += throughput
+         }
+         assert_eq!(rgb, Vector3::zeros());
+      }
+      return (scattered / n as f64, through / n as f64);
+   }
+
+   #[test]
+   fn free_flights_let_through_the_transmittance()
+   {
+      // Absorbs only, more so in blue
+      let medium = Medium::new(Vector3::new(0.1, 0.5, 2.), Vector3::zeros(), 0.);
+      let (scattered, through) = average_flight(&medium, 1.);
+      assert_eq!(scattered, Vector3::zeros());
+      assert!((through - medium.transmittance(1.)).abs().max() < 0.01, "{:?}", through);
+   }
+
+   #[test]
+   fn free_flights_without_absorption_keep_all_the_light()
+   {
+      // Scatters only, so whatever doesn't make it through scatters once
+      let medium = Medium::new(Vector3::zeros(), Vector3::new(0.2, 1., 3.), 0.5);
+      let (scattered, through) = average_flight(&medium, 1.);
+      assert!((scattered + through - Vector3::new(1., 1., 1.)).abs().max() < 0.02, "{:?} {:?}", scattered, through);
+      assert!((through - medium.transmittance(1.)).abs().max() < 0.02, "{:?}", through);
+   }
+
+   #[test]
+   fn interfaces_switch_between_their_medium_and_the_fog()
+   {
+      let fog = Medium::new(Vector3::new(0.1, 0.1, 0.1), Vector3::zeros(), 0.);
+      let mut water = Material::from_scattering(Scattering::Lambertian(Lambertian::new(Vector3::zeros())));
+      let n = Vector3::new(0., 0., 1.);
+      // No medium: nothing changes
+      assert!(medium_after(&-n, &n, &water, None, Some(&fog)).is_none());
+      water.medium = Some(Medium::new(Vector3::new(0.5, 0.2, 0.1), Vector3::zeros(), 0.));
+      assert_eq!(medium_after(&-n, &n, &water, Some(&fog), Some(&fog)).unwrap().sigma_a, Vector3::new(0.5, 0.2, 0.1));
+      assert_eq!(medium_after(&n, &n, &water, water.medium.as_ref(), Some(&fog)).unwrap().sigma_a, fog.sigma_a);
+   }
+
+   #[test]
+   fn shadow_rays_are_dimmed_by_every_medium_on_the_way()
+   {
+      // A ball of water of radius 1 in the fog, whose surface lets light through
+      let fog = Medium::new(Vector3::new(0.01, 0.02, 0.03), Vector3::new(0.01, 0.01, 0.01), 0.);
+      let water = Medium::new(Vector3::new(0.5, 0.2, 0.1), Vector3::zeros(), 0.);
+      let mut surface = Material::from_scattering(Scattering::Interface(Interface));
+      surface.medium = Some(water.clone());
+      let objects: Vec<Box<dyn Object + Sync>> = vec![Box::new(Sphere::new(&Vector3::zeros(), 1., surface))];
+      let mut rng = SmallRng::seed_from_u64(1);
+
+      // From x = -3 to x = 3, in steps of 2: 4 units of fog and 2 of water
+      let ray = Ray { origin: Vector3::new(-3., 0., 0.), direction: Vector3::new(2., 0., 0.) };
+      let through = transmittance(&ray, 3., &objects, Some(&fog), Some(&fog), None, &mut rng);
+      let expected = fog.transmittance(4.).component_mul(&water.transmittance(2.));
+      assert!((through - expected).norm() < 1.0e-12);
+
+      // Anything solid stops it
+      let objects: Vec<Box<dyn Object + Sync>> = vec![
+         Box::new(Plane::new(&Vector3::zeros(), &Vector3::new(1., 0., 0.), Material::gray()))
+      ];
+      assert_eq!(transmittance(&ray, 3., &objects, Some(&fog), Some(&fog), None, &mut rng), Vector3::zeros());
+   }
+
+   #[test]
+   fn delta_tracking_matches_the_plain_medium()
+   {
+      let medium = Medium::new(Vector3::new(0.1, 0.5, 2.), Vector3::new(1., 0.5, 0.2), 0.);
+      let mut gridded = medium.clone();
+      let grid = VoxelGrid::new([2, 2, 2], &Vector3::new(-1., -1., -1.), &Vector3::new(1., 1., 1.), vec![1.; 8], None);
+      gridded.set_grid(grid, 1., 1.);
+      let (scattered, through) = average_flight(&medium, 1.);
+      let (gridded_scattered, gridded_through) = average_flight(&gridded, 1.);
+      assert!((scattered - gridded_scattered).abs().max() < 0.02, "{:?} {:?}", scattered, gridded_scattered);
+      assert!((through - gridded_through).abs().max() < 0.02, "{:?} {:?}", through, gridded_through);
+   }
+}
@@ -2,11 +2,44 @@ pub mod raycolor;
 pub use self::raycolor::raycolor;
 
 pub mod first_hit;
-pub use self::first_hit::first_hit;
+pub use self::first_hit::{ first_hit, first_hit_material };
 
 pub mod blinn_phong_shading;
 pub use self::blinn_phong_shading::blinn_phong_shading;
 
+pub mod ambient_occlusion;
+pub use self::ambient_occlusion::{ ambient_occlusion, visibility };
+
+pub mod aov;
+pub use self::aov::{ Aov, aov_values, aov_to_display };
+
+pub mod area_lights;
+pub use self::area_lights::AreaLights;
+
+pub mod media;
+pub use self::media::{ medium_after, transmittance, in_scattering, free_flight };
+
+pub mod photon_map;
+pub use self::photon_map::PhotonMap;
+
+pub mod path_trace;
+pub use self::path_trace::path_trace;
+
+pub mod bidirectional;
+pub use self::bidirectional::{ bidirectional, check_no_media };
+
+pub mod display;
+pub use self::display::{ DisplayTransform, ToneMapper, Encoding };
+
+pub mod denoise;
+pub use self::denoise::denoise;
+
+pub mod pixel_stats;
+pub use self::pixel_stats::PixelStats;
+
+pub mod settings;
+pub use self::settings::{ RenderMode, RenderSettings, ImageFormat, Dither };
+
 
 pub mod reflect;
 pub use self::reflect::reflect;
@@ -0,0 +1,261 @@
+// for the capital I
+#![allow(non_snake_case)]
+
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+use rand::Rng;
+use rand::rngs::SmallRng;
+
+use crate::scene::{ Ray, Light, Bsdf, Medium };
+use crate::geometry::Object;
+use crate::render::{ first_hit_material, AreaLights, PhotonMap, medium_after, transmittance, free_flight };
+use crate::spectrum::color_at;
+
+
+// Light arriving straight from the lights at <p> and reflected towards <wo>.
+// Intensities follow the Whitted renderer's convention: a white Lambertian
+// surface facing a light of intensity I (at any distance) reflects I.
+// Shadow rays start in the medium <media>.0 when they leave on the side <n>
+// points to, <media>.1 otherwise. Light colors are taken at <wavelength>, if
+// there is one.
+#[allow(clippy::too_many_arguments)]
+fn direct_light(p: &Vector3<f64>, wo: &Vector3<f64>, n: &Vector3<f64>, bsdf: &dyn Bsdf,
+                objects: &Vec<Box<dyn Object + Sync>>, lights: &Vec<Box<dyn Light + Sync>>,
+                fog: Option<&Medium>, media: (Option<&Medium>, Option<&Medium>), wavelength: Option<f64>,
+                rng: &mut SmallRng) -> Vector3<f64>
+{
+   let mut rgb = Vector3::new(0., 0., 0.);
+   let mut l = Vector3::new(0., 0., 0.);
+   for light in lights.iter() {
+      let mut max_t = 0.;
+      light.direction(p, &mut l, &mut max_t);
+      let f = bsdf.eval(wo, &l, n);
+      if f == Vector3::new(0., 0., 0.) {
+         continue;
+      }
+
+      let sray = Ray { origin: *p, direction: l };
+      let medium = if l.dot(n) >= 0. { media.0 } else { media.1 };
+      let through = transmittance(&sray, max_t, objects, fog, medium, wavelength, rng);
+      if through != Vector3::new(0., 0., 0.) {
+         let I = color_at(&light.get_intensity(), wavelength).component_mul(&through);
+         rgb += PI * f.component_mul(&I);
+      }
+   }
+   return rgb;
+}
+
+
+// Light arriving at <p> from a point picked on one of the emissive objects,
+// weighted against the chance of the BSDF sampling the same direction
+// (through <media> and at <wavelength> as for direct_light)
+#[allow(clippy::too_many_arguments)]
+fn area_light(p: &Vector3<f64>, wo: &Vector3<f64>, n: &Vector3<f64>, bsdf: &dyn Bsdf,
+              objects: &Vec<Box<dyn Object + Sync>>, area_lights: &AreaLights, u: [f64; 3],
+              fog: Option<&Medium>, media: (Option<&Medium>, Option<&Medium>), wavelength: Option<f64>,
+              rng: &mut SmallRng) -> Vector3<f64>
+{
+   let zero = Vector3::new(0., 0., 0.);
+   let sample = match area_lights.sample(objects, p, u) {
+      Some(sample) => sample,
+      None => return zero
+   };
+   let f = bsdf.eval(wo, &sample.wi, n);
+   if f == zero {
+      return zero;
+   }
+
+   // Anything (including the far side of the light itself) in the way?
+   let sray = Ray { origin: *p, direction: sample.wi };
+   let medium = if sample.wi.dot(n) >= 0. { media.0 } else { media.1 };
+   let through = transmittance(&sray, sample.distance * (1. - 1.0e-6), objects, fog, medium, wavelength, rng);
+   if through == zero {
+      return zero;
+   }
+
+   let weight = power_heuristic(sample.pdf, bsdf.pdf(wo, &sample.wi, n));
+   let radiance = color_at(&sample.radiance, wavelength);
+   return weight / sample.pdf * f.component_mul(&radiance).component_mul(&through);
+}
+
+
+// Multiple importance sampling weight for a direction picked with density
+// <pdf> by one strategy when another would have picked it with <other_pdf>
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64
+{
+   return pdf * pdf / (pdf * pdf + other_pdf * other_pdf);
+}
+
+
+/**
+ * Estimate the light coming back along <ray> with one random path: at each
+ * hit add the direct light from every light source and from a point on one
+ * emissive object, then continue in a direction sampled from the material.
+ * Emissive objects found by that bounce are weighed against having sampled
+ * them directly (multiple importance sampling), so small bright lights and
+ * large dim ones both come out smooth. After a few bounces paths are ended
+ * at random (Russian roulette), with the survivors weighted up to make up
+ * for it.
+ *
+ * In a medium (the <fog>, or the inside of an object that has one) a path
+ * may also scatter before reaching the next surface, at a distance picked
+ * by how dense the medium is, and is lit and continued there the same way,
+ * with the medium's phase function in place of a BSDF.
+ *
+ * With a <wavelength> the path carries light of just that wavelength:
+ * colors are turned into their spectra's values there, which are the same
+ * in all three channels of the result, dielectrics with dispersion bend
+ * it by their index for it, and media absorb, scatter and glow as much as
+ * they do at that wavelength.
+ *
+ * With <photons>, caustics come from the photon map instead: its estimate
+ * is added at every surface that isn't perfectly specular, and emissive
+ * objects the path reaches from there through perfectly specular bounces
+ * alone don't count, as the photons already brought their light.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn path_trace(ray: &Ray,
+                  objects: &Vec<Box<dyn Object + Sync>>,
+                  lights: &Vec<Box<dyn Light + Sync>>,
+                  area_lights: &AreaLights,
+                  fog: Option<&Medium>,
+                  photons: Option<&PhotonMap>,
+                  wavelength: Option<f64>,
+                  max_depth: usize,
+                  rng: &mut SmallRng) -> Vector3<f64>
+{
+   let mut rgb = Vector3::new(0., 0., 0.);
+   let mut throughput = Vector3::new(1., 1., 1.);
+   let mut ray = Ray { origin: ray.origin, direction: ray.direction };
+   // Density the last bounce was sampled with, zero for the camera ray and
+   // perfectly specular bounces, which light sampling can't reproduce, and
+   // where it happened (interfaces in between don't count)
+   let mut bsdf_pdf = 0.;
+   let mut bounced_from = ray.origin;
+   // The camera is in the fog (and not inside any object)
+   let mut medium = fog;
+   // Whether the photon map was looked at since the last bounce that wasn't
+   // perfectly specular
+   let mut gathered = false;
+
+   let mut depth = 0;
+   while depth < max_depth {
+      let mut n = Vector3::new(0., 0., 0.);
+      let mut t = 0.;
+      let mut hit_id = 0usize;
+      let mat = first_hit_material(&ray, 1.0e-6, objects, &mut hit_id, &mut t, &mut n);
+      let hit = mat.is_some();
+
+      if let Some(medium) = medium {
+         // The path may scatter (or be absorbed) before it gets to the surface
+         let length = ray.direction.norm();
+         let d = ray.direction / length;
+         let distance = if hit { t * length } else { f64::INFINITY };
+         let scattered = free_flight(&ray.origin, &d, distance, medium, wavelength, rng, &mut throughput, &mut rgb);
+         if throughput == Vector3::new(0., 0., 0.) {
+            break;
+         }
+
+         if let Some(s) = scattered {
+            let p = ray.origin + s * d;
+            let wo = -d;
+            let phase = &medium.phase;
+            // The phase function has no use for a normal, but the shadow
+            // rays need one to pick their medium: any will do
+            let media = (Some(medium), Some(medium));
+            rgb += throughput.component_mul(&direct_light(&p, &wo, &wo, phase, objects, lights, fog, media, wavelength, rng));
+            if !area_lights.is_empty() {
+               let u = [rng.gen(), rng.gen(), rng.gen()];
+               rgb += throughput.component_mul(&area_light(&p, &wo, &wo, phase, objects, area_lights, u, fog, media, wavelength, rng));
+            }
+
+            let sample = match phase.sample(&wo, &wo, [rng.gen(), rng.gen(), rng.gen()]) {
+               Some(sample) => sample,
+               None => break
+            };
+            bsdf_pdf = sample.pdf;
+            bounced_from = p;
+            gathered = false;
+
+            if depth >= 3 {
+               let survive = throughput.max().min(0.95);
+               if rng.gen::<f64>() >= survive {
+                  break;
+               }
+               throughput /= survive;
+            }
+            ray = Ray { origin: p, direction: sample.wi };
+            depth += 1;
+            continue;
+         }
+      }
+
+      if !hit {
+         break;
+      }
+
+      let p = ray.origin + t * ray.direction;
+      let wo = -ray.direction.normalize();
+      let n = n.normalize();
+      let mat = mat.unwrap();
+      let spectral = wavelength.map(|lambda| mat.scattering.at_wavelength(lambda));
+      let bsdf = match &spectral {
+         Some(scattering) => scattering.bsdf(),
+         None => mat.bsdf()
+      };
+
+      // Straight through interfaces, into or out of the medium they bound
+      if mat.is_interface() {
+         medium = medium_after(&ray.direction, &n, mat, medium, fog);
+         ray = Ray { origin: p, direction: ray.direction };
+         continue;
+      }
+
+      // Caustics the photons already brought
+      let caustic = gathered && bsdf_pdf == 0. && area_lights.contains(hit_id);
+      if mat.is_emissive() && !caustic {
+         let mut weight = 1.;
+         if bsdf_pdf > 0. {
+            weight = power_heuristic(bsdf_pdf, area_lights.pdf(objects, hit_id, &bounced_from, &p, &n));
+         }
+         rgb += weight * throughput.component_mul(&color_at(&mat.emission, wavelength));
+      }
+
+      // Media on either side of the surface, for the shadow rays
+      let media = (medium_after(&n, &n, mat, medium, fog), medium_after(&-n, &n, mat, medium, fog));
+      rgb += throughput.component_mul(&direct_light(&p, &wo, &n, bsdf, objects, lights, fog, media, wavelength, rng));
+      if !area_lights.is_empty() {
+         let u = [rng.gen(), rng.gen(), rng.gen()];
+         rgb += throughput.component_mul(&area_light(&p, &wo, &n, bsdf, objects, area_lights, u, fog, media, wavelength, rng));
+      }
+      if let Some(photons) = photons.filter(|_| !mat.is_perfectly_specular()) {
+         rgb += throughput.component_mul(&color_at(&photons.radiance(&p, &wo, &n, bsdf), wavelength));
+      }
+
+      let sample = match bsdf.sample(&wo, &n, [rng.gen(), rng.gen(), rng.gen()]) {
+         Some(sample) => sample,
+         None => break
+      };
+      throughput = throughput.component_mul(&sample.weight);
+      bsdf_pdf = sample.pdf;
+      bounced_from = p;
+      if sample.pdf > 0. {
+         gathered = photons.is_some();
+      }
+
+      if depth >= 3 {
+         let survive = throughput.max().min(0.95);
+         if rng.gen::<f64>() >= survive {
+            break;
+         }
+         throughput /= survive;
+      }
+      medium = medium_after(&sample.wi, &n, mat, medium, fog);
+      ray = Ray { origin: p, direction: sample.wi };
+      depth += 1;
+   }
+
+   return rgb;
+}
@@ -0,0 +1,532 @@
+/**
+ * Photon mapping for caustics: light focused onto a surface by mirrors and
+ * glass, which paths from the camera almost never find (and never do from
+ * point lights). Photons are shot from the lights at the objects that can
+ * focus light, followed through their perfectly specular bounces, and
+ * stored where they land on anything else. The caustic light leaving a
+ * point is then estimated from the photons found within a small radius of
+ * it, each weighted by the BSDF for the direction it came from.
+ *
+ * Photons from point lights follow the renderers' convention that point
+ * lights don't fall off with distance: their power grows with the square of
+ * the distance travelled, so that a lens brightens what is behind it by how
+ * much it concentrates the light and no more.
+ */
+use std::collections::BinaryHeap;
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+use rand::{ Rng, SeedableRng };
+use rand::rngs::SmallRng;
+use rayon::prelude::*;
+
+use crate::scene::{ Ray, Light, Bsdf, Material, Scattering, Medium };
+use crate::geometry::{ Object, orthonormal_basis };
+use crate::render::{ first_hit_material, medium_after, AreaLights };
+use crate::spectrum::{ color_at, sample_wavelength, wavelength_to_rgb };
+
+
+// Photons are shot in blocks of this many, each with its own random numbers
+const BLOCK_SIZE: usize = 4096;
+
+// How fast the radius shrinks from one pass to the next in progressive
+// photon mapping (Knaus and Zwicker's alpha)
+const PROGRESSIVE_ALPHA: f64 = 2. / 3.;
+
+// Photons looked at to pick a largest radius when none is given
+const SUGGESTION_SAMPLES: usize = 64;
+
+// Photons only count on surfaces facing about the same way as the one being
+// shaded, so they don't leak around corners
+const MIN_NORMAL_COSINE: f64 = 0.9;
+
+
+// Where a photon landed, the unit direction back to where it came from, and
+// the light it carries
+#[derive(Clone, Copy)]
+pub struct Photon {
+   pub position: Vector3<f64>,
+   pub normal: Vector3<f64>,
+   pub wi: Vector3<f64>,
+   pub power: Vector3<f64>
+}
+
+
+/**
+ * The photons, as a balanced kd-tree laid out in a single array: the
+ * photon in the middle of each range splits it along <axes> at its index,
+ * with the ones before it on the low side and the ones after it on the high
+ * side.
+ *
+ * The light at a point is found from its <neighbors> nearest photons, within
+ * <max_radius> at most, so the estimate is sharp where photons are dense and
+ * smooth where they're sparse. In progressive photon mapping only those
+ * within <scale> times the distance to the farthest of them count, with
+ * <scale> shrinking a little every pass.
+ */
+pub struct PhotonMap {
+   photons: Vec<Photon>,
+   axes: Vec<usize>,
+   pub neighbors: usize,
+   pub max_radius: f64,
+   pub scale: f64
+}
+
+
+// Surfaces with a perfectly specular part, which can focus light
+fn casts_caustics(material: &Material) -> bool
+{
+   return match &material.scattering {
+      Scattering::Mirror(_) | Scattering::Dielectric(_) => true,
+      Scattering::BlinnPhong(bsdf) => bsdf.km != Vector3::new(0., 0., 0.),
+      _ => false
+   };
+}
+
+
+// Where photons are shot: at the spheres bounding the objects that cast
+// caustics, or everywhere if any of them can't be bounded
+struct Targets {
+   spheres: Vec<(Vector3<f64>, f64)>,
+   everywhere: bool
+}
+
+
+impl Targets {
+   // A direction from <origin> towards one of the targets (picked at
+   // random) and its density per solid angle
+   fn sample(&self, origin: &Vector3<f64>, u: [f64; 3]) -> (Vector3<f64>, f64)
+   {
+      if self.everywhere {
+         let z = 1. - 2. * u[1];
+         let r = (1. - z * z).max(0.).sqrt();
+         let phi = 2. * PI * u[2];
+         return (Vector3::new(r * phi.cos(), r * phi.sin(), z), 1. / (4. * PI));
+      }
+      let k = ((u[0] * self.spheres.len() as f64) as usize).min(self.spheres.len() - 1);
+      let (center, radius) = self.spheres[k];
+      let to_center = center - origin;
+      let distance = to_center.norm();
+      let cos_max = if distance > radius { (1. - (radius / distance).powi(2)).sqrt() } else { -1. };
+
+      // Uniform over the cone of directions that can hit the sphere
+      let w = to_center / distance;
+      let (a, b) = orthonormal_basis(&w);
+      let cos_theta = 1. - u[1] * (1. - cos_max);
+      let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+      let phi = 2. * PI * u[2];
+      let d = sin_theta * (phi.cos() * a + phi.sin() * b) + cos_theta * w;
+      return (d, self.pdf(origin, &d));
+   }
+
+   // Density of sample() picking the unit direction <d> from <origin>
+   fn pdf(&self, origin: &Vector3<f64>, d: &Vector3<f64>) -> f64
+   {
+      if self.everywhere {
+         return 1. / (4. * PI);
+      }
+      let mut pdf = 0.;
+      for (center, radius) in self.spheres.iter() {
+         let to_center = center - origin;
+         let distance = to_center.norm();
+         if distance <= *radius {
+            pdf += 1. / (4. * PI);
+            continue;
+         }
+         let cos_max = (1. - (radius / distance).powi(2)).sqrt();
+         if d.dot(&to_center) / distance >= cos_max {
+            pdf += 1. / (2. * PI * (1. - cos_max));
+         }
+      }
+      return pdf / self.spheres.len() as f64;
+   }
+}
+
+
+impl PhotonMap {
+   /**
+    * Shoot <count> photons from the point lights in <lights> and from
+    * <area_lights>, if given, and keep the ones that reach a surface that
+    * isn't perfectly specular after at least one perfectly specular bounce,
+    * within <max_depth> bounces. With <spectral> each photon has a single
+    * wavelength, so dispersive glass splits it up. <seed> picks the random
+    * numbers. A <max_radius> of zero is picked to suit the photons found.
+    */
+   #[allow(clippy::too_many_arguments)]
+   pub fn shoot(count: usize, objects: &Vec<Box<dyn Object + Sync>>, lights: &Vec<Box<dyn Light + Sync>>,
+                area_lights: Option<&AreaLights>, fog: Option<&Medium>, spectral: bool, max_depth: usize,
+                neighbors: usize, max_radius: f64, scale: f64, seed: u64) -> PhotonMap
+   {
+      let mut targets = Targets { spheres: Vec::new(), everywhere: false };
+      for object in objects.iter() {
+         if casts_caustics(object.get_material()) {
+            match object.bounding_sphere() {
+               Some(sphere) => targets.spheres.push(sphere),
+               None => targets.everywhere = true
+            }
+         }
+      }
+
+      // Every point light, and the area lights as one more
+      let points: Vec<&Box<dyn Light + Sync>> = lights.iter().filter(|light| light.position().is_some()).collect();
+      let area_lights = area_lights.filter(|area_lights| !area_lights.is_empty());
+      let n_emitters = points.len() + if area_lights.is_some() { 1 } else { 0 };
+
+      let mut photons = Vec::new();
+      if count > 0 && n_emitters > 0 && (targets.everywhere || !targets.spheres.is_empty()) {
+         let n_blocks = count.div_ceil(BLOCK_SIZE);
+         photons = (0..n_blocks).into_par_iter().flat_map_iter(|block| {
+            let mut rng = SmallRng::seed_from_u64(seed * n_blocks as u64 + block as u64);
+            let mut found = Vec::new();
+            for _ in (block * BLOCK_SIZE)..((block + 1) * BLOCK_SIZE).min(count) {
+               let wavelength = if spectral { Some(sample_wavelength(rng.gen())) } else { None };
+               let lambda = wavelength.map(|(lambda, _)| lambda);
+
+               // Pick a light, a point on it and a direction
+               let k = ((rng.gen::<f64>() * n_emitters as f64) as usize).min(n_emitters - 1);
+               let u = [rng.gen(), rng.gen(), rng.gen()];
+               let (start, d, power, from_point) = if k < points.len() {
+                  let origin = points[k].position().unwrap();
+                  let (d, pdf) = targets.sample(&origin, u);
+                  // A white Lambertian surface facing a point light of
+                  // intensity I reflects I: it gets PI I per unit area
+                  let power = PI * color_at(&points[k].get_intensity(), lambda) / pdf;
+                  (origin, d, power, true)
+               } else {
+                  let (id, q, n, area_pdf) = match area_lights.unwrap().sample_point(objects, [rng.gen(), rng.gen(), rng.gen()]) {
+                     Some(sample) => sample,
+                     None => continue
+                  };
+                  let (d, pdf) = targets.sample(&q, u);
+                  let radiance = color_at(&objects[id].get_material().emission, lambda);
+                  (q, d, radiance * n.dot(&d).abs() / (area_pdf * pdf), false)
+               };
+               let power = power * n_emitters as f64 / count as f64;
+
+               trace_photon(&start, &d, power, from_point, objects, fog, lambda, max_depth, &mut rng,
+                            &mut |mut photon: Photon| {
+                  if let Some((lambda, pdf)) = wavelength {
+                     photon.power = wavelength_to_rgb(lambda, photon.power.mean() / pdf);
+                  }
+                  found.push(photon);
+               });
+            }
+            return found;
+         }).collect();
+      }
+
+      let mut axes = vec![0; photons.len()];
+      build(&mut photons, &mut axes);
+      let mut map = PhotonMap { photons, axes, neighbors: neighbors.max(1), max_radius, scale };
+      if map.max_radius <= 0. {
+         map.max_radius = map.suggested_radius();
+      }
+      return map;
+   }
+
+   pub fn len(&self) -> usize
+   {
+      return self.photons.len();
+   }
+
+   pub fn is_empty(&self) -> bool
+   {
+      return self.photons.is_empty();
+   }
+
+   // A largest radius for when the scene doesn't give one: far enough for
+   // all but the loneliest tenth of the photons, from a sample of them, to
+   // find their neighbors
+   fn suggested_radius(&self) -> f64
+   {
+      if self.photons.len() <= self.neighbors {
+         return 1.;
+      }
+      let step = self.photons.len().div_ceil(SUGGESTION_SAMPLES);
+      let mut distances: Vec<f64> = (0..self.photons.len()).step_by(step).map(|k| {
+         let mut nearest = BinaryHeap::new();
+         self.nearest(0, self.photons.len(), &self.photons[k].position, None, f64::INFINITY, &mut nearest);
+         return f64::from_bits(nearest.peek().unwrap().0).sqrt();
+      }).collect();
+      let k = distances.len() * 9 / 10;
+      let (_, radius, _) = distances.select_nth_unstable_by(k, |a, b| a.total_cmp(b));
+      return radius.max(1.0e-6);
+   }
+
+   // The scale for the pass after <pass> (counting from zero) of a
+   // progressive render: the area photons are gathered from shrinks slowly
+   // enough that each pass keeps most of the photons the one before had
+   pub fn next_scale(&self, pass: usize) -> f64
+   {
+      let i = (pass + 1) as f64;
+      return self.scale * ((i + PROGRESSIVE_ALPHA) / (i + 1.)).sqrt();
+   }
+
+   /**
+    * Caustic light leaving <p> (with unit normal <n>, scattering by <bsdf>)
+    * towards <wo>: the nearest photons, each scattered by the BSDF, over the
+    * area of the disk they were found in.
+    */
+   pub fn radiance(&self, p: &Vector3<f64>, wo: &Vector3<f64>, n: &Vector3<f64>, bsdf: &dyn Bsdf) -> Vector3<f64>
+   {
+      let mut sum = Vector3::new(0., 0., 0.);
+      let mut nearest = BinaryHeap::with_capacity(self.neighbors + 1);
+      let max_r2 = self.max_radius * self.max_radius;
+      self.nearest(0, self.photons.len(), p, Some(n), max_r2, &mut nearest);
+      if nearest.is_empty() {
+         return sum;
+      }
+
+      // The disk reaches the farthest of them, unless there weren't enough
+      // within the largest radius
+      let r2 = if nearest.len() < self.neighbors { max_r2 } else { f64::from_bits(nearest.peek().unwrap().0) };
+      let r2 = self.scale * self.scale * r2;
+      for (d2, k) in nearest.into_iter() {
+         let photon = &self.photons[k];
+         let cos = n.dot(&photon.wi).abs();
+         if f64::from_bits(d2) <= r2 && cos > 0. {
+            sum += bsdf.eval(wo, &photon.wi, n).component_mul(&photon.power) / cos;
+         }
+      }
+      return sum / (PI * r2);
+   }
+
+   /**
+    * Keep the <neighbors> photons of the subtree over [lo, hi) nearest to
+    * <p>, within sqrt(<max_r2>), in <nearest> with the farthest on top. Each
+    * is kept as its squared distance, as bits (which sort like the distances
+    * do, as they're positive), and its index. With <n> only the photons on
+    * surfaces facing about the same way count.
+    */
+   fn nearest(&self, lo: usize, hi: usize, p: &Vector3<f64>, n: Option<&Vector3<f64>>, max_r2: f64,
+              nearest: &mut BinaryHeap<(u64, usize)>)
+   {
+      if lo >= hi {
+         return;
+      }
+      let mid = (lo + hi) / 2;
+      let photon = &self.photons[mid];
+      let d2 = (photon.position - p).norm_squared();
+      let facing = n.is_none_or(|n| photon.normal.dot(n) >= MIN_NORMAL_COSINE);
+      if facing && d2 <= max_r2 {
+         if nearest.len() < self.neighbors {
+            nearest.push((d2.to_bits(), mid));
+         } else if d2 < f64::from_bits(nearest.peek().unwrap().0) {
+            nearest.pop();
+            nearest.push((d2.to_bits(), mid));
+         }
+      }
+
+      // The side <p> is on first, the other one only if the sphere that
+      // could still hold nearer photons reaches it
+      let delta = p[self.axes[mid]] - photon.position[self.axes[mid]];
+      let (near, far) = if delta < 0. { ((lo, mid), (mid + 1, hi)) } else { ((mid + 1, hi), (lo, mid)) };
+      self.nearest(near.0, near.1, p, n, max_r2, nearest);
+      let reach = if nearest.len() < self.neighbors { max_r2 } else { f64::from_bits(nearest.peek().unwrap().0) };
+      if delta * delta <= reach {
+         self.nearest(far.0, far.1, p, n, max_r2, nearest);
+      }
+   }
+}
+
+
+// Arrange <photons> into the kd-tree, splitting each range at its median
+// along the axis it is most spread out on
+fn build(photons: &mut [Photon], axes: &mut [usize])
+{
+   if photons.len() <= 1 {
+      return;
+   }
+   let mut min = photons[0].position;
+   let mut max = min;
+   for photon in photons.iter() {
+      min = min.inf(&photon.position);
+      max = max.sup(&photon.position);
+   }
+   let axis = (max - min).imax();
+   let mid = photons.len() / 2;
+   photons.select_nth_unstable_by(mid, |a, b| a.position[axis].total_cmp(&b.position[axis]));
+   axes[mid] = axis;
+
+   let (low, rest) = photons.split_at_mut(mid);
+   let (low_axes, rest_axes) = axes.split_at_mut(mid);
+   build(low, low_axes);
+   build(&mut rest[1..], &mut rest_axes[1..]);
+}
+
+
+// Follow a photon carrying <power> from <origin> in the unit direction <d>
+// through perfectly specular bounces, handing each place it lands to <store>.
+// Photons from a point light (<from_point>) get brighter with the square of
+// the distance travelled.
+#[allow(clippy::too_many_arguments)]
+fn trace_photon(origin: &Vector3<f64>, d: &Vector3<f64>, power: Vector3<f64>, from_point: bool,
+                objects: &Vec<Box<dyn Object + Sync>>, fog: Option<&Medium>, wavelength: Option<f64>,
+                max_depth: usize, rng: &mut SmallRng, store: &mut dyn FnMut(Photon))
+{
+   let mut ray = Ray { origin: *origin, direction: *d };
+   let mut power = power;
+   let mut medium = fog;
+   let mut travelled = 0.;
+   let mut specular = false;
+
+   let mut depth = 0;
+   while depth < max_depth && power != Vector3::new(0., 0., 0.) {
+      let mut n = Vector3::new(0., 0., 0.);
+      let mut t = 0.;
+      let mut hit_id = 0usize;
+      let material = match first_hit_material(&ray, 1.0e-6, objects, &mut hit_id, &mut t, &mut n) {
+         Some(material) => material,
+         None => return
+      };
+      travelled += t;
+      if let Some(medium) = medium {
+         let spectral = wavelength.map(|lambda| medium.at_wavelength(lambda));
+         let medium = spectral.as_ref().unwrap_or(medium);
+         power = power.component_mul(&medium.transmittance_along(&ray.origin, &ray.direction, t, rng));
+      }
+
+      let p = ray.origin + t * ray.direction;
+      let wo = -ray.direction;
+      let n = n.normalize();
+      if material.is_interface() {
+         medium = medium_after(&ray.direction, &n, material, medium, fog);
+         ray = Ray { origin: p, direction: ray.direction };
+         continue;
+      }
+
+      if specular && !material.is_perfectly_specular() {
+         let scale = if from_point { travelled * travelled } else { 1. };
+         store(Photon { position: p, normal: n, wi: wo, power: scale * power });
+      }
+
+      // Only perfectly specular bounces carry the caustic on
+      let spectral = wavelength.map(|lambda| material.scattering.at_wavelength(lambda));
+      let bsdf = match &spectral {
+         Some(scattering) => scattering.bsdf(),
+         None => material.bsdf()
+      };
+      let sample = match bsdf.sample(&wo, &n, [rng.gen(), rng.gen(), rng.gen()]) {
+         Some(sample) if sample.pdf == 0. => sample,
+         _ => return
+      };
+      power = power.component_mul(&sample.weight);
+      specular = true;
+      medium = medium_after(&sample.wi, &n, material, medium, fog);
+      ray = Ray { origin: p, direction: sample.wi };
+      depth += 1;
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::scene::{ Lambertian, Mirror, PointLight };
+   use crate::geometry::{ Plane, Disk };
+
+   fn map(photons: Vec<Photon>, neighbors: usize) -> PhotonMap
+   {
+      let mut photons = photons;
+      let mut axes = vec![0; photons.len()];
+      build(&mut photons, &mut axes);
+      return PhotonMap { photons, axes, neighbors, max_radius: f64::INFINITY, scale: 1. };
+   }
+
+   fn photon(position: Vector3<f64>) -> Photon
+   {
+      let up = Vector3::new(0., 0., 1.);
+      return Photon { position, normal: up, wi: up, power: Vector3::new(1., 1., 1.) };
+   }
+
+   #[test]
+   fn the_tree_finds_the_nearest_photons()
+   {
+      let mut rng = SmallRng::seed_from_u64(1);
+      let photons: Vec<Photon> = (0..2000).map(|_| photon(Vector3::new(rng.gen(), rng.gen(), rng.gen()))).collect();
+      let map = map(photons.clone(), 10);
+      for _ in 0..50 {
+         let p = Vector3::new(rng.gen(), rng.gen(), rng.gen());
+         let mut nearest = BinaryHeap::new();
+         map.nearest(0, map.len(), &p, None, f64::INFINITY, &mut nearest);
+         let mut found: Vec<f64> = nearest.into_iter().map(|(d2, _)| f64::from_bits(d2)).collect();
+         found.sort_by(|a, b| a.total_cmp(b));
+
+         let mut all: Vec<f64> = photons.iter().map(|photon| (photon.position - p).norm_squared()).collect();
+         all.sort_by(|a, b| a.total_cmp(b));
+         assert_eq!(found, all[..10].to_vec());
+
+         // Within a radius, only what's there
+         let mut nearest = BinaryHeap::new();
+         map.nearest(0, map.len(), &p, None, all[4], &mut nearest);
+         assert_eq!(nearest.len(), 5);
+      }
+   }
+
+   #[test]
+   fn only_photons_on_surfaces_facing_the_same_way_count()
+   {
+      let mut photons = vec![photon(Vector3::zeros()), photon(Vector3::new(0.1, 0., 0.))];
+      photons[1].normal = Vector3::new(1., 0., 0.);
+      let map = map(photons, 10);
+      let mut nearest = BinaryHeap::new();
+      map.nearest(0, map.len(), &Vector3::zeros(), Some(&Vector3::new(0., 0., 1.)), 1., &mut nearest);
+      assert_eq!(nearest.len(), 1);
+   }
+
+   #[test]
+   fn even_photons_give_their_density_of_light()
+   {
+      // 100 x 100 photons over the unit square, a power of 1 per unit area
+      // landing on a surface of albedo 0.5
+      let steps = 100;
+      let mut photons = Vec::new();
+      for i in 0..steps {
+         for j in 0..steps {
+            let mut p = photon(Vector3::new((i as f64 + 0.5) / steps as f64, (j as f64 + 0.5) / steps as f64, 0.));
+            p.power /= (steps * steps) as f64;
+            photons.push(p);
+         }
+      }
+      let map = map(photons, 200);
+      let bsdf = Lambertian::new(Vector3::new(0.5, 0.5, 0.5));
+      let up = Vector3::new(0., 0., 1.);
+      let rgb = map.radiance(&Vector3::new(0.5, 0.5, 0.), &up, &up, &bsdf);
+      // (A little high, as the farthest photon sits right on the disk's edge)
+      assert!((rgb.x - 0.5 / PI).abs() < 0.03 * 0.5 / PI, "{:?}", rgb);
+   }
+
+   #[test]
+   fn a_mirror_reflects_a_point_light_onto_the_floor()
+   {
+      // A light under a mirror: the floor gets what it would from the
+      // light's image behind the mirror, which reflects I with no falloff
+      let floor = Material::from_scattering(Scattering::Lambertian(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))));
+      let mirror = Material::from_scattering(Scattering::Mirror(Mirror::new(Vector3::new(1., 1., 1.))));
+      let down = Vector3::new(0., -1., 0.);
+      let objects: Vec<Box<dyn Object + Sync>> = vec![
+         Box::new(Plane::new(&Vector3::zeros(), &-down, floor.clone())),
+         Box::new(Disk::new(&Vector3::new(0., 2., 0.), &down, 20., mirror))
+      ];
+      let lights: Vec<Box<dyn Light + Sync>> = vec![Box::new(PointLight::new(Vector3::new(1., 1., 1.), Vector3::new(0., 1., 0.)))];
+      let map = PhotonMap::shoot(400000, &objects, &lights, None, None, false, 4, 200, 0.3, 1., 1);
+      assert!(!map.is_empty());
+
+      let up = -down;
+      let rgb = map.radiance(&Vector3::zeros(), &up, &up, floor.bsdf());
+      assert!((rgb.x - 0.5).abs() < 0.05, "{:?}", rgb);
+      // Further out, with the image of the light 3 above, at 45 degrees
+      let rgb = map.radiance(&Vector3::new(3., 0., 0.), &up, &up, floor.bsdf());
+      assert!((rgb.x - 0.5 * 0.5f64.sqrt()).abs() < 0.05, "{:?}", rgb);
+   }
+
+   #[test]
+   fn progressive_passes_shrink_the_radius_slowly()
+   {
+      let map = map(Vec::new(), 10);
+      let first = map.next_scale(0);
+      assert!(first < 1. && first > 0.9);
+      assert!(map.next_scale(10) > first);
+   }
+}
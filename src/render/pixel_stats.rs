@@ -0,0 +1,138 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::bsdf::luminance;
+
+
+/**
+ * Running statistics of the samples taken for one pixel: their sum, for the
+ * mean color, and the mean and variance of their luminance, updated one
+ * sample at a time with Welford's method. The variance tells how noisy the
+ * pixel still is, which is what adaptive sampling goes by.
+ */
+#[derive(Clone, Copy)]
+pub struct PixelStats {
+   // Samples taken
+   pub n: usize,
+   sum: Vector3<f64>,
+   mean_luminance: f64,
+   // Sum of squared differences from the mean luminance
+   m2: f64
+}
+
+
+impl Default for PixelStats {
+   fn default() -> PixelStats
+   {
+      return PixelStats { n: 0, sum: Vector3::new(0., 0., 0.), mean_luminance: 0., m2: 0. };
+   }
+}
+
+
+impl PixelStats {
+   pub fn add(&mut self, rgb: &Vector3<f64>)
+   {
+      self.n += 1;
+      self.sum += rgb;
+      let l = luminance(rgb);
+      let delta = l - self.mean_luminance;
+      self.mean_luminance += delta / self.n as f64;
+      self.m2 += delta * (l - self.mean_luminance);
+   }
+
+   // The pixel's color so far, black before the first sample
+   pub fn mean(&self) -> Vector3<f64>
+   {
+      if self.n == 0 {
+         return Vector3::new(0., 0., 0.);
+      }
+      return self.sum / self.n as f64;
+   }
+
+   // How far off the mean luminance is likely to be: the variance of the
+   // samples over their number. Unknown (infinite) before two samples.
+   pub fn variance_of_mean(&self) -> f64
+   {
+      if self.n < 2 {
+         return f64::INFINITY;
+      }
+      return self.m2 / (self.n - 1) as f64 / self.n as f64;
+   }
+
+   // The standard error of the mean luminance relative to the luminance
+   // itself. Near black pixels are measured against 0.01 instead, so a
+   // little noise in the shadows doesn't count as a huge error.
+   pub fn relative_error(&self) -> f64
+   {
+      return self.variance_of_mean().sqrt() / f64::max(self.mean_luminance, 0.01);
+   }
+
+   // Whether the pixel has had at least <min_samples> and its relative error
+   // is down to <threshold>
+   pub fn converged(&self, min_samples: usize, threshold: f64) -> bool
+   {
+      return self.n >= min_samples && self.relative_error() <= threshold;
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn gray(l: f64) -> Vector3<f64>
+   {
+      return Vector3::new(l, l, l);
+   }
+
+   #[test]
+   fn matches_the_two_pass_mean_and_variance()
+   {
+      // Grays have their own value as luminance
+      assert!((luminance(&gray(1.)) - 1.).abs() < 1.0e-12);
+      let samples = [0.5, 2., 1., 4., 0.25, 3.];
+      let mut stats = PixelStats::default();
+      assert_eq!(stats.mean(), gray(0.));
+      assert_eq!(stats.variance_of_mean(), f64::INFINITY);
+      stats.add(&gray(samples[0]));
+      assert_eq!(stats.variance_of_mean(), f64::INFINITY);
+      for &l in samples[1..].iter() {
+         stats.add(&gray(l));
+      }
+
+      let n = samples.len() as f64;
+      let mean = samples.iter().sum::<f64>() / n;
+      let variance = samples.iter().map(|l| (l - mean) * (l - mean)).sum::<f64>() / (n - 1.);
+      assert_eq!(stats.n, samples.len());
+      assert!((stats.mean() - gray(mean)).norm() < 1.0e-12);
+      assert!((stats.variance_of_mean() - variance / n).abs() < 1.0e-12);
+      assert!((stats.relative_error() - (variance / n).sqrt() / mean).abs() < 1.0e-12);
+   }
+
+   #[test]
+   fn converges_once_the_noise_is_down()
+   {
+      let mut stats = PixelStats::default();
+      for _ in 0..4 {
+         stats.add(&gray(1.));
+      }
+      // No noise at all, but not enough samples yet
+      assert_eq!(stats.relative_error(), 0.);
+      assert!(!stats.converged(8, 0.01));
+      assert!(stats.converged(4, 0.01));
+
+      // Alternating 0 and 2: a standard error of 1 / sqrt(n) about a mean of 1
+      let mut stats = PixelStats::default();
+      for k in 0..100 {
+         stats.add(&gray(2. * (k % 2) as f64));
+      }
+      assert!((stats.relative_error() - (100f64 / 99.).sqrt() / 10.).abs() < 1.0e-9);
+      assert!(!stats.converged(16, 0.05) && stats.converged(16, 0.2));
+
+      // Dark pixels are measured against 0.01
+      let mut stats = PixelStats::default();
+      stats.add(&gray(0.));
+      stats.add(&gray(0.002));
+      assert!((stats.relative_error() - 0.001 / 0.01).abs() < 1.0e-9);
+   }
+}
@@ -1,17 +1,24 @@
 use nalgebra as na;
 use na::{ Vector3 };
+use rand::rngs::SmallRng;
 
-use crate::scene::{ Ray, Light };
-use crate::render::{ first_hit, blinn_phong_shading, reflect };
+use crate::scene::{ Ray, Light, AmbientLight, Medium };
+use crate::render::{ first_hit_material, blinn_phong_shading, medium_after, in_scattering, PhotonMap };
 use crate::geometry::Object;
 
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn raycolor(  ray: &Ray, 
                   min_t: f64, 
                   objects: &Vec<Box<dyn Object + Sync>>, 
                   lights: &Vec<Box<dyn Light + Sync>>, 
+                  ambient: &AmbientLight,
+                  fog: Option<&Medium>,
+                  medium: Option<&Medium>,
+                  photons: Option<&PhotonMap>,
                   n_recursive_calls: usize, 
+                  rng: &mut SmallRng,
                   rgb: &mut Vector3<f64>) -> bool
 {
    if n_recursive_calls > 4 {
@@ -23,31 +30,63 @@ pub fn raycolor(  ray: &Ray,
    let mut t = 0.0f64;
    let mut hit_id = 0usize;
 
-   // Check if we hit an object. If not, then return false:
-   if !first_hit(&ray, min_t, objects, &mut hit_id, &mut t, &mut n) {
-      return false;
-   }
+   // Check if we hit an object. If not, then return false, unless the ray
+   // goes through a medium, which lights up along the whole way:
+   let mat = match first_hit_material(ray, min_t, objects, &mut hit_id, &mut t, &mut n) {
+      Some(mat) => mat,
+      None => {
+         if let Some(medium) = medium {
+            *rgb = in_scattering(ray, f64::INFINITY, medium, fog, objects, lights, ambient, rng);
+            return true;
+         }
+         return false;
+      }
+   };
 
    // If we made it here, then the viewing ray has intersected an object (hit).
    // Now we evauluate the shading model and set the pixel color:
-   *rgb = blinn_phong_shading(ray, hit_id, &t, &n, objects, lights);
+   *rgb = blinn_phong_shading(ray, mat, &t, &n, objects, lights, ambient, fog, medium, rng);
 
-   // Now we add ideal specular reflection and mirror reflection (pg 87).
-   // We need to set up a new mirror ray (mray):
-   // Get the real intersection point between the viewing ray and the surface
+   // Glowing surfaces show their own light on top of what they reflect.
+   // (Only the path tracer lets them light up anything else.)
+   *rgb += mat.emission;
+
+   // Plus the light mirrors and glass focus onto it
+   if let Some(photons) = photons.filter(|_| !mat.is_perfectly_specular()) {
+      *rgb += photons.radiance(&(ray.origin + t * ray.direction), &-ray.direction.normalize(), &n.normalize(), mat.bsdf());
+   }
+
+   // Now we add ideal specular reflection and mirror reflection (pg 87),
+   // and refraction for glass: follow every perfectly specular direction the
+   // material has. We need to set up a new ray (mray) for each, from the real
+   // intersection point between the viewing ray and the surface
    let e = ray.origin;
    let d = ray.direction;
-   let mray = Ray{origin: e + t * d, direction: reflect(&d.normalize(), &n.normalize())};
-
-   // Now we can recursively add to our rgb pixel color. 
-   // s = 1e-5 is our fudge factor to move off the surface
-   let mut mrgb = Vector3::new(0.,0.,0.);
-   if raycolor(&mray, 1.0e-6, objects, lights, n_recursive_calls + 1, &mut mrgb) {
-      // Then we can update the rgb value. Componenet wise multiplication
-      let mat = objects[hit_id].get_material();
-      *rgb += Vector3::new(mat.km[0] * mrgb[0], mat.km[1] * mrgb[1], mat.km[2] * mrgb[2]);
+   let bsdf = mat.bsdf();
+   // Going through an interface isn't a bounce, so it doesn't count
+   // towards the limit
+   let n_calls = if mat.is_interface() { n_recursive_calls } else { n_recursive_calls + 1 };
+   for (direction, weight) in bsdf.specular(&-d.normalize(), &n.normalize()) {
+      let mray = Ray{origin: e + t * d, direction};
+      // Refracted rays may go into (or out of) a medium
+      let mmedium = medium_after(&mray.direction, &n, mat, medium, fog);
+
+      // Now we can recursively add to our rgb pixel color. 
+      // s = 1e-5 is our fudge factor to move off the surface
+      let mut mrgb = Vector3::new(0.,0.,0.);
+      if raycolor(&mray, 1.0e-6, objects, lights, ambient, fog, mmedium, photons, n_calls, rng, &mut mrgb) {
+         // Then we can update the rgb value. Componenet wise multiplication
+         *rgb += Vector3::new(weight[0] * mrgb[0], weight[1] * mrgb[1], weight[2] * mrgb[2]);
+      }
    }
 
+   // Whatever the ray found is dimmed by the medium on the way, which adds
+   // the light it scatters towards us
+   if let Some(medium) = medium {
+      let distance = t * d.norm();
+      *rgb = medium.transmittance_along(&e, &d.normalize(), distance, rng).component_mul(rgb)
+         + in_scattering(ray, t, medium, fog, objects, lights, ambient, rng);
+   }
 
    return true;
 }
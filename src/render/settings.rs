@@ -0,0 +1,179 @@
+use crate::render::{ Aov, DisplayTransform };
+
+
+// Which algorithm computes the pixel colors
+#[derive(Clone, Copy, PartialEq)]
+pub enum RenderMode {
+   // One ray per pixel, Blinn-Phong shading with shadow and mirror rays
+   Whitted,
+   // Monte Carlo path tracing, averaging <samples> paths per pixel
+   PathTrace,
+   // Bidirectional path tracing: <samples> pairs of camera and light
+   // subpaths per pixel, joined every way they can be
+   Bidirectional,
+   // Grayscale ambient occlusion: how open the surroundings of each
+   // primary hit are
+   AmbientOcclusion
+}
+
+
+impl RenderMode {
+   // The mode called <name> in scene files and on the command line
+   pub fn from_name(name: &str) -> RenderMode
+   {
+      return match name {
+         "whitted" => RenderMode::Whitted,
+         "path" => RenderMode::PathTrace,
+         "bdpt" => RenderMode::Bidirectional,
+         "ao" => RenderMode::AmbientOcclusion,
+         _ => panic!("Error: unknown render mode \"{}\" (expected \"whitted\", \"path\", \"bdpt\" or \"ao\")", name)
+      };
+   }
+}
+
+
+// File formats the render can be saved in
+#[derive(Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+   // Binary PPM, 8 or 16 bits, clamped to [0, 1]
+   Ppm,
+   // PNG, 8 or 16 bits, clamped to [0, 1]
+   Png,
+   // Portable float map, 32-bit floats
+   Pfm,
+   // Radiance RGBE (.hdr)
+   Hdr,
+   // OpenEXR, 32-bit floats, with the extra passes as layers of one file
+   Exr
+}
+
+
+impl ImageFormat {
+   // The format called <name> in scene files and on the command line, which
+   // is also its file extension
+   pub fn from_name(name: &str) -> ImageFormat
+   {
+      return match name {
+         "ppm" => ImageFormat::Ppm,
+         "png" => ImageFormat::Png,
+         "pfm" => ImageFormat::Pfm,
+         "hdr" => ImageFormat::Hdr,
+         "exr" => ImageFormat::Exr,
+         _ => panic!("Error: unknown image format \"{}\" (expected \"ppm\", \"png\", \"pfm\", \"hdr\" or \"exr\")", name)
+      };
+   }
+
+   pub fn extension(&self) -> &str
+   {
+      return match self {
+         ImageFormat::Ppm => "ppm",
+         ImageFormat::Png => "png",
+         ImageFormat::Pfm => "pfm",
+         ImageFormat::Hdr => "hdr",
+         ImageFormat::Exr => "exr"
+      };
+   }
+}
+
+
+// How the error from rounding to whole levels is spread out in the 8 and 16
+// bit images
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dither {
+   // Plain truncation, which can show bands on smooth gradients
+   None,
+   // An 8 x 8 Bayer matrix: fine but regular crosshatching
+   Bayer,
+   // A blue noise mask: grain without any visible pattern
+   BlueNoise
+}
+
+
+impl Dither {
+   pub fn from_name(name: &str) -> Dither
+   {
+      return match name {
+         "none" => Dither::None,
+         "bayer" => Dither::Bayer,
+         "blue_noise" => Dither::BlueNoise,
+         _ => panic!("Error: unknown dither \"{}\" (expected \"none\", \"bayer\" or \"blue_noise\")", name)
+      };
+   }
+}
+
+
+pub struct RenderSettings {
+   pub mode: RenderMode,
+   // Paths per pixel (path tracing only, bidirectional or not)
+   pub samples: usize,
+   // Longest path, in bounces (path tracing only, bidirectional or not)
+   pub max_depth: usize,
+   // Give each path a single wavelength, for dispersion (path tracing only,
+   // bidirectional or not)
+   pub spectral: bool,
+   // Photons shot for the caustics (none without), how many of the nearest
+   // ones the light at a point is found from, and how far they can be (zero
+   // to pick a distance). Progressive renders shoot them again every pass,
+   // gathering from less far each time.
+   pub photons: usize,
+   pub photon_neighbors: usize,
+   pub photon_radius: f64,
+
+   // Hemisphere rays per pixel for ambient occlusion, and the distance
+   // beyond which hits don't occlude
+   pub ao_samples: usize,
+   pub ao_distance: f64,
+   // Also write an ambient occlusion image next to the render
+   pub ao_pass: bool,
+
+   // Extra passes written next to the render
+   pub aovs: Vec<Aov>,
+
+   // Every format the images are saved in
+   pub formats: Vec<ImageFormat>,
+   // Exposure, tone mapping and encoding for the PPM and PNG images
+   pub display: DisplayTransform,
+   // Quantization of the PPM and PNG images: 8 or 16 bits per channel,
+   // and the dithering
+   pub bit_depth: usize,
+   pub dither: Dither,
+
+   // Render in passes of one sample per pixel, averaged into the image, up
+   // to <samples> (or <ao_samples>) passes. Stops early after <time_limit>
+   // seconds, and saves what it has every <snapshot_passes> passes (zero for
+   // never) or <snapshot_seconds> seconds.
+   pub progressive: bool,
+   pub time_limit: f64,
+   pub snapshot_passes: usize,
+   pub snapshot_seconds: f64,
+
+   // Keep sampling a pixel only while it's noisy: stop once it has had
+   // <min_samples> and the standard error of its brightness is down to
+   // <error_threshold> of the brightness, or at <samples>. In Whitted mode
+   // this spreads rays over each pixel, which otherwise gets one.
+   pub adaptive: bool,
+   pub min_samples: usize,
+   pub error_threshold: f64,
+   // Also write an image of how many samples each pixel took
+   pub sample_map: bool,
+
+   // Smooth out the noise before saving, averaging over up to
+   // <denoise_radius> pixels around each one
+   pub denoise: bool,
+   pub denoise_radius: usize
+}
+
+
+impl Default for RenderSettings {
+   fn default() -> RenderSettings
+   {
+      return RenderSettings { mode: RenderMode::Whitted, samples: 16, max_depth: 8, spectral: false,
+                              photons: 0, photon_neighbors: 50, photon_radius: 0.,
+                              ao_samples: 16, ao_distance: f64::INFINITY, ao_pass: false, aovs: Vec::new(),
+                              formats: vec![ImageFormat::Ppm], display: DisplayTransform::default(),
+                              bit_depth: 8, dither: Dither::None,
+                              progressive: false, time_limit: f64::INFINITY, snapshot_passes: 0,
+                              snapshot_seconds: f64::INFINITY, adaptive: false, min_samples: 8,
+                              error_threshold: 0.02, sample_map: false, denoise: false, denoise_radius: 5 };
+   }
+}
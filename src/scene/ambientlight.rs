@@ -0,0 +1,37 @@
+// for the capital I
+#![allow(non_snake_case)]
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+
+// Light that reaches everything equally, standing in for all the light
+// bouncing around the scene. It isn't a Light: it has no direction and
+// casts no shadows, except when ambient occlusion is turned on.
+pub struct AmbientLight {
+   // Intensity (color)
+   pub I: Vector3<f64>,
+
+   // Rays shot over the hemisphere to see how much of the surroundings is
+   // open, zero to turn ambient occlusion off
+   pub occlusion_samples: usize,
+   // Hits further away than this don't count as occluding
+   pub occlusion_distance: f64
+}
+
+
+impl AmbientLight {
+   pub fn new(I: Vector3<f64>) -> AmbientLight
+   {
+      return AmbientLight { I, occlusion_samples: 0, occlusion_distance: f64::INFINITY };
+   }
+}
+
+
+// What scenes without an ambient light get: a dim white
+impl Default for AmbientLight {
+   fn default() -> AmbientLight
+   {
+      return AmbientLight::new(Vector3::new(0.1, 0.1, 0.1));
+   }
+}
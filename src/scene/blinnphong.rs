@@ -0,0 +1,148 @@
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::bsdf::{ Bsdf, BsdfSample, luminance, facing, cosine_sample_hemisphere };
+use crate::render::reflect;
+
+
+// The classic (not energy conserving) ambient + Lambert + Blinn-Phong
+// specular model, plus a mirror reflection. Both sides of the surface look
+// the same.
+#[derive(Clone)]
+pub struct BlinnPhong {
+   // Ambient, Diffuse, Specular, Mirror color
+   pub ka: Vector3<f64>,
+   pub kd: Vector3<f64>,
+   pub ks: Vector3<f64>,
+   pub km: Vector3<f64>,
+
+   // Phong exponent
+   pub phong_exp: f64
+}
+
+
+impl BlinnPhong {
+   pub fn new(ka: Vector3<f64>, kd: Vector3<f64>, ks: Vector3<f64>, km: Vector3<f64>, phong_exp: f64) -> BlinnPhong
+   {
+      return BlinnPhong { ka, kd, ks, km, phong_exp };
+   }
+
+   // Chance that sample() takes the mirror bounce
+   fn mirror_probability(&self) -> f64
+   {
+      let mirror = luminance(&self.km);
+      let total = mirror + luminance(&self.kd) + luminance(&self.ks);
+      if total <= 0. {
+         return 0.;
+      }
+      return mirror / total;
+   }
+}
+
+
+impl Bsdf for BlinnPhong {
+   // The diffuse and specular shading terms, over PI so that a light of
+   // intensity I adds kd I (n . l) + ks I (n . h)^p like it always has
+   fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, n: &Vector3<f64>) -> Vector3<f64>
+   {
+      let n = facing(n, wo);
+      let h = (wo + wi).normalize();
+      let diffuse = self.kd * f64::max(0.0, n.dot(wi));
+      let specular = self.ks * f64::powf(f64::max(0.0, n.dot(&h)), self.phong_exp);
+      return (diffuse + specular) / PI;
+   }
+
+   // Either the mirror reflection or a cosine weighted bounce for the diffuse
+   // and specular terms, in proportion to their brightness
+   fn sample(&self, wo: &Vector3<f64>, n: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample>
+   {
+      let p_mirror = self.mirror_probability();
+      if u[0] < p_mirror {
+         return Some(BsdfSample { wi: reflect(&-wo, n), weight: self.km / p_mirror, pdf: 0. });
+      }
+      if self.kd == Vector3::new(0., 0., 0.) && self.ks == Vector3::new(0., 0., 0.) {
+         return None;
+      }
+
+      let wi = cosine_sample_hemisphere(&facing(n, wo), u[1], u[2]);
+      let pdf = self.pdf(wo, &wi, n);
+      if pdf <= 0. {
+         return None;
+      }
+      return Some(BsdfSample { wi, weight: self.eval(wo, &wi, n) / pdf, pdf });
+   }
+
+   fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, n: &Vector3<f64>) -> f64
+   {
+      return (1. - self.mirror_probability()) * f64::max(0., facing(n, wo).dot(wi)) / PI;
+   }
+
+   fn specular(&self, wo: &Vector3<f64>, n: &Vector3<f64>) -> Vec<(Vector3<f64>, Vector3<f64>)>
+   {
+      if self.km == Vector3::new(0., 0., 0.) {
+         return Vec::new();
+      }
+      return vec![(reflect(&-wo, n), self.km)];
+   }
+
+   fn albedo(&self) -> Vector3<f64>
+   {
+      return self.kd;
+   }
+
+   fn ambient(&self) -> Vector3<f64>
+   {
+      return self.ka;
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn blinn_phong() -> BlinnPhong
+   {
+      let gray = Vector3::new(0.5, 0.5, 0.5);
+      return BlinnPhong::new(gray, gray, Vector3::new(0.2, 0.2, 0.2), Vector3::new(0.1, 0.1, 0.1), 20.);
+   }
+
+   #[test]
+   fn both_sides_look_the_same()
+   {
+      let bsdf = blinn_phong();
+      let n = Vector3::new(0., 0., 1.);
+      let wo = Vector3::new(0.3, 0., 1.).normalize();
+      let wi = Vector3::new(-0.5, 0.2, 1.).normalize();
+      let front = bsdf.eval(&wo, &wi, &n);
+      assert!(front[0] > 0.);
+      // The same directions seen from behind the surface
+      assert!((bsdf.eval(&-wo, &-wi, &n) - front).norm() < 1e-15);
+      assert!((bsdf.pdf(&-wo, &-wi, &n) - bsdf.pdf(&wo, &wi, &n)).abs() < 1e-15);
+      assert!(bsdf.pdf(&-wo, &-wi, &n) > 0.);
+
+      // Nothing is sampled through the surface
+      assert_eq!(bsdf.pdf(&wo, &-wi, &n), 0.);
+   }
+
+   #[test]
+   fn samples_stay_on_the_viewer_side()
+   {
+      let bsdf = blinn_phong();
+      let n = Vector3::new(0., 0., 1.);
+      let wo = Vector3::new(0.2, 0.1, -1.).normalize();
+      for i in 0..100 {
+         let u = [(i as f64 + 0.5) / 100., ((i * 37) % 100) as f64 / 100., ((i * 61) % 100) as f64 / 100.];
+         if let Some(sample) = bsdf.sample(&wo, &n, u) {
+            assert!(sample.wi.dot(&n) < 0., "sample {:?} went through the surface", sample.wi);
+            // Past the mirror bounce, weighted by eval over pdf
+            if u[0] >= bsdf.mirror_probability() {
+               let weight = bsdf.eval(&wo, &sample.wi, &n) / bsdf.pdf(&wo, &sample.wi, &n);
+               assert!((sample.weight - weight).norm() < 1e-12);
+            }
+         }
+      }
+   }
+}
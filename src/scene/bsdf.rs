@@ -0,0 +1,85 @@
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::geometry::orthonormal_basis;
+
+
+/**
+ * How a surface scatters light: the interface every material kind
+ * implements, so the renderers never need to know which kind they hit.
+ *
+ * All directions are unit vectors pointing away from the surface: <wo>
+ * towards the viewer (back along the incoming ray), <wi> towards the light.
+ * <n> is the surface normal as the object reports it (pointing out of
+ * solids); each BSDF decides what to do when <wo> is on the other side.
+ */
+pub trait Bsdf {
+   // f(wo, wi) |n . wi|: the BSDF with the cosine term folded in. Perfectly
+   // specular (mirror, glass) parts can't be evaluated and are left out.
+   fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, n: &Vector3<f64>) -> Vector3<f64>;
+
+   // Pick a <wi> for <wo> with probability roughly proportional to eval(),
+   // using the uniform random numbers <u>. None means the path ends here.
+   fn sample(&self, wo: &Vector3<f64>, n: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample>;
+
+   // Probability density (per solid angle) of sample() picking <wi>. Zero
+   // for perfectly specular BSDFs.
+   fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, n: &Vector3<f64>) -> f64;
+
+   // The perfectly specular directions leaving towards <wo>, with the color
+   // each one is weighted by. The Whitted renderer follows every one of
+   // these instead of sampling.
+   fn specular(&self, _wo: &Vector3<f64>, _n: &Vector3<f64>) -> Vec<(Vector3<f64>, Vector3<f64>)>
+   {
+      return Vec::new();
+   }
+
+   // The surface's base color, as shown in the albedo pass
+   fn albedo(&self) -> Vector3<f64>;
+
+   // How much of the ambient light the surface reflects
+   fn ambient(&self) -> Vector3<f64>;
+}
+
+
+// The result of sampling a BSDF
+pub struct BsdfSample {
+   pub wi: Vector3<f64>,
+   // eval / pdf (or the specular weight), what the path throughput gets
+   // multiplied by
+   pub weight: Vector3<f64>,
+   // Density (per solid angle) of picking <wi>, zero for a perfectly
+   // specular bounce
+   pub pdf: f64
+}
+
+
+// Relative brightness of a color, e.g. for picking between lobes
+pub fn luminance(c: &Vector3<f64>) -> f64
+{
+   return 0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2];
+}
+
+
+// The normal flipped if need be to be on the same side as <wo>, for surfaces
+// that look the same from both sides
+pub fn facing(n: &Vector3<f64>, wo: &Vector3<f64>) -> Vector3<f64>
+{
+   if n.dot(wo) < 0. {
+      return -n;
+   }
+   return *n;
+}
+
+
+// Direction in the hemisphere around the unit <n> with density cos / PI,
+// from two uniform random numbers
+pub fn cosine_sample_hemisphere(n: &Vector3<f64>, u1: f64, u2: f64) -> Vector3<f64>
+{
+   let (u, v) = orthonormal_basis(n);
+   let r = u1.sqrt();
+   let phi = 2. * PI * u2;
+   return r * phi.cos() * u + r * phi.sin() * v + (1. - u1).max(0.).sqrt() * n;
+}
@@ -19,6 +19,12 @@ pub struct Camera {
 
 
 impl Camera {
+   // Where camera rays start, as a ray parameter: viewing rays are scaled so
+   // the image plane is at t = 1, and everything between the eye and the
+   // image plane is clipped away. An object the image plane cuts through is
+   // seen cut open.
+   pub const NEAR_PLANE: f64 = 1.;
+
    pub fn new( e: Vector3<f64>, 
                u: Vector3<f64>, 
                v: Vector3<f64>, 
@@ -29,4 +35,24 @@ impl Camera {
    {
       Camera{e, u, v, w, d, width, height}
    }
+
+   // Where the point <p> shows up in an <n_x> by <n_y> image, in pixels from
+   // the top left corner (the opposite of Ray::through). None if it's
+   // behind the camera or outside the image.
+   pub fn raster(&self, p: &Vector3<f64>, n_x: usize, n_y: usize) -> Option<(f64, f64)>
+   {
+      let to_p = p - self.e;
+      let depth = -to_p.dot(&self.w);
+      if depth <= 0. {
+         return None;
+      }
+      // Onto the image plane
+      let scale = self.d / depth;
+      let x = (to_p.dot(&self.u) * scale + self.width / 2.) / self.width * n_x as f64;
+      let y = (self.height / 2. - to_p.dot(&self.v) * scale) / self.height * n_y as f64;
+      if x < 0. || x >= n_x as f64 || y < 0. || y >= n_y as f64 {
+         return None;
+      }
+      return Some((x, y));
+   }
 }
@@ -0,0 +1,182 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::bsdf::{ Bsdf, BsdfSample };
+use crate::scene::Dispersion;
+use crate::render::reflect;
+
+
+// Smooth glass, water, etc: light is either reflected or refracted, in the
+// proportions given by the Fresnel equations. Transmitted light is tinted by
+// <tint>. The normal must point out of the object to tell entering from
+// leaving.
+#[derive(Clone)]
+pub struct Dielectric {
+   // Index of refraction of the inside, relative to the outside
+   pub ior: f64,
+   pub tint: Vector3<f64>,
+   // How <ior> changes with the wavelength, for spectral rendering. (<ior> is
+   // then its value at the reference wavelength.)
+   pub dispersion: Option<Dispersion>
+}
+
+
+// Where the light goes at a smooth interface
+struct Split {
+   reflected: Vector3<f64>,
+   // None on total internal reflection
+   refracted: Option<Vector3<f64>>,
+   // Fraction of the light that's reflected
+   fresnel: f64
+}
+
+
+impl Dielectric {
+   pub fn new(ior: f64, tint: Vector3<f64>) -> Dielectric
+   {
+      return Dielectric { ior, tint, dispersion: None };
+   }
+
+   fn split(&self, wo: &Vector3<f64>, n: &Vector3<f64>) -> Split
+   {
+      // Flip things around if we're inside looking out
+      let entering = n.dot(wo) > 0.;
+      let (n, eta) = if entering { (*n, 1. / self.ior) } else { (-n, self.ior) };
+      let reflected = reflect(&-wo, &n);
+
+      // Snell's law, sin_t = eta sin_i
+      let cos_i = n.dot(wo).min(1.);
+      let sin2_t = eta * eta * (1. - cos_i * cos_i);
+      if sin2_t >= 1. {
+         return Split { reflected, refracted: None, fresnel: 1. };
+      }
+      let cos_t = (1. - sin2_t).sqrt();
+      let refracted = (-eta * wo + (eta * cos_i - cos_t) * n).normalize();
+
+      // Exact Fresnel reflectance for unpolarized light
+      let r_parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+      let r_perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+      let fresnel = 0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular);
+      return Split { reflected, refracted: Some(refracted), fresnel };
+   }
+}
+
+
+impl Bsdf for Dielectric {
+   fn eval(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _n: &Vector3<f64>) -> Vector3<f64>
+   {
+      return Vector3::new(0., 0., 0.);
+   }
+
+   // Reflect with probability fresnel, refract otherwise
+   fn sample(&self, wo: &Vector3<f64>, n: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample>
+   {
+      let split = self.split(wo, n);
+      return match split.refracted {
+         Some(refracted) if u[0] >= split.fresnel => Some(BsdfSample { wi: refracted, weight: self.tint, pdf: 0. }),
+         _ => Some(BsdfSample { wi: split.reflected, weight: Vector3::new(1., 1., 1.), pdf: 0. })
+      };
+   }
+
+   fn pdf(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _n: &Vector3<f64>) -> f64
+   {
+      return 0.;
+   }
+
+   fn specular(&self, wo: &Vector3<f64>, n: &Vector3<f64>) -> Vec<(Vector3<f64>, Vector3<f64>)>
+   {
+      let split = self.split(wo, n);
+      let mut directions = vec![(split.reflected, Vector3::new(1., 1., 1.) * split.fresnel)];
+      if let Some(refracted) = split.refracted {
+         directions.push((refracted, self.tint * (1. - split.fresnel)));
+      }
+      return directions;
+   }
+
+   fn albedo(&self) -> Vector3<f64>
+   {
+      return self.tint;
+   }
+
+   fn ambient(&self) -> Vector3<f64>
+   {
+      return Vector3::new(0., 0., 0.);
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn glass() -> Dielectric
+   {
+      return Dielectric::new(1.5, Vector3::new(1., 1., 1.));
+   }
+
+   fn n() -> Vector3<f64>
+   {
+      return Vector3::new(0., 0., 1.);
+   }
+
+   // sin of the angle between the unit <w> and the z axis
+   fn sin_z(w: &Vector3<f64>) -> f64
+   {
+      return (1. - w.z * w.z).max(0.).sqrt();
+   }
+
+   #[test]
+   fn straight_on_reflects_four_percent()
+   {
+      let split = glass().split(&n(), &n());
+      assert!((split.fresnel - 0.04).abs() < 1.0e-12);
+      assert!((split.refracted.unwrap() + n()).norm() < 1.0e-12);
+      assert!((split.reflected - n()).norm() < 1.0e-12);
+      // Same from inside
+      assert!((glass().split(&-n(), &n()).fresnel - 0.04).abs() < 1.0e-12);
+   }
+
+   #[test]
+   fn refraction_follows_snell_and_reverses()
+   {
+      let wo = Vector3::new(0.6, 0., 0.8);
+      let split = glass().split(&wo, &n());
+      let refracted = split.refracted.unwrap();
+      assert!(refracted.z < 0.);
+      assert!((sin_z(&wo) - 1.5 * sin_z(&refracted)).abs() < 1.0e-12);
+      // The path can be followed the other way
+      let back = glass().split(&refracted, &n()).refracted.unwrap();
+      assert!((back - wo).norm() < 1.0e-12);
+      assert!(split.fresnel > 0.04 && split.fresnel < 1.);
+   }
+
+   #[test]
+   fn total_internal_reflection_past_the_critical_angle()
+   {
+      // From inside, sin > 1 / 1.5 can't get out
+      let wo = Vector3::new(0.8, 0., -0.6);
+      let split = glass().split(&wo, &n());
+      assert!(split.refracted.is_none());
+      assert_eq!(split.fresnel, 1.);
+      assert!((split.reflected - Vector3::new(-0.8, 0., -0.6)).norm() < 1.0e-12);
+      let directions = glass().specular(&wo, &n());
+      assert_eq!(directions.len(), 1);
+      assert_eq!(glass().sample(&wo, &n(), [0.99, 0., 0.]).unwrap().wi, split.reflected);
+   }
+
+   #[test]
+   fn specular_directions_share_the_light()
+   {
+      let tinted = Dielectric::new(1.33, Vector3::new(0.5, 1., 1.));
+      let wo = Vector3::new(0.3, 0.4, 0.5).normalize();
+      let directions = tinted.specular(&wo, &n());
+      assert_eq!(directions.len(), 2);
+      let total = directions[0].1 + directions[1].1;
+      assert!((total.y - 1.).abs() < 1.0e-12);
+      assert!(total.x < 1.);
+      // sample() picks one of them with the Fresnel odds
+      let fresnel = directions[0].1.y;
+      assert_eq!(tinted.sample(&wo, &n(), [fresnel * 0.99, 0., 0.]).unwrap().wi, directions[0].0);
+      assert_eq!(tinted.sample(&wo, &n(), [fresnel * 1.01, 0., 0.]).unwrap().wi, directions[1].0);
+   }
+}
@@ -0,0 +1,82 @@
+/**
+ * How the index of refraction of a transparent material changes with the
+ * wavelength of the light, which splits white light into colors in prisms
+ * and gives diamonds their fire. Wavelengths are in nanometers, while the
+ * coefficients use micrometers, as glass catalogs do.
+ */
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+   // n = a + b / lambda^2
+   Cauchy { a: f64, b: f64 },
+   // n^2 = 1 + sum of b[i] lambda^2 / (lambda^2 - c[i])
+   Sellmeier { b: [f64; 3], c: [f64; 3] }
+}
+
+
+// The wavelength indices of refraction are usually quoted at (the sodium D
+// line), used for the material when not rendering spectrally
+pub const REFERENCE_WAVELENGTH: f64 = 589.3;
+
+
+impl Dispersion {
+   // Sellmeier coefficients of some common materials
+   pub fn named(name: &str) -> Option<Dispersion>
+   {
+      let (b, c) = match name {
+         // Borosilicate crown glass, the usual lens glass
+         "bk7" => ([1.03961212, 0.231792344, 1.01046945], [0.00600069867, 0.0200179144, 103.560653]),
+         // Dense flint glass, for strong dispersion
+         "sf11" => ([1.73759695, 0.313747346, 1.89878101], [0.013188707, 0.0623068142, 155.23629]),
+         "fused_silica" => ([0.6961663, 0.4079426, 0.8974794], [0.004679148, 0.01351206, 97.934]),
+         "diamond" => ([4.3356, 0.3306, 0.], [0.011236, 0.030625, 0.]),
+         _ => return None
+      };
+      return Some(Dispersion::Sellmeier { b, c });
+   }
+
+   pub fn ior(&self, lambda: f64) -> f64
+   {
+      let micrometers = lambda * 1.0e-3;
+      let l2 = micrometers * micrometers;
+      return match self {
+         Dispersion::Cauchy { a, b } => a + b / l2,
+         Dispersion::Sellmeier { b, c } => {
+            let mut n2 = 1.;
+            for i in 0..3 {
+               n2 += b[i] * l2 / (l2 - c[i]);
+            }
+            n2.sqrt()
+         }
+      };
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn glasses_have_their_catalog_index()
+   {
+      // At the helium d line, which catalogs quote
+      let bk7 = Dispersion::named("bk7").unwrap();
+      assert!((bk7.ior(587.56) - 1.5168).abs() < 1.0e-4);
+      let sf11 = Dispersion::named("sf11").unwrap();
+      assert!((sf11.ior(587.56) - 1.7847).abs() < 1.0e-4);
+      let diamond = Dispersion::named("diamond").unwrap();
+      assert!((diamond.ior(REFERENCE_WAVELENGTH) - 2.417).abs() < 2.0e-3);
+      assert!(Dispersion::named("cheese").is_none());
+   }
+
+   #[test]
+   fn blue_bends_more_than_red()
+   {
+      for name in ["bk7", "sf11", "fused_silica", "diamond"] {
+         let glass = Dispersion::named(name).unwrap();
+         assert!(glass.ior(400.) > glass.ior(550.) && glass.ior(550.) > glass.ior(700.), "{}", name);
+      }
+      let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+      assert!((cauchy.ior(500.) - (1.5 + 0.004 / 0.25)).abs() < 1.0e-12);
+   }
+}
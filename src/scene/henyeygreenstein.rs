@@ -0,0 +1,139 @@
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::bsdf::{ Bsdf, BsdfSample };
+use crate::geometry::orthonormal_basis;
+
+
+/**
+ * The Henyey-Greenstein phase function: how light that scatters inside a
+ * medium spreads over the new directions. <g> in (-1, 1) is the average
+ * cosine of the bend, positive for mostly forward scattering (fog, haze),
+ * zero for the same in every direction and negative for mostly back.
+ *
+ * It's a Bsdf so that the path tracer can light and continue paths inside
+ * media just as it does on surfaces. As a Bsdf it ignores the normal and has
+ * no cosine factor, and <wo> and <wi> both point away from the scattering
+ * point.
+ */
+#[derive(Clone, Copy)]
+pub struct HenyeyGreenstein {
+   pub g: f64
+}
+
+
+impl HenyeyGreenstein {
+   pub fn new(g: f64) -> HenyeyGreenstein
+   {
+      return HenyeyGreenstein { g };
+   }
+
+   // Density over the sphere of directions for light leaving along <wi>
+   // towards <wo>
+   pub fn phase(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64
+   {
+      let cos_theta = wo.normalize().dot(&wi.normalize());
+      let denominator = 1. + self.g * self.g + 2. * self.g * cos_theta;
+      return (1. - self.g * self.g) / (4. * PI * denominator * denominator.max(1.0e-12).sqrt());
+   }
+}
+
+
+impl Bsdf for HenyeyGreenstein {
+   fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, _n: &Vector3<f64>) -> Vector3<f64>
+   {
+      let p = self.phase(wo, wi);
+      return Vector3::new(p, p, p);
+   }
+
+   // Sampled exactly, so the weight is always one
+   fn sample(&self, wo: &Vector3<f64>, _n: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample>
+   {
+      let g = self.g;
+      let cos_theta = if g.abs() < 1.0e-3 {
+         1. - 2. * u[0]
+      } else {
+         let s = (1. - g * g) / (1. + g - 2. * g * u[0]);
+         -(1. + g * g - s * s) / (2. * g)
+      };
+      let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+      let phi = 2. * PI * u[1];
+
+      let w = wo.normalize();
+      let (a, b) = orthonormal_basis(&w);
+      let wi = sin_theta * phi.cos() * a + sin_theta * phi.sin() * b + cos_theta * w;
+      return Some(BsdfSample { wi, weight: Vector3::new(1., 1., 1.), pdf: self.phase(&w, &wi) });
+   }
+
+   fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, _n: &Vector3<f64>) -> f64
+   {
+      return self.phase(wo, wi);
+   }
+
+   fn albedo(&self) -> Vector3<f64>
+   {
+      return Vector3::new(1., 1., 1.);
+   }
+
+   fn ambient(&self) -> Vector3<f64>
+   {
+      return Vector3::new(1., 1., 1.);
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const STEPS: usize = 2000;
+
+   // ∫ f(cos θ) over the sphere of directions around an axis
+   fn integrate(f: impl Fn(f64) -> f64) -> f64
+   {
+      let mut total = 0.;
+      for i in 0..STEPS {
+         let cos_theta = -1. + 2. * (i as f64 + 0.5) / STEPS as f64;
+         total += f(cos_theta);
+      }
+      return 2. * PI * total * 2. / STEPS as f64;
+   }
+
+   #[test]
+   fn phase_functions_are_normalized_with_mean_cosine_g()
+   {
+      let wo = Vector3::new(0., 0., 1.);
+      for g in [-0.7, -0.2, 0., 0.3, 0.8] {
+         let hg = HenyeyGreenstein::new(g);
+         let at = |cos_theta: f64| hg.phase(&wo, &Vector3::new((1. - cos_theta * cos_theta).sqrt(), 0., cos_theta));
+         assert!((integrate(at) - 1.).abs() < 1.0e-3, "g = {}", g);
+         // Forward means the light keeps going: <wi> opposite <wo>
+         assert!((integrate(|c| -c * at(c)) - g).abs() < 1.0e-3, "g = {}", g);
+      }
+   }
+
+   #[test]
+   fn samples_follow_the_phase_function()
+   {
+      let wo = Vector3::new(0.48, -0.6, 0.64);
+      for g in [-0.5, 0., 0.0005, 0.9] {
+         let hg = HenyeyGreenstein::new(g);
+         let steps = 200;
+         let mut mean_cos = 0.;
+         for i in 0..steps {
+            for j in 0..steps {
+               let u = [(i as f64 + 0.5) / steps as f64, (j as f64 + 0.5) / steps as f64, 0.5];
+               let sample = hg.sample(&wo, &wo, u).unwrap();
+               assert!((sample.wi.norm() - 1.).abs() < 1.0e-9);
+               assert_eq!(sample.weight, Vector3::new(1., 1., 1.));
+               assert!((sample.pdf - hg.pdf(&wo, &sample.wi, &wo)).abs() < 1.0e-9 * sample.pdf.max(1.));
+               mean_cos -= wo.dot(&sample.wi);
+            }
+         }
+         mean_cos /= (steps * steps) as f64;
+         assert!((mean_cos - g).abs() < 1.0e-3, "g = {}: {}", g, mean_cos);
+      }
+   }
+}
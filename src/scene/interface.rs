@@ -0,0 +1,43 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::bsdf::{ Bsdf, BsdfSample };
+
+
+// No surface at all: light goes straight through. Used for the boundary of
+// a medium, like a puff of smoke, that shouldn't reflect or refract anything.
+#[derive(Clone)]
+pub struct Interface;
+
+
+impl Bsdf for Interface {
+   fn eval(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _n: &Vector3<f64>) -> Vector3<f64>
+   {
+      return Vector3::new(0., 0., 0.);
+   }
+
+   fn sample(&self, wo: &Vector3<f64>, _n: &Vector3<f64>, _u: [f64; 3]) -> Option<BsdfSample>
+   {
+      return Some(BsdfSample { wi: -wo, weight: Vector3::new(1., 1., 1.), pdf: 0. });
+   }
+
+   fn pdf(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _n: &Vector3<f64>) -> f64
+   {
+      return 0.;
+   }
+
+   fn specular(&self, wo: &Vector3<f64>, _n: &Vector3<f64>) -> Vec<(Vector3<f64>, Vector3<f64>)>
+   {
+      return vec![(-wo, Vector3::new(1., 1., 1.))];
+   }
+
+   fn albedo(&self) -> Vector3<f64>
+   {
+      return Vector3::new(0., 0., 0.);
+   }
+
+   fn ambient(&self) -> Vector3<f64>
+   {
+      return Vector3::new(0., 0., 0.);
+   }
+}
@@ -0,0 +1,99 @@
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::bsdf::{ Bsdf, BsdfSample, facing, cosine_sample_hemisphere };
+
+
+// A perfectly matte surface, equally bright from every direction
+#[derive(Clone)]
+pub struct Lambertian {
+   pub albedo: Vector3<f64>
+}
+
+
+impl Lambertian {
+   pub fn new(albedo: Vector3<f64>) -> Lambertian
+   {
+      return Lambertian { albedo };
+   }
+}
+
+
+impl Bsdf for Lambertian {
+   fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, n: &Vector3<f64>) -> Vector3<f64>
+   {
+      let n = facing(n, wo);
+      return self.albedo * (f64::max(0., n.dot(wi)) / PI);
+   }
+
+   fn sample(&self, wo: &Vector3<f64>, n: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample>
+   {
+      let wi = cosine_sample_hemisphere(&facing(n, wo), u[1], u[2]);
+      let pdf = self.pdf(wo, &wi, n);
+      if pdf <= 0. {
+         return None;
+      }
+      // eval / pdf, the cosines and PIs cancel
+      return Some(BsdfSample { wi, weight: self.albedo, pdf });
+   }
+
+   fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, n: &Vector3<f64>) -> f64
+   {
+      return f64::max(0., facing(n, wo).dot(wi)) / PI;
+   }
+
+   fn albedo(&self) -> Vector3<f64>
+   {
+      return self.albedo;
+   }
+
+   fn ambient(&self) -> Vector3<f64>
+   {
+      return self.albedo;
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn samples_are_cosine_weighted_on_the_viewer_side()
+   {
+      let bsdf = Lambertian::new(Vector3::new(0.2, 0.4, 0.6));
+      let n = Vector3::new(0., 1., 0.);
+      for wo in [Vector3::new(0., 1., 0.), Vector3::new(0.6, -0.8, 0.)] {
+         let sample = bsdf.sample(&wo, &n, [0., 0.3, 0.6]).unwrap();
+         assert!(sample.wi.dot(&(wo.y.signum() * n)) > 0.);
+         let pdf = bsdf.pdf(&wo, &sample.wi, &n);
+         assert!((pdf - sample.wi.y.abs() / PI).abs() < 1.0e-12);
+         assert!((sample.weight - bsdf.eval(&wo, &sample.wi, &n) / pdf).norm() < 1.0e-12);
+      }
+   }
+
+   #[test]
+   fn reflects_its_albedo()
+   {
+      // ∫ eval over the hemisphere, on a grid even in solid angle
+      let bsdf = Lambertian::new(Vector3::new(0.2, 0.4, 0.6));
+      let n = Vector3::new(0., 0., 1.);
+      let wo = Vector3::new(0., 0.6, 0.8);
+      let steps = 200;
+      let mut total = Vector3::zeros();
+      for i in 0..steps {
+         let cos_theta = (i as f64 + 0.5) / steps as f64;
+         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+         for j in 0..steps {
+            let phi = 2. * PI * (j as f64 + 0.5) / steps as f64;
+            total += bsdf.eval(&wo, &Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta), &n);
+         }
+      }
+      total *= 2. * PI / (steps * steps) as f64;
+      assert!((total - bsdf.albedo).norm() < 1.0e-4);
+      // Nothing goes through
+      assert_eq!(bsdf.eval(&wo, &-n, &n), Vector3::zeros());
+   }
+}
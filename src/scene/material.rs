@@ -1,22 +1,127 @@
 use nalgebra as na;
 use na::{ Vector3 };
 
+use crate::scene::{ Bsdf, BlinnPhong, Lambertian, Mirror, Dielectric, Microfacet, Interface, Medium };
+use crate::spectrum::color_at;
 
+
+// Every way a surface can scatter light. Shading code only talks to them
+// through the Bsdf each one hands out (the Whitted shader aside, which keeps
+// the original Blinn-Phong terms), so adding a kind means adding a variant
+// here and an implementation of Bsdf.
+#[derive(Clone)]
+pub enum Scattering {
+   BlinnPhong(BlinnPhong),
+   Lambertian(Lambertian),
+   Mirror(Mirror),
+   Dielectric(Dielectric),
+   // Physically based metal/roughness material
+   Microfacet(Microfacet),
+   // No surface, just the boundary of a medium
+   Interface(Interface)
+}
+
+
+impl Scattering {
+   pub fn bsdf(&self) -> &dyn Bsdf
+   {
+      return match self {
+         Scattering::BlinnPhong(bsdf) => bsdf,
+         Scattering::Lambertian(bsdf) => bsdf,
+         Scattering::Mirror(bsdf) => bsdf,
+         Scattering::Dielectric(bsdf) => bsdf,
+         Scattering::Microfacet(bsdf) => bsdf,
+         Scattering::Interface(bsdf) => bsdf
+      };
+   }
+
+   // See Material::is_perfectly_specular
+   pub fn is_perfectly_specular(&self) -> bool
+   {
+      return matches!(self, Scattering::Mirror(_) | Scattering::Dielectric(_) | Scattering::Interface(_));
+   }
+
+   // The same scattering for light of the single wavelength <lambda>: every
+   // color becomes the gray of its spectrum there, and dispersive
+   // dielectrics get the index of refraction for it
+   pub fn at_wavelength(&self, lambda: f64) -> Scattering
+   {
+      let gray = |color: &Vector3<f64>| color_at(color, Some(lambda));
+      return match self {
+         Scattering::BlinnPhong(bsdf) => Scattering::BlinnPhong(BlinnPhong::new(
+            gray(&bsdf.ka), gray(&bsdf.kd), gray(&bsdf.ks), gray(&bsdf.km), bsdf.phong_exp)),
+         Scattering::Lambertian(bsdf) => Scattering::Lambertian(Lambertian::new(gray(&bsdf.albedo))),
+         Scattering::Mirror(bsdf) => Scattering::Mirror(Mirror::new(gray(&bsdf.color))),
+         Scattering::Dielectric(bsdf) => Scattering::Dielectric(Dielectric {
+            ior: bsdf.dispersion.map(|dispersion| dispersion.ior(lambda)).unwrap_or(bsdf.ior),
+            tint: gray(&bsdf.tint),
+            dispersion: bsdf.dispersion
+         }),
+         Scattering::Microfacet(bsdf) => Scattering::Microfacet(Microfacet::new(
+            gray(&bsdf.base_color), bsdf.metallic, bsdf.roughness, bsdf.specular)),
+         Scattering::Interface(bsdf) => Scattering::Interface(bsdf.clone())
+      };
+   }
+}
+
+
+#[derive(Clone)]
 pub struct Material {
-   // Ambient, Diffuse, Specular, Mirror color
-   pub ka: Vector3<f64>,
-   pub kd: Vector3<f64>,
-   pub ks: Vector3<f64>,
-   pub km: Vector3<f64>,
-
-   // Phong exponent
-   pub phong_exp: f64
+   pub scattering: Scattering,
+
+   // Radiance the surface gives off by itself, the same in every direction
+   // and from both sides. Zero for everything but lights.
+   pub emission: Vector3<f64>,
+
+   // Index of the material in the scene's "materials" array
+   pub id: usize,
+
+   // What fills the inside of the (closed) objects made of this material,
+   // None for vacuum
+   pub medium: Option<Medium>
+}
+
+
+impl Material {
+   // A material that doesn't glow
+   pub fn from_scattering(scattering: Scattering) -> Material
+   {
+      return Material { scattering, emission: Vector3::new(0., 0., 0.), id: 0, medium: None };
+   }
+
+   // The scattering function at a hit
+   pub fn bsdf(&self) -> &dyn Bsdf
+   {
+      return self.scattering.bsdf();
+   }
+
+   pub fn is_emissive(&self) -> bool
+   {
+      return self.emission != Vector3::new(0., 0., 0.);
+   }
+
+   // Whether the surface only ever reflects or refracts light in a single
+   // direction (so it has nothing to show of light from anywhere else)
+   pub fn is_perfectly_specular(&self) -> bool
+   {
+      return self.scattering.is_perfectly_specular();
+   }
+
+   // Whether light passes straight through the surface, so it doesn't cast
+   // a shadow
+   pub fn is_interface(&self) -> bool
+   {
+      return matches!(self.scattering, Scattering::Interface(_));
+   }
 }
 
 
+// A plain gray material, for tests that need some surface but don't look at
+// how it shades
+#[cfg(test)]
 impl Material {
-   pub fn new(ka: Vector3<f64>, kd: Vector3<f64>, ks: Vector3<f64>, km: Vector3<f64>, phong_exp: f64) -> Material
+   pub fn gray() -> Material
    {
-      return Material { ka, kd, ks, km, phong_exp };
+      return Material::from_scattering(Scattering::Lambertian(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))));
    }
 }
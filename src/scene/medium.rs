@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use nalgebra as na;
+use na::{ Vector3 };
+use rand::Rng;
+use rand::rngs::SmallRng;
+
+use crate::scene::{ HenyeyGreenstein, VoxelGrid };
+use crate::scene::bsdf::luminance;
+use crate::spectrum::{ cie_xyz, xyz_to_srgb, color_at };
+
+
+// Temperature steps of the table of blackbody colors, in kelvin
+const GLOW_STEP: f64 = 10.;
+
+// Temperature at which the glow has the brightness asked for
+const GLOW_REFERENCE: f64 = 1500.;
+
+
+/**
+ * A participating medium: fog, smoke, murky water, fire. Per unit of
+ * distance, a fraction <sigma_a> of the light going through is absorbed and
+ * a fraction <sigma_s> scattered into other directions, as the phase
+ * function says. Each is per color channel.
+ *
+ * Without a grid the medium is the same everywhere. With one, the
+ * coefficients are scaled by the grid's density, and hot voxels glow.
+ */
+#[derive(Clone)]
+pub struct Medium {
+   pub sigma_a: Vector3<f64>,
+   pub sigma_s: Vector3<f64>,
+   pub phase: HenyeyGreenstein,
+   pub grid: Option<Arc<VoxelGrid>>,
+   // Light given off, relative to the absorption, by every GLOW_STEP kelvin
+   glow: Arc<Vec<Vector3<f64>>>,
+   // Multiplies the grid's temperatures to get kelvin
+   temperature_scale: f64,
+   // The single wavelength the medium is seen at, if any (see at_wavelength)
+   wavelength: Option<f64>
+}
+
+
+impl Medium {
+   pub fn new(sigma_a: Vector3<f64>, sigma_s: Vector3<f64>, g: f64) -> Medium
+   {
+      return Medium { sigma_a, sigma_s, phase: HenyeyGreenstein::new(g), grid: None, glow: Arc::new(Vec::new()),
+                      temperature_scale: 1., wavelength: None };
+   }
+
+   // Shape the medium by <grid>. Where the grid has a temperature the medium
+   // glows like a blackbody (as much as it absorbs, as hot things do), with
+   // <emission_strength> the brightness at 1500 K.
+   pub fn set_grid(&mut self, grid: VoxelGrid, emission_strength: f64, temperature_scale: f64)
+   {
+      self.glow = Arc::new(Vec::new());
+      if let Some(temperature) = &grid.temperature {
+         let hottest = temperature.iter().cloned().fold(0., f64::max) * temperature_scale;
+         let reference = luminance(&blackbody(GLOW_REFERENCE));
+         let n_steps = (hottest / GLOW_STEP).ceil() as usize + 2;
+         self.glow = Arc::new((0..n_steps)
+            .map(|k| emission_strength / reference * blackbody(k as f64 * GLOW_STEP))
+            .collect());
+      }
+      self.temperature_scale = temperature_scale;
+      self.grid = Some(Arc::new(grid));
+   }
+
+   // The same medium for light of the single wavelength <lambda>: the
+   // coefficients and the glow become the grays of their spectra there, as
+   // for Scattering::at_wavelength. Cheap, the grid and the glow are shared.
+   pub fn at_wavelength(&self, lambda: f64) -> Medium
+   {
+      let mut medium = self.clone();
+      medium.sigma_a = color_at(&self.sigma_a, Some(lambda));
+      medium.sigma_s = color_at(&self.sigma_s, Some(lambda));
+      medium.wavelength = Some(lambda);
+      return medium;
+   }
+
+   // Fraction of the light lost per unit of distance, by any means (where
+   // the density is one)
+   pub fn sigma_t(&self) -> Vector3<f64>
+   {
+      return self.sigma_a + self.sigma_s;
+   }
+
+   // The most light lost per unit of distance anywhere, in any channel
+   pub fn majorant(&self) -> f64
+   {
+      return match &self.grid {
+         Some(grid) => grid.max_density * self.sigma_t().max(),
+         None => self.sigma_t().max()
+      };
+   }
+
+   // Light given off at <p> per unit of distance
+   pub fn emission(&self, p: &Vector3<f64>) -> Vector3<f64>
+   {
+      let grid = match &self.grid {
+         Some(grid) if !self.glow.is_empty() => grid,
+         _ => return Vector3::new(0., 0., 0.)
+      };
+      let kelvin = grid.temperature(p) * self.temperature_scale;
+      let u = (kelvin / GLOW_STEP).clamp(0., (self.glow.len() - 1) as f64);
+      let k = (u as usize).min(self.glow.len() - 2);
+      let glow = color_at(&self.glow[k].lerp(&self.glow[k + 1], u - k as f64), self.wavelength);
+      return grid.density(p) * self.sigma_a.component_mul(&glow);
+   }
+
+   // Fraction of the light that makes it through <distance> of the medium
+   // (which may be infinite), where it's the same everywhere
+   pub fn transmittance(&self, distance: f64) -> Vector3<f64>
+   {
+      return self.sigma_t().map(|sigma| if sigma == 0. { 1. } else { (-sigma * distance).exp() });
+   }
+
+   /**
+    * Fraction of the light that makes it from <origin> <distance> along the
+    * unit direction <d>. Exact without a grid; with one it's estimated by
+    * ratio tracking: random points are picked as if the medium were as
+    * dense as it gets everywhere, and each lets through the fraction of the
+    * light that the actual medium there doesn't take.
+    */
+   pub fn transmittance_along(&self, origin: &Vector3<f64>, d: &Vector3<f64>, distance: f64,
+                              rng: &mut SmallRng) -> Vector3<f64>
+   {
+      let grid = match &self.grid {
+         Some(grid) => grid,
+         None => return self.transmittance(distance)
+      };
+      let mut through = Vector3::new(1., 1., 1.);
+      let (t_near, t_far) = match grid.clip(origin, d, distance) {
+         Some(span) => span,
+         None => return through
+      };
+      let majorant = self.majorant();
+      if majorant == 0. {
+         return through;
+      }
+
+      let mut s = t_near;
+      loop {
+         s -= (1. - rng.gen::<f64>()).ln() / majorant;
+         if s >= t_far {
+            return through;
+         }
+         let sigma_t = grid.density(&(origin + s * d)) * self.sigma_t();
+         through = through.component_mul(&sigma_t.map(|sigma| 1. - sigma / majorant));
+
+         // Little is left: stop at random, keeping the average right
+         if through.max() < 0.1 {
+            if rng.gen::<f64>() < 0.5 {
+               return Vector3::new(0., 0., 0.);
+            }
+            through *= 2.;
+         }
+      }
+   }
+}
+
+
+/**
+ * The color of a blackbody at <kelvin>, in linear sRGB (negative components
+ * clamped), from Planck's law weighted by the CIE 1931 color matching
+ * functions. Only the ratios matter: the scale is arbitrary.
+ */
+fn blackbody(kelvin: f64) -> Vector3<f64>
+{
+   if kelvin <= 0. {
+      return Vector3::new(0., 0., 0.);
+   }
+   let mut xyz = Vector3::new(0., 0., 0.);
+   for step in 0..=80 {
+      let lambda = 380. + 5. * step as f64;
+      // Planck's law, without the constant factors, with lambda in micrometers
+      let micrometers = lambda * 1.0e-3;
+      let radiance = 1. / (micrometers.powi(5) * ((14387.77 / (micrometers * kelvin)).exp() - 1.));
+      xyz += radiance * cie_xyz(lambda);
+   }
+   let rgb = xyz_to_srgb(&xyz);
+   return rgb.map(|c| c.max(0.));
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use rand::SeedableRng;
+   use crate::spectrum::rgb_to_spectrum;
+
+   #[test]
+   fn tracking_in_a_uniform_grid_matches_the_plain_medium()
+   {
+      let (min, max) = (Vector3::zeros(), Vector3::new(1., 1., 1.));
+      let mut medium = Medium::new(Vector3::new(0.5, 1., 2.), Vector3::new(0.5, 0.5, 0.5), 0.);
+      let plain = medium.transmittance(0.6);
+      medium.set_grid(VoxelGrid::new([2, 2, 2], &min, &max, vec![1.; 8], None), 1., 1.);
+      assert_eq!(medium.majorant(), 2.5);
+      assert_eq!(medium.emission(&Vector3::new(0.5, 0.5, 0.5)), Vector3::zeros());
+
+      // From inside the box, and from outside it, which only counts the part in it
+      let mut rng = SmallRng::seed_from_u64(1);
+      let n = 100000;
+      let d = Vector3::new(0., 0., 1.);
+      for (origin, distance) in [(Vector3::new(0.5, 0.5, 0.2), 0.6), (Vector3::new(0.5, 0.5, -2.), 2.6)] {
+         let mut through = Vector3::zeros();
+         for _ in 0..n {
+            through += medium.transmittance_along(&origin, &d, distance, &mut rng);
+         }
+         through /= n as f64;
+         assert!((through - plain).abs().max() < 0.01, "{:?} against {:?}", through, plain);
+      }
+      // Missing the box altogether
+      assert_eq!(medium.transmittance_along(&Vector3::new(2., 0.5, 0.5), &d, 10., &mut rng), Vector3::new(1., 1., 1.));
+   }
+
+   #[test]
+   fn blackbodies_go_from_red_to_blue()
+   {
+      assert_eq!(blackbody(0.), Vector3::zeros());
+      let chromaticity = |kelvin: f64| blackbody(kelvin) / blackbody(kelvin).sum();
+      let (ember, sun, sky) = (chromaticity(1500.), chromaticity(6500.), chromaticity(20000.));
+      assert!(ember.x > 2. * ember.z);
+      // 6500 K is close to sRGB's white
+      assert!((sun - Vector3::new(1., 1., 1.) / 3.).abs().max() < 0.03, "{:?}", sun);
+      assert!(sky.z > sky.x);
+      // Hotter is brighter
+      assert!(luminance(&blackbody(3000.)) > 10. * luminance(&blackbody(1500.)));
+   }
+
+   #[test]
+   fn tinted_media_follow_their_spectra()
+   {
+      // Absorbs green and blue, so it lets red through
+      let medium = Medium::new(Vector3::new(0., 1., 1.), Vector3::new(0.5, 0.5, 0.5), 0.);
+      let red = medium.at_wavelength(650.);
+      let blue = medium.at_wavelength(450.);
+      assert!(red.sigma_a.x == red.sigma_a.y && red.sigma_a.y == red.sigma_a.z);
+      assert!(red.sigma_a.x < 0.2 && blue.sigma_a.x > 0.8);
+      assert!((red.sigma_a.x - rgb_to_spectrum(&medium.sigma_a, 650.)).abs() < 1.0e-12);
+      // A gray stays (very nearly) the same gray at every wavelength
+      assert!((red.sigma_s - medium.sigma_s).norm() < 1.0e-3);
+      assert!((blue.sigma_s - medium.sigma_s).norm() < 1.0e-3);
+      assert!(red.transmittance(1.).x > blue.transmittance(1.).x);
+   }
+
+   #[test]
+   fn glow_is_seen_at_the_wavelength()
+   {
+      let (min, max) = (Vector3::new(0., 0., 0.), Vector3::new(1., 1., 1.));
+      let grid = VoxelGrid::new([2, 2, 2], &min, &max, vec![1.; 8], Some(vec![1500.; 8]));
+      let mut medium = Medium::new(Vector3::new(1., 1., 1.), Vector3::new(0., 0., 0.), 0.);
+      medium.set_grid(grid, 1., 1.);
+
+      let p = Vector3::new(0.5, 0.5, 0.5);
+      let rgb = medium.emission(&p);
+      // Embers are red
+      assert!(rgb.x > rgb.z);
+      let red = medium.at_wavelength(650.).emission(&p);
+      let blue = medium.at_wavelength(450.).emission(&p);
+      assert!(red.x == red.z && red.x > blue.x);
+      assert!((red.x - rgb_to_spectrum(&rgb, 650.)).abs() < 1.0e-9);
+   }
+}
@@ -0,0 +1,288 @@
+// for the capital D, F, G
+#![allow(non_snake_case)]
+
+use std::f64::consts::PI;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::geometry::orthonormal_basis;
+use crate::scene::bsdf::{ Bsdf, BsdfSample, luminance, facing, cosine_sample_hemisphere };
+
+
+/**
+ * A physically based metal/roughness material, as used by most PBR asset
+ * pipelines (glTF, Disney's "principled" model):
+ *    - a GGX (Trowbridge-Reitz) microfacet distribution for the specular lobe,
+ *      with Smith's separable masking-shadowing and Schlick's Fresnel
+ *    - a Lambertian diffuse lobe for dielectrics (metallic = 0), which fades
+ *      out as the material gets more metallic
+ *
+ * Roughness is the artist-facing "perceptual" roughness, the GGX alpha is
+ * its square.
+ */
+#[derive(Clone)]
+pub struct Microfacet {
+   pub base_color: Vector3<f64>,
+   // 0 for dielectrics, 1 for metals
+   pub metallic: f64,
+   pub roughness: f64,
+   // Specular reflectance of dielectrics: 0.5 is the common 4% at normal incidence
+   pub specular: f64
+}
+
+
+impl Microfacet {
+   pub fn new(base_color: Vector3<f64>, metallic: f64, roughness: f64, specular: f64) -> Microfacet
+   {
+      return Microfacet { base_color, metallic: metallic.clamp(0., 1.), roughness: roughness.clamp(0., 1.), specular };
+   }
+
+   // GGX alpha, kept away from 0 where the distribution becomes a delta
+   fn alpha(&self) -> f64
+   {
+      return f64::max(self.roughness * self.roughness, 1.0e-3);
+   }
+
+   // Reflectance at normal incidence: 8% * specular for dielectrics, tinted
+   // by the base color for metals
+   fn f0(&self) -> Vector3<f64>
+   {
+      let dielectric = Vector3::new(1., 1., 1.) * 0.08 * self.specular;
+      return dielectric * (1. - self.metallic) + self.base_color * self.metallic;
+   }
+
+   fn fresnel(&self, cos_theta: f64) -> Vector3<f64>
+   {
+      let f0 = self.f0();
+      return f0 + (Vector3::new(1., 1., 1.) - f0) * (1. - cos_theta).max(0.).powi(5);
+   }
+
+   // Normal distribution D(h)
+   fn distribution(&self, n_dot_h: f64) -> f64
+   {
+      let a2 = self.alpha() * self.alpha();
+      let denominator = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+      return a2 / (PI * denominator * denominator);
+   }
+
+   // Smith masking for one direction
+   fn smith_g1(&self, n_dot_w: f64) -> f64
+   {
+      let a2 = self.alpha() * self.alpha();
+      return 2. * n_dot_w / (n_dot_w + (a2 + (1. - a2) * n_dot_w * n_dot_w).sqrt());
+   }
+
+   // Probability of sampling the specular lobe rather than the diffuse one
+   fn specular_probability(&self, n_dot_v: f64) -> f64
+   {
+      let specular = luminance(&self.fresnel(n_dot_v));
+      let diffuse = luminance(&self.base_color) * (1. - self.metallic);
+      if specular + diffuse <= 0. {
+         return 1.;
+      }
+      return specular / (specular + diffuse);
+   }
+}
+
+
+// Both sides of the surface look the same
+impl Bsdf for Microfacet {
+
+   fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, n: &Vector3<f64>) -> Vector3<f64>
+   {
+      let n = facing(n, wo);
+      let n_dot_v = n.dot(wo);
+      let n_dot_l = n.dot(wi);
+      if n_dot_v <= 0. || n_dot_l <= 0. {
+         return Vector3::new(0., 0., 0.);
+      }
+      let h = (wo + wi).normalize();
+      let n_dot_h = n.dot(&h).max(0.);
+      let v_dot_h = wo.dot(&h).max(0.);
+
+      let F = self.fresnel(v_dot_h);
+      let D = self.distribution(n_dot_h);
+      let G = self.smith_g1(n_dot_v) * self.smith_g1(n_dot_l);
+      let specular = F * (D * G / (4. * n_dot_v * n_dot_l));
+
+      // Whatever isn't reflected at the surface gets into the diffuse layer
+      let diffuse = (Vector3::new(1., 1., 1.) - F).component_mul(&self.base_color) * ((1. - self.metallic) / PI);
+
+      return (specular + diffuse) * n_dot_l;
+   }
+
+   fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, n: &Vector3<f64>) -> f64
+   {
+      let n = facing(n, wo);
+      let n_dot_v = n.dot(wo);
+      let n_dot_l = n.dot(wi);
+      if n_dot_v <= 0. || n_dot_l <= 0. {
+         return 0.;
+      }
+      let h = (wo + wi).normalize();
+      let specular_pdf = self.distribution(n.dot(&h).max(0.)) * n.dot(&h).max(0.) / (4. * wo.dot(&h).max(1.0e-12));
+      let diffuse_pdf = n_dot_l / PI;
+      let p = self.specular_probability(n_dot_v);
+      return p * specular_pdf + (1. - p) * diffuse_pdf;
+   }
+
+   /**
+    * Importance sample an incoming direction for the outgoing <wo>: pick the
+    * specular lobe (sampling half vectors from D) or the diffuse one (cosine
+    * weighted) at random, then weight by the combined pdf of both.
+    */
+   fn sample(&self, wo: &Vector3<f64>, n: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample>
+   {
+      let n_outward = n;
+      let n = facing(n, wo);
+      let n_dot_v = n.dot(wo);
+      if n_dot_v <= 0. {
+         return None;
+      }
+      let (tangent, bitangent) = orthonormal_basis(&n);
+
+      let wi = if u[0] < self.specular_probability(n_dot_v) {
+         // Half vector from the GGX distribution, then mirror wo about it
+         let a2 = self.alpha() * self.alpha();
+         let cos_theta = ((1. - u[1]) / (1. + (a2 - 1.) * u[1])).sqrt();
+         let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+         let phi = 2. * PI * u[2];
+         let h = sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * n;
+         2. * wo.dot(&h) * h - wo
+      } else {
+         cosine_sample_hemisphere(&n, u[1], u[2])
+      };
+
+      let pdf = self.pdf(wo, &wi, n_outward);
+      if pdf <= 0. {
+         return None;
+      }
+      return Some(BsdfSample { wi, weight: self.eval(wo, &wi, n_outward) / pdf, pdf });
+   }
+
+   fn albedo(&self) -> Vector3<f64>
+   {
+      return self.base_color;
+   }
+
+   fn ambient(&self) -> Vector3<f64>
+   {
+      return self.base_color;
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // ∫ f over the hemisphere above n = +z, on a grid even in cos(theta) and
+   // phi (which is even in solid angle)
+   fn integrate(f: impl Fn(&Vector3<f64>) -> f64) -> f64
+   {
+      let steps = 400;
+      let mut sum = 0.;
+      for i in 0..steps {
+         let cos_theta = (i as f64 + 0.5) / steps as f64;
+         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+         for j in 0..steps {
+            let phi = 2. * PI * (j as f64 + 0.5) / steps as f64;
+            sum += f(&Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+         }
+      }
+      return sum * 2. * PI / (steps * steps) as f64;
+   }
+
+   // u on a grid of <steps>^3 cells
+   fn grid(steps: usize) -> impl Iterator<Item = [f64; 3]>
+   {
+      let cell = move |k: usize| (k as f64 + 0.5) / steps as f64;
+      return (0..steps * steps * steps).map(move |k| [cell(k / (steps * steps)), cell(k / steps % steps), cell(k % steps)]);
+   }
+
+   fn wo() -> Vector3<f64>
+   {
+      return Vector3::new(0.5, 0., 1.).normalize();
+   }
+
+   #[test]
+   fn distribution_covers_the_surface_once()
+   {
+      // The projected area of the microfacets is that of the surface. D is
+      // even around n, and peaked for smooth surfaces, so integrate finely
+      // over the angle from n alone.
+      for roughness in [0.1, 0.3, 0.6, 1.] {
+         let bsdf = Microfacet::new(Vector3::new(1., 1., 1.), 1., roughness, 0.5);
+         let steps = 100000;
+         let d_theta = PI / 2. / steps as f64;
+         let area: f64 = (0..steps).map(|i| {
+            let theta = (i as f64 + 0.5) * d_theta;
+            bsdf.distribution(theta.cos()) * theta.cos() * theta.sin()
+         }).sum::<f64>() * 2. * PI * d_theta;
+         assert!((area - 1.).abs() < 1.0e-3, "roughness {}: {}", roughness, area);
+      }
+   }
+
+   #[test]
+   fn pdf_matches_what_sample_picks()
+   {
+      // Half vectors that mirror wo below the horizon end the path, and the
+      // pdf over the hemisphere is short by as much
+      let n = Vector3::new(0., 0., 1.);
+      for (metallic, roughness) in [(1., 0.5), (0., 0.5), (0.5, 0.8)] {
+         let bsdf = Microfacet::new(Vector3::new(0.8, 0.6, 0.4), metallic, roughness, 0.5);
+         let total = integrate(|wi| bsdf.pdf(&wo(), wi, &n));
+         let steps = 40;
+         let kept = grid(steps).filter(|&u| bsdf.sample(&wo(), &n, u).is_some()).count();
+         let kept = kept as f64 / (steps * steps * steps) as f64;
+         assert!(total <= 1.01 && (total - kept).abs() < 0.01, "metallic {}, roughness {}: {} {}", metallic, roughness, total, kept);
+      }
+   }
+
+   #[test]
+   fn white_surfaces_reflect_no_more_than_they_get()
+   {
+      let n = Vector3::new(0., 0., 1.);
+      for (metallic, roughness) in [(1., 0.3), (1., 1.), (0., 0.5), (0., 1.)] {
+         let bsdf = Microfacet::new(Vector3::new(1., 1., 1.), metallic, roughness, 0.5);
+         let reflected = integrate(|wi| bsdf.eval(&wo(), wi, &n).x);
+         assert!(reflected < 1.01, "metallic {}, roughness {}: {}", metallic, roughness, reflected);
+      }
+      // Light is only lost to masking, which is little on smooth surfaces
+      let bsdf = Microfacet::new(Vector3::new(1., 1., 1.), 1., 0.3, 0.5);
+      assert!(integrate(|wi| bsdf.eval(&wo(), wi, &n).x) > 0.95);
+   }
+
+   #[test]
+   fn eval_is_reciprocal_and_two_sided()
+   {
+      let bsdf = Microfacet::new(Vector3::new(0.9, 0.5, 0.2), 0.3, 0.4, 0.5);
+      let n = Vector3::new(0., 0., 1.);
+      let wi = Vector3::new(-0.2, 0.4, 1.).normalize();
+      // eval has the cosine of wi folded in
+      let forward = bsdf.eval(&wo(), &wi, &n) / wi.z;
+      let backward = bsdf.eval(&wi, &wo(), &n) / wo().z;
+      assert!((forward - backward).norm() < 1.0e-12);
+      let flip = Vector3::new(1., 1., -1.);
+      let below = bsdf.eval(&wo().component_mul(&flip), &wi.component_mul(&flip), &n);
+      assert!((below - bsdf.eval(&wo(), &wi, &n)).norm() < 1.0e-12);
+   }
+
+   #[test]
+   fn samples_are_weighted_by_eval_over_pdf()
+   {
+      let bsdf = Microfacet::new(Vector3::new(0.9, 0.5, 0.2), 0.3, 0.4, 0.5);
+      let n = Vector3::new(0., 0., 1.);
+      let mut taken = 0;
+      for u in grid(6) {
+         if let Some(sample) = bsdf.sample(&wo(), &n, u) {
+            assert!((sample.wi.norm() - 1.).abs() < 1.0e-9 && sample.wi.z > 0.);
+            let weight = bsdf.eval(&wo(), &sample.wi, &n) / bsdf.pdf(&wo(), &sample.wi, &n);
+            assert!((sample.weight - weight).norm() < 1.0e-12);
+            taken += 1;
+         }
+      }
+      assert!(taken > 150);
+   }
+}
@@ -0,0 +1,54 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+use crate::scene::bsdf::{ Bsdf, BsdfSample };
+use crate::render::reflect;
+
+
+// A perfect mirror, tinted by <color>
+#[derive(Clone)]
+pub struct Mirror {
+   pub color: Vector3<f64>
+}
+
+
+impl Mirror {
+   pub fn new(color: Vector3<f64>) -> Mirror
+   {
+      return Mirror { color };
+   }
+}
+
+
+impl Bsdf for Mirror {
+   fn eval(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _n: &Vector3<f64>) -> Vector3<f64>
+   {
+      return Vector3::new(0., 0., 0.);
+   }
+
+   fn sample(&self, wo: &Vector3<f64>, n: &Vector3<f64>, _u: [f64; 3]) -> Option<BsdfSample>
+   {
+      return Some(BsdfSample { wi: reflect(&-wo, n), weight: self.color, pdf: 0. });
+   }
+
+   fn pdf(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _n: &Vector3<f64>) -> f64
+   {
+      return 0.;
+   }
+
+   fn specular(&self, wo: &Vector3<f64>, n: &Vector3<f64>) -> Vec<(Vector3<f64>, Vector3<f64>)>
+   {
+      return vec![(reflect(&-wo, n), self.color)];
+   }
+
+   fn albedo(&self) -> Vector3<f64>
+   {
+      return self.color;
+   }
+
+   // Mirrors only show what they reflect
+   fn ambient(&self) -> Vector3<f64>
+   {
+      return Vector3::new(0., 0., 0.);
+   }
+}
@@ -1,4 +1,4 @@
-/**
+/*!
  * This is just the module for scene. <scene> is a module. 
  * In rust, pretty much every .rs file is a module.
  * https://stackoverflow.com/questions/22596920/split-a-module-across-several-files
@@ -18,8 +18,44 @@ pub use self::pointlight::PointLight;
 pub mod directionallight;
 pub use self::directionallight::DirectionalLight;
 
+pub mod ambientlight;
+pub use self::ambientlight::AmbientLight;
+
 pub mod material;
-pub use self::material::Material;
+pub use self::material::{ Material, Scattering };
+
+pub mod bsdf;
+pub use self::bsdf::Bsdf;
+
+pub mod blinnphong;
+pub use self::blinnphong::BlinnPhong;
+
+pub mod lambertian;
+pub use self::lambertian::Lambertian;
+
+pub mod mirror;
+pub use self::mirror::Mirror;
+
+pub mod dispersion;
+pub use self::dispersion::Dispersion;
+
+pub mod dielectric;
+pub use self::dielectric::Dielectric;
+
+pub mod microfacet;
+pub use self::microfacet::Microfacet;
+
+pub mod interface;
+pub use self::interface::Interface;
+
+pub mod henyeygreenstein;
+pub use self::henyeygreenstein::HenyeyGreenstein;
+
+pub mod voxelgrid;
+pub use self::voxelgrid::VoxelGrid;
+
+pub mod medium;
+pub use self::medium::Medium;
 
 
 
@@ -36,4 +72,10 @@ pub trait Light {
    //    max_t:   parametric distance from q along dir to light (may be inf)
    fn direction(&self, q: &Vector3<f64>, dir: &mut Vector3<f64>, max_t: &mut f64);
    fn get_intensity(&self) -> Vector3<f64>;
+
+   // Where the light is, for lights at a point (which photons are shot from)
+   fn position(&self) -> Option<Vector3<f64>>
+   {
+      return None;
+   }
 }
@@ -36,4 +36,9 @@ impl Light for PointLight {
    {
       return self.I;
    }
+
+   fn position(&self) -> Option<Vector3<f64>>
+   {
+      return Some(self.p);
+   }
 }
@@ -17,10 +17,18 @@ pub struct Ray {
 impl Ray {
    // Compute a viewing ray
    pub fn new(cam: &Camera, i: usize, j: usize, n_x: usize, n_y: usize) -> Ray 
+   {
+      // Through the center of pixel (i, j)
+      return Ray::through(cam, i as f64 + 0.5, j as f64 + 0.5, n_x, n_y);
+   }
+
+   // Compute a viewing ray through any point (x, y) of the image, measured in
+   // pixels from the top left corner (e.g. for jittered samples)
+   pub fn through(cam: &Camera, x: f64, y: f64, n_x: usize, n_y: usize) -> Ray
    {
       // Get the scalar (weights) for the uvw coordinates:
-      let u = -(cam.width / 2.0) + (cam.width * (i as f64 + 0.5) / n_x as f64);
-      let v = (cam.height / 2.0) - (cam.height * (j as f64 + 0.5) / n_y as f64);
+      let u = -(cam.width / 2.0) + (cam.width * x / n_x as f64);
+      let v = (cam.height / 2.0) - (cam.height * y / n_y as f64);
       let w = -cam.d;
 
       // Find the point <s> of (i, j) in the uvw camera frame coordinates
@@ -0,0 +1,161 @@
+use nalgebra as na;
+use na::{ Vector3 };
+
+
+/**
+ * Densities (and optionally temperatures) on a regular grid of voxels that
+ * fills the box from <min> to <max>. Values sit at the centers of the voxels,
+ * are stored x first, then y, then z, and are interpolated trilinearly in
+ * between. Outside the box the density is zero.
+ */
+pub struct VoxelGrid {
+   pub dims: [usize; 3],
+   pub min: Vector3<f64>,
+   pub max: Vector3<f64>,
+   pub density: Vec<f64>,
+   pub temperature: Option<Vec<f64>>,
+   // The largest density anywhere, to bound the medium for delta tracking
+   pub max_density: f64
+}
+
+
+impl VoxelGrid {
+   pub fn new(dims: [usize; 3], min: &Vector3<f64>, max: &Vector3<f64>, density: Vec<f64>,
+              temperature: Option<Vec<f64>>) -> VoxelGrid
+   {
+      let max_density = density.iter().cloned().fold(0., f64::max);
+      return VoxelGrid { dims, min: min.inf(max), max: min.sup(max), density, temperature, max_density };
+   }
+
+   pub fn density(&self, p: &Vector3<f64>) -> f64
+   {
+      return self.interpolate(&self.density, p);
+   }
+
+   // Zero where there's no temperature channel
+   pub fn temperature(&self, p: &Vector3<f64>) -> f64
+   {
+      return match &self.temperature {
+         Some(temperature) => self.interpolate(temperature, p),
+         None => 0.
+      };
+   }
+
+   // The part [t_near, t_far] of the ray from <origin> along <d> that is
+   // inside the box, clipped to [0, max_t]. None if there isn't any.
+   pub fn clip(&self, origin: &Vector3<f64>, d: &Vector3<f64>, max_t: f64) -> Option<(f64, f64)>
+   {
+      let mut t_near = 0.;
+      let mut t_far = max_t;
+      for axis in 0..3 {
+         if d[axis] == 0. {
+            if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+               return None;
+            }
+            continue;
+         }
+         let t0 = (self.min[axis] - origin[axis]) / d[axis];
+         let t1 = (self.max[axis] - origin[axis]) / d[axis];
+         t_near = f64::max(t_near, t0.min(t1));
+         t_far = f64::min(t_far, t0.max(t1));
+         if t_near >= t_far {
+            return None;
+         }
+      }
+      return Some((t_near, t_far));
+   }
+
+   fn interpolate(&self, values: &[f64], p: &Vector3<f64>) -> f64
+   {
+      let mut lower = [0usize; 3];
+      let mut fraction = [0.; 3];
+      for axis in 0..3 {
+         if p[axis] < self.min[axis] || p[axis] > self.max[axis] {
+            return 0.;
+         }
+         // Continuous voxel coordinate, with voxel centers at whole numbers
+         // and the edge voxels held out to the sides of the box
+         let n = self.dims[axis];
+         let u = (p[axis] - self.min[axis]) / (self.max[axis] - self.min[axis]) * n as f64 - 0.5;
+         let u = u.clamp(0., (n - 1) as f64);
+         lower[axis] = (u.floor() as usize).min(n.saturating_sub(2));
+         fraction[axis] = u - lower[axis] as f64;
+      }
+
+      let [n_x, n_y, _] = self.dims;
+      let mut value = 0.;
+      for corner in 0..8 {
+         let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+         let mut weight = 1.;
+         let mut index = [0usize; 3];
+         for axis in 0..3 {
+            index[axis] = (lower[axis] + offset[axis]).min(self.dims[axis] - 1);
+            weight *= if offset[axis] == 1 { fraction[axis] } else { 1. - fraction[axis] };
+         }
+         if weight > 0. {
+            value += weight * values[(index[2] * n_y + index[1]) * n_x + index[0]];
+         }
+      }
+      return value;
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // 2 x 2 x 2 voxels over the unit cube, density x + 2y + 4z at the centers
+   fn grid() -> VoxelGrid
+   {
+      let mut density = Vec::new();
+      for z in 0..2 {
+         for y in 0..2 {
+            for x in 0..2 {
+               density.push((x + 2 * y + 4 * z) as f64);
+            }
+         }
+      }
+      return VoxelGrid::new([2, 2, 2], &Vector3::new(1., 1., 1.), &Vector3::zeros(), density, None);
+   }
+
+   #[test]
+   fn densities_are_trilinear_between_the_centers()
+   {
+      let grid = grid();
+      assert_eq!(grid.min, Vector3::zeros());
+      assert_eq!(grid.max_density, 7.);
+      // Centers at 0.25 and 0.75: a linear function comes back exactly
+      let linear = |p: &Vector3<f64>| 2. * (p.x - 0.25) + 4. * (p.y - 0.25) + 8. * (p.z - 0.25);
+      for p in [Vector3::new(0.25, 0.25, 0.25), Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.3, 0.6, 0.7)] {
+         assert!((grid.density(&p) - linear(&p)).abs() < 1.0e-12);
+      }
+      // Held flat out to the sides of the box, zero beyond it
+      assert!((grid.density(&Vector3::new(0.1, 0.75, 0.75)) - 6.).abs() < 1.0e-12);
+      assert_eq!(grid.density(&Vector3::new(1.1, 0.5, 0.5)), 0.);
+      assert_eq!(grid.temperature(&Vector3::new(0.5, 0.5, 0.5)), 0.);
+   }
+
+   #[test]
+   fn single_voxels_are_constant()
+   {
+      let grid = VoxelGrid::new([1, 1, 1], &Vector3::zeros(), &Vector3::new(1., 1., 1.), vec![3.], Some(vec![900.]));
+      assert_eq!(grid.density(&Vector3::new(0.9, 0.1, 0.5)), 3.);
+      assert_eq!(grid.temperature(&Vector3::new(0.2, 0.1, 0.5)), 900.);
+   }
+
+   #[test]
+   fn rays_are_clipped_to_the_box()
+   {
+      let grid = grid();
+      let d = Vector3::new(0., 0., -1.);
+      assert_eq!(grid.clip(&Vector3::new(0.5, 0.5, 3.), &d, 10.), Some((2., 3.)));
+      assert_eq!(grid.clip(&Vector3::new(0.5, 0.5, 3.), &d, 2.5), Some((2., 2.5)));
+      assert_eq!(grid.clip(&Vector3::new(0.5, 0.5, 0.5), &d, 10.), Some((0., 0.5)));
+      assert_eq!(grid.clip(&Vector3::new(1.5, 0.5, 3.), &d, 10.), None);
+      assert_eq!(grid.clip(&Vector3::new(0.5, 0.5, 3.), &-d, 10.), None);
+      let diagonal = Vector3::new(1., 1., 0.).normalize();
+      let (near, far) = grid.clip(&Vector3::new(-1., -1., 0.5), &diagonal, 10.).unwrap();
+      assert!((near - 2f64.sqrt()).abs() < 1.0e-12 && (far - 2. * 2f64.sqrt()).abs() < 1.0e-12);
+   }
+}
@@ -0,0 +1,213 @@
+/**
+ * Light as a function of wavelength, for spectral rendering. The scene is
+ * still described in RGB: colors are turned into spectra with Smits' method
+ * ("An RGB to Spectrum Conversion for Reflectances", 1999), which builds
+ * each one from a white and the six spectra of the pure primaries and
+ * secondaries, and the light found at each wavelength goes back to RGB
+ * through the CIE 1931 XYZ color matching functions.
+ *
+ * Wavelengths are in nanometers.
+ */
+use std::sync::OnceLock;
+
+use nalgebra as na;
+use na::{ Vector3 };
+
+
+// Smits' spectra, sampled at 10 wavelengths evenly spread over [380, 720]
+const SMITS_FIRST: f64 = 380.;
+const SMITS_LAST: f64 = 720.;
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// The range wavelengths are sampled from
+const FIRST_WAVELENGTH: f64 = 360.;
+const LAST_WAVELENGTH: f64 = 830.;
+
+
+// The CIE 1931 color matching functions at <lambda>, from the piecewise
+// Gaussian fit by Wyman, Sloan and Shirley (2013)
+pub fn cie_xyz(lambda: f64) -> Vector3<f64>
+{
+   let lobe = |mean: f64, left: f64, right: f64| -> f64 {
+      let x = (lambda - mean) / if lambda < mean { left } else { right };
+      return (-0.5 * x * x).exp();
+   };
+   let x_bar = 1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) - 0.065 * lobe(501.1, 20.4, 26.2);
+   let y_bar = 0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1);
+   let z_bar = 1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8);
+   return Vector3::new(x_bar, y_bar, z_bar);
+}
+
+
+// CIE XYZ to linear sRGB (which may come out negative)
+pub fn xyz_to_srgb(xyz: &Vector3<f64>) -> Vector3<f64>
+{
+   return Vector3::new( 3.2406 * xyz[0] - 1.5372 * xyz[1] - 0.4986 * xyz[2],
+                       -0.9689 * xyz[0] + 1.8758 * xyz[1] + 0.0415 * xyz[2],
+                        0.0557 * xyz[0] - 0.2040 * xyz[1] + 1.0570 * xyz[2]);
+}
+
+
+fn smits(table: &[f64; 10], lambda: f64) -> f64
+{
+   let u = ((lambda - SMITS_FIRST) / (SMITS_LAST - SMITS_FIRST) * 9.).clamp(0., 9.);
+   let k = (u as usize).min(8);
+   let f = u - k as f64;
+   return (1. - f) * table[k] + f * table[k + 1];
+}
+
+
+// The value at <lambda> of the spectrum for the color <rgb>. Any color
+// works, including lights brighter than one: white goes to a flat spectrum
+// of the same height.
+pub fn rgb_to_spectrum(rgb: &Vector3<f64>, lambda: f64) -> f64
+{
+   let (r, g, b) = (rgb[0], rgb[1], rgb[2]);
+   let s = |table: &[f64; 10]| smits(table, lambda);
+   if r <= g && r <= b {
+      // Red is the smallest: white, plus cyan, plus blue or green
+      let base = r * s(&SMITS_WHITE);
+      if g <= b {
+         return base + (g - r) * s(&SMITS_CYAN) + (b - g) * s(&SMITS_BLUE);
+      }
+      return base + (b - r) * s(&SMITS_CYAN) + (g - b) * s(&SMITS_GREEN);
+   } else if g <= r && g <= b {
+      let base = g * s(&SMITS_WHITE);
+      if r <= b {
+         return base + (r - g) * s(&SMITS_MAGENTA) + (b - r) * s(&SMITS_BLUE);
+      }
+      return base + (b - g) * s(&SMITS_MAGENTA) + (r - b) * s(&SMITS_RED);
+   }
+   let base = b * s(&SMITS_WHITE);
+   if r <= g {
+      return base + (r - b) * s(&SMITS_YELLOW) + (g - r) * s(&SMITS_GREEN);
+   }
+   return base + (g - b) * s(&SMITS_YELLOW) + (r - g) * s(&SMITS_RED);
+}
+
+
+// <color> as seen at <wavelength>, as a gray that the RGB shading code can
+// work with unchanged, or as it is when not rendering spectrally
+pub fn color_at(color: &Vector3<f64>, wavelength: Option<f64>) -> Vector3<f64>
+{
+   return match wavelength {
+      Some(lambda) => {
+         let s = rgb_to_spectrum(color, lambda);
+         Vector3::new(s, s, s)
+      }
+      None => *color
+   };
+}
+
+
+// Pick a wavelength from the uniform random number <u>, mostly where the eye
+// is sensitive, with the density of having picked it. (The distribution and
+// its inverse are from pbrt-v4.)
+pub fn sample_wavelength(u: f64) -> (f64, f64)
+{
+   let lambda = 538. - 138.888889 * (0.85691062 - 1.82750197 * u).atanh();
+   let lambda = lambda.clamp(FIRST_WAVELENGTH, LAST_WAVELENGTH);
+   let pdf = 0.0039398042 / (0.0072 * (lambda - 538.)).cosh().powi(2);
+   return (lambda, pdf);
+}
+
+
+/**
+ * The RGB color of light of <radiance> at the single wavelength <lambda>.
+ * Scaled so that a flat spectrum (the white of rgb_to_spectrum) integrates
+ * to white: the flat spectrum is taken as the white point, like sRGB's D65.
+ */
+pub fn wavelength_to_rgb(lambda: f64, radiance: f64) -> Vector3<f64>
+{
+   static WHITE: OnceLock<Vector3<f64>> = OnceLock::new();
+   let white = WHITE.get_or_init(|| {
+      let mut sum = Vector3::new(0., 0., 0.);
+      let mut lambda = FIRST_WAVELENGTH;
+      while lambda <= LAST_WAVELENGTH {
+         sum += xyz_to_srgb(&cie_xyz(lambda));
+         lambda += 1.;
+      }
+      return sum;
+   });
+   return radiance * xyz_to_srgb(&cie_xyz(lambda)).component_div(white);
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // What a spectral render averages the color <rgb> out to, integrating
+   // over every nanometer
+   fn round_trip(rgb: &Vector3<f64>) -> Vector3<f64>
+   {
+      let mut sum = Vector3::new(0., 0., 0.);
+      let mut lambda = FIRST_WAVELENGTH;
+      while lambda <= LAST_WAVELENGTH {
+         sum += wavelength_to_rgb(lambda, rgb_to_spectrum(rgb, lambda));
+         lambda += 1.;
+      }
+      return sum;
+   }
+
+   #[test]
+   fn colors_come_back_from_their_spectra()
+   {
+      // Grays exactly (but for the little wiggle in Smits' white)
+      for gray in [1., 0.18, 5.] {
+         let rgb = Vector3::new(gray, gray, gray);
+         assert!((round_trip(&rgb) - rgb).abs().max() < 1.0e-3 * gray);
+      }
+      // Colors close, primaries a little duller
+      for rgb in [Vector3::new(0.8, 0.5, 0.2), Vector3::new(0.2, 0.4, 0.6), Vector3::new(1., 0., 0.)] {
+         let back = round_trip(&rgb);
+         assert!((back - rgb).abs().max() < 0.15, "{:?} came back as {:?}", rgb, back);
+      }
+      assert_eq!(rgb_to_spectrum(&Vector3::new(0., 0., 0.), 500.), 0.);
+   }
+
+   #[test]
+   fn spectra_have_the_hue_of_their_color()
+   {
+      let red = Vector3::new(0.9, 0.1, 0.1);
+      assert!(rgb_to_spectrum(&red, 650.) > 0.8 && rgb_to_spectrum(&red, 450.) < 0.2);
+      let blue = Vector3::new(0.1, 0.1, 0.9);
+      assert!(rgb_to_spectrum(&blue, 450.) > 0.8 && rgb_to_spectrum(&blue, 650.) < 0.2);
+      assert_eq!(color_at(&red, None), red);
+      let s = rgb_to_spectrum(&red, 600.);
+      assert_eq!(color_at(&red, Some(600.)), Vector3::new(s, s, s));
+   }
+
+   #[test]
+   fn wavelengths_are_picked_with_the_density_given()
+   {
+      // The density is the inverse of how fast the wavelength moves with u
+      let h = 1.0e-6;
+      for k in 1..20 {
+         let u = k as f64 / 20.;
+         let (lambda, pdf) = sample_wavelength(u);
+         assert!((FIRST_WAVELENGTH..=LAST_WAVELENGTH).contains(&lambda));
+         let slope = (sample_wavelength(u + h).0 - sample_wavelength(u - h).0) / (2. * h);
+         assert!((slope * pdf - 1.).abs() < 1.0e-3, "u = {}: {} {}", u, slope, pdf);
+      }
+      // Mostly in the middle of the visible range
+      assert!(sample_wavelength(0.5).0 > 500. && sample_wavelength(0.5).0 < 600.);
+   }
+
+   #[test]
+   fn the_eye_is_most_sensitive_to_green()
+   {
+      let peak = (380..780).max_by(|&a, &b| cie_xyz(a as f64).y.partial_cmp(&cie_xyz(b as f64).y).unwrap()).unwrap();
+      assert!((550..=560).contains(&peak));
+      assert!((cie_xyz(peak as f64).y - 1.).abs() < 0.02);
+      // And pure wavelengths have their color
+      assert!(wavelength_to_rgb(450., 1.).z > wavelength_to_rgb(450., 1.).x);
+      assert!(wavelength_to_rgb(620., 1.).x > wavelength_to_rgb(620., 1.).z);
+   }
+}